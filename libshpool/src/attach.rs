@@ -25,13 +25,13 @@ use anyhow::{anyhow, bail, Context};
 use nix::unistd;
 use shpool_protocol::{
     AttachHeader, AttachReplyHeader, ConnectHeader, DetachReply, DetachRequest, MaybeSwitch,
-    ResizeReply, ResizeRequest, SessionMessageReply, SessionMessageRequest,
-    SessionMessageRequestPayload, TtySize,
+    OutputLogFormat, ResizeReply, ResizeRequest, RestartPolicy, SessionMessageReply,
+    SessionMessageRequest, SessionMessageRequestPayload, TtySize,
 };
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    config, duration, protocol,
+    choose, config, duration, protocol,
     protocol::{ClientResult, PipeBytesResult},
     template, test_hooks,
     tty::TtySizeExt as _,
@@ -46,10 +46,17 @@ pub fn run(
     name: String,
     force: bool,
     background: bool,
+    watch: bool,
+    share: bool,
     ttl: Option<String>,
     cmd: Option<String>,
     dir: Option<String>,
     start_cmd: Option<String>,
+    output_log: Option<OutputLogFormat>,
+    keybinding_profile: Option<String>,
+    restart: Option<RestartPolicy>,
+    max_restarts: Option<u32>,
+    linger: bool,
 ) -> anyhow::Result<()> {
     info!("\n\n======================== STARTING ATTACH ============================\n\n");
     test_hooks::emit("attach-startup");
@@ -83,7 +90,24 @@ pub fn run(
         None => None,
     };
 
-    let attach = Attach { config_manager, force, background, ttl, tmpls, socket };
+    let attach = Attach {
+        config_manager,
+        force,
+        background,
+        watch,
+        share,
+        shared_client_id: Arc::new(Mutex::new(None)),
+        sig_handler_session_name_slot: None,
+        renamed_to: Arc::new(Mutex::new(None)),
+        ttl,
+        output_log,
+        keybinding_profile,
+        restart,
+        max_restarts,
+        linger,
+        tmpls,
+        socket,
+    };
 
     attach.run()
 }
@@ -92,13 +116,28 @@ struct Attach {
     config_manager: config::Manager,
     force: bool,
     background: bool,
+    watch: bool,
+    share: bool,
+    /// Set when we joined a session as a shared client, so the
+    /// signal handler can tag resizes with our id.
+    shared_client_id: Arc<Mutex<Option<u64>>>,
+    /// Where the signal handler gets the name of the session to resize.
+    sig_handler_session_name_slot: Option<Arc<Mutex<String>>>,
+    /// Set when the session we are attached to gets renamed out from
+    /// under us.
+    renamed_to: Arc<Mutex<Option<String>>>,
     ttl: Option<time::Duration>,
+    output_log: Option<OutputLogFormat>,
+    keybinding_profile: Option<String>,
+    restart: Option<RestartPolicy>,
+    max_restarts: Option<u32>,
+    linger: bool,
     tmpls: Templates,
     socket: PathBuf,
 }
 
 impl Attach {
-    fn run(self) -> anyhow::Result<()> {
+    fn run(mut self) -> anyhow::Result<()> {
         // This is the first time we dial the daemon, so we do want to show
         // warnings. After this we shouldn't show them again.
         let mut client = self.dial_client(false).context("dialing daemon")?;
@@ -110,8 +149,17 @@ impl Attach {
         let var_map = maybe_switch.vars.iter().cloned().collect();
         let mut resolved = self.tmpls.apply(&var_map);
 
-        let sig_handler_session_name_slot = if !self.background {
-            Some(SignalHandler::new(resolved.session_name.clone(), self.socket.clone()).spawn()?)
+        // Watchers have no say in the session size, so there is no
+        // point forwarding resizes.
+        self.sig_handler_session_name_slot = if !self.background && !self.watch {
+            Some(
+                SignalHandler::new(
+                    resolved.session_name.clone(),
+                    Arc::clone(&self.shared_client_id),
+                    self.socket.clone(),
+                )
+                .spawn()?,
+            )
         } else {
             None
         };
@@ -119,16 +167,33 @@ impl Attach {
         info!("looping on attach_with_name");
         loop {
             info!("attaching to '{}'", resolved.session_name);
+            let mut current = resolved.session_name.clone();
             match self.attach_resolved(resolved) {
                 Ok(AttachResult::Done) => return Ok(()),
                 Ok(AttachResult::Switch(s)) => maybe_switch = s,
                 Err(e) => return Err(e),
             }
+            if let Some(name) = self.renamed_to.lock().unwrap().take() {
+                self.tmpls.session_name = template::Template::literal(&name);
+                current = name;
+            }
+
+            let switch_to = if maybe_switch.choose {
+                Some(choose::pick(&self.socket, Some(&current))?.unwrap_or(current))
+            } else {
+                maybe_switch.switch_to.take()
+            };
+            if let Some(name) = switch_to {
+                // Following a template after being sent somewhere specific
+                // would just switch us straight back the next time a var
+                // changes, so stick with the new session from here on.
+                self.tmpls.session_name = template::Template::literal(&name);
+            }
 
             let var_map = maybe_switch.vars.iter().cloned().collect();
             resolved = self.tmpls.apply(&var_map);
 
-            if let Some(ref slot) = sig_handler_session_name_slot {
+            if let Some(ref slot) = self.sig_handler_session_name_slot {
                 let mut slot = slot.lock().unwrap();
                 *slot = resolved.session_name.clone();
             }
@@ -228,7 +293,29 @@ impl Attach {
 
         info!("entering bidi streaming mode");
         let session_name_tmpl = self.tmpls.session_name.clone();
+        let sig_handler_session_name_slot = self.sig_handler_session_name_slot.clone();
+        let renamed_to = Arc::clone(&self.renamed_to);
         match attach_client.pipe_bytes(move |maybe_switch: &MaybeSwitch| {
+            let mut renamed_to = renamed_to.lock().unwrap();
+            if let Some(name) = &maybe_switch.renamed_to {
+                info!("session renamed to '{}'", name);
+                if let Some(slot) = &sig_handler_session_name_slot {
+                    *slot.lock().unwrap() = name.clone();
+                }
+                *renamed_to = Some(name.clone());
+                return false;
+            }
+            if maybe_switch.choose {
+                return true;
+            }
+            let current = renamed_to.as_ref().unwrap_or(&resolved.session_name);
+            if let Some(name) = &maybe_switch.switch_to {
+                return name != current;
+            }
+            // A renamed session has left its template behind.
+            if renamed_to.is_some() {
+                return false;
+            }
             let var_map: HashMap<String, String> = maybe_switch.vars.iter().cloned().collect();
             session_name_tmpl.apply(&var_map) != resolved.session_name
         }) {
@@ -286,12 +373,20 @@ impl Attach {
                 cmd: resolved.cmd.clone(),
                 dir: start_dir,
                 start_cmd: resolved.start_cmd.clone(),
+                watch: self.watch,
+                share: self.share,
+                output_log: self.output_log,
+                keybinding_profile: self.keybinding_profile.clone(),
+                restart: self.restart,
+                max_restarts: self.max_restarts,
+                linger: self.linger,
             }))
             .context("writing attach header")?;
 
         let attach_resp: AttachReplyHeader = client.read_reply().context("reading attach reply")?;
         info!("attach_resp.status={:?}", attach_resp.status);
 
+        *self.shared_client_id.lock().unwrap() = None;
         {
             use shpool_protocol::AttachStatus::*;
             match attach_resp.status {
@@ -302,6 +397,14 @@ impl Attach {
                     eprintln!("forbidden: {reason}");
                     return Err(anyhow!("forbidden: {reason}"));
                 }
+                Shared { client_id } => {
+                    info!("sharing session '{}' as client {}", resolved.session_name, client_id);
+                    *self.shared_client_id.lock().unwrap() = Some(client_id);
+                }
+                NotFound => {
+                    eprintln!("no session named '{}' to watch", resolved.session_name);
+                    return Err(anyhow!("session '{}' not found", resolved.session_name));
+                }
                 Attached { warnings } => {
                     for warning in warnings.into_iter() {
                         eprintln!("shpool: warn: {warning}");
@@ -419,12 +522,17 @@ enum AttachResult {
 
 struct SignalHandler {
     session_name: Arc<Mutex<String>>,
+    shared_client_id: Arc<Mutex<Option<u64>>>,
     socket: PathBuf,
 }
 
 impl SignalHandler {
-    fn new(session_name: String, socket: PathBuf) -> Self {
-        SignalHandler { session_name: Arc::new(Mutex::new(session_name)), socket }
+    fn new(
+        session_name: String,
+        shared_client_id: Arc<Mutex<Option<u64>>>,
+        socket: PathBuf,
+    ) -> Self {
+        SignalHandler { session_name: Arc::new(Mutex::new(session_name)), shared_client_id, socket }
     }
 
     fn spawn(self) -> anyhow::Result<Arc<Mutex<String>>> {
@@ -472,6 +580,7 @@ impl SignalHandler {
                 session_name: self.get_session_name(),
                 payload: SessionMessageRequestPayload::Resize(ResizeRequest {
                     tty_size: tty_size.clone(),
+                    client_id: *self.shared_client_id.lock().unwrap(),
                 }),
             }))
             .context("writing resize request")?;
@@ -0,0 +1,82 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Context};
+use shpool_protocol::{CaptureReply, CaptureRequest, ConnectHeader};
+
+use crate::{protocol, protocol::ClientResult};
+
+pub fn run(
+    session: String,
+    lines: Option<u16>,
+    json_output: bool,
+    socket: PathBuf,
+) -> anyhow::Result<()> {
+    let mut client = match protocol::Client::new(socket) {
+        Ok(ClientResult::JustClient(c)) => c,
+        Ok(ClientResult::VersionMismatch { warning, client }) => {
+            eprintln!("warning: {warning}, try restarting your daemon");
+            client
+        }
+        Err(err) => {
+            let io_err = err.downcast::<io::Error>()?;
+            if io_err.kind() == io::ErrorKind::NotFound {
+                eprintln!("could not connect to daemon");
+            }
+            return Err(io_err).context("connecting to daemon");
+        }
+    };
+
+    client
+        .write_connect_header(ConnectHeader::Capture(CaptureRequest {
+            session: session.clone(),
+            lines,
+        }))
+        .context("sending capture header")?;
+    let reply: CaptureReply = client.read_reply().context("reading reply")?;
+
+    let contents = match reply {
+        CaptureReply::Contents(contents) => contents,
+        CaptureReply::NotFound => {
+            eprintln!("not found: {session}");
+            return Err(anyhow!("not found: {session}"));
+        }
+        CaptureReply::NoSpool => {
+            eprintln!(
+                "session '{session}' has no output to capture (session_restore_mode is simple)"
+            );
+            return Err(anyhow!("no output spool for {session}"));
+        }
+    };
+
+    if json_output {
+        let capture = serde_json::json!({
+            "session": session,
+            "lines": lines,
+            "contents": String::from_utf8_lossy(&contents),
+        });
+        println!("{}", serde_json::to_string_pretty(&capture)?);
+    } else {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&contents).context("writing capture")?;
+        stdout.flush().context("flushing capture")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,770 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*! The full-screen session picker behind `shpool choose` and the `choose`
+  keybinding action.
+
+  The picker lists the sessions from a `ListReply` and re-lists whenever
+  the daemon's event stream says a session has changed, so the list stays
+  current while it is up. Typing fuzzy filters the list, the arrow keys
+  (or Ctrl-p and Ctrl-n) move the selection, enter attaches, Ctrl-k kills,
+  Ctrl-d detaches, Ctrl-r renames, and Esc, Ctrl-c or Ctrl-g go back
+  without picking anything. If the filter does not exactly name a session,
+  enter can also create a new session by that name.
+
+  Renaming swaps the filter line for a prompt asking for the new name.
+  Enter renames, and Esc, Ctrl-c or Ctrl-g go back to picking.
+*/
+
+use std::{
+    io::{self, Read, Write},
+    os::{fd::AsFd, unix::net::UnixStream},
+    path::{Path, PathBuf},
+    time,
+};
+
+use anyhow::Context;
+use nix::poll::{self, PollFd, PollFlags};
+use shpool_protocol::{
+    ConnectHeader, DetachReply, DetachRequest, KillReply, KillRequest, ListReply, RenameReply,
+    RenameRequest, Session, SessionStatus, TtySize,
+};
+use tracing::{info, warn};
+
+use crate::{
+    attach, config, consts, duration, protocol, protocol::ClientResult, tty, tty::TtySizeExt,
+};
+
+/// How often to redraw when nothing is happening, which keeps the ages
+/// ticking along.
+const TICK_MS: u16 = 1000;
+
+/// The footer shown when there is no message to show instead.
+const HELP: &str = "enter: attach  ctrl-k: kill  ctrl-d: detach  ctrl-r: rename  esc: quit";
+
+/// The footer shown while asking for a new name.
+const RENAME_HELP: &str = "enter: rename  esc: cancel";
+
+/// Pick a session, then attach to it.
+pub fn run(socket: PathBuf, config_manager: config::Manager) -> anyhow::Result<()> {
+    let Some(name) = pick(&socket, None)? else {
+        return Ok(());
+    };
+    attach::run(
+        socket,
+        config_manager,
+        name,
+        false,
+        false,
+        false,
+        false,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        false,
+    )
+}
+
+/// Show the picker and let the user choose a session. Returns None if
+/// they would rather not pick one, or if they pick `current`, the session
+/// they came from. The chosen name might not be a session yet, in which
+/// case attaching to it creates it.
+pub fn pick(socket: &Path, current: Option<&str>) -> anyhow::Result<Option<String>> {
+    let mut picker = Picker::new(socket, current);
+    picker.refresh()?;
+    let mut events = subscribe(socket);
+
+    let _tty_guard = tty::set_attach_flags()?;
+    let mut screen = Screen::enter()?;
+    let stdin = io::stdin();
+    let mut buf = vec![0; consts::BUF_SIZE];
+    loop {
+        screen.draw(&picker, time::SystemTime::now())?;
+
+        let (stdin_ready, events_ready) = {
+            let mut poll_fds = vec![PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
+            if let Some(events) = &events {
+                poll_fds.push(PollFd::new(events.as_fd(), PollFlags::POLLIN));
+            }
+            match poll::poll(&mut poll_fds, TICK_MS) {
+                Ok(_) => {}
+                // most likely a resize, which the redraw takes care of
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(e) => return Err(e).context("polling for input"),
+            }
+            let ready = |fd: &PollFd| {
+                fd.revents().is_some_and(|r| r.intersects(PollFlags::POLLIN | PollFlags::POLLHUP))
+            };
+            (ready(&poll_fds[0]), poll_fds.get(1).is_some_and(ready))
+        };
+
+        if let Some(stream) = events.as_mut().filter(|_| events_ready) {
+            match stream.read(&mut buf) {
+                Ok(n) if n > 0 => picker.refresh()?,
+                res => {
+                    warn!("events stream went away ({:?}), polling the list instead", res);
+                    events = None;
+                }
+            }
+        } else if events.is_none() {
+            picker.refresh()?;
+        }
+
+        if stdin_ready {
+            let n = stdin.lock().read(&mut buf).context("reading stdin")?;
+            if n == 0 {
+                return Ok(None);
+            }
+            for key in parse_keys(&buf[..n]) {
+                if let Some(choice) = picker.handle(key)? {
+                    return Ok(choice);
+                }
+            }
+        }
+    }
+}
+
+/// Listen for changes to the sessions. Without this the picker still
+/// works, it just has to poll.
+fn subscribe(socket: &Path) -> Option<UnixStream> {
+    let events_socket = crate::daemon::events::socket_path(socket);
+    let res = UnixStream::connect(&events_socket)
+        .and_then(|mut stream| stream.write_all(b"type=session.*\n").map(|_| stream));
+    match res {
+        Ok(stream) => Some(stream),
+        Err(e) => {
+            warn!("subscribing to events at {:?}: {:?}", events_socket, e);
+            None
+        }
+    }
+}
+
+fn dial(socket: &Path) -> anyhow::Result<protocol::Client> {
+    match protocol::Client::new(socket) {
+        Ok(ClientResult::JustClient(c)) => Ok(c),
+        // The picker takes up the whole screen, so there is nowhere to
+        // show the warning.
+        Ok(ClientResult::VersionMismatch { client, .. }) => Ok(client),
+        Err(err) => Err(err).context("connecting to daemon"),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Backspace,
+    /// Ctrl-u, clear the filter.
+    Clear,
+    Up,
+    Down,
+    Enter,
+    Kill,
+    Detach,
+    Rename,
+    Cancel,
+}
+
+/// Turn a chunk of raw terminal input into keys, dropping anything the
+/// picker has no use for.
+fn parse_keys(buf: &[u8]) -> Vec<Key> {
+    let mut keys = vec![];
+    let mut i = 0;
+    while i < buf.len() {
+        let b = buf[i];
+        i += 1;
+        match b {
+            0x1b => {
+                match buf.get(i) {
+                    // A lone escape, since escape sequences show up all
+                    // in one read.
+                    None => keys.push(Key::Cancel),
+                    Some(b'[') | Some(b'O') => {
+                        i += 1;
+                        while i < buf.len() && !(0x40..=0x7e).contains(&buf[i]) {
+                            i += 1;
+                        }
+                        match buf.get(i) {
+                            Some(b'A') => keys.push(Key::Up),
+                            Some(b'B') => keys.push(Key::Down),
+                            _ => {}
+                        }
+                        i += 1;
+                    }
+                    // alt chords
+                    Some(_) => i += 1,
+                }
+            }
+            b'\r' | b'\n' => keys.push(Key::Enter),
+            0x7f | 0x08 => keys.push(Key::Backspace),
+            0x15 => keys.push(Key::Clear),
+            0x10 => keys.push(Key::Up),
+            0x0e => keys.push(Key::Down),
+            0x0b => keys.push(Key::Kill),
+            0x04 => keys.push(Key::Detach),
+            0x12 => keys.push(Key::Rename),
+            0x03 | 0x07 => keys.push(Key::Cancel),
+            _ if b < 0x20 => {}
+            _ => {
+                let start = i - 1;
+                while i < buf.len() && buf[i] >= 0x20 && buf[i] != 0x7f {
+                    i += 1;
+                }
+                keys.extend(String::from_utf8_lossy(&buf[start..i]).chars().map(Key::Char));
+            }
+        }
+    }
+    keys
+}
+
+/// How well `query` fuzzy matches `candidate`, or None if it does not
+/// match at all. Every query char has to show up in order, and runs of
+/// chars and matches at the start of a word score higher.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut prev_match = None;
+    for q in query.chars() {
+        let offset =
+            candidate[next..].iter().position(|c| c.to_lowercase().eq(q.to_lowercase()))?;
+        let pos = next + offset;
+        score += 1;
+        if prev_match.is_some_and(|prev| prev + 1 == pos) {
+            score += 5;
+        }
+        if pos == 0 || "-_./:".contains(candidate[pos - 1]) {
+            score += 3;
+        }
+        prev_match = Some(pos);
+        next = pos + 1;
+    }
+    Some(score)
+}
+
+#[derive(Debug)]
+enum Row<'a> {
+    Session(&'a Session),
+    /// Create a new session with the given name.
+    New(&'a str),
+}
+
+struct Picker<'a> {
+    socket: &'a Path,
+    current: Option<&'a str>,
+    sessions: Vec<Session>,
+    query: String,
+    selected: usize,
+    message: Option<String>,
+    /// The session being renamed and the new name typed so far, while
+    /// asking for one.
+    renaming: Option<(String, String)>,
+}
+
+impl<'a> Picker<'a> {
+    fn new(socket: &'a Path, current: Option<&'a str>) -> Self {
+        Picker {
+            socket,
+            current,
+            sessions: vec![],
+            query: String::new(),
+            selected: 0,
+            message: None,
+            renaming: None,
+        }
+    }
+
+    fn refresh(&mut self) -> anyhow::Result<()> {
+        let mut client = dial(self.socket)?;
+        client.write_connect_header(ConnectHeader::List).context("sending list connect header")?;
+        let reply: ListReply = client.read_reply().context("reading reply")?;
+        self.sessions = reply.sessions;
+        self.sessions.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(())
+    }
+
+    /// The sessions matching the filter, best match first, followed by
+    /// the option to make a new session if the filter could name one.
+    fn rows(&self) -> Vec<Row<'_>> {
+        let mut scored: Vec<(i64, &Session)> = self
+            .sessions
+            .iter()
+            .filter_map(|s| fuzzy_score(&self.query, &s.name).map(|score| (score, s)))
+            .collect();
+        // a stable sort, so ties stay sorted by name
+        scored.sort_by_key(|(score, _)| -score);
+        let mut rows: Vec<Row> = scored.into_iter().map(|(_, s)| Row::Session(s)).collect();
+        if !self.query.is_empty() && !self.sessions.iter().any(|s| s.name == self.query) {
+            rows.push(Row::New(&self.query));
+        }
+        rows
+    }
+
+    /// Act on a key. Returns Some once the user is done picking.
+    fn handle(&mut self, key: Key) -> anyhow::Result<Option<Option<String>>> {
+        if !matches!(key, Key::Up | Key::Down) {
+            self.message = None;
+        }
+        if self.renaming.is_some() {
+            self.handle_rename(key)?;
+            return Ok(None);
+        }
+        match key {
+            Key::Char(c) => {
+                self.query.push(c);
+                self.selected = 0;
+            }
+            Key::Backspace => {
+                self.query.pop();
+                self.selected = 0;
+            }
+            Key::Clear => {
+                self.query.clear();
+                self.selected = 0;
+            }
+            Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Down => {
+                self.selected = (self.selected + 1).min(self.rows().len().saturating_sub(1))
+            }
+            Key::Cancel => return Ok(Some(None)),
+            Key::Enter => return Ok(self.enter()),
+            Key::Kill => self.kill()?,
+            Key::Detach => self.detach()?,
+            Key::Rename => self.start_rename(),
+        }
+        Ok(None)
+    }
+
+    /// Act on a key while asking for a new name.
+    fn handle_rename(&mut self, key: Key) -> anyhow::Result<()> {
+        let Some((_, new_name)) = self.renaming.as_mut() else {
+            return Ok(());
+        };
+        match key {
+            Key::Char(c) => new_name.push(c),
+            Key::Backspace => {
+                new_name.pop();
+            }
+            Key::Clear => new_name.clear(),
+            Key::Cancel => self.renaming = None,
+            Key::Enter => self.rename()?,
+            Key::Up | Key::Down | Key::Kill | Key::Detach | Key::Rename => {}
+        }
+        Ok(())
+    }
+
+    fn selected_row(&self) -> Option<Row<'_>> {
+        self.rows().into_iter().nth(self.selected)
+    }
+
+    fn enter(&mut self) -> Option<Option<String>> {
+        let (name, attached) = match self.selected_row()? {
+            Row::Session(s) => (s.name.clone(), matches!(s.status, SessionStatus::Attached)),
+            Row::New(name) => (String::from(name), false),
+        };
+        if Some(name.as_str()) == self.current {
+            return Some(None);
+        }
+        if attached {
+            self.message = Some(format!("session '{name}' already has a terminal attached"));
+            return None;
+        }
+        if name.contains(|c: char| c.is_whitespace() || c == '/') || name == "." || name == ".." {
+            self.message = Some(format!("invalid session name '{name}'"));
+            return None;
+        }
+        Some(Some(name))
+    }
+
+    fn kill(&mut self) -> anyhow::Result<()> {
+        let Some(Row::Session(session)) = self.selected_row() else {
+            return Ok(());
+        };
+        let name = session.name.clone();
+        if Some(name.as_str()) == self.current {
+            self.message = Some(String::from("can't kill the session you came from"));
+            return Ok(());
+        }
+
+        info!("killing '{}' from the picker", name);
+        let mut client = dial(self.socket)?;
+        client
+            .write_connect_header(ConnectHeader::Kill(KillRequest { sessions: vec![name.clone()] }))
+            .context("writing kill request header")?;
+        let reply: KillReply = client.read_reply().context("reading reply")?;
+        self.message = Some(if reply.not_found_sessions.is_empty() {
+            format!("killed '{name}'")
+        } else {
+            format!("session '{name}' is already gone")
+        });
+        self.refresh()?;
+        self.selected = self.selected.min(self.rows().len().saturating_sub(1));
+        Ok(())
+    }
+
+    fn detach(&mut self) -> anyhow::Result<()> {
+        let Some(Row::Session(session)) = self.selected_row() else {
+            return Ok(());
+        };
+        let name = session.name.clone();
+
+        info!("detaching '{}' from the picker", name);
+        let mut client = dial(self.socket)?;
+        client
+            .write_connect_header(ConnectHeader::Detach(DetachRequest {
+                sessions: vec![name.clone()],
+            }))
+            .context("writing detach request header")?;
+        let reply: DetachReply = client.read_reply().context("reading reply")?;
+        self.message = Some(if !reply.not_found_sessions.is_empty() {
+            format!("session '{name}' is already gone")
+        } else if !reply.not_attached_sessions.is_empty() {
+            format!("session '{name}' is not attached")
+        } else {
+            format!("detached '{name}'")
+        });
+        self.refresh()
+    }
+
+    fn start_rename(&mut self) {
+        let Some(Row::Session(session)) = self.selected_row() else {
+            return;
+        };
+        let name = session.name.clone();
+        if Some(name.as_str()) == self.current {
+            self.message = Some(String::from("can't rename the session you came from"));
+            return;
+        }
+        self.renaming = Some((name, String::new()));
+    }
+
+    fn rename(&mut self) -> anyhow::Result<()> {
+        let Some((old_name, new_name)) = self.renaming.clone() else {
+            return Ok(());
+        };
+
+        info!("renaming '{}' to '{}' from the picker", old_name, new_name);
+        let mut client = dial(self.socket)?;
+        client
+            .write_connect_header(ConnectHeader::Rename(RenameRequest {
+                old_name: old_name.clone(),
+                new_name: new_name.clone(),
+            }))
+            .context("writing rename request header")?;
+        let reply: RenameReply = client.read_reply().context("reading reply")?;
+        // Leave the prompt up if another name might do better.
+        self.message = Some(match reply {
+            RenameReply::Ok => {
+                self.renaming = None;
+                format!("renamed '{old_name}' to '{new_name}'")
+            }
+            RenameReply::NotFound => {
+                self.renaming = None;
+                format!("session '{old_name}' is already gone")
+            }
+            RenameReply::NameTaken => format!("there is already a session called '{new_name}'"),
+            RenameReply::InvalidName => format!("invalid session name '{new_name}'"),
+        });
+        self.refresh()?;
+        self.selected = self.selected.min(self.rows().len().saturating_sub(1));
+        Ok(())
+    }
+
+    /// The top line, which is where the cursor sits.
+    fn prompt(&self) -> String {
+        match &self.renaming {
+            Some((old_name, new_name)) => format!("rename '{old_name}' to: {new_name}"),
+            None => format!("filter: {}", self.query),
+        }
+    }
+
+    /// Lay out the picker as one string per screen line.
+    fn render(&self, now: time::SystemTime, size: &TtySize) -> Vec<String> {
+        let rows = self.rows();
+        let name_width = rows
+            .iter()
+            .map(|row| match row {
+                Row::Session(s) => s.name.chars().count(),
+                Row::New(_) => 0,
+            })
+            .max()
+            .unwrap_or(0)
+            .max("NAME".len());
+
+        let mut lines = vec![
+            self.prompt(),
+            format!("  {:name_width$}  {:<18}  {:<8}  LAST ATTACHED", "NAME", "STATUS", "AGE"),
+        ];
+        // Keep the selection on screen, leaving room for the header and
+        // footer.
+        let visible = (size.rows as usize).saturating_sub(3).max(1);
+        let top = (self.selected + 1).saturating_sub(visible);
+        for (i, row) in rows.iter().enumerate().skip(top).take(visible) {
+            let text = match row {
+                Row::Session(s) => {
+                    let last_attached = match s.status {
+                        SessionStatus::Attached => String::from("now"),
+                        _ => s
+                            .last_disconnected_at_unix_ms
+                            .or(s.last_connected_at_unix_ms)
+                            .map(|ms| format!("{} ago", since(now, ms)))
+                            .unwrap_or_else(|| String::from("never")),
+                    };
+                    let note =
+                        if Some(s.name.as_str()) == self.current { "  (current)" } else { "" };
+                    format!(
+                        "{:name_width$}  {:<18}  {:<8}  {}{}",
+                        s.name,
+                        s.status_label(),
+                        since(now, s.started_at_unix_ms),
+                        last_attached,
+                        note,
+                    )
+                }
+                Row::New(name) => format!("+ new session '{name}'"),
+            };
+            let text: String = text.chars().take((size.cols as usize).saturating_sub(2)).collect();
+            lines.push(if i == self.selected {
+                format!("\x1b[7m> {text}\x1b[0m")
+            } else {
+                format!("  {text}")
+            });
+        }
+        while lines.len() + 1 < size.rows as usize {
+            lines.push(String::new());
+        }
+        let help = if self.renaming.is_some() { RENAME_HELP } else { HELP };
+        lines.push(self.message.clone().unwrap_or_else(|| String::from(help)));
+        lines
+    }
+}
+
+/// How long ago `unix_ms` was.
+fn since(now: time::SystemTime, unix_ms: i64) -> String {
+    let then = time::UNIX_EPOCH + time::Duration::from_millis(unix_ms.max(0) as u64);
+    duration::format(now.duration_since(then).unwrap_or_default())
+}
+
+/// The picker draws on the alternate screen so that whatever was on the
+/// terminal before comes back once it is done.
+struct Screen {
+    stdout: io::Stdout,
+}
+
+impl Screen {
+    fn enter() -> anyhow::Result<Self> {
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h").context("entering alt screen")?;
+        Ok(Screen { stdout })
+    }
+
+    fn draw(&mut self, picker: &Picker, now: time::SystemTime) -> anyhow::Result<()> {
+        let size = TtySize::from_fd(consts::STDOUT_FD)
+            .ok()
+            .filter(|s| s.rows > 0 && s.cols > 0)
+            .unwrap_or(TtySize { rows: 24, cols: 80, xpixel: 0, ypixel: 0 });
+        let mut frame = String::from("\x1b[H\x1b[2J");
+        frame.push_str(&picker.render(now, &size).join("\r\n"));
+        // leave the cursor at the end of the filter
+        frame.push_str(&format!("\x1b[1;{}H", picker.prompt().chars().count() + 1));
+        self.stdout.write_all(frame.as_bytes()).context("drawing picker")?;
+        self.stdout.flush().context("flushing picker")
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = self.stdout.write_all(b"\x1b[?1049l");
+        let _ = self.stdout.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session(name: &str, status: SessionStatus) -> Session {
+        Session { name: String::from(name), status, ..Default::default() }
+    }
+
+    fn picker_with(names: &[&str]) -> Picker<'static> {
+        let mut picker = Picker::new(Path::new("/nonexistent"), Some("main"));
+        picker.sessions =
+            names.iter().map(|name| session(name, SessionStatus::Disconnected)).collect();
+        picker
+    }
+
+    fn row_names(picker: &Picker) -> Vec<String> {
+        picker
+            .rows()
+            .into_iter()
+            .map(|row| match row {
+                Row::Session(s) => s.name.clone(),
+                Row::New(name) => format!("+{name}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keys() {
+        use Key::*;
+        let cases: Vec<(&[u8], Vec<Key>)> = vec![
+            (b"ab\r", vec![Char('a'), Char('b'), Enter]),
+            (b"\x1b[A\x1bOB\x10\x0e", vec![Up, Down, Up, Down]),
+            (b"\x1b", vec![Cancel]),
+            (b"\x1b[1;5Cx", vec![Char('x')]),
+            (b"\x1bbx", vec![Char('x')]),
+            (
+                b"\x7f\x08\x15\x0b\x04\x12\x03\x07",
+                vec![Backspace, Backspace, Clear, Kill, Detach, Rename, Cancel, Cancel],
+            ),
+            ("é\x01".as_bytes(), vec![Char('é')]),
+        ];
+        for (input, want) in cases.into_iter() {
+            assert_eq!(parse_keys(input), want, "input={input:?}");
+        }
+    }
+
+    #[test]
+    fn fuzzy() {
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+        assert_eq!(fuzzy_score("xyz", "main"), None);
+        assert_eq!(fuzzy_score("mna", "main"), None);
+        assert!(fuzzy_score("MN", "main").is_some());
+        // runs and word starts beat scattered matches
+        assert!(fuzzy_score("dev", "dev-box") > fuzzy_score("dev", "d-e-v"));
+        assert!(fuzzy_score("b", "dev-box") > fuzzy_score("b", "abc"));
+    }
+
+    #[test]
+    fn filtering() {
+        let mut picker = picker_with(&["api-server", "main", "scratch", "web"]);
+        assert_eq!(row_names(&picker), vec!["api-server", "main", "scratch", "web"]);
+
+        picker.query = String::from("er");
+        assert_eq!(row_names(&picker), vec!["api-server", "+er"]);
+
+        // word starts come first, then it goes by name
+        picker.query = String::from("a");
+        assert_eq!(row_names(&picker), vec!["api-server", "main", "scratch", "+a"]);
+
+        picker.query = String::from("main");
+        assert_eq!(row_names(&picker), vec!["main"]);
+
+        picker.query = String::from("new");
+        assert_eq!(row_names(&picker), vec!["+new"]);
+    }
+
+    #[test]
+    fn picking() -> anyhow::Result<()> {
+        let mut picker = picker_with(&["a", "b", "main"]);
+        picker.sessions[1].status = SessionStatus::Attached;
+
+        // the current session just means going back
+        for key in parse_keys(b"main\r") {
+            if let Some(choice) = picker.handle(key)? {
+                assert_eq!(choice, None);
+            }
+        }
+
+        picker.query.clear();
+        picker.handle(Key::Down)?;
+        assert_eq!(picker.handle(Key::Enter)?, None);
+        assert_eq!(picker.message.as_deref(), Some("session 'b' already has a terminal attached"));
+
+        picker.handle(Key::Up)?;
+        assert_eq!(picker.handle(Key::Enter)?, Some(Some(String::from("a"))));
+
+        picker.query = String::from("bad name");
+        picker.selected = 0;
+        assert_eq!(picker.handle(Key::Enter)?, None);
+        assert_eq!(picker.message.as_deref(), Some("invalid session name 'bad name'"));
+
+        picker.handle(Key::Clear)?;
+        assert_eq!(picker.message, None);
+        for key in parse_keys(b"fresh") {
+            picker.handle(key)?;
+        }
+        assert_eq!(picker.handle(Key::Enter)?, Some(Some(String::from("fresh"))));
+
+        assert_eq!(picker.handle(Key::Cancel)?, Some(None));
+        Ok(())
+    }
+
+    #[test]
+    fn renaming() -> anyhow::Result<()> {
+        let mut picker = picker_with(&["a", "main"]);
+
+        picker.handle(Key::Down)?;
+        picker.handle(Key::Rename)?;
+        assert!(picker.renaming.is_none());
+        assert_eq!(picker.message.as_deref(), Some("can't rename the session you came from"));
+
+        picker.handle(Key::Up)?;
+        picker.handle(Key::Rename)?;
+        assert_eq!(picker.prompt(), "rename 'a' to: ");
+        // keys go to the new name rather than the filter
+        for key in parse_keys(b"bx\x7f\x0bc") {
+            assert_eq!(picker.handle(key)?, None);
+        }
+        assert_eq!(picker.prompt(), "rename 'a' to: bc");
+        assert_eq!(picker.query, "");
+
+        let size = TtySize { rows: 6, cols: 80, xpixel: 0, ypixel: 0 };
+        let lines = picker.render(time::UNIX_EPOCH, &size);
+        assert_eq!(lines[0], "rename 'a' to: bc");
+        assert_eq!(lines[5], RENAME_HELP);
+
+        // backing out goes back to picking, not out of the picker
+        assert_eq!(picker.handle(Key::Cancel)?, None);
+        assert!(picker.renaming.is_none());
+        assert_eq!(picker.prompt(), "filter: ");
+        Ok(())
+    }
+
+    #[test]
+    fn rendering() {
+        let now = time::UNIX_EPOCH + time::Duration::from_secs(10_000);
+        let mut picker = picker_with(&["main", "other"]);
+        picker.sessions[0].started_at_unix_ms = 9_000_000;
+        picker.sessions[0].status = SessionStatus::Attached;
+        picker.sessions[1].started_at_unix_ms = 4_000_000;
+        picker.sessions[1].last_disconnected_at_unix_ms = Some(9_940_000);
+        picker.selected = 1;
+
+        let size = TtySize { rows: 6, cols: 80, xpixel: 0, ypixel: 0 };
+        let lines = picker.render(now, &size);
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "filter: ");
+        assert!(lines[1].starts_with("  NAME "), "{:?}", lines[1]);
+        assert!(lines[2].starts_with("  main "), "{:?}", lines[2]);
+        assert!(lines[2].contains("attached"), "{:?}", lines[2]);
+        assert!(lines[2].contains("16m40s"), "{:?}", lines[2]);
+        assert!(lines[2].ends_with("now  (current)"), "{:?}", lines[2]);
+        assert!(lines[3].starts_with("\x1b[7m> other "), "{:?}", lines[3]);
+        assert!(lines[3].contains("1h40m"), "{:?}", lines[3]);
+        assert!(lines[3].contains("1m ago"), "{:?}", lines[3]);
+        assert_eq!(lines[5], HELP);
+
+        // with only room for one row, the selection stays on screen
+        let size = TtySize { rows: 4, cols: 10, xpixel: 0, ypixel: 0 };
+        let lines = picker.render(now, &size);
+        assert_eq!(lines[2], "\x1b[7m> other  d\x1b[0m");
+        assert_eq!(lines.len(), 4);
+    }
+}
@@ -88,12 +88,45 @@ pub fn resolve_sessions(sessions: &mut Vec<String>, action: &str) -> anyhow::Res
     Ok(())
 }
 
+/// Match `text` against a shell style glob where `*` matches any run of
+/// characters and `?` matches exactly one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume if the current attempt fails: just after the last
+    // `*` seen, and the text position that `*` is currently matched up to.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    // Let the last `*` swallow one more character.
+                    p = bp;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 #[cfg(test)]
 mod tests {
     use std::cell::Cell;
     use std::time::Duration;
 
-    use super::{sleep_unless, PollStrategy};
+    use super::{glob_match, sleep_unless, PollStrategy};
 
     #[test]
     fn sleep_unless_returns_immediately_when_stop_is_true() {
@@ -137,4 +170,20 @@ mod tests {
         assert!(stopped);
         assert!(checks.get() >= 3);
     }
+
+    #[test]
+    fn glob_match_cases() {
+        assert!(glob_match("", ""));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "session.created"));
+        assert!(glob_match("session.*", "session.created"));
+        assert!(glob_match("*.changed", "log_level.changed"));
+        assert!(glob_match("s?ss*n.*d", "session.resized"));
+        assert!(glob_match("a*b*c", "axxbyybzc"));
+        assert!(glob_match("dev", "dev"));
+        assert!(!glob_match("dev", "devx"));
+        assert!(!glob_match("dev?", "dev"));
+        assert!(!glob_match("session.*", "var.changed"));
+        assert!(!glob_match("a*b*c", "axxbyyb"));
+    }
 }
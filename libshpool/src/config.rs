@@ -17,15 +17,21 @@ use std::{
     collections::HashMap,
     fs,
     path::{Path, PathBuf},
-    sync::{Arc, RwLock, RwLockReadGuard},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, RwLockReadGuard,
+    },
 };
 
 use anyhow::{Context as _, Result};
 use serde_derive::Deserialize;
+use shpool_protocol::{OutputLogFormat, RestartPolicy};
 use tracing::{info, warn};
 
 use crate::{config_watcher::ConfigWatcher, daemon::keybindings, test_hooks, user};
 
+type ReloadListener = Box<dyn Fn(Option<&anyhow::Error>) + Send>;
+
 /// Exposes the shpool config file, watching for file updates
 /// so that the user does not need to restart the daemon when
 /// they edit their config.
@@ -37,6 +43,10 @@ use crate::{config_watcher::ConfigWatcher, daemon::keybindings, test_hooks, user
 pub struct Manager {
     /// The config value.
     config: Arc<RwLock<Config>>,
+    /// Called after every attempted reload, see `on_reload`.
+    reload_listeners: Arc<Mutex<Vec<ReloadListener>>>,
+    /// Bumped every time a changed config gets loaded, see `generation`.
+    generation: Arc<AtomicUsize>,
     _watcher: Arc<ConfigWatcher>,
 }
 
@@ -69,24 +79,45 @@ impl Manager {
             }
         };
 
+        let mut last_sources = Self::sources(&config_files);
         let config = Self::load(&config_files).context("loading initial config")?;
         info!("starting with config: {:?}", config);
         let config = Arc::new(RwLock::new(config));
+        let reload_listeners: Arc<Mutex<Vec<ReloadListener>>> = Arc::new(Mutex::new(vec![]));
+        let generation = Arc::new(AtomicUsize::new(0));
 
         let watcher = {
             let config = config.clone();
+            let reload_listeners = Arc::clone(&reload_listeners);
+            let generation = Arc::clone(&generation);
             // create a owned version of config_files to move to the watcher thread.
             let config_files: Vec<_> = config_files.iter().map(|f| f.to_path_buf()).collect();
             ConfigWatcher::new(move || {
                 info!("reloading config");
-                let mut config = config.write().unwrap();
-                match Self::load(&config_files) {
-                    Ok(c) => {
+                let sources = Self::sources(&config_files);
+                let res = {
+                    let mut config = config.write().unwrap();
+                    Self::load(&config_files).map(|c| {
                         info!("new config: {:?}", c);
                         *config = c;
+                    })
+                };
+                if let Err(err) = &res {
+                    warn!("error loading config file: {:?}", err);
+                }
+                // The watcher also fires when nothing actually changed (e.g.
+                // right after it starts watching), which listeners have no
+                // use for.
+                if res.is_ok() && sources != last_sources {
+                    generation.fetch_add(1, Ordering::AcqRel);
+                    test_hooks::emit("daemon-config-changed");
+                }
+                if res.is_err() || sources != last_sources {
+                    for listener in reload_listeners.lock().unwrap().iter() {
+                        listener(res.as_ref().err());
                     }
-                    Err(err) => warn!("error loading config file: {:?}", err),
                 }
+                last_sources = sources;
                 test_hooks::emit("daemon-reload-config");
             })
             .context("building watcher")?
@@ -94,16 +125,41 @@ impl Manager {
         for path in config_files {
             watcher.watch(path).context("registering config file for watching")?;
         }
-        let manager = Manager { config, _watcher: Arc::new(watcher) };
+        let manager = Manager { config, reload_listeners, generation, _watcher: Arc::new(watcher) };
 
         Ok(manager)
     }
 
+    /// Register a callback to run after each attempt to reload the config
+    /// because a config file changed. It gets the error if the new config
+    /// could not be loaded, in which case the old one stays in effect.
+    /// Successful reloads that found the files unchanged are not reported.
+    pub fn on_reload(&self, listener: impl Fn(Option<&anyhow::Error>) + Send + 'static) {
+        self.reload_listeners.lock().unwrap().push(Box::new(listener));
+    }
+
+    /// A counter that goes up every time a changed config gets loaded.
+    /// Anything built from the config can remember the generation it was
+    /// built at and rebuild itself once the generation moves on.
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::Acquire)
+    }
+
     /// Get the current config value.
     pub fn get(&self) -> RwLockReadGuard<'_, Config> {
         self.config.read().unwrap()
     }
 
+    /// The raw contents of each config file, None for files that could not
+    /// be read.
+    fn sources<T>(config_files: T) -> Vec<Option<String>>
+    where
+        T: IntoIterator,
+        T::Item: AsRef<Path>,
+    {
+        config_files.into_iter().map(|path| fs::read_to_string(path).ok()).collect()
+    }
+
     /// Load config by merging configurations from a list of Paths.
     ///
     /// Paths come later in the list takes higher priority.
@@ -265,9 +321,57 @@ pub struct Config {
     /// the vt100 engine has been replaced.
     pub vt100_output_spool_width: Option<u16>,
 
+    /// If set, the daemon will periodically save each session's output
+    /// spool along with its name, working directory and timestamps under
+    /// the runtime dir. After a daemon restart, saved sessions show up
+    /// as dead but restorable, and attaching to one will spawn a fresh
+    /// shell in the saved directory and replay the saved output.
+    pub persist_sessions: Option<bool>,
+
+    /// How often to save session state when `persist_sessions` is set,
+    /// in the same format accepted by the --ttl flag. 30s by default.
+    /// A restored session's output can be up to this old, since anything
+    /// the session printed after the last save is lost.
+    pub persist_interval: Option<String>,
+
+    /// Restart policies for sessions whose command exits, picked by
+    /// session name or command. Only sessions started with a custom
+    /// `--cmd` can be restarted.
+    pub restart_profile: Option<Vec<RestartProfile>>,
+
+    /// If true, sessions stay listed after their shell exits, and
+    /// attaching to them shows their final screen, until they are
+    /// dismissed with `shpool kill`.
+    pub linger: Option<bool>,
+
+    /// How long an exited session lingers before it gets forgotten
+    /// about, in the same format as the --ttl flag. Forever by default.
+    pub linger_timeout: Option<String>,
+
+    /// If set, each new session tees its output into a log file in its
+    /// session dir ($SHPOOL_SESSION_DIR), rotating it as it grows. Can
+    /// be overridden for a single session with `shpool attach
+    /// --output-log`.
+    pub output_log: Option<OutputLog>,
+
+    /// How to pick the size of the pty when several clients are
+    /// driving the same session with `shpool attach --share`.
+    /// Defaults to the smallest client so that everyone can see
+    /// the whole screen.
+    pub shared_size_policy: Option<SharedSizePolicy>,
+
     /// The user supplied keybindings.
     pub keybinding: Option<Vec<Keybinding>>,
 
+    /// Named sets of keybindings to use instead of the top level ones
+    /// for some sessions.
+    pub keybinding_profile: Option<Vec<KeybindingProfile>>,
+
+    /// How long to wait, in milliseconds, for the next key of a
+    /// keybinding before giving up and passing the keys typed so far
+    /// through to the shell. By default, shpool waits forever.
+    pub keybinding_timeout_ms: Option<u64>,
+
     /// A prefix to inject into the prompt of freshly spawned shells.
     /// The prefix will get included in the shell's prompt variable
     /// verbatim except that the string '$SHPOOL_SESSION_NAME' will
@@ -279,6 +383,12 @@ pub struct Config {
     /// environment variable.
     pub prompt_prefix: Option<String>,
 
+    /// If true, `{var}` in the prompt prefix gets replaced with the
+    /// current value of the shpool var `var`. Off by default so that
+    /// braces in existing prefixes keep meaning what they meant to the
+    /// shell. `${...}` is always left for the shell either way.
+    pub prompt_prefix_vars: Option<bool>,
+
     /// Control when and how shpool will display the message of the day.
     pub motd: Option<MotdDisplayMode>,
 
@@ -329,8 +439,18 @@ impl Config {
             vt100_output_spool_width: self
                 .vt100_output_spool_width
                 .or(another.vt100_output_spool_width),
+            persist_sessions: self.persist_sessions.or(another.persist_sessions),
+            persist_interval: self.persist_interval.or(another.persist_interval),
+            restart_profile: self.restart_profile.or(another.restart_profile),
+            linger: self.linger.or(another.linger),
+            linger_timeout: self.linger_timeout.or(another.linger_timeout),
+            output_log: self.output_log.or(another.output_log),
+            shared_size_policy: self.shared_size_policy.or(another.shared_size_policy),
             keybinding: self.keybinding.or(another.keybinding),
+            keybinding_profile: self.keybinding_profile.or(another.keybinding_profile),
+            keybinding_timeout_ms: self.keybinding_timeout_ms.or(another.keybinding_timeout_ms),
             prompt_prefix: self.prompt_prefix.or(another.prompt_prefix),
+            prompt_prefix_vars: self.prompt_prefix_vars.or(another.prompt_prefix_vars),
             motd: self.motd.or(another.motd),
             motd_args: self.motd_args.or(another.motd_args),
             var_default: self.var_default.or(another.var_default),
@@ -338,6 +458,21 @@ impl Config {
     }
 }
 
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputLog {
+    /// What to write to the log. Raw bytes by default.
+    pub format: Option<OutputLogFormat>,
+    /// Rotate the log once it reaches this many bytes. 10 MiB by
+    /// default.
+    pub max_size: Option<u64>,
+    /// Rotate the log once it has been written to for this long, in the
+    /// same format accepted by the --ttl flag. Unlimited by default.
+    pub max_age: Option<String>,
+    /// How many rotated logs to keep around next to the live one. 3 by
+    /// default.
+    pub keep: Option<usize>,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Keybinding {
     /// The keybinding to map to an action. The syntax for these keybindings
@@ -347,6 +482,44 @@ pub struct Keybinding {
     pub action: keybindings::Action,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct KeybindingProfile {
+    /// The name to pick this profile by with `shpool attach --keybindings`.
+    pub name: String,
+    /// Glob patterns for the names of the sessions that should use this
+    /// profile.
+    #[serde(default)]
+    pub sessions: Vec<String>,
+    /// Glob patterns for the `--cmd` of the sessions that should use this
+    /// profile.
+    #[serde(default)]
+    pub cmds: Vec<String>,
+    /// The keybindings, which replace the top level ones entirely.
+    #[serde(default)]
+    pub keybinding: Vec<Keybinding>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RestartProfile {
+    /// Glob patterns for the names of the sessions that should use this
+    /// profile.
+    #[serde(default)]
+    pub sessions: Vec<String>,
+    /// Glob patterns for the `--cmd` of the sessions that should use this
+    /// profile.
+    #[serde(default)]
+    pub cmds: Vec<String>,
+    /// When to restart the command.
+    pub restart: RestartPolicy,
+    /// How many times to restart the command before letting the session
+    /// end. Unlimited by default.
+    pub max_restarts: Option<u32>,
+    /// How long to wait before the first restart, in the same format as
+    /// the --ttl flag. The wait doubles with every restart that follows
+    /// quickly on the last one. 1s by default.
+    pub backoff: Option<String>,
+}
+
 #[derive(Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum SessionRestoreMode {
@@ -366,6 +539,19 @@ pub enum SessionRestoreMode {
     Lines(u16),
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SharedSizePolicy {
+    /// Use the smallest rows and columns out of all the clients.
+    #[default]
+    Smallest,
+    /// Use the largest rows and columns out of all the clients.
+    Largest,
+    /// Use the size of whichever client most recently attached
+    /// or resized its terminal.
+    MostRecent,
+}
+
 #[derive(Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum SessionRestoreEngine {
@@ -452,6 +638,24 @@ mod test {
             session_restore_engine = "vterm"
             "#,
             r#"
+            persist_sessions = true
+            persist_interval = "10s"
+            "#,
+            r#"
+            [[restart_profile]]
+            cmds = ["npm run *"]
+            restart = "on-failure"
+            max_restarts = 5
+            backoff = "2s"
+            "#,
+            r#"
+            linger = true
+            linger_timeout = "1d"
+            "#,
+            r#"
+            shared_size_policy = "most-recent"
+            "#,
+            r#"
             [[var_default]]
             var = "foo"
             value = "bar"
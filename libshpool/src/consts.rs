@@ -22,6 +22,7 @@ pub const BUF_SIZE: usize = 1024 * 16;
 pub const HEARTBEAT_DURATION: time::Duration = time::Duration::from_millis(500);
 
 pub const STDIN_FD: i32 = 0;
+pub const STDOUT_FD: i32 = 1;
 pub const STDERR_FD: i32 = 2;
 
 // Used to determine when the shell has started up so we can attempt to sniff
@@ -0,0 +1,314 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*! Checkpointing of session state so that sessions survive daemon restarts.
+
+  When the `persist_sessions` config option is set, every session gets
+  two extra files in its session dir: a `checkpoint.json` metadata file
+  and a `spool` file holding the most recent restore buffer. The spool
+  is written by the shell->client thread, since that is the only thread
+  with access to the output spool, and the metadata is written by a
+  dedicated checkpointer thread which periodically walks the session
+  table.
+
+  When the daemon starts up, any checkpoints it finds are loaded as
+  restorable sessions. The processes inside them are long gone, so
+  attaching to one spawns a fresh shell in the saved directory and
+  replays the saved spool.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread, time,
+};
+
+use anyhow::Context;
+use parking_lot::Mutex;
+use serde_derive::{Deserialize, Serialize};
+use tracing::{info, span, warn, Level};
+
+use super::shell;
+use crate::{config, duration};
+
+const META_FILE: &str = "checkpoint.json";
+const SPOOL_FILE: &str = "spool";
+
+const DEFAULT_PERSIST_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+/// The metadata saved for each session. The output spool is
+/// stored separately since it is written by a different thread.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionMeta {
+    pub name: String,
+    /// The working directory of the shell process as of the last checkpoint.
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// The custom command the session was launched with, if any.
+    #[serde(default)]
+    pub cmd: Option<String>,
+    #[serde(default)]
+    pub started_at_unix_ms: i64,
+    #[serde(default)]
+    pub last_connected_at_unix_ms: Option<i64>,
+    #[serde(default)]
+    pub last_disconnected_at_unix_ms: Option<i64>,
+}
+
+/// A session from a previous daemon which can be brought back to life.
+#[derive(Debug)]
+pub struct Restorable {
+    pub meta: SessionMeta,
+    /// The saved restore buffer.
+    pub spool: Vec<u8>,
+}
+
+/// Returns true if session persistence is turned on.
+pub fn enabled(config: &config::Manager) -> bool {
+    config.get().persist_sessions.unwrap_or(false)
+}
+
+/// The effective checkpoint interval.
+pub fn interval(config: &config::Manager) -> time::Duration {
+    match config.get().persist_interval.as_ref() {
+        Some(src) => match duration::parse(src) {
+            Ok(d) => d,
+            Err(e) => {
+                warn!("parsing persist_interval, using default: {:?}", e);
+                DEFAULT_PERSIST_INTERVAL
+            }
+        },
+        None => DEFAULT_PERSIST_INTERVAL,
+    }
+}
+
+/// Save the restore buffer for the session with the given session dir.
+pub fn write_spool(session_dir: &Path, restore_buf: &[u8]) -> anyhow::Result<()> {
+    write_atomic(session_dir, SPOOL_FILE, restore_buf)
+}
+
+/// Save the metadata for the session with the given session dir.
+pub fn write_meta(session_dir: &Path, meta: &SessionMeta) -> anyhow::Result<()> {
+    let buf = serde_json::to_vec(meta).context("serializing session metadata")?;
+    write_atomic(session_dir, META_FILE, &buf)
+}
+
+/// Remove any checkpoint for the session with the given session dir.
+/// Other files in the session dir are left alone.
+pub fn clear(session_dir: &Path) {
+    for file in [META_FILE, SPOOL_FILE] {
+        match fs::remove_file(session_dir.join(file)) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("removing checkpoint file {}: {:?}", file, e),
+        }
+    }
+}
+
+// Write to a tmp file then rename so that a crash halfway through a
+// checkpoint never leaves a truncated file behind.
+fn write_atomic(session_dir: &Path, file: &str, buf: &[u8]) -> anyhow::Result<()> {
+    fs::create_dir_all(session_dir).context("creating session dir")?;
+    let mut tmp =
+        tempfile::NamedTempFile::new_in(session_dir).context("creating checkpoint tmp file")?;
+    tmp.write_all(buf).context("writing checkpoint tmp file")?;
+    tmp.persist(session_dir.join(file)).context("persisting checkpoint file")?;
+    Ok(())
+}
+
+/// Load all the checkpoints found in the given sessions dir.
+pub fn load_all(sessions_dir: &Path) -> anyhow::Result<HashMap<String, Restorable>> {
+    let mut restorable = HashMap::new();
+    let entries = match fs::read_dir(sessions_dir) {
+        Ok(e) => e,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(restorable),
+        Err(e) => return Err(e).context("reading sessions dir"),
+    };
+
+    for entry in entries {
+        let entry = entry.context("reading sessions dir entry")?;
+        if is_rename_link(&entry) {
+            continue;
+        }
+        let session_dir = entry.path();
+        let meta_buf = match fs::read(session_dir.join(META_FILE)) {
+            Ok(b) => b,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                warn!("reading checkpoint metadata in {:?}: {:?}", session_dir, e);
+                continue;
+            }
+        };
+        let meta: SessionMeta = match serde_json::from_slice(&meta_buf) {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("parsing checkpoint metadata in {:?}, discarding: {:?}", session_dir, e);
+                clear(&session_dir);
+                continue;
+            }
+        };
+        let spool = match fs::read(session_dir.join(SPOOL_FILE)) {
+            Ok(b) => b,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => {
+                warn!("reading checkpoint spool in {:?}: {:?}", session_dir, e);
+                vec![]
+            }
+        };
+
+        info!("found restorable session '{}'", meta.name);
+        restorable.insert(meta.name.clone(), Restorable { meta, spool });
+    }
+
+    Ok(restorable)
+}
+
+/// Run the checkpointer thread loop, which periodically writes out
+/// the metadata for every live session and cleans up checkpoints for
+/// sessions which no longer exist. Should be invoked in a dedicated
+/// thread.
+pub fn run(
+    shells: Arc<Mutex<HashMap<String, Box<shell::Session>>>>,
+    restorable: Arc<Mutex<HashMap<String, Restorable>>>,
+    sessions_dir: PathBuf,
+    config: config::Manager,
+) -> anyhow::Result<()> {
+    let _s = span!(Level::INFO, "checkpointer").entered();
+
+    loop {
+        thread::sleep(interval(&config));
+        if !enabled(&config) {
+            continue;
+        }
+
+        let metas: Vec<SessionMeta> = {
+            let _s = span!(Level::INFO, "lock(shells)").entered();
+            let shells = shells.lock();
+            shells
+                .iter()
+                // An exited shell is about to be removed from the table, and
+                // the shell->client thread has already cleared its checkpoint.
+                .filter(|(_, s)| s.child_exit_notifier.wait(Some(time::Duration::ZERO)).is_none())
+                .map(|(name, s)| s.checkpoint_meta(name))
+                .collect()
+        };
+
+        let mut keep = HashSet::new();
+        for meta in metas.iter() {
+            keep.insert(meta.name.clone());
+            if let Err(e) = write_meta(&sessions_dir.join(&meta.name), meta) {
+                warn!("checkpointing '{}': {:?}", meta.name, e);
+            }
+        }
+        keep.extend(restorable.lock().keys().cloned());
+
+        let entries = match fs::read_dir(&sessions_dir) {
+            Ok(e) => e,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                warn!("reading sessions dir: {:?}", e);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !keep.contains(&name)
+                && !is_rename_link(&entry)
+                && entry.path().join(META_FILE).exists()
+            {
+                info!("clearing stale checkpoint for '{}'", name);
+                clear(&entry.path());
+            }
+        }
+    }
+}
+
+// Renaming a session leaves a link from the old session dir to the new
+// one, which must not be mistaken for a session of its own.
+fn is_rename_link(entry: &fs::DirEntry) -> bool {
+    entry.file_type().map(|t| t.is_symlink()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(30000)]
+    fn round_trip() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let session_dir = dir.path().join("sh1");
+        write_meta(
+            &session_dir,
+            &SessionMeta {
+                name: String::from("sh1"),
+                cwd: Some(String::from("/tmp")),
+                cmd: None,
+                started_at_unix_ms: 42,
+                last_connected_at_unix_ms: Some(43),
+                last_disconnected_at_unix_ms: None,
+            },
+        )?;
+        write_spool(&session_dir, b"some output")?;
+
+        let mut restorable = load_all(dir.path())?;
+        let r = restorable.remove("sh1").expect("sh1 to be restorable");
+        assert_eq!(r.meta.cwd.as_deref(), Some("/tmp"));
+        assert_eq!(r.meta.started_at_unix_ms, 42);
+        assert_eq!(r.spool, b"some output");
+
+        clear(&session_dir);
+        assert!(load_all(dir.path())?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    #[timeout(30000)]
+    fn skips_dirs_without_checkpoint() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("sh1"))?;
+        fs::write(dir.path().join("sh1").join("forward.env"), "FOO=bar")?;
+
+        assert!(load_all(dir.path())?.is_empty());
+        assert!(load_all(&dir.path().join("does-not-exist"))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    #[timeout(30000)]
+    fn skips_rename_links() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let meta = SessionMeta {
+            name: String::from("new"),
+            cwd: None,
+            cmd: None,
+            started_at_unix_ms: 0,
+            last_connected_at_unix_ms: None,
+            last_disconnected_at_unix_ms: None,
+        };
+        write_meta(&dir.path().join("new"), &meta)?;
+        std::os::unix::fs::symlink("new", dir.path().join("old"))?;
+
+        let restorable = load_all(dir.path())?;
+        assert_eq!(restorable.keys().collect::<Vec<_>>(), vec!["new"]);
+
+        Ok(())
+    }
+}
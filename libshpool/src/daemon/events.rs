@@ -8,19 +8,32 @@
 //! (including U+000A LINE FEED) to be escaped inside strings, so framing
 //! by `\n` is unambiguous.
 //!
-//! Events carry no payload beyond their type — they signal that *something*
-//! changed in the session table. Subscribers learn the new state by calling
-//! `shpool list` (or the equivalent over the main socket). Subscribers that
-//! fall too far behind are dropped and may simply reconnect.
+//! Events carry what changed (e.g. the session name, or a var's new value)
+//! and are stamped with a publish-time wall clock timestamp and a
+//! per-daemon sequence number, so subscribers can react without a follow-up
+//! `shpool list` and can tell when they have missed something. Subscribers that
+//! fall too far behind are dropped; the sink keeps a bounded history of recent
+//! events so that a reconnecting subscriber can send `resume_from=<seq>` and be
+//! replayed what it missed (or told about the gap, if the history no longer
+//! reaches back that far).
+//! The same request line can narrow the stream down with `type=<glob>` and
+//! `session=<glob>` filters, which the sink applies before queueing events.
 //!
 //! Architecture: a single `events-sink` thread owns all subscriber state and
 //! does all I/O via non-blocking `poll(2)`. `publish()` is O(1) on the
 //! daemon's hot path: a `try_send` on a bounded channel + a 1-byte write to
-//! a self-pipe to wake the sink.
+//! a self-pipe to wake the sink. The sink assigns sequence numbers and
+//! serializes each event once, so `seq` always increases in wire order
+//! even when several threads publish concurrently.
+//!
+//! Code inside the daemon that needs to block until something happens to
+//! a session (like `shpool wait`) can also [`EventBus::watch`] the bus,
+//! which hands it a copy of every event straight from `publish()` without
+//! going through the sink.
 
 use std::{
     collections::VecDeque,
-    io::{self, Write},
+    io::{self, Read, Write},
     os::{
         fd::{AsFd, BorrowedFd, OwnedFd},
         unix::net::{UnixListener, UnixStream},
@@ -29,12 +42,12 @@ use std::{
     sync::{
         atomic::{AtomicBool, Ordering},
         mpsc::{self, Receiver, SyncSender, TrySendError},
-        Arc, LazyLock,
+        Arc,
     },
-    thread,
+    thread, time,
 };
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use nix::{
     errno::Errno,
     poll::{self, PollFd, PollFlags, PollTimeout},
@@ -43,27 +56,182 @@ use nix::{
 use serde_derive::Serialize;
 use tracing::{error, info, warn};
 
+use crate::common::glob_match;
+
 /// Per-subscriber outbound queue depth (events). Subscribers that fall this
 /// far behind are dropped and must reconnect.
 const SUBSCRIBER_QUEUE_DEPTH: usize = 64;
 
+/// How many of the most recent events the sink keeps around for
+/// subscribers that connect with `resume_from`.
+const EVENT_HISTORY_DEPTH: usize = 1024;
+
+/// How long a new subscriber has to send its request line before it is
+/// started on the live stream without one.
+const RESUME_WINDOW: time::Duration = time::Duration::from_millis(200);
+
+/// Longest request line a subscriber may send.
+const MAX_REQUEST_LEN: usize = 1024;
+
 /// Capacity of the publish-to-sink channel. Reaching it means the sink is
 /// wedged -- a real bug, not a tunable.
 const EVENT_CHANNEL_CAP: usize = 4096;
 
 /// An event published on the events socket.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
 #[serde(tag = "type")]
-#[allow(clippy::enum_variant_names)]
 pub enum Event {
     #[serde(rename = "session.created")]
-    SessionCreated,
+    SessionCreated { session: String },
     #[serde(rename = "session.attached")]
-    SessionAttached,
+    SessionAttached { session: String },
     #[serde(rename = "session.detached")]
-    SessionDetached,
+    SessionDetached { session: String },
     #[serde(rename = "session.removed")]
-    SessionRemoved,
+    SessionRemoved {
+        session: String,
+        reason: RemovalReason,
+        /// The shell's exit status, if it had already been collected when
+        /// the session was removed.
+        exit_status: Option<i32>,
+    },
+    /// An attach was turned away because another client is already
+    /// attached to the session.
+    #[serde(rename = "session.busy")]
+    SessionBusy { session: String },
+    /// The session was renamed with `shpool rename`. `session` is the
+    /// new name.
+    #[serde(rename = "session.renamed")]
+    SessionRenamed { session: String, old_name: String },
+    /// The session's shell exited, but the session lingers on so that
+    /// its final screen can be looked at. It gets a session.removed
+    /// event once it is dismissed or expires.
+    #[serde(rename = "session.exited")]
+    SessionExited { session: String, exit_status: i32 },
+    /// The session's command exited and its restart policy started it
+    /// up again. `restarts` counts restarts so far, including this one.
+    #[serde(rename = "session.restarted")]
+    SessionRestarted { session: String, exit_status: i32, restarts: u32 },
+    /// The session's pty changed size.
+    #[serde(rename = "session.resized")]
+    SessionResized { session: String, rows: u16, cols: u16 },
+    /// `shpool var set` or `shpool var unset` was called.
+    #[serde(rename = "var.changed")]
+    VarChanged {
+        var: String,
+        /// The new value, None if the var was unset.
+        value: Option<String>,
+    },
+    /// The daemon noticed a config file change and tried to reload it.
+    #[serde(rename = "config.reloaded")]
+    ConfigReloaded {
+        ok: bool,
+        /// Why the reload failed, in which case the old config is still
+        /// in effect.
+        error: Option<String>,
+    },
+    #[serde(rename = "log_level.changed")]
+    LogLevelChanged { level: String },
+}
+
+impl Event {
+    /// The event's `type` on the wire.
+    fn kind(&self) -> &'static str {
+        match self {
+            Event::SessionCreated { .. } => "session.created",
+            Event::SessionAttached { .. } => "session.attached",
+            Event::SessionDetached { .. } => "session.detached",
+            Event::SessionRemoved { .. } => "session.removed",
+            Event::SessionBusy { .. } => "session.busy",
+            Event::SessionRenamed { .. } => "session.renamed",
+            Event::SessionExited { .. } => "session.exited",
+            Event::SessionRestarted { .. } => "session.restarted",
+            Event::SessionResized { .. } => "session.resized",
+            Event::VarChanged { .. } => "var.changed",
+            Event::ConfigReloaded { .. } => "config.reloaded",
+            Event::LogLevelChanged { .. } => "log_level.changed",
+        }
+    }
+
+    /// The session the event is about, if it is about one.
+    fn session(&self) -> Option<&str> {
+        match self {
+            Event::SessionCreated { session }
+            | Event::SessionAttached { session }
+            | Event::SessionDetached { session }
+            | Event::SessionRemoved { session, .. }
+            | Event::SessionBusy { session }
+            | Event::SessionRenamed { session, .. }
+            | Event::SessionExited { session, .. }
+            | Event::SessionRestarted { session, .. }
+            | Event::SessionResized { session, .. } => Some(session),
+            Event::VarChanged { .. }
+            | Event::ConfigReloaded { .. }
+            | Event::LogLevelChanged { .. } => None,
+        }
+    }
+}
+
+/// Why a session left the session table.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemovalReason {
+    /// The shell exited on its own.
+    Exited,
+    /// `shpool kill` was called on the session.
+    Killed,
+    /// The session's `--ttl` ran out.
+    Ttl,
+    /// A stale entry whose shell had already died was replaced by a new
+    /// session of the same name.
+    Reaped,
+    /// A lingering session was dismissed with `shpool kill`.
+    Dismissed,
+    /// A lingering session outlived the linger timeout.
+    Expired,
+}
+
+/// An event together with the time it was published, as it travels from
+/// a publisher to the sink.
+#[derive(Debug)]
+struct Published {
+    event: Event,
+    ts_unix_ms: i64,
+}
+
+/// What travels from the bus to the sink thread.
+enum ToSink {
+    Event(Published),
+    /// Hand every event after this one to the given in-process watcher,
+    /// see `EventBus::watch`.
+    Watch(mpsc::Sender<Event>),
+}
+
+/// Sent in place of replayed events that have already been trimmed from
+/// the history. Unlike real events it has no `seq` of its own.
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "events.gap")]
+struct Gap {
+    first_missed_seq: u64,
+    last_missed_seq: u64,
+}
+
+/// The last line a subscriber whose request could not be parsed gets
+/// before being disconnected.
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "events.rejected")]
+struct Rejected {
+    error: String,
+}
+
+/// The wire form of an event: the event's own fields flattened next to
+/// the envelope fields the sink stamps on.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    seq: u64,
+    ts_unix_ms: i64,
 }
 
 /// The publish surface of the events system: fans out events to all
@@ -72,7 +240,7 @@ pub enum Event {
 /// owned by the [`EventBusHandle`] returned alongside this from
 /// [`EventBus::start`]; dropping that handle stops and joins the sink.
 pub struct EventBus {
-    event_tx: SyncSender<Arc<str>>,
+    event_tx: SyncSender<ToSink>,
     wake_tx: OwnedFd,
     sink_dead_logged: AtomicBool,
 }
@@ -112,13 +280,34 @@ impl EventBus {
         Ok((bus, EventBusHandle { shutdown_tx, sink: Some(join) }))
     }
 
-    /// Broadcast `event` to all current subscribers. Non-blocking: a
-    /// `try_send` on the publish-to-sink channel + a 1-byte wake. Takes no
-    /// internal lock, so it is safe to call under arbitrary outer locks.
+    /// Broadcast `event` to all current subscribers and watchers. Non-blocking:
+    /// a `try_send` on the publish-to-sink channel + a 1-byte wake. Takes
+    /// no internal lock, so it is safe to call under arbitrary outer locks.
     /// Publishing under the lock that protects the state being announced
     /// keeps wire-order = causal-order across mutators.
-    pub fn publish(&self, event: &Event) {
-        match self.event_tx.try_send(serialize_line(event)) {
+    pub fn publish(&self, event: Event) {
+        let ts_unix_ms = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        self.send_to_sink(ToSink::Event(Published { event, ts_unix_ms }));
+    }
+
+    /// Get a copy of every event published from now on, until the
+    /// returned receiver is dropped. Watchers are fed by the sink thread
+    /// just like subscribers are, so an event shows up shortly after
+    /// `publish` returns rather than right away, but always in publish
+    /// order. The channel is unbounded, so the receiver should be drained
+    /// promptly. If the sink is gone, the receiver is disconnected from
+    /// the start.
+    pub fn watch(&self) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.send_to_sink(ToSink::Watch(tx));
+        rx
+    }
+
+    fn send_to_sink(&self, msg: ToSink) {
+        match self.event_tx.try_send(msg) {
             Ok(()) => {}
             Err(TrySendError::Full(_)) => {
                 warn!("events channel full; sink is wedged");
@@ -218,7 +407,7 @@ impl Drop for ListenerGuard {
 /// socket file never outlives the thread serving it.
 struct Sink {
     listener: UnixListener,
-    event_rx: Receiver<Arc<str>>,
+    event_rx: Receiver<ToSink>,
     wake_rx: OwnedFd,
     shutdown_rx: OwnedFd,
     _guard: ListenerGuard,
@@ -228,6 +417,9 @@ impl Sink {
     fn run(self) {
         let Sink { listener, event_rx, wake_rx, shutdown_rx, _guard } = self;
         let mut subs: Vec<SubscriberWriter> = Vec::new();
+        let mut joining: Vec<Joining> = Vec::new();
+        let mut history = History::new(EVENT_HISTORY_DEPTH);
+        let mut watchers: Vec<mpsc::Sender<Event>> = Vec::new();
         // 4 KiB drain buffer; the bytes are signal-only and discarded.
         let mut wake_buf = [0u8; 4096];
         // Reused across iterations to avoid reallocating each loop. `fds`
@@ -238,22 +430,29 @@ impl Sink {
         let mut sub_revents: Vec<PollFlags> = Vec::new();
 
         // Fixed positions in the poll set: 0 = wake fd, 1 = listener fd
-        // (revents ignored -- see below), 2 = shutdown fd, 3.. =
-        // subscribers wanting POLLOUT.
+        // (revents ignored -- see below), 2 = shutdown fd, then one entry
+        // per joining subscriber (revents ignored too: every joining sub
+        // is polled for its request each iteration), then subscribers
+        // wanting POLLOUT.
         const WAKE_FD_IDX: usize = 0;
         const SHUTDOWN_FD_IDX: usize = 2;
-        const SUB_FDS_START: usize = 3;
+        const JOINING_FDS_START: usize = 3;
 
         loop {
             sub_pollfd_idx.clear();
             sub_revents.clear();
 
             // Build the poll set fresh each iteration: wake fd (POLLIN),
-            // listener fd (POLLIN), each subscriber that wants POLLOUT.
-            let mut fds: Vec<PollFd> = Vec::with_capacity(SUB_FDS_START + subs.len());
+            // listener fd (POLLIN), each joining sub (POLLIN), each
+            // subscriber that wants POLLOUT.
+            let sub_fds_start = JOINING_FDS_START + joining.len();
+            let mut fds: Vec<PollFd> = Vec::with_capacity(sub_fds_start + subs.len());
             fds.push(PollFd::new(wake_rx.as_fd(), PollFlags::POLLIN));
             fds.push(PollFd::new(listener.as_fd(), PollFlags::POLLIN));
             fds.push(PollFd::new(shutdown_rx.as_fd(), PollFlags::POLLIN));
+            for j in joining.iter() {
+                fds.push(PollFd::new(j.stream.as_fd(), PollFlags::POLLIN));
+            }
             for (i, sub) in subs.iter().enumerate() {
                 if sub.wants_pollout() {
                     fds.push(PollFd::new(sub.as_fd(), PollFlags::POLLOUT));
@@ -261,7 +460,19 @@ impl Sink {
                 }
             }
 
-            match poll::poll(&mut fds, PollTimeout::NONE) {
+            // Joining subs that never send a request are promoted when their
+            // resume window closes, so wake up for the earliest deadline.
+            let timeout = match joining.iter().map(|j| j.deadline).min() {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(time::Instant::now());
+                    // Round up so we never wake just short of the deadline
+                    // and spin.
+                    let ms = remaining.as_millis().saturating_add(1).min(u16::MAX as u128);
+                    PollTimeout::from(ms as u16)
+                }
+                None => PollTimeout::NONE,
+            };
+            match poll::poll(&mut fds, timeout) {
                 Ok(_) => {}
                 Err(Errno::EINTR) => continue,
                 Err(e) => panic!("events sink poll: {:?}", e),
@@ -276,7 +487,7 @@ impl Sink {
             let shutdown_revents = fds[SHUTDOWN_FD_IDX].revents().unwrap_or(PollFlags::empty());
             sub_revents.extend(
                 (0..sub_pollfd_idx.len())
-                    .map(|k| fds[SUB_FDS_START + k].revents().unwrap_or(PollFlags::empty())),
+                    .map(|k| fds[sub_fds_start + k].revents().unwrap_or(PollFlags::empty())),
             );
             drop(fds);
 
@@ -294,12 +505,13 @@ impl Sink {
             // listener POLLIN can lag behind `connect(2)` returning, and if
             // we were woken by the wake-fd alone we still want to catch any
             // queued connections so the same iteration's broadcast reaches
-            // them. The accept syscall is cheap (returns WouldBlock
-            // immediately when the queue is empty).
+            // them (via their replay, once they finish joining). The accept
+            // syscall is cheap (returns WouldBlock immediately when the
+            // queue is empty).
             loop {
                 match listener.accept() {
-                    Ok((stream, _addr)) => match SubscriberWriter::new(stream) {
-                        Ok(sub) => subs.push(sub),
+                    Ok((stream, _addr)) => match Joining::new(stream, history.next_seq()) {
+                        Ok(j) => joining.push(j),
                         Err(e) => warn!("registering events subscriber: {:?}", e),
                     },
                     Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
@@ -333,14 +545,25 @@ impl Sink {
                 // event flushes immediately and the next enqueue starts from
                 // empty. Without this, a burst larger than SUBSCRIBER_QUEUE_DEPTH
                 // would overflow even healthy subs because broadcast enqueues
-                // every event before any drive runs.
-                while let Ok(line) = event_rx.try_recv() {
+                // every event before any drive runs. Joining subs are skipped;
+                // the history catches them up when they are promoted.
+                while let Ok(msg) = event_rx.try_recv() {
+                    let published = match msg {
+                        ToSink::Watch(tx) => {
+                            watchers.push(tx);
+                            continue;
+                        }
+                        ToSink::Event(published) => published,
+                    };
+                    // Watchers whose receiver is gone get pruned here.
+                    watchers.retain(|tx| tx.send(published.event.clone()).is_ok());
+                    let stamped = history.push(published);
                     for sub in subs.iter_mut() {
-                        if sub.dropped {
+                        if sub.dropped || !sub.filter.matches(&stamped.event) {
                             continue;
                         }
                         let was_empty = !sub.wants_pollout();
-                        if let Err(Overflow::CapExceeded) = sub.enqueue(Arc::clone(&line)) {
+                        if let Err(Overflow::CapExceeded) = sub.enqueue(Arc::clone(&stamped.line)) {
                             warn!("dropping events subscriber: queue full");
                             sub.dropped = true;
                             continue;
@@ -355,6 +578,35 @@ impl Sink {
                 }
             }
 
+            // Promote joining subs that have sent their request (or whose
+            // window closed) to full subscribers, replaying from the history
+            // everything they asked for up to the events broadcast above.
+            let now = time::Instant::now();
+            let mut k = 0;
+            while k < joining.len() {
+                let Some(request) = joining[k].poll_request(now) else {
+                    k += 1;
+                    continue;
+                };
+                let j = joining.swap_remove(k);
+                let request = match request {
+                    Ok(request) => request,
+                    Err(e) => {
+                        info!("rejecting events subscriber: {:?}", e);
+                        reject(j.stream, &e);
+                        continue;
+                    }
+                };
+                let start = request.resume_from.map(|seq| seq.saturating_add(1));
+                let mut sub = SubscriberWriter::from_stream(j.stream, request.filter);
+                sub.replay(history.since(start.unwrap_or(j.start_seq), &sub.filter));
+                if let Err(e) = sub.drive() {
+                    info!("events subscriber gone: {:?}", e);
+                    continue;
+                }
+                subs.push(sub);
+            }
+
             // Drive writes for subs whose POLLOUT (or error) fired.
             for (k, &i) in sub_pollfd_idx.iter().enumerate() {
                 // Short-circuit: a sub marked dropped during the broadcast
@@ -383,6 +635,189 @@ impl Sink {
     }
 }
 
+/// An event as the sink has numbered and rendered it.
+struct Stamped {
+    seq: u64,
+    event: Event,
+    line: Arc<str>,
+}
+
+/// The most recent events, already rendered to wire lines, so that
+/// subscribers can be caught up on what they missed.
+struct History {
+    entries: VecDeque<Stamped>,
+    capacity: usize,
+    next_seq: u64,
+}
+
+impl History {
+    fn new(capacity: usize) -> Self {
+        // Sequence numbers start at 1 for each daemon and are only ever
+        // assigned here, in the order lines are handed to subscribers.
+        History { entries: VecDeque::with_capacity(capacity), capacity, next_seq: 1 }
+    }
+
+    /// The `seq` the next pushed event will get.
+    fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Assign `published` the next sequence number, render it and remember
+    /// it.
+    fn push(&mut self, published: Published) -> &Stamped {
+        let line = serialize_line(&published, self.next_seq);
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Stamped { seq: self.next_seq, event: published.event, line });
+        self.next_seq += 1;
+        self.entries.back().expect("just pushed")
+    }
+
+    /// The lines a subscriber whose stream starts at `start` should get
+    /// before the live stream: everything still retained from `start` on
+    /// that passes `filter`, preceded by a gap marker if some events in
+    /// that range have already been trimmed. We can't tell whether the
+    /// trimmed events would have passed the filter, so the gap is reported
+    /// regardless. A `start` past the next seq can only come from a
+    /// previous daemon's numbering, so it is treated as wanting everything.
+    fn since(&self, start: u64, filter: &Filter) -> Vec<Arc<str>> {
+        let start = if start > self.next_seq { 1 } else { start };
+        let oldest = self.entries.front().map(|e| e.seq).unwrap_or(self.next_seq);
+        let mut out = Vec::new();
+        if start < oldest {
+            out.push(gap_line(start, oldest - 1));
+        }
+        out.extend(
+            self.entries
+                .iter()
+                .filter(|e| e.seq >= start && filter.matches(&e.event))
+                .map(|e| Arc::clone(&e.line)),
+        );
+        out
+    }
+}
+
+/// A just-accepted subscriber that may still send a request line saying
+/// where its stream should start and which events it wants. Until it does
+/// (or its window closes) it gets no events; the [`History`] catches it up
+/// once it is promoted.
+struct Joining {
+    stream: UnixStream,
+    /// The seq of the first event after this subscriber connected: where
+    /// the stream starts if it does not ask to resume.
+    start_seq: u64,
+    deadline: time::Instant,
+    buf: Vec<u8>,
+}
+
+impl Joining {
+    fn new(stream: UnixStream, start_seq: u64) -> anyhow::Result<Self> {
+        stream.set_nonblocking(true).context("setting events subscriber stream non-blocking")?;
+        Ok(Joining {
+            stream,
+            start_seq,
+            deadline: time::Instant::now() + RESUME_WINDOW,
+            buf: Vec::new(),
+        })
+    }
+
+    /// Read whatever the subscriber has sent so far. Returns its request
+    /// once that is decided (the default one if it sent nothing in time),
+    /// or None to keep waiting.
+    fn poll_request(&mut self, now: time::Instant) -> Option<anyhow::Result<Request>> {
+        let mut chunk = [0u8; 64];
+        loop {
+            if let Some(nl) = self.buf.iter().position(|b| *b == b'\n') {
+                let line = String::from_utf8_lossy(&self.buf[..nl]);
+                return Some(Request::parse(&line));
+            }
+            if self.buf.len() > MAX_REQUEST_LEN {
+                return Some(Err(anyhow!("request longer than {} bytes", MAX_REQUEST_LEN)));
+            }
+            match (&self.stream).read(&mut chunk) {
+                // The subscriber shut down its write half without asking
+                // for anything.
+                Ok(0) => return Some(Ok(Request::default())),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return if now >= self.deadline { Some(Ok(Request::default())) } else { None };
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // Promote anyway; the first write will notice the broken
+                // stream and drop the subscriber.
+                Err(e) => {
+                    info!("reading events subscriber request: {:?}", e);
+                    return Some(Ok(Request::default()));
+                }
+            }
+        }
+    }
+}
+
+/// What a subscriber asked for in its request line.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Request {
+    /// The last seq the subscriber saw, if it wants to be caught up.
+    resume_from: Option<u64>,
+    filter: Filter,
+}
+
+impl Request {
+    /// Parse a request line: whitespace separated `key=value` fields, any
+    /// of which may be left out (so a blank line is the default request).
+    /// `type` and `session` may be repeated.
+    fn parse(line: &str) -> anyhow::Result<Self> {
+        let mut request = Request::default();
+        for field in line.split_whitespace() {
+            let (key, val) = field.split_once('=').ok_or(anyhow!("malformed field {:?}", field))?;
+            match key {
+                "resume_from" => {
+                    let seq = val
+                        .parse::<u64>()
+                        .with_context(|| format!("parsing resume_from seq {:?}", val))?;
+                    request.resume_from = Some(seq);
+                }
+                "type" => request.filter.types.push(String::from(val)),
+                "session" => request.filter.sessions.push(String::from(val)),
+                _ => return Err(anyhow!("unknown field {:?}", key)),
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// Which events a subscriber wants. An event passes if its type matches
+/// one of the `types` globs and its session matches one of the `sessions`
+/// globs, where an empty list lets everything through. Events that are not
+/// about a session never pass a `sessions` filter.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Filter {
+    types: Vec<String>,
+    sessions: Vec<String>,
+}
+
+impl Filter {
+    fn matches(&self, event: &Event) -> bool {
+        let type_ok =
+            self.types.is_empty() || self.types.iter().any(|t| glob_match(t, event.kind()));
+        let session_ok = self.sessions.is_empty()
+            || event.session().is_some_and(|s| self.sessions.iter().any(|p| glob_match(p, s)));
+        type_ok && session_ok
+    }
+}
+
+/// Tell a subscriber why its request was refused. Best effort: the stream is
+/// dropped right after, and a single short line fits in the empty socket
+/// buffer of a fresh connection.
+fn reject(mut stream: UnixStream, err: &anyhow::Error) {
+    let rejected = Rejected { error: format!("{err:#}") };
+    let line = serde_json::to_string(&rejected).expect("rejections are infallible to serialize");
+    if let Err(e) = stream.write_all(format!("{line}\n").as_bytes()) {
+        info!("writing events request rejection: {:?}", e);
+    }
+}
+
 /// Per-subscriber state owned by the sink. Exposes an event-shaped surface
 /// (`enqueue`, `drive`, `wants_pollout`); the partial-write state machine
 /// (offset into `pending.front()`) is hidden from the sink loop.
@@ -390,6 +825,12 @@ struct SubscriberWriter {
     stream: UnixStream,
     pending: VecDeque<Arc<str>>,
     front_offset: usize,
+    /// Only events passing this are enqueued.
+    filter: Filter,
+    /// How many lines at the front of `pending` are replayed history. They
+    /// don't count against SUBSCRIBER_QUEUE_DEPTH, which only bounds how
+    /// far a subscriber may fall behind the live stream.
+    backlog: usize,
     /// Set when the sink decides this sub should be removed; the actual
     /// `Vec` removal happens at end-of-iteration via `subs.retain`. We
     /// can't remove mid-iteration because `sub_pollfd_idx` holds indices
@@ -409,21 +850,45 @@ enum DriveOutcome {
 }
 
 impl SubscriberWriter {
+    #[cfg(test)]
     fn new(stream: UnixStream) -> anyhow::Result<Self> {
         stream.set_nonblocking(true).context("setting events subscriber stream non-blocking")?;
-        Ok(Self { stream, pending: VecDeque::new(), front_offset: 0, dropped: false })
+        Ok(Self::from_stream(stream, Filter::default()))
+    }
+
+    /// Wrap a stream that is already non-blocking.
+    fn from_stream(stream: UnixStream, filter: Filter) -> Self {
+        Self {
+            stream,
+            pending: VecDeque::new(),
+            front_offset: 0,
+            filter,
+            backlog: 0,
+            dropped: false,
+        }
     }
 
     fn enqueue(&mut self, line: Arc<str>) -> Result<(), Overflow> {
-        if self.pending.len() >= SUBSCRIBER_QUEUE_DEPTH {
+        if self.pending.len() - self.backlog >= SUBSCRIBER_QUEUE_DEPTH {
             return Err(Overflow::CapExceeded);
         }
         self.pending.push_back(line);
         Ok(())
     }
 
+    /// Queue replayed history. Only valid before any live line has been
+    /// enqueued.
+    fn replay(&mut self, lines: Vec<Arc<str>>) {
+        debug_assert!(self.pending.is_empty());
+        self.backlog = lines.len();
+        self.pending.extend(lines);
+    }
+
     fn drive(&mut self) -> io::Result<DriveOutcome> {
-        drive_pending(&mut self.stream, &mut self.pending, &mut self.front_offset)
+        let before = self.pending.len();
+        let outcome = drive_pending(&mut self.stream, &mut self.pending, &mut self.front_offset);
+        self.backlog = self.backlog.saturating_sub(before - self.pending.len());
+        outcome
     }
 
     fn wants_pollout(&self) -> bool {
@@ -471,26 +936,20 @@ fn drive_pending<W: Write>(
     Ok(DriveOutcome::AllFlushed)
 }
 
-/// Wire-form `Arc<str>` per `Event` variant, lazily built once via serde
-/// (so the wire format stays driven by the `#[serde(rename = ...)]`
-/// annotations) and cloned cheaply on every publish thereafter. Each
-/// `Arc::clone` on the hot path is one atomic increment — no allocation,
-/// no serialization.
-fn serialize_line(event: &Event) -> Arc<str> {
-    fn build(e: Event) -> Arc<str> {
-        let s = serde_json::to_string(&e).expect("Event variants are infallible to serialize");
-        Arc::from(format!("{s}\n"))
-    }
-    static CREATED: LazyLock<Arc<str>> = LazyLock::new(|| build(Event::SessionCreated));
-    static ATTACHED: LazyLock<Arc<str>> = LazyLock::new(|| build(Event::SessionAttached));
-    static DETACHED: LazyLock<Arc<str>> = LazyLock::new(|| build(Event::SessionDetached));
-    static REMOVED: LazyLock<Arc<str>> = LazyLock::new(|| build(Event::SessionRemoved));
-    match event {
-        Event::SessionCreated => Arc::clone(&CREATED),
-        Event::SessionAttached => Arc::clone(&ATTACHED),
-        Event::SessionDetached => Arc::clone(&DETACHED),
-        Event::SessionRemoved => Arc::clone(&REMOVED),
-    }
+/// The wire line telling a subscriber that the events from `first` to
+/// `last` (inclusive) are no longer available.
+fn gap_line(first: u64, last: u64) -> Arc<str> {
+    let gap = Gap { first_missed_seq: first, last_missed_seq: last };
+    let s = serde_json::to_string(&gap).expect("gaps are infallible to serialize");
+    Arc::from(format!("{s}\n"))
+}
+
+/// Render one wire line for `published`, stamped with `seq`. Serialized
+/// once per event; every subscriber shares the resulting `Arc<str>`.
+fn serialize_line(published: &Published, seq: u64) -> Arc<str> {
+    let envelope = Envelope { event: &published.event, seq, ts_unix_ms: published.ts_unix_ms };
+    let s = serde_json::to_string(&envelope).expect("events are infallible to serialize");
+    Arc::from(format!("{s}\n"))
 }
 
 fn make_self_pipe() -> io::Result<(OwnedFd, OwnedFd)> {
@@ -526,6 +985,20 @@ mod tests {
         serde_json::to_string(event).unwrap()
     }
 
+    fn created() -> Event {
+        Event::SessionCreated { session: String::from("s") }
+    }
+
+    fn parse(line: &str) -> serde_json::Value {
+        assert!(line.ends_with('\n'), "line not newline terminated: {line:?}");
+        serde_json::from_str(line).unwrap()
+    }
+
+    /// The `type` of a wire line.
+    fn kind(line: &str) -> String {
+        parse(line)["type"].as_str().unwrap().to_string()
+    }
+
     /// Per-test scaffolding: tempdir + socket path + bus + sink handle.
     /// `_handle` is declared first so it drops first at end-of-scope --
     /// shutting down and joining the sink (which unlinks the socket)
@@ -549,9 +1022,9 @@ mod tests {
     /// chance to broadcast.
     const READ_TIMEOUT: Duration = Duration::from_secs(10);
 
-    /// Wire form of `Event::SessionCreated` — the most-published event in
-    /// the test suite.
-    const CREATED_LINE: &str = "{\"type\":\"session.created\"}\n";
+    /// A representative wire line, for tests that only push bytes around.
+    const CREATED_LINE: &str =
+        "{\"type\":\"session.created\",\"session\":\"s\",\"seq\":1,\"ts_unix_ms\":0}\n";
 
     fn read_line(stream: &mut UnixStream) -> String {
         stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
@@ -573,19 +1046,27 @@ mod tests {
             .collect()
     }
 
+    /// Connect a subscriber that starts from the live stream right away,
+    /// rather than after RESUME_WINDOW.
+    fn connect(path: &Path) -> UnixStream {
+        let mut stream = UnixStream::connect(path).unwrap();
+        stream.write_all(b"\n").unwrap();
+        stream
+    }
+
     /// Connect a fresh subscriber, then publish a probe event and consume
     /// it. Returning means the sink has accepted the connection and
     /// broadcast at least one event to it. Probe is `SessionCreated`;
     /// callers continue with their own publishes from a clean stream.
     fn connect_registered(path: &Path, bus: &EventBus) -> UnixStream {
-        let mut stream = UnixStream::connect(path).unwrap();
+        let mut stream = connect(path);
         // Sleep briefly so the OS schedules the sink thread to accept
         // this connection before the publisher's wake byte arrives.
         // `thread::yield_now()` is only a scheduler hint; under heavy
         // parallel-test load it isn't reliable. A 1ms sleep guarantees
         // a context switch.
         thread::sleep(Duration::from_millis(1));
-        bus.publish(&Event::SessionCreated);
+        bus.publish(created());
         let _ = read_line(&mut stream);
         stream
     }
@@ -596,10 +1077,9 @@ mod tests {
     /// guarantees all queued subs are registered before the probe is
     /// broadcast.
     fn connect_n_registered(path: &Path, bus: &EventBus, n: usize) -> Vec<UnixStream> {
-        let mut streams: Vec<UnixStream> =
-            (0..n).map(|_| UnixStream::connect(path).unwrap()).collect();
+        let mut streams: Vec<UnixStream> = (0..n).map(|_| connect(path)).collect();
         thread::sleep(Duration::from_millis(1));
-        bus.publish(&Event::SessionCreated);
+        bus.publish(created());
         for s in streams.iter_mut() {
             let _ = read_line(s);
         }
@@ -607,31 +1087,113 @@ mod tests {
     }
 
     #[test]
-    fn events_serialize_with_only_type() {
+    fn events_serialize_with_type_and_payload() {
         let cases = [
-            (Event::SessionCreated, r#"{"type":"session.created"}"#),
-            (Event::SessionAttached, r#"{"type":"session.attached"}"#),
-            (Event::SessionDetached, r#"{"type":"session.detached"}"#),
-            (Event::SessionRemoved, r#"{"type":"session.removed"}"#),
+            (created(), r#"{"type":"session.created","session":"s"}"#),
+            (
+                Event::SessionAttached { session: String::from("s") },
+                r#"{"type":"session.attached","session":"s"}"#,
+            ),
+            (
+                Event::SessionDetached { session: String::from("s") },
+                r#"{"type":"session.detached","session":"s"}"#,
+            ),
+            (
+                Event::SessionRemoved {
+                    session: String::from("s"),
+                    reason: RemovalReason::Exited,
+                    exit_status: Some(3),
+                },
+                r#"{"type":"session.removed","session":"s","reason":"exited","exit_status":3}"#,
+            ),
+            (
+                Event::SessionRemoved {
+                    session: String::from("s"),
+                    reason: RemovalReason::Ttl,
+                    exit_status: None,
+                },
+                r#"{"type":"session.removed","session":"s","reason":"ttl","exit_status":null}"#,
+            ),
+            (
+                Event::SessionRenamed { session: String::from("s"), old_name: String::from("o") },
+                r#"{"type":"session.renamed","session":"s","old_name":"o"}"#,
+            ),
+            (
+                Event::SessionExited { session: String::from("s"), exit_status: 2 },
+                r#"{"type":"session.exited","session":"s","exit_status":2}"#,
+            ),
+            (
+                Event::SessionRemoved {
+                    session: String::from("s"),
+                    reason: RemovalReason::Dismissed,
+                    exit_status: Some(2),
+                },
+                r#"{"type":"session.removed","session":"s","reason":"dismissed","exit_status":2}"#,
+            ),
+            (
+                Event::SessionRestarted { session: String::from("s"), exit_status: 3, restarts: 1 },
+                r#"{"type":"session.restarted","session":"s","exit_status":3,"restarts":1}"#,
+            ),
+            (
+                Event::SessionResized { session: String::from("s"), rows: 24, cols: 80 },
+                r#"{"type":"session.resized","session":"s","rows":24,"cols":80}"#,
+            ),
+            (
+                Event::VarChanged { var: String::from("v"), value: None },
+                r#"{"type":"var.changed","var":"v","value":null}"#,
+            ),
+            (
+                Event::ConfigReloaded { ok: false, error: Some(String::from("bad")) },
+                r#"{"type":"config.reloaded","ok":false,"error":"bad"}"#,
+            ),
         ];
         for (event, expected) in &cases {
             assert_eq!(json(event), *expected, "variant {event:?}");
         }
     }
 
+    #[test]
+    fn serialize_line_stamps_envelope_after_event_fields() {
+        let published = Published { event: created(), ts_unix_ms: 1234 };
+        assert_eq!(
+            &*serialize_line(&published, 7),
+            "{\"type\":\"session.created\",\"session\":\"s\",\"seq\":7,\"ts_unix_ms\":1234}\n"
+        );
+    }
+
     #[test]
     fn bus_publish_with_no_subscribers_is_a_noop() {
         let dir = tempfile::tempdir().unwrap();
         let (bus, _handle) = EventBus::start(dir.path().join("events.socket")).unwrap();
-        bus.publish(&Event::SessionCreated);
+        bus.publish(created());
     }
 
     #[test]
     fn bus_publish_reaches_subscriber() {
         let h = harness();
         let mut stream = connect_registered(&h.path, &h.bus);
-        h.bus.publish(&Event::SessionCreated);
-        assert_eq!(read_line(&mut stream), CREATED_LINE);
+        h.bus.publish(Event::SessionCreated { session: String::from("foo") });
+        let event = parse(&read_line(&mut stream));
+        assert_eq!(event["type"], "session.created");
+        assert_eq!(event["session"], "foo");
+        assert!(event["ts_unix_ms"].as_i64().unwrap() > 0);
+    }
+
+    #[test]
+    fn bus_publish_reaches_watchers() {
+        let dir = tempfile::tempdir().unwrap();
+        let (bus, _handle) = EventBus::start(dir.path().join("events.socket")).unwrap();
+        let early = bus.watch();
+        bus.publish(Event::SessionCreated { session: String::from("a") });
+        let late = bus.watch();
+        drop(early);
+        bus.publish(Event::SessionCreated { session: String::from("b") });
+        bus.publish(Event::SessionCreated { session: String::from("c") });
+
+        let recv = || late.recv_timeout(time::Duration::from_secs(5));
+        assert_eq!(recv(), Ok(Event::SessionCreated { session: String::from("b") }));
+        assert_eq!(recv(), Ok(Event::SessionCreated { session: String::from("c") }));
+        assert!(late.try_recv().is_err());
     }
 
     #[test]
@@ -641,8 +1203,8 @@ mod tests {
         let mut probe = connect_registered(&h.path, &h.bus);
 
         drop(victim);
-        h.bus.publish(&Event::SessionAttached);
-        assert_eq!(read_line(&mut probe), "{\"type\":\"session.attached\"}\n");
+        h.bus.publish(Event::SessionAttached { session: String::from("s") });
+        assert_eq!(kind(&read_line(&mut probe)), "session.attached");
     }
 
     // Publish is `try_send` + 1-byte wake -- independent of N. A regression that
@@ -660,7 +1222,7 @@ mod tests {
 
         let start = Instant::now();
         for _ in 0..1000 {
-            h.bus.publish(&Event::SessionCreated);
+            h.bus.publish(created());
         }
         let elapsed = start.elapsed();
         assert!(
@@ -688,7 +1250,7 @@ mod tests {
                 thread::spawn(move || {
                     for _ in 0..n_per_thread {
                         let _g = outer.lock();
-                        bus.publish(&Event::SessionCreated);
+                        bus.publish(created());
                     }
                 })
             })
@@ -697,8 +1259,14 @@ mod tests {
             handle.join().unwrap();
         }
 
-        for line in read_n_lines(&mut stream, total) {
-            assert_eq!(line, CREATED_LINE);
+        // Whatever order the threads won the race in, sequence numbers
+        // must be consecutive in wire order.
+        let seqs: Vec<u64> = read_n_lines(&mut stream, total)
+            .iter()
+            .map(|line| parse(line)["seq"].as_u64().unwrap())
+            .collect();
+        for pair in seqs.windows(2) {
+            assert_eq!(pair[1], pair[0] + 1, "seqs not consecutive: {seqs:?}");
         }
     }
 
@@ -726,7 +1294,7 @@ mod tests {
         let mut streams: Vec<UnixStream> = (0..n)
             .map(|_| {
                 let path = h.path.clone();
-                thread::spawn(move || UnixStream::connect(&path).unwrap())
+                thread::spawn(move || connect(&path))
             })
             .collect::<Vec<_>>()
             .into_iter()
@@ -736,9 +1304,9 @@ mod tests {
         // Probe to verify each concurrently-dialed sub is registered: the
         // sink's accept-before-wake order ensures all queued connects
         // join `subs` before this publish broadcasts.
-        h.bus.publish(&Event::SessionCreated);
+        h.bus.publish(created());
         for stream in streams.iter_mut() {
-            assert_eq!(read_line(stream), CREATED_LINE);
+            assert_eq!(kind(&read_line(stream)), "session.created");
         }
     }
 
@@ -751,12 +1319,11 @@ mod tests {
         // Stay under SUBSCRIBER_QUEUE_DEPTH with margin so no sub is dropped.
         let n_events = 32;
         for _ in 0..n_events {
-            h.bus.publish(&Event::SessionCreated);
+            h.bus.publish(created());
         }
-        let expected = CREATED_LINE;
         for stream in streams.iter_mut() {
             for line in read_n_lines(stream, n_events) {
-                assert_eq!(line, expected);
+                assert_eq!(kind(&line), "session.created");
             }
         }
     }
@@ -766,20 +1333,233 @@ mod tests {
         let h = harness();
         let mut stream = connect_registered(&h.path, &h.bus);
 
-        h.bus.publish(&Event::SessionCreated);
-        h.bus.publish(&Event::SessionAttached);
-        h.bus.publish(&Event::SessionDetached);
-        h.bus.publish(&Event::SessionRemoved);
+        let session = || String::from("s");
+        h.bus.publish(created());
+        h.bus.publish(Event::SessionAttached { session: session() });
+        h.bus.publish(Event::SessionDetached { session: session() });
+        h.bus.publish(Event::SessionRemoved {
+            session: session(),
+            reason: RemovalReason::Killed,
+            exit_status: None,
+        });
 
         let lines = read_n_lines(&mut stream, 4);
+        let kinds: Vec<String> = lines.iter().map(|l| kind(l)).collect();
+        assert_eq!(
+            kinds,
+            ["session.created", "session.attached", "session.detached", "session.removed"]
+        );
+        // The probe from `connect_registered` took seq 1.
+        let seqs: Vec<u64> = lines.iter().map(|l| parse(l)["seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, [2, 3, 4, 5]);
+        assert_eq!(parse(&lines[3])["reason"], "killed");
+    }
+
+    #[test]
+    fn resume_from_replays_missed_events_then_goes_live() {
+        let h = harness();
+        // Takes seq 1.
+        let _probe = connect_registered(&h.path, &h.bus);
+        h.bus.publish(Event::SessionAttached { session: String::from("s") });
+        h.bus.publish(Event::SessionDetached { session: String::from("s") });
+
+        let mut stream = UnixStream::connect(&h.path).unwrap();
+        stream.write_all(b"resume_from=1\n").unwrap();
+        stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut next = || {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            parse(&line)
+        };
+        let (first, second) = (next(), next());
+        assert_eq!((&first["type"], &first["seq"]), (&"session.attached".into(), &2.into()));
+        assert_eq!((&second["type"], &second["seq"]), (&"session.detached".into(), &3.into()));
+
+        h.bus.publish(created());
+        assert_eq!(next()["seq"], 4);
+    }
+
+    #[test]
+    fn resume_past_trimmed_history_reports_gap() {
+        let h = harness();
+        for _ in 0..(EVENT_HISTORY_DEPTH + 2) {
+            h.bus.publish(created());
+        }
+
+        let mut stream = UnixStream::connect(&h.path).unwrap();
+        stream.write_all(b"resume_from=0\n").unwrap();
+        let lines = read_n_lines(&mut stream, 2);
+        let gap = parse(&lines[0]);
+        assert_eq!(gap["type"], "events.gap");
+        assert_eq!(gap["first_missed_seq"], 1);
+        assert_eq!(gap["last_missed_seq"], 2);
+        assert_eq!(parse(&lines[1])["seq"], 3);
+    }
+
+    #[test]
+    fn subscriber_without_request_gets_events_after_window() {
+        let h = harness();
+        let mut stream = UnixStream::connect(&h.path).unwrap();
+        thread::sleep(Duration::from_millis(1));
+        h.bus.publish(created());
+        assert_eq!(parse(&read_line(&mut stream))["seq"], 1);
+    }
+
+    #[test]
+    fn history_since_replays_what_is_retained() {
+        let mut history = History::new(3);
+        for _ in 0..5 {
+            history.push(Published { event: created(), ts_unix_ms: 0 });
+        }
+        let seqs = |start| -> Vec<serde_json::Value> {
+            history
+                .since(start, &Filter::default())
+                .iter()
+                .map(|l| {
+                    let v = parse(l);
+                    if v["type"] == "events.gap" {
+                        serde_json::json!([v["first_missed_seq"], v["last_missed_seq"]])
+                    } else {
+                        v["seq"].clone()
+                    }
+                })
+                .collect()
+        };
+        assert_eq!(seqs(4), [4, 5]);
+        assert_eq!(seqs(3), [3, 4, 5]);
+        assert_eq!(seqs(6), Vec::<serde_json::Value>::new());
+        assert_eq!(seqs(1), [serde_json::json!([1, 2]), 3.into(), 4.into(), 5.into()]);
+        // A seq from a previous daemon's numbering.
+        assert_eq!(seqs(100), seqs(1));
+    }
+
+    #[test]
+    fn parse_request_cases() {
+        let types = |ts: &[&str]| ts.iter().map(|t| String::from(*t)).collect::<Vec<_>>();
+        assert_eq!(Request::parse("").unwrap(), Request::default());
+        assert_eq!(Request::parse("  ").unwrap(), Request::default());
+        assert_eq!(Request::parse("resume_from=42").unwrap().resume_from, Some(42));
+        assert_eq!(Request::parse("resume_from=7\r").unwrap().resume_from, Some(7));
         assert_eq!(
-            lines,
-            [
-                CREATED_LINE,
-                "{\"type\":\"session.attached\"}\n",
-                "{\"type\":\"session.detached\"}\n",
-                "{\"type\":\"session.removed\"}\n",
-            ]
+            Request::parse("type=session.* session=dev-? type=var.changed resume_from=3").unwrap(),
+            Request {
+                resume_from: Some(3),
+                filter: Filter {
+                    types: types(&["session.*", "var.changed"]),
+                    sessions: types(&["dev-?"]),
+                },
+            }
+        );
+        assert!(Request::parse("resume_from=").is_err());
+        assert!(Request::parse("resume_from=-1").is_err());
+        assert!(Request::parse("hello").is_err());
+        assert!(Request::parse("colour=blue").is_err());
+    }
+
+    #[test]
+    fn filter_matches_type_and_session() {
+        let removed = Event::SessionRemoved {
+            session: String::from("dev-1"),
+            reason: RemovalReason::Killed,
+            exit_status: None,
+        };
+        let var = Event::VarChanged { var: String::from("x"), value: None };
+        let filter = |types: &[&str], sessions: &[&str]| Filter {
+            types: types.iter().map(|t| String::from(*t)).collect(),
+            sessions: sessions.iter().map(|s| String::from(*s)).collect(),
+        };
+
+        assert!(filter(&[], &[]).matches(&removed));
+        assert!(filter(&[], &[]).matches(&var));
+        assert!(filter(&["session.*"], &[]).matches(&removed));
+        assert!(!filter(&["session.*"], &[]).matches(&var));
+        assert!(filter(&["var.changed", "session.removed"], &[]).matches(&var));
+        assert!(filter(&[], &["dev-*"]).matches(&removed));
+        assert!(!filter(&[], &["prod", "dev"]).matches(&removed));
+        // Events that aren't about a session never pass a session filter.
+        assert!(!filter(&[], &["*"]).matches(&var));
+        assert!(filter(&["session.removed"], &["dev-?"]).matches(&removed));
+        assert!(!filter(&["session.created"], &["dev-?"]).matches(&removed));
+    }
+
+    #[test]
+    fn kind_matches_serialized_type() {
+        let events = [
+            Event::SessionCreated { session: String::from("s") },
+            Event::SessionAttached { session: String::from("s") },
+            Event::SessionDetached { session: String::from("s") },
+            Event::SessionRemoved {
+                session: String::from("s"),
+                reason: RemovalReason::Exited,
+                exit_status: Some(0),
+            },
+            Event::SessionBusy { session: String::from("s") },
+            Event::SessionRenamed { session: String::from("s"), old_name: String::from("o") },
+            Event::SessionExited { session: String::from("s"), exit_status: 1 },
+            Event::SessionRestarted { session: String::from("s"), exit_status: 1, restarts: 2 },
+            Event::SessionResized { session: String::from("s"), rows: 1, cols: 2 },
+            Event::VarChanged { var: String::from("v"), value: None },
+            Event::ConfigReloaded { ok: true, error: None },
+            Event::LogLevelChanged { level: String::from("info") },
+        ];
+        for event in events {
+            let v = serde_json::to_value(&event).unwrap();
+            assert_eq!(v["type"], event.kind());
+            assert_eq!(v.get("session").and_then(|s| s.as_str()), event.session());
+        }
+    }
+
+    #[test]
+    fn filtered_subscriber_gets_only_matching_events() {
+        let h = harness();
+        let session = |name: &str| Event::SessionAttached { session: String::from(name) };
+        h.bus.publish(session("dev-1"));
+        h.bus.publish(session("prod"));
+        h.bus.publish(Event::VarChanged { var: String::from("v"), value: None });
+        h.bus.publish(session("dev-2"));
+
+        // Resuming from the very start makes the replay, and so the
+        // moment of promotion, deterministic.
+        let mut stream = UnixStream::connect(&h.path).unwrap();
+        stream.write_all(b"resume_from=0 type=session.* session=dev-*\n").unwrap();
+        stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut next = || {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            parse(&line)
+        };
+        assert_eq!(next()["session"], "dev-1");
+        assert_eq!(next()["session"], "dev-2");
+
+        h.bus.publish(session("prod"));
+        h.bus.publish(Event::LogLevelChanged { level: String::from("debug") });
+        h.bus.publish(session("dev-3"));
+        let live = next();
+        assert_eq!(live["session"], "dev-3");
+        assert_eq!(live["seq"], 7);
+    }
+
+    #[test]
+    fn malformed_request_is_rejected() {
+        let h = harness();
+        let mut stream = UnixStream::connect(&h.path).unwrap();
+        stream.write_all(b"type=session.* bogus\n").unwrap();
+        let line = read_line(&mut stream);
+        let v = parse(&line);
+        assert_eq!(v["type"], "events.rejected");
+        assert!(v["error"].as_str().unwrap().contains("bogus"), "{v}");
+        // The sink hangs up after rejecting.
+        let mut rest = String::new();
+        assert_eq!(stream.read_to_string(&mut rest).unwrap(), 0);
+    }
+
+    #[test]
+    fn gap_line_serializes_with_type() {
+        assert_eq!(
+            &*gap_line(3, 9),
+            "{\"type\":\"events.gap\",\"first_missed_seq\":3,\"last_missed_seq\":9}\n"
         );
     }
 
@@ -797,8 +1577,8 @@ mod tests {
         let mut fast = UnixStream::connect(&path).unwrap();
 
         // Probe both subs (fast reads it; slow's tiny buffer fits one
-        // event of ~30 bytes).
-        bus.publish(&Event::SessionCreated);
+        // event of ~80 bytes).
+        bus.publish(created());
         let _ = read_line(&mut fast);
 
         // Interleave publish + read so fast's kernel buffer never fills
@@ -809,14 +1589,13 @@ mod tests {
         // events, then pending overflows and the sink drops slow. The
         // test passes only if fast continues receiving while slow is
         // overflowing or after slow is dropped.
-        let expected = CREATED_LINE;
         fast.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
         let mut reader = BufReader::new(&mut fast);
         for _ in 0..1000 {
-            bus.publish(&Event::SessionCreated);
+            bus.publish(created());
             let mut line = String::new();
             reader.read_line(&mut line).unwrap();
-            assert_eq!(line, expected);
+            assert_eq!(kind(&line), "session.created");
         }
     }
 
@@ -1025,6 +1804,18 @@ mod tests {
         assert!(matches!(err, Err(Overflow::CapExceeded)));
     }
 
+    #[test]
+    fn replayed_backlog_does_not_count_against_depth() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut sub = SubscriberWriter::new(a).unwrap();
+        sub.replay((0..SUBSCRIBER_QUEUE_DEPTH * 2).map(|i| format!("old-{i}\n").into()).collect());
+        for i in 0..SUBSCRIBER_QUEUE_DEPTH {
+            sub.enqueue(format!("event-{i}\n").into()).unwrap();
+        }
+        let err = sub.enqueue("one-too-many\n".into());
+        assert!(matches!(err, Err(Overflow::CapExceeded)));
+    }
+
     #[test]
     fn subscriber_writer_overflows_when_peer_blocks() {
         // Use a socket pair so we can shrink the *server-side* send buffer
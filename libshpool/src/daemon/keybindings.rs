@@ -34,19 +34,34 @@
 //! chord ::= key
 //!         | key '-' chord
 //!
-//! key ::= mod | sym
+//! key ::= mod | sym | raw
 //!
-//! mod ::= 'Ctrl'
+//! mod ::= 'Ctrl' | 'Alt' | 'Meta' | 'Shift'
 //!
-//! sym ::= 'Space' | <lowercase letters> | <numbers>
+//! sym ::= 'Space' | 'Tab' | 'Enter' | 'Return' | 'Esc' | 'Escape'
+//!       | 'Backspace' | 'Up' | 'Down' | 'Left' | 'Right' | 'Home' | 'End'
+//!       | 'Insert' | 'Delete' | 'PageUp' | 'PageDown' | 'F1' ... 'F12'
+//!       | <any other single printable char besides '-' and '<'>
+//!
+//! raw ::= '<' <bytes, with \e, \xHH, \n, \r, \t, \\ and \> escapes> '>'
 //! ```
 //!
 //! chords bind tighter than sequnces. A chord must be pressed all at once
 //! while a sequence should have the keys pressed one after another.
 //!
-//! For now, only fairly limited chords are supported. Chords must either
-//! be singletons besides 'Ctrl' or of the form 'Ctrl-x' where
-//! x is some non-'Ctrl' key.
+//! A chord is any number of distinct mod keys followed by a single sym.
+//! 'Alt' and 'Meta' are two names for the same key. 'Shift' may only
+//! modify letters and named keys, shifted symbols are written directly
+//! (so '!' rather than 'Shift-1'). A key name that starts with an uppercase
+//! letter must be separated from whatever follows it, so 'A b' rather than
+//! 'Ab'. A raw key matches exactly the bytes it spells out and cannot be
+//! combined with mod keys.
+//!
+//! Since terminals disagree about how to encode some keys, a chord may
+//! match several byte sequences. Besides the traditional encodings (an
+//! ESC prefix for Alt, CSI and SS3 sequences for the cursor and function
+//! keys), modified keys also match the xterm modifyOtherKeys and kitty
+//! keyboard protocol encodings.
 
 use std::{collections::HashMap, fmt};
 
@@ -59,10 +74,6 @@ use super::trie::{Trie, TrieCursor, TrieTab};
 // Keybindings table
 //
 
-// TODO(ethan): should I have some notion of a cooldown time
-//              where sequences don't count if they are pressed
-//              too slowly?
-
 /// Bindings represents an engine for scanning through user input
 /// and occasionally emitting actions that should be acted upon.
 pub struct Bindings {
@@ -79,6 +90,13 @@ pub struct Bindings {
     sequences: Trie<ChordAtom, Action, Vec<Option<usize>>>,
     /// The current match state in the sequences trie.
     sequences_cursor: TrieCursor,
+    /// The bytes scanned so far for the sequence currently being matched.
+    typed: Vec<u8>,
+    /// How many of the bytes in `typed` belong to the sequence's first chord.
+    first_chord_len: usize,
+    /// The bytes of the first chord of the binding that matched most
+    /// recently, exactly as they were typed.
+    matched_prefix: Vec<u8>,
 }
 
 /// The result of advancing the binding engine by a single byte.
@@ -122,18 +140,25 @@ impl Bindings {
         let mut sequences = Trie::new();
 
         let mut chord_atom_counter: usize = 0;
-        let mut chord_atom_tab = HashMap::new();
+        // Chords are identified by the bytes they generate in a plain
+        // terminal, so that two names for the same key (say 'Tab' and
+        // 'Ctrl-i') end up as the same atom.
+        let mut chord_atom_tab: HashMap<Vec<u8>, ChordAtom> = HashMap::new();
+        // Every byte sequence we need to recognize, along with the atom it
+        // stands for and the chord it came from (for error messages).
+        let mut encodings: Vec<(Vec<u8>, ChordAtom, String)> = vec![];
 
         let tokenizer = Lexer::new();
         for (binding_src, action) in bindings.into_iter() {
             let tokens =
                 tokenizer.tokenize(binding_src.chars()).context("tokenizing keybinding")?;
             let sequence = parse(tokens).context("parsing keybinding")?;
+            let mut atoms = vec![];
             for chord in sequence.0.iter() {
-                // resolving the key code will also check the validity
-                let code = chord.key_code()?;
+                // resolving the key codes will also check the validity
+                let codes = chord.key_codes()?;
 
-                let chord_atom = chord_atom_tab.entry(chord.clone()).or_insert_with(|| {
+                let chord_atom = *chord_atom_tab.entry(codes[0].clone()).or_insert_with(|| {
                     let atom = ChordAtom(chord_atom_counter as u8);
                     chord_atom_counter += 1;
                     atom
@@ -144,11 +169,35 @@ impl Bindings {
                         u8::MAX
                     ));
                 }
+                atoms.push(chord_atom);
 
-                chords.insert(vec![code].into_iter(), *chord_atom);
+                for code in codes.into_iter() {
+                    if !encodings.iter().any(|(c, a, _)| *c == code && *a == chord_atom) {
+                        encodings.push((code, chord_atom, chord.to_string()));
+                    }
+                }
             }
-            sequences
-                .insert(sequence.0.iter().map(|chord| *chord_atom_tab.get(chord).unwrap()), action);
+            sequences.insert(atoms.into_iter(), action);
+        }
+
+        // The chords trie can only say which chord it has seen once it has
+        // seen all of the chord's bytes, so no chord may generate a prefix of
+        // what another one generates.
+        for (i, (code, atom, chord)) in encodings.iter().enumerate() {
+            for (other_code, other_atom, other_chord) in encodings[i + 1..].iter() {
+                if atom != other_atom
+                    && (code.starts_with(other_code) || other_code.starts_with(code))
+                {
+                    return Err(anyhow!(
+                        "keys '{}' and '{}' cannot be told apart, one is a prefix of the other",
+                        chord,
+                        other_chord
+                    ));
+                }
+            }
+        }
+        for (code, atom, _) in encodings.into_iter() {
+            chords.insert(code.into_iter(), atom);
         }
 
         Ok(Bindings {
@@ -156,6 +205,9 @@ impl Bindings {
             chords_cursor: TrieCursor::Start,
             sequences,
             sequences_cursor: TrieCursor::Start,
+            typed: vec![],
+            first_chord_len: 0,
+            matched_prefix: vec![],
         })
     }
 
@@ -163,24 +215,32 @@ impl Bindings {
     /// bindings engine while possibly emitting an action that the caller
     /// should perform in response to a keybinding that has just been completed.
     pub fn transition(&mut self, byte: u8) -> BindingResult {
+        self.typed.push(byte);
         self.chords_cursor = self.chords.advance(self.chords_cursor, byte);
         if let Some(chord_atom) = self.chords.get(self.chords_cursor) {
             self.chords_cursor = TrieCursor::Start;
+            if let TrieCursor::Start = self.sequences_cursor {
+                self.first_chord_len = self.typed.len();
+            }
 
             self.sequences_cursor = self.sequences.advance(self.sequences_cursor, *chord_atom);
             match self.sequences_cursor {
                 TrieCursor::Match { is_partial, .. } if is_partial => BindingResult::Partial,
                 TrieCursor::Match { .. } => {
                     let cursor = self.sequences_cursor;
-                    self.sequences_cursor = TrieCursor::Start;
-                    if let Some(action) = self.sequences.get(cursor) {
-                        BindingResult::Match(*action)
-                    } else {
-                        BindingResult::NoMatch
+                    let action = self.sequences.get(cursor).cloned();
+                    if action.is_some() {
+                        self.matched_prefix.clear();
+                        self.matched_prefix.extend_from_slice(&self.typed[..self.first_chord_len]);
+                    }
+                    self.reset();
+                    match action {
+                        Some(action) => BindingResult::Match(action),
+                        None => BindingResult::NoMatch,
                     }
                 }
                 _ => {
-                    self.sequences_cursor = TrieCursor::Start;
+                    self.reset();
                     BindingResult::NoMatch
                 }
             }
@@ -188,23 +248,125 @@ impl Bindings {
             match self.chords_cursor {
                 TrieCursor::Match { is_partial, .. } if is_partial => BindingResult::Partial,
                 _ => {
-                    // no match, reset
-                    self.sequences_cursor = TrieCursor::Start;
-                    self.chords_cursor = TrieCursor::Start;
+                    self.reset();
                     BindingResult::NoMatch
                 }
             }
         }
     }
+
+    /// Forget about any partially matched keybinding.
+    pub fn reset(&mut self) {
+        self.chords_cursor = TrieCursor::Start;
+        self.sequences_cursor = TrieCursor::Start;
+        self.typed.clear();
+    }
+
+    /// The bytes generated by the first chord of the binding that
+    /// matched most recently. This is what the `sendprefix` action
+    /// passes through to the shell.
+    pub fn matched_prefix(&self) -> &[u8] {
+        &self.matched_prefix
+    }
+}
+
+/// InputFilter runs user input through a Bindings engine on its way to
+/// the shell. Bytes which might be part of a keybinding get held back until
+/// it is clear whether they are. If they turn out to be a keybinding, they
+/// get dropped, otherwise they are passed along exactly as they came in, no
+/// matter how the input was split up into chunks.
+pub struct InputFilter {
+    bindings: Bindings,
+    /// The input held back for the keybinding currently being matched.
+    held: Vec<u8>,
+}
+
+impl InputFilter {
+    pub fn new(bindings: Bindings) -> Self {
+        InputFilter { bindings, held: vec![] }
+    }
+
+    /// Scan `input` until either a keybinding fires or we run out of
+    /// input, adding whatever should be sent to the shell to `out`.
+    /// Returns the number of bytes scanned and the action for the
+    /// keybinding that fired, if any. For the `sendprefix` action, the
+    /// prefix will already have been added to `out`.
+    pub fn scan(&mut self, input: &[u8], out: &mut Vec<u8>) -> (usize, Option<Action>) {
+        for (i, byte) in input.iter().enumerate() {
+            let mut result = self.bindings.transition(*byte);
+            if result == BindingResult::NoMatch && !self.held.is_empty() {
+                // The byte that broke the match might still be the
+                // start of another one.
+                out.append(&mut self.held);
+                result = self.bindings.transition(*byte);
+            }
+            match result {
+                BindingResult::NoMatch => {
+                    out.append(&mut self.held);
+                    out.push(*byte);
+                }
+                BindingResult::Partial => self.held.push(*byte),
+                BindingResult::Match(action) => {
+                    self.held.clear();
+                    if action == Action::SendPrefix {
+                        out.extend_from_slice(self.bindings.matched_prefix());
+                    }
+                    return (i + 1, Some(action));
+                }
+            }
+        }
+        (input.len(), None)
+    }
+
+    /// Returns true if some input is being held back waiting to see if
+    /// it is a keybinding.
+    pub fn is_holding(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    /// Give up on the keybinding currently being matched, adding the input
+    /// held back for it to `out`.
+    pub fn flush(&mut self, out: &mut Vec<u8>) {
+        out.append(&mut self.held);
+        self.bindings.reset();
+    }
+}
+
+/// key_code parses `src` as a single chord (i.e. "Ctrl-c" or "Space")
+/// and returns the bytes that pressing it would generate in a terminal
+/// that does not use any fancy keyboard protocol.
+pub fn key_code(src: &str) -> anyhow::Result<Vec<u8>> {
+    let tokens = Lexer::new().tokenize(src.chars()).context("tokenizing key")?;
+    let sequence = parse(tokens).context("parsing key")?;
+    match &sequence.0[..] {
+        [chord] => Ok(chord.key_codes()?.swap_remove(0)),
+        _ => Err(anyhow!("not a single key: {}", src)),
+    }
 }
 
-#[derive(Eq, PartialEq, Debug, Deserialize, Copy, Clone)]
+#[derive(Eq, PartialEq, Debug, Deserialize, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum Action {
     /// detaches the current shpool session
     Detach,
     /// does nothing, useful for testing the keybinding engine and not much else
     NoOp,
+    /// kills the current shpool session
+    Kill,
+    /// detaches and then attaches to the named session, creating it
+    /// if it does not exist yet
+    Switch(String),
+    /// switches to the session which was most recently detached from
+    Previous,
+    /// passes the first chord of the binding through to the shell, so that
+    /// a key used as a prefix for other bindings can still be typed
+    SendPrefix,
+    /// turns logging of the session's output on or off
+    ToggleLog,
+    /// detaches and shows a picker to choose a session to switch to
+    Choose,
+    /// clears the spool that gets used to redraw the screen on reattach
+    ClearSpool,
 }
 
 //
@@ -219,91 +381,242 @@ pub struct Sequence(Vec<Chord>);
 #[derive(Eq, PartialEq, Debug, Hash, Clone)]
 pub struct Chord(Vec<String>);
 
+/// The mod keys held down as part of a chord.
+#[derive(Default, Clone, Copy)]
+struct Mods {
+    shift: bool,
+    alt: bool,
+    ctrl: bool,
+}
+
+impl Mods {
+    /// The modifier parameter that xterm style escape sequences use
+    /// to encode this set of mod keys.
+    fn param(&self) -> u8 {
+        1 + self.shift as u8 + 2 * self.alt as u8 + 4 * self.ctrl as u8
+    }
+
+    fn any(&self) -> bool {
+        self.shift || self.alt || self.ctrl
+    }
+}
+
 impl Chord {
-    /// Make sure the chord is valid.
+    /// Make sure the chord is valid, and split it into its mod keys
+    /// and the key that they modify.
     ///
     /// Valid forms are:
     ///   sym
-    ///   Ctrl-sym
-    fn check_valid(&self) -> anyhow::Result<()> {
+    ///   raw
+    ///   mod-...-mod-sym
+    fn split(&self) -> anyhow::Result<(Mods, &str)> {
+        let mut mods = Mods::default();
         for key in self.0.iter() {
             if !Self::is_key(key) {
                 return Err(anyhow!("invalid chord: {}: invalid key", self));
             }
+            let held = match key.as_str() {
+                "Ctrl" => &mut mods.ctrl,
+                "Alt" | "Meta" => &mut mods.alt,
+                "Shift" => &mut mods.shift,
+                _ => continue,
+            };
+            if *held {
+                return Err(anyhow!("invalid chord: {}: {} cannot be repeated", self, key));
+            }
+            *held = true;
         }
 
-        if self.0.len() == 1 {
-            if Self::is_ctrl(&self.0[0]) {
-                return Err(anyhow!("invalid chord: {}: Ctrl is not a cord", self));
+        let Some((key, mod_keys)) = self.0.split_last() else {
+            return Err(anyhow!("invalid chord: empty"));
+        };
+        if Self::is_mod(key) {
+            return Err(anyhow!("invalid chord: {}: {} must be followed by a key", self, key));
+        }
+        if let Some(key) = mod_keys.iter().find(|k| !Self::is_mod(k)) {
+            return Err(anyhow!("invalid chord: {}: {} is not a mod key", self, key));
+        }
+        if Self::is_raw(key) && mods.any() {
+            return Err(anyhow!("invalid chord: {}: raw keys cannot have mod keys", self));
+        }
+
+        Ok((mods, key))
+    }
+
+    /// key_codes returns the byte sequences that this chord might
+    /// generate when pressed. The first one is what a terminal sends
+    /// when it is not using any fancy keyboard protocol.
+    fn key_codes(&self) -> anyhow::Result<Vec<Vec<u8>>> {
+        let (mods, key) = self.split()?;
+        let m = mods.param();
+
+        let mut codes = if let Some(raw) = key.strip_prefix('<') {
+            vec![decode_raw(raw.strip_suffix('>').unwrap_or(raw))?]
+        } else if let Some((_, fin)) = CURSOR_KEYS.iter().find(|(name, _)| *name == key) {
+            if mods.any() {
+                vec![csi(&format!("1;{m}{fin}"))]
+            } else {
+                let mut codes = vec![csi(&fin.to_string()), ss3(*fin)];
+                match key {
+                    "Home" => codes.extend([csi("1~"), csi("7~")]),
+                    "End" => codes.extend([csi("4~"), csi("8~")]),
+                    _ => {}
+                }
+                codes
             }
-        } else if self.0.len() == 2 {
-            if !Self::is_ctrl(&self.0[0]) {
-                return Err(anyhow!("invalid chord: {}: Ctrl is the only supported mod key", self));
+        } else if let Some((_, fin, n)) = PF_KEYS.iter().find(|(name, _, _)| *name == key) {
+            if mods.any() {
+                vec![csi(&format!("1;{m}{fin}"))]
+            } else {
+                vec![ss3(*fin), csi(&format!("{n}~"))]
             }
-            if Self::is_ctrl(&self.0[1]) {
-                return Err(anyhow!("invalid chord: {}: Ctrl cannot be repeated", self));
+        } else if let Some((_, n)) = TILDE_KEYS.iter().find(|(name, _)| *name == key) {
+            if mods.any() {
+                vec![csi(&format!("{n};{m}~"))]
+            } else {
+                vec![csi(&format!("{n}~"))]
             }
+        } else if let Some((_, c)) = TEXT_KEYS.iter().find(|(name, _)| *name == key) {
+            let legacy = match (key, mods.ctrl, mods.shift) {
+                ("Space", true, false) => Some(vec![0]),
+                ("Backspace", true, false) => Some(vec![8]),
+                ("Tab", false, true) => Some(csi("Z")),
+                (_, false, false) => Some(vec![*c]),
+                _ => None,
+            };
+            Self::with_other_keys(mods, legacy, *c as u32, *c as u32)
+        } else if let Some(mut c) = key.chars().next() {
+            let mut mods = mods;
+            if mods.shift {
+                if !c.is_ascii_lowercase() {
+                    return Err(anyhow!(
+                        "invalid chord: {}: Shift only goes with letters and named keys",
+                        self
+                    ));
+                }
+                c = c.to_ascii_uppercase();
+            }
+            mods.shift = c.is_ascii_uppercase();
+
+            let legacy = if mods.ctrl {
+                let ctrl_chord = format!("Ctrl-{}", c.to_ascii_lowercase());
+                CONTROL_CODES.iter().find(|(chord, _)| *chord == ctrl_chord).map(|(_, b)| vec![*b])
+            } else {
+                Some(c.to_string().into_bytes())
+            };
+            Self::with_other_keys(mods, legacy, c as u32, c.to_ascii_lowercase() as u32)
         } else {
-            return Err(anyhow!("invalid chord: {}", self));
+            vec![]
+        };
+
+        // An encoding which starts with the first one could never be
+        // matched, since we would always stop at the first one.
+        if let Some((first, rest)) = codes.split_first() {
+            let mut rest = rest.to_vec();
+            rest.retain(|code| !code.starts_with(first));
+            codes.truncate(1);
+            codes.extend(rest);
         }
-        Ok(())
+        if codes.is_empty() {
+            return Err(anyhow!("unknown key code for chord: {}", self));
+        }
+        Ok(codes)
     }
 
-    /// key_code returns the byte that this chord generates when pressed.
-    ///
-    /// Eventually, we might want to extend this to support chords that
-    /// generate multiple codes, but for now we only support single-code
-    /// chords.
-    fn key_code(&self) -> anyhow::Result<u8> {
-        self.check_valid()?;
-
-        if self.0.len() == 1 && Self::is_sym(&self.0[0]) {
-            if self.0[0] == "Space" {
-                return Ok(b' ');
+    /// Add the modifyOtherKeys and kitty protocol encodings for a key
+    /// with the given codepoint (as xterm reports it, shifted, and as kitty
+    /// reports it, unshifted) to its traditional encoding, if it has one.
+    fn with_other_keys(
+        mods: Mods,
+        legacy: Option<Vec<u8>>,
+        xterm_cp: u32,
+        kitty_cp: u32,
+    ) -> Vec<Vec<u8>> {
+        let mut codes = vec![];
+        if let Some(mut legacy) = legacy {
+            if mods.alt {
+                legacy.insert(0, ESC);
             }
-            let c = self.0[0].chars().next().unwrap();
-            return Ok(c as u32 as u8);
+            codes.push(legacy);
         }
-
-        if self.0.len() == 2 {
-            let ctrl_chord = format!("{self}");
-            for (chord, code) in CONTROL_CODES.iter() {
-                if ctrl_chord == *chord {
-                    return Ok(*code);
-                }
-            }
+        if mods.ctrl || mods.alt || (mods.shift && codes.is_empty()) {
+            let m = mods.param();
+            codes.push(csi(&format!("27;{m};{xterm_cp}~")));
+            codes.push(csi(&format!("{kitty_cp};{m}u")));
         }
-
-        Err(anyhow!("unknown key code for chord: {}", self))
+        codes
     }
 
     fn is_key(key: &str) -> bool {
-        Self::is_ctrl(key) || Self::is_sym(key)
+        Self::is_mod(key) || Self::is_sym(key) || Self::is_raw(key)
     }
 
-    fn is_ctrl(key: &str) -> bool {
-        key == "Ctrl"
+    fn is_mod(key: &str) -> bool {
+        matches!(key, "Ctrl" | "Alt" | "Meta" | "Shift")
+    }
+
+    fn is_raw(key: &str) -> bool {
+        key.len() > 2 && key.starts_with('<') && key.ends_with('>')
     }
 
     fn is_sym(key: &str) -> bool {
-        if key == "Space" {
-            return true;
+        if KEY_NAMES.contains(&key) {
+            return !Self::is_mod(key);
         }
 
-        if matches!(key, "\\" | "[" | "]" | "@" | "^" | "_" | "?") {
-            return true;
+        let mut chars = key.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => !c.is_whitespace() && !c.is_control() && c != '-' && c != '<',
+            _ => false,
         }
+    }
+}
 
-        if key.len() != 1 {
-            return false;
-        }
+const ESC: u8 = 0x1b;
+
+/// A control sequence introducer followed by `rest`.
+fn csi(rest: &str) -> Vec<u8> {
+    let mut code = vec![ESC, b'['];
+    code.extend(rest.as_bytes());
+    code
+}
 
-        let c = key.chars().next().unwrap();
+/// A single shift three sequence for the given final char.
+fn ss3(fin: char) -> Vec<u8> {
+    vec![ESC, b'O', fin as u8]
+}
 
-        // If we expanded our alphabet size a bit, we can include the
-        // uppercase letters using this method if we wanted to.
-        c.is_digit(10 + 26)
+/// Turn the inside of a raw key into the bytes it stands for.
+fn decode_raw(src: &str) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut chars = src.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('e') => bytes.push(ESC),
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('>') => bytes.push(b'>'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| anyhow!("bad \\x escape in raw key: '\\x{}'", hex))?;
+                bytes.push(byte);
+            }
+            Some(c) => return Err(anyhow!("unknown escape in raw key: '\\{}'", c)),
+            None => return Err(anyhow!("raw key ends with a lone '\\'")),
+        }
     }
+    if bytes.is_empty() {
+        return Err(anyhow!("raw key is empty"));
+    }
+    Ok(bytes)
 }
 
 impl fmt::Display for Chord {
@@ -352,7 +665,7 @@ fn parse<T: IntoIterator<Item = Token>>(tokens: T) -> anyhow::Result<Sequence> {
 //
 
 struct Lexer {
-    words_trie: Trie<char, (), HashMap<char, usize>>,
+    words: &'static [&'static str],
 }
 
 #[derive(Eq, PartialEq, Debug)]
@@ -363,61 +676,48 @@ enum Token {
 
 impl Lexer {
     fn new() -> Self {
-        let words = vec!["Ctrl", "Space"];
-        let mut words_trie = Trie::new();
-        for word in words {
-            words_trie.insert(word.chars(), ());
-        }
-        Lexer { words_trie }
+        Lexer { words: &KEY_NAMES }
     }
 
     fn tokenize<S: Iterator<Item = char>>(&self, src: S) -> anyhow::Result<Vec<Token>> {
+        let src: Vec<char> = src.collect();
         let mut tokens = vec![];
-        let mut word_chars = vec![];
-        let mut cursor = TrieCursor::Start;
-        for c in src {
+        let mut i = 0;
+        while i < src.len() {
+            let c = src[i];
             if c.is_whitespace() {
-                continue;
-            }
-
-            let new_cursor = self.words_trie.advance(cursor, c);
-            match new_cursor {
-                TrieCursor::Start => return Err(anyhow!("internal error: trie bug")),
-                TrieCursor::NoMatch => {
-                    cursor = TrieCursor::Start;
-
-                    word_chars.push(c);
-                    for c in word_chars.iter() {
-                        match *c {
-                            '-' => tokens.push(Token::Dash),
-                            '\\' => tokens.push(Token::Key(String::from("\\"))),
-                            '[' => tokens.push(Token::Key(String::from("["))),
-                            ']' => tokens.push(Token::Key(String::from("]"))),
-                            '@' => tokens.push(Token::Key(String::from("@"))),
-                            '^' => tokens.push(Token::Key(String::from("^"))),
-                            '_' => tokens.push(Token::Key(String::from("_"))),
-                            '?' => tokens.push(Token::Key(String::from("?"))),
-                            '0'..='9' => tokens.push(Token::Key(String::from(*c))),
-                            'a'..='z' => tokens.push(Token::Key(String::from(*c))),
-                            _ => return Err(anyhow!("unexpected char: '{}'", *c)),
-                        }
-                    }
-                    word_chars.clear();
-                    continue;
+                i += 1;
+            } else if c == '-' {
+                tokens.push(Token::Dash);
+                i += 1;
+            } else if c == '<' {
+                // a raw key runs until the first unescaped '>'
+                let mut j = i + 1;
+                while j < src.len() && src[j] != '>' {
+                    j += if src[j] == '\\' { 2 } else { 1 };
                 }
-                TrieCursor::Match { is_partial, .. } => {
-                    word_chars.push(c);
-                    if is_partial {
-                        cursor = new_cursor;
-                    } else {
-                        tokens.push(Token::Key(word_chars.iter().collect()));
-
-                        // reset match state
-                        cursor = TrieCursor::Start;
-                        word_chars.clear();
-                        continue;
-                    }
+                if j >= src.len() {
+                    return Err(anyhow!("unterminated raw key"));
+                }
+                let raw: String = src[i..=j].iter().collect();
+                decode_raw(&raw[1..raw.len() - 1])?;
+                tokens.push(Token::Key(raw));
+                i = j + 1;
+            } else if c.is_ascii_uppercase() {
+                // Names are only ever made of letters and digits, and must
+                // be separated from whatever follows.
+                let word: String =
+                    src[i..].iter().take_while(|c| c.is_ascii_alphanumeric()).collect();
+                if word.len() > 1 && !self.words.contains(&word.as_str()) {
+                    return Err(anyhow!("unexpected chars: '{}' is not a key name", word));
                 }
+                i += word.len();
+                tokens.push(Token::Key(word));
+            } else if c.is_control() {
+                return Err(anyhow!("unexpected char: {:?}", c));
+            } else {
+                tokens.push(Token::Key(String::from(c)));
+                i += 1;
             }
         }
 
@@ -429,6 +729,81 @@ impl Lexer {
 // Data Tables
 //
 
+/// All the multi-char key names, including the mod keys.
+const KEY_NAMES: [&str; 33] = [
+    "Ctrl",
+    "Alt",
+    "Meta",
+    "Shift",
+    "Space",
+    "Tab",
+    "Enter",
+    "Return",
+    "Esc",
+    "Escape",
+    "Backspace",
+    "Up",
+    "Down",
+    "Left",
+    "Right",
+    "Home",
+    "End",
+    "Insert",
+    "Delete",
+    "PageUp",
+    "PageDown",
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "F5",
+    "F6",
+    "F7",
+    "F8",
+    "F9",
+    "F10",
+    "F11",
+    "F12",
+];
+
+/// Keys which a plain terminal sends as a single byte, along with that
+/// byte, which is also the codepoint that keyboard protocols use for them.
+const TEXT_KEYS: [(&str, u8); 7] = [
+    ("Space", b' '),
+    ("Tab", b'\t'),
+    ("Enter", b'\r'),
+    ("Return", b'\r'),
+    ("Esc", ESC),
+    ("Escape", ESC),
+    ("Backspace", 0x7f),
+];
+
+/// Keys sent as `CSI x` (or `SS3 x` in application cursor mode), along
+/// with the final char x.
+const CURSOR_KEYS: [(&str, char); 6] =
+    [("Up", 'A'), ("Down", 'B'), ("Right", 'C'), ("Left", 'D'), ("Home", 'H'), ("End", 'F')];
+
+/// The function keys sent as `SS3 x`, along with the final char x and the
+/// number some terminals send as `CSI n ~` instead.
+const PF_KEYS: [(&str, char, u8); 4] =
+    [("F1", 'P', 11), ("F2", 'Q', 12), ("F3", 'R', 13), ("F4", 'S', 14)];
+
+/// Keys sent as `CSI n ~`, along with the number n.
+const TILDE_KEYS: [(&str, u8); 12] = [
+    ("Insert", 2),
+    ("Delete", 3),
+    ("PageUp", 5),
+    ("PageDown", 6),
+    ("F5", 15),
+    ("F6", 17),
+    ("F7", 18),
+    ("F8", 19),
+    ("F9", 20),
+    ("F10", 21),
+    ("F11", 23),
+    ("F12", 24),
+];
+
 // This table was generated experimentally by logging the key
 // codes the shpool daemon receives and pressing the Ctrl-<key>
 // combo for all the lower-case letters, numbers, some symbols,
@@ -458,8 +833,8 @@ const CONTROL_CODES: [(&str, u8); 42] = [
     ("Ctrl-u", 21),
     ("Ctrl-v", 22),
     ("Ctrl-w", 23),
-    ("Ctrl-y", 24),
-    ("Ctrl-x", 25),
+    ("Ctrl-x", 24),
+    ("Ctrl-y", 25),
     ("Ctrl-z", 26),
     ("Ctrl-@", 0),
     ("Ctrl-2", 0),
@@ -515,6 +890,11 @@ mod test {
             ),
             (vec![("Ctrl-a", Action::Detach)], vec![1], BindingResult::Match(Action::Detach)),
             (vec![("Ctrl-Space", Action::Detach)], vec![0], BindingResult::Match(Action::Detach)),
+            // Ctrl-x sends 24 and Ctrl-y sends 25. These used to be mixed up.
+            (vec![("Ctrl-x", Action::Detach)], vec![24], BindingResult::Match(Action::Detach)),
+            (vec![("Ctrl-y", Action::Detach)], vec![25], BindingResult::Match(Action::Detach)),
+            (vec![("Ctrl-x", Action::Detach)], vec![25], BindingResult::NoMatch),
+            (vec![("Ctrl-y", Action::Detach)], vec![24], BindingResult::NoMatch),
             (
                 vec![("Ctrl-Space Ctrl-d", Action::Detach)],
                 vec![0, 4],
@@ -535,6 +915,88 @@ mod test {
                 BindingResult::Match(Action::Detach),
             ),
             (vec![("Ctrl-\\ d", Action::Detach)], vec![28], BindingResult::Partial),
+            (
+                vec![("Alt-x", Action::Detach)],
+                b"\x1bx".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (vec![("Alt-x", Action::Detach)], b"\x1b".to_vec(), BindingResult::Partial),
+            (vec![("Alt-x", Action::Detach)], b"\x1by".to_vec(), BindingResult::NoMatch),
+            (
+                vec![("Meta-X", Action::Detach)],
+                b"\x1bX".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (
+                vec![("Ctrl-Alt-d", Action::Detach)],
+                b"\x1b\x04".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            // xterm modifyOtherKeys
+            (
+                vec![("Ctrl-a", Action::Detach)],
+                b"\x1b[27;5;97~".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            // the kitty keyboard protocol
+            (
+                vec![("Ctrl-a", Action::Detach)],
+                b"\x1b[97;5u".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (
+                vec![("Ctrl-Shift-a", Action::Detach)],
+                b"\x1b[97;6u".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (vec![("Ctrl-a", Action::Detach)], b"\x1b[97;6u".to_vec(), BindingResult::NoMatch),
+            (
+                vec![("Up", Action::Detach)],
+                b"\x1b[A".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (
+                vec![("Up", Action::Detach)],
+                b"\x1bOA".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (vec![("Up", Action::Detach)], b"\x1b[B".to_vec(), BindingResult::NoMatch),
+            (
+                vec![("Ctrl-Left", Action::Detach)],
+                b"\x1b[1;5D".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (
+                vec![("F1", Action::Detach)],
+                b"\x1bOP".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (
+                vec![("F12", Action::Detach)],
+                b"\x1b[24~".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (
+                vec![("Shift-F5", Action::Detach)],
+                b"\x1b[15;2~".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (
+                vec![("Shift-Tab", Action::Detach)],
+                b"\x1b[Z".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (vec![("!", Action::Detach)], b"!".to_vec(), BindingResult::Match(Action::Detach)),
+            (
+                vec![("<\\e[200~>", Action::Detach)],
+                b"\x1b[200~".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
+            (
+                vec![("Ctrl-Space F2 q", Action::Detach)],
+                b"\x00\x1bOQq".to_vec(),
+                BindingResult::Match(Action::Detach),
+            ),
         ];
 
         for (bindings_mapping, keypresses, final_output) in cases.into_iter() {
@@ -550,14 +1012,61 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_key_code() -> anyhow::Result<()> {
+        let cases = vec![
+            ("Ctrl-c", Some(&b"\x03"[..])),
+            ("Ctrl-x", Some(b"\x18")),
+            ("Ctrl-y", Some(b"\x19")),
+            ("Ctrl-m", Some(b"\r")),
+            ("Space", Some(b" ")),
+            ("q", Some(b"q")),
+            ("Ctrl-Space Ctrl-q", None),
+            ("ls", None),
+            ("Ctrl", None),
+            ("Q", Some(b"Q")),
+            ("Shift-q", Some(b"Q")),
+            ("~", Some(b"~")),
+            ("Alt-f", Some(b"\x1bf")),
+            ("Meta-Ctrl-c", Some(b"\x1b\x03")),
+            ("Ctrl-1", Some(b"\x1b[27;5;49~")),
+            ("Shift-Enter", Some(b"\x1b[27;2;13~")),
+            ("Alt-Enter", Some(b"\x1b\r")),
+            ("Ctrl-Backspace", Some(b"\x08")),
+            ("Esc", Some(b"\x1b")),
+            ("Down", Some(b"\x1b[B")),
+            ("Alt-Right", Some(b"\x1b[1;3C")),
+            ("Home", Some(b"\x1b[H")),
+            ("PageDown", Some(b"\x1b[6~")),
+            ("Ctrl-Delete", Some(b"\x1b[3;5~")),
+            ("F4", Some(b"\x1bOS")),
+            ("Ctrl-F4", Some(b"\x1b[1;5S")),
+            ("F10", Some(b"\x1b[21~")),
+            ("<\\e[1;2P>", Some(b"\x1b[1;2P")),
+            ("<a\\x00\\>>", Some(b"a\x00>")),
+            ("Shift-1", None),
+            ("Ctrl-<x>", None),
+        ];
+
+        for (src, code) in cases.into_iter() {
+            assert_eq!(key_code(src).ok().as_deref(), code, "src={src}");
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_cord_validity() -> anyhow::Result<()> {
         let cases = vec![
             ("Ctrl-x", ""),
-            ("a-a", "Ctrl is the only supported mod key"),
+            ("Ctrl-Alt-Shift-F1", ""),
+            ("a-a", "a is not a mod key"),
             ("Ctrl-a-x", "invalid chord"),
-            ("a-Ctrl", "Ctrl is the only supported mod key"),
+            ("a-Ctrl", "Ctrl must be followed by a key"),
+            ("Ctrl", "Ctrl must be followed by a key"),
             ("Ctrl-Ctrl", "Ctrl cannot be repeated"),
+            ("Alt-Meta-x", "Meta cannot be repeated"),
+            ("Alt-<\\e>", "raw keys cannot have mod keys"),
         ];
 
         let tokenizer = Lexer::new();
@@ -567,8 +1076,8 @@ mod test {
             let chord = seq.0[0].clone();
 
             if errstr.is_empty() {
-                chord.check_valid()?;
-            } else if let Err(e) = chord.check_valid() {
+                chord.split()?;
+            } else if let Err(e) = chord.split() {
                 let got = format!("{e:?}");
                 assert!(got.contains(errstr));
             } else {
@@ -651,6 +1160,22 @@ mod test {
                     Token::Key(String::from("d")),
                 ],
             ),
+            ("F1 0", vec![Token::Key(String::from("F1")), Token::Key(String::from("0"))]),
+            ("F10", vec![Token::Key(String::from("F10"))]),
+            ("A b", vec![Token::Key(String::from("A")), Token::Key(String::from("b"))]),
+            (
+                "Alt-!",
+                vec![Token::Key(String::from("Alt")), Token::Dash, Token::Key(String::from("!"))],
+            ),
+            (
+                "<\\e[>] <->",
+                vec![
+                    Token::Key(String::from("<\\e[>")),
+                    Token::Key(String::from("]")),
+                    Token::Key(String::from("<->")),
+                ],
+            ),
+            ("<\\>>", vec![Token::Key(String::from("<\\>>"))]),
         ];
 
         let tokenizer = Lexer::new();
@@ -674,9 +1199,198 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_ambiguous_bindings() {
+        let cases = vec![
+            vec![("Esc", Action::Detach), ("Alt-x", Action::Kill)],
+            vec![("Ctrl-[ d", Action::Detach), ("Up", Action::Kill)],
+            vec![("Alt-O", Action::Detach), ("F1", Action::Kill)],
+            vec![("<\\e[1>", Action::Detach), ("Ctrl-Up", Action::Kill)],
+        ];
+        for bindings in cases.into_iter() {
+            match Bindings::new(bindings) {
+                Ok(_) => panic!("expected an error"),
+                Err(err) => assert!(format!("{err:?}").contains("cannot be told apart")),
+            }
+        }
+
+        // different names for the same key are fine
+        assert!(Bindings::new(vec![("Esc d", Action::Detach), ("Ctrl-[ k", Action::Kill)]).is_ok());
+        assert!(Bindings::new(vec![("Tab", Action::Detach), ("Ctrl-i k", Action::Kill)]).is_ok());
+    }
+
+    #[test]
+    fn test_matched_prefix() -> anyhow::Result<()> {
+        let mut bindings = Bindings::new(vec![
+            ("Ctrl-a Ctrl-a", Action::SendPrefix),
+            ("Ctrl-a k", Action::Kill),
+            ("Ctrl-\\ s", Action::Switch(String::from("other"))),
+        ])?;
+        assert_eq!(bindings.matched_prefix(), &[] as &[u8]);
+
+        assert_eq!(bindings.transition(1), BindingResult::Partial);
+        assert_eq!(bindings.transition(1), BindingResult::Match(Action::SendPrefix));
+        assert_eq!(bindings.matched_prefix(), &[1]);
+
+        assert_eq!(bindings.transition(28), BindingResult::Partial);
+        assert_eq!(
+            bindings.transition(b's'),
+            BindingResult::Match(Action::Switch(String::from("other")))
+        );
+        assert_eq!(bindings.matched_prefix(), &[28]);
+
+        // the prefix gets passed along the way it was typed
+        let mut bindings = Bindings::new(vec![("F1 F1", Action::SendPrefix)])?;
+        for byte in b"\x1b[11~\x1bOP" {
+            bindings.transition(*byte);
+        }
+        assert_eq!(bindings.matched_prefix(), b"\x1b[11~");
+
+        Ok(())
+    }
+
+    /// Feed `input` through a filter in chunks of `chunk_len` bytes,
+    /// returning what came out the other side and the actions that fired.
+    fn filter_chunked(
+        bindings: Vec<(&str, Action)>,
+        input: &[u8],
+        chunk_len: usize,
+    ) -> anyhow::Result<(Vec<u8>, Vec<Action>)> {
+        let mut filter = InputFilter::new(Bindings::new(bindings)?);
+        let mut out = vec![];
+        let mut actions = vec![];
+        for chunk in input.chunks(chunk_len) {
+            let mut chunk = chunk;
+            while !chunk.is_empty() {
+                let (scanned, action) = filter.scan(chunk, &mut out);
+                chunk = &chunk[scanned..];
+                actions.extend(action);
+            }
+        }
+        filter.flush(&mut out);
+        Ok((out, actions))
+    }
+
+    fn paste_bindings() -> Vec<(&'static str, Action)> {
+        vec![
+            ("Ctrl-Space Ctrl-q", Action::Detach),
+            ("Ctrl-a Ctrl-a k", Action::Kill),
+            ("Up", Action::Previous),
+            ("Alt-z", Action::NoOp),
+            ("Ctrl-a Ctrl-a Ctrl-b", Action::SendPrefix),
+        ]
+    }
+
+    #[test]
+    fn test_input_filter_paste() -> anyhow::Result<()> {
+        // A big paste made up of lots of near misses for the bindings, with
+        // none of the bytes which could finish one off.
+        let alphabet = [0, 1, 0x1b, b'[', b'O', b'a', b'\r', b'\n', 0xff];
+        let mut state: u32 = 12345;
+        let paste: Vec<u8> = (0..8192)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                alphabet[(state >> 16) as usize % alphabet.len()]
+            })
+            .collect();
+        // make sure we are actually exercising the partial matches
+        assert!(paste.windows(2).any(|w| w == [1, 1]));
+        assert!(paste.windows(2).any(|w| w == [0x1b, b'[']));
+
+        for chunk_len in [1, 2, 3, 7, 64, 4096, 8192] {
+            let (out, actions) = filter_chunked(paste_bindings(), &paste, chunk_len)?;
+            assert_eq!(actions, vec![], "chunk_len={chunk_len}");
+            assert!(out == paste, "chunk_len={chunk_len}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_filter_matches() -> anyhow::Result<()> {
+        let cases: Vec<(&[u8], &[u8], Vec<Action>)> = vec![
+            (b"echo hi\x00\x11bye", b"echo hibye", vec![Action::Detach]),
+            (b"\x00\x00\x11", b"\x00", vec![Action::Detach]),
+            (b"ls\x01\x01k\x01\x01\x02", b"ls\x01", vec![Action::Kill, Action::SendPrefix]),
+            (b"\x1b[97;5u\x01\x1b[98;5u", b"\x1b[97;5u", vec![Action::SendPrefix]),
+            (b"\x01\x01\x01\x01k", b"\x01\x01", vec![Action::Kill]),
+            (b"vi\x1b[A\x1b[B\x1bOA", b"vi\x1b[B", vec![Action::Previous, Action::Previous]),
+            (b"\x1bz\x1by\x1b", b"\x1by\x1b", vec![Action::NoOp]),
+            (b"\x01\x01\x00\x11", b"\x01\x01", vec![Action::Detach]),
+            (b"\x01\x01\x00x\x1b[", b"\x01\x01\x00x\x1b[", vec![]),
+        ];
+
+        for (input, want_out, want_actions) in cases.into_iter() {
+            for chunk_len in 1..=input.len() {
+                let (out, actions) = filter_chunked(paste_bindings(), input, chunk_len)?;
+                assert_eq!(out, want_out, "input={input:?} chunk_len={chunk_len}");
+                assert_eq!(actions, want_actions, "input={input:?} chunk_len={chunk_len}");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_filter_flush() -> anyhow::Result<()> {
+        let mut filter = InputFilter::new(Bindings::new(paste_bindings())?);
+        let mut out = vec![];
+
+        assert_eq!(filter.scan(b"a\x01\x01", &mut out), (3, None));
+        assert_eq!(out, b"a");
+        assert!(filter.is_holding());
+
+        // giving up on the binding passes the prefix through untouched,
+        // and the next keys start from scratch
+        filter.flush(&mut out);
+        assert!(!filter.is_holding());
+        assert_eq!(out, b"a\x01\x01");
+        assert_eq!(filter.scan(b"k", &mut out), (1, None));
+        assert_eq!(out, b"a\x01\x01k");
+        assert_eq!(filter.scan(b"\x00\x11x", &mut out), (2, Some(Action::Detach)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_action_deserialize() -> anyhow::Result<()> {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            action: Action,
+        }
+
+        let cases = vec![
+            (r#"action = "detach""#, Action::Detach),
+            (r#"action = "kill""#, Action::Kill),
+            (r#"action = "previous""#, Action::Previous),
+            (r#"action = "sendprefix""#, Action::SendPrefix),
+            (r#"action = "togglelog""#, Action::ToggleLog),
+            (r#"action = "choose""#, Action::Choose),
+            (r#"action = "clearspool""#, Action::ClearSpool),
+            (r#"action = { switch = "main" }"#, Action::Switch(String::from("main"))),
+        ];
+        for (src, want) in cases.into_iter() {
+            let got: Wrapper = toml::from_str(src)?;
+            assert_eq!(got.action, want);
+        }
+        assert!(toml::from_str::<Wrapper>(r#"action = "switch""#).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_tokenize_err() -> anyhow::Result<()> {
-        let cases = vec![("CtrCtrl", "unexpected char"), ("Ctrc", "unexpected char")];
+        let cases = vec![
+            ("CtrCtrl", "unexpected char"),
+            ("Ctrc", "unexpected char"),
+            ("Ab", "unexpected char"),
+            ("F13", "unexpected char"),
+            ("Ctrl-\x07", "unexpected char"),
+            ("<\\e", "unterminated raw key"),
+            ("<>", "raw key is empty"),
+            ("<\\q>", "unknown escape"),
+            ("<\\xzz>", "bad \\x escape"),
+        ];
 
         let tokenizer = Lexer::new();
         for (src, errsubstr) in cases.into_iter() {
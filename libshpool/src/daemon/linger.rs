@@ -0,0 +1,231 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*! Lingering keeps a session around after its shell exits so that
+  you can find out how a detached job ended. Exited sessions get
+  moved out of the session table into a table of their own, holding
+  just the exit status and the final screen, where they stay until
+  they are dismissed with `shpool kill` or the linger timeout passes.
+
+  Sessions that exit with a client attached get moved over right
+  away by the attach handler. Nothing notices when a detached
+  session's shell exits though, so this module also runs a thread
+  that sweeps them out of the session table every so often.
+*/
+
+use std::{collections::HashMap, io, sync::Arc, thread, time};
+
+use parking_lot::Mutex;
+use shpool_protocol::{Session, SessionStatus};
+use tracing::{info, span, warn, Level};
+
+use super::{events, shell};
+use crate::{config, duration};
+
+/// How often to look for exited sessions and expire old ones.
+const CHECK_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// What is left of a session once its shell has exited.
+pub struct Exited {
+    pub exit_status: i32,
+    pub exited_at: time::SystemTime,
+    started_at: time::SystemTime,
+    last_connected_at: Option<time::SystemTime>,
+    last_disconnected_at: Option<time::SystemTime>,
+    cmd: Option<String>,
+    start_cmd: Option<String>,
+    /// Shared with the shell->client thread, which might still be on
+    /// its way out when the session gets retired.
+    final_screen: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+impl Exited {
+    fn new(session: &shell::Session, exit_status: i32) -> Self {
+        let timestamps = session.lifecycle_timestamps.lock();
+        Exited {
+            exit_status,
+            exited_at: time::SystemTime::now(),
+            started_at: session.started_at,
+            last_connected_at: timestamps.last_connected_at,
+            last_disconnected_at: timestamps.last_disconnected_at,
+            cmd: session.cmd.clone(),
+            start_cmd: session.start_cmd.clone(),
+            final_screen: Arc::clone(&session.final_screen),
+        }
+    }
+
+    /// Show the final screen to a client, then send it the exit status
+    /// just like it would have gotten had it been attached when the
+    /// shell exited.
+    pub fn replay<W: io::Write>(&self, mut sink: W) -> io::Result<()> {
+        let screen = self.final_screen.lock().clone();
+        if let Some(screen) = screen {
+            shell::SessionInner::write_restore_buf(&mut sink, &screen)?;
+        }
+        shell::SessionInner::write_exit_chunk(&mut sink, self.exit_status);
+        Ok(())
+    }
+
+    /// The entry to show for the session in `shpool list`.
+    pub fn to_session(&self, name: &str) -> Session {
+        let unix_ms = |t: time::SystemTime| {
+            t.duration_since(time::UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0)
+        };
+        Session {
+            name: String::from(name),
+            started_at_unix_ms: unix_ms(self.started_at),
+            last_connected_at_unix_ms: self.last_connected_at.map(unix_ms),
+            last_disconnected_at_unix_ms: self.last_disconnected_at.map(unix_ms),
+            status: SessionStatus::Disconnected,
+            exit_status: Some(self.exit_status),
+            cmd: self.cmd.clone(),
+            start_cmd: self.start_cmd.clone(),
+            exited_at_unix_ms: Some(unix_ms(self.exited_at)),
+            ..Session::default()
+        }
+    }
+}
+
+/// If the named session's shell has exited and the session should
+/// linger, move it from `shells` over to `exited`. Returns true if
+/// the session was retired.
+pub fn retire(
+    name: &str,
+    shells: &mut HashMap<String, Box<shell::Session>>,
+    exited: &mut HashMap<String, Exited>,
+    config: &config::Manager,
+    events_bus: &events::EventBus,
+) -> bool {
+    let Some(session) = shells.get(name) else {
+        return false;
+    };
+    if !lingers(config, session) {
+        return false;
+    }
+    let Some(exit_status) = session.exit_status() else {
+        return false;
+    };
+
+    info!("'{}' exited with status {}, lingering", name, exit_status);
+    if let Some(session) = shells.remove(name) {
+        exited.insert(String::from(name), Exited::new(&session, exit_status));
+    }
+    events_bus.publish(events::Event::SessionExited { session: String::from(name), exit_status });
+    true
+}
+
+/// Retire all the detached sessions whose shell has exited and whose
+/// shell->client thread has finished up.
+pub fn sweep(
+    shells: &mut HashMap<String, Box<shell::Session>>,
+    exited: &mut HashMap<String, Exited>,
+    config: &config::Manager,
+    events_bus: &events::EventBus,
+) {
+    let done: Vec<String> = shells
+        .iter()
+        .filter(|(_, session)| {
+            if !lingers(config, session) {
+                return false;
+            }
+            // Attaching takes the shells lock before the inner lock, so
+            // nobody can attach while we hold the shells lock.
+            let Some(mut inner) = session.inner.try_lock() else {
+                return false;
+            };
+            if session.exit_status().is_none() {
+                return false;
+            }
+            match inner.shell_to_client_join_h.take_if(|h| h.is_finished()) {
+                Some(h) => {
+                    match h.join() {
+                        Ok(Err(e)) => warn!("shell->client thread: {:?}", e),
+                        Err(e) => warn!("joining shell->client thread: {:?}", e),
+                        Ok(Ok(())) => {}
+                    }
+                    true
+                }
+                None => inner.shell_to_client_join_h.is_none(),
+            }
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    for name in done.into_iter() {
+        retire(&name, shells, exited, config, events_bus);
+    }
+}
+
+/// True if the session sticks around in the exited table once its shell
+/// exits.
+pub fn lingers(config: &config::Manager, session: &shell::Session) -> bool {
+    session.linger || config.get().linger.unwrap_or(false)
+}
+
+/// Run the linger thread loop. Should be invoked in a dedicated thread.
+pub fn run(
+    shells: Arc<Mutex<HashMap<String, Box<shell::Session>>>>,
+    exited: Arc<Mutex<HashMap<String, Exited>>>,
+    config: config::Manager,
+    events_bus: Arc<events::EventBus>,
+) -> anyhow::Result<()> {
+    let _s = span!(Level::INFO, "linger").entered();
+
+    // The last bad linger_timeout we complained about, so a typo doesn't
+    // flood the log.
+    let mut bad_timeout = None;
+
+    loop {
+        thread::sleep(CHECK_INTERVAL);
+
+        let timeout = match &config.get().linger_timeout {
+            Some(src) => match duration::parse(src) {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    if bad_timeout.as_ref() != Some(src) {
+                        warn!("parsing linger_timeout, ignoring it: {:?}", e);
+                        bad_timeout = Some(src.clone());
+                    }
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let _s = span!(Level::INFO, "lock(shells)").entered();
+        let mut shells = shells.lock();
+        let mut exited = exited.lock();
+        sweep(&mut shells, &mut exited, &config, &events_bus);
+
+        let Some(timeout) = timeout else {
+            continue;
+        };
+        let now = time::SystemTime::now();
+        let expired: Vec<String> = exited
+            .iter()
+            .filter(|(_, e)| e.exited_at + timeout <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in expired.into_iter() {
+            info!("forgetting about '{}', it has lingered long enough", name);
+            if let Some(e) = exited.remove(&name) {
+                events_bus.publish(events::Event::SessionRemoved {
+                    session: name,
+                    reason: events::RemovalReason::Expired,
+                    exit_status: Some(e.exit_status),
+                });
+            }
+        }
+    }
+}
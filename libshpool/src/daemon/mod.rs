@@ -12,18 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{env, os::unix::net::UnixListener, path::PathBuf};
+use std::{env, os::unix::net::UnixListener, path::PathBuf, sync::Arc};
 
 use anyhow::Context;
 use tracing::{info, instrument};
 
 use crate::{config, consts, daemonize, hooks};
 
+mod checkpoint;
 mod etc_environment;
 pub(crate) mod events;
 mod exit_notify;
 pub mod keybindings;
+mod linger;
+mod output_log;
+mod output_watch;
 mod pager;
+mod restarter;
 mod server;
 mod shell;
 mod shell_inject;
@@ -71,6 +76,15 @@ pub fn run(
     let events_socket = events::socket_path(&socket);
     let (events_bus, _events_handle) =
         events::EventBus::start(events_socket.clone()).context("starting events bus")?;
+    {
+        let events_bus = Arc::clone(&events_bus);
+        config_manager.on_reload(move |err| {
+            events_bus.publish(events::Event::ConfigReloaded {
+                ok: err.is_none(),
+                error: err.map(|e| format!("{e:#}")),
+            })
+        });
+    }
     let server =
         server::Server::new(config_manager, hooks, runtime_dir, log_level_handle, events_bus)?;
 
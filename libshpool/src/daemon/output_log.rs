@@ -0,0 +1,504 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*! Logging of session output to disk.
+
+  When output logging is turned on for a session, the shell->client thread
+  tees everything the shell writes into a log file in the session dir,
+  in one of three formats: the raw bytes, plain text with the escape
+  sequences stripped out, or an asciinema v2 cast. The log gets rotated
+  once it grows too big or too old, keeping a few of the most recent
+  rotated files around as `<log>.1`, `<log>.2`, and so on.
+
+  Rotation is only checked when there is output to write, so an idle
+  session's log can outlive its max age until the session says something.
+*/
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time,
+};
+
+use anyhow::Context;
+use serde_derive::Serialize;
+use shpool_protocol::OutputLogFormat;
+use tracing::warn;
+
+use crate::{config, duration};
+
+const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_KEEP: usize = 3;
+
+/// The fully resolved logging settings for a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// Never `Off`.
+    pub format: OutputLogFormat,
+    pub max_size: u64,
+    pub max_age: Option<time::Duration>,
+    pub keep: usize,
+}
+
+impl Settings {
+    /// Work out how a new session should log its output given the config
+    /// and the format requested in the attach header, if any. Returns None
+    /// if the session should not be logged.
+    pub fn resolve(
+        config: Option<&config::OutputLog>,
+        requested: Option<OutputLogFormat>,
+    ) -> Option<Self> {
+        let format = match (requested, config) {
+            (Some(format), _) => format,
+            (None, Some(c)) => c.format.unwrap_or(OutputLogFormat::Raw),
+            (None, None) => return None,
+        };
+        if format == OutputLogFormat::Off {
+            return None;
+        }
+        Some(Self::with_format(config, format))
+    }
+
+    /// The settings to use if logging gets switched on partway through a
+    /// session which was not being logged. Falls back to raw logs if the
+    /// config does not name a format, or turns logging off.
+    pub fn toggled_on(config: Option<&config::OutputLog>) -> Self {
+        let format = match config.and_then(|c| c.format) {
+            Some(OutputLogFormat::Off) | None => OutputLogFormat::Raw,
+            Some(format) => format,
+        };
+        Self::with_format(config, format)
+    }
+
+    fn with_format(config: Option<&config::OutputLog>, format: OutputLogFormat) -> Self {
+        let default = config::OutputLog::default();
+        let config = config.unwrap_or(&default);
+        let max_age = match config.max_age.as_ref().map(|src| duration::parse(src)) {
+            Some(Ok(d)) => Some(d),
+            Some(Err(e)) => {
+                warn!("parsing output_log.max_age, not rotating by age: {:?}", e);
+                None
+            }
+            None => None,
+        };
+        Settings {
+            format,
+            max_size: config.max_size.unwrap_or(DEFAULT_MAX_SIZE),
+            max_age,
+            keep: config.keep.unwrap_or(DEFAULT_KEEP),
+        }
+    }
+}
+
+/// The live log file for a session with the given format.
+pub fn path(session_dir: &Path, format: OutputLogFormat) -> PathBuf {
+    session_dir.join(match format {
+        OutputLogFormat::Stripped => "output.txt",
+        OutputLogFormat::Asciicast => "output.cast",
+        OutputLogFormat::Raw | OutputLogFormat::Off => "output.log",
+    })
+}
+
+/// A session's output log.
+pub enum OutputLog {
+    Raw(RotatingFile),
+    // Boxed since the escape parser is a good deal bigger than the
+    // other variants.
+    Stripped(Box<strip_ansi_escapes::Writer<RotatingFile>>),
+    Asciicast(Cast),
+}
+
+impl OutputLog {
+    /// Open (or create) the log in `session_dir` for a session whose pty
+    /// currently has the given size.
+    pub fn open(
+        session_dir: &Path,
+        settings: &Settings,
+        rows: u16,
+        cols: u16,
+    ) -> anyhow::Result<Self> {
+        fs::create_dir_all(session_dir).context("creating session dir")?;
+        let file = RotatingFile::open(path(session_dir, settings.format), settings)?;
+        Ok(match settings.format {
+            OutputLogFormat::Stripped => {
+                OutputLog::Stripped(Box::new(strip_ansi_escapes::Writer::new(file)))
+            }
+            OutputLogFormat::Asciicast => {
+                OutputLog::Asciicast(Cast::new(file, rows, cols).context("starting cast")?)
+            }
+            OutputLogFormat::Raw | OutputLogFormat::Off => OutputLog::Raw(file),
+        })
+    }
+
+    /// Record a chunk of output from the shell.
+    pub fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            OutputLog::Raw(file) => file.write_all(buf),
+            // The stripper buffers up to the end of the line, so flush to
+            // get partial lines like prompts out as well.
+            OutputLog::Stripped(w) => w.write_all(buf).and_then(|_| w.flush()),
+            OutputLog::Asciicast(cast) => cast.output(buf),
+        }
+    }
+
+    /// Record that the session's pty changed size.
+    pub fn resize(&mut self, rows: u16, cols: u16) -> io::Result<()> {
+        match self {
+            OutputLog::Asciicast(cast) => cast.resize(rows, cols),
+            OutputLog::Raw(_) | OutputLog::Stripped(_) => Ok(()),
+        }
+    }
+}
+
+/// A log file that gets rotated once it is over the size or age limit.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: fs::File,
+    /// Bytes in the live file.
+    size: u64,
+    opened_at: time::Instant,
+    max_size: u64,
+    max_age: Option<time::Duration>,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, settings: &Settings) -> anyhow::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("opening output log {:?}", path))?;
+        let size = file.metadata().context("statting output log")?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            opened_at: time::Instant::now(),
+            max_size: settings.max_size,
+            max_age: settings.max_age,
+            keep: settings.keep,
+        })
+    }
+
+    fn is_due(&self) -> bool {
+        self.size > 0
+            && (self.size >= self.max_size
+                || self.max_age.is_some_and(|age| self.opened_at.elapsed() >= age))
+    }
+
+    /// Rotate the file if it is over a limit. Returns true if it did.
+    fn rotate_if_due(&mut self) -> io::Result<bool> {
+        if !self.is_due() {
+            return Ok(false);
+        }
+        self.rotate()?;
+        Ok(true)
+    }
+
+    /// Shift `<log>.n` to `<log>.n+1` (dropping the oldest), move the live
+    /// file to `<log>.1`, and start a fresh live file.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut p = self.path.clone().into_os_string();
+            p.push(format!(".{n}"));
+            PathBuf::from(p)
+        };
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                match fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        self.opened_at = time::Instant::now();
+        Ok(())
+    }
+
+    /// Write all of `buf` to the live file, without rotating part way
+    /// through.
+    fn append(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.rotate_if_due()?;
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// An asciinema v2 cast: a header line followed by one JSON array per
+/// event, each timestamped relative to the start of the file. Every
+/// rotated file gets its own header so that each one can be replayed on
+/// its own.
+pub struct Cast {
+    file: RotatingFile,
+    started_at: time::Instant,
+    rows: u16,
+    cols: u16,
+    /// The start of a utf8 sequence which got split across reads.
+    partial: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct CastHeader {
+    version: u8,
+    width: u16,
+    height: u16,
+    timestamp: u64,
+}
+
+impl Cast {
+    fn new(mut file: RotatingFile, rows: u16, cols: u16) -> io::Result<Self> {
+        // A second header in the middle of a cast would make it
+        // unplayable, so an old log from a previous session with the same
+        // name gets rotated out of the way.
+        if file.size > 0 {
+            file.rotate()?;
+        }
+        let mut cast = Cast { file, started_at: time::Instant::now(), rows, cols, partial: vec![] };
+        cast.header()?;
+        Ok(cast)
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        self.started_at = time::Instant::now();
+        let header = CastHeader {
+            version: 2,
+            width: self.cols,
+            height: self.rows,
+            timestamp: time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        };
+        let line = serde_json::to_string(&header).map_err(io::Error::other)?;
+        self.file.append(format!("{line}\n").as_bytes())
+    }
+
+    /// Start a one-off recording at `path`, as done by `shpool record`,
+    /// replacing anything already there. Recordings are never rotated.
+    pub fn record(path: PathBuf, rows: u16, cols: u16) -> anyhow::Result<Self> {
+        fs::File::create(&path).with_context(|| format!("creating recording {:?}", path))?;
+        let settings = Settings {
+            format: OutputLogFormat::Asciicast,
+            max_size: u64::MAX,
+            max_age: None,
+            keep: 0,
+        };
+        let file = RotatingFile::open(path, &settings)?;
+        Cast::new(file, rows, cols).context("starting recording")
+    }
+
+    pub fn output(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.partial.extend_from_slice(buf);
+        // Hold back a trailing incomplete utf8 sequence until the rest of
+        // it shows up. Anything actually invalid gets replaced.
+        let complete = match std::str::from_utf8(&self.partial) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => self.partial.len(),
+        };
+        if complete == 0 {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&self.partial[..complete]).into_owned();
+        self.partial.drain(..complete);
+        self.event("o", &text)
+    }
+
+    pub fn resize(&mut self, rows: u16, cols: u16) -> io::Result<()> {
+        self.rows = rows;
+        self.cols = cols;
+        self.event("r", &format!("{cols}x{rows}"))
+    }
+
+    fn event(&mut self, code: &str, data: &str) -> io::Result<()> {
+        if self.file.rotate_if_due()? {
+            self.header()?;
+        }
+        let at = self.started_at.elapsed().as_micros() as f64 / 1_000_000.0;
+        let line = serde_json::to_string(&(at, code, data)).map_err(io::Error::other)?;
+        self.file.append(format!("{line}\n").as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings(format: OutputLogFormat, max_size: u64, keep: usize) -> Settings {
+        Settings { format, max_size, max_age: None, keep }
+    }
+
+    #[test]
+    fn resolve() {
+        assert_eq!(Settings::resolve(None, None), None);
+        assert_eq!(Settings::resolve(None, Some(OutputLogFormat::Off)), None);
+        assert_eq!(
+            Settings::resolve(None, Some(OutputLogFormat::Stripped)),
+            Some(settings(OutputLogFormat::Stripped, DEFAULT_MAX_SIZE, DEFAULT_KEEP))
+        );
+
+        let config = config::OutputLog {
+            format: Some(OutputLogFormat::Asciicast),
+            max_size: Some(100),
+            max_age: Some(String::from("1h")),
+            keep: Some(0),
+        };
+        let resolved = Settings::resolve(Some(&config), None).unwrap();
+        assert_eq!(resolved.format, OutputLogFormat::Asciicast);
+        assert_eq!(resolved.max_size, 100);
+        assert_eq!(resolved.max_age, Some(time::Duration::from_secs(3600)));
+        assert_eq!(resolved.keep, 0);
+        assert_eq!(Settings::resolve(Some(&config), Some(OutputLogFormat::Off)), None);
+        assert_eq!(
+            Settings::resolve(Some(&config), Some(OutputLogFormat::Raw)).map(|s| s.format),
+            Some(OutputLogFormat::Raw)
+        );
+        assert_eq!(
+            Settings::resolve(Some(&config::OutputLog::default()), None).map(|s| s.format),
+            Some(OutputLogFormat::Raw)
+        );
+    }
+
+    #[test]
+    fn toggled_on() {
+        assert_eq!(
+            Settings::toggled_on(None),
+            settings(OutputLogFormat::Raw, DEFAULT_MAX_SIZE, DEFAULT_KEEP)
+        );
+        let mut config = config::OutputLog {
+            format: Some(OutputLogFormat::Off),
+            max_size: Some(100),
+            ..config::OutputLog::default()
+        };
+        assert_eq!(
+            Settings::toggled_on(Some(&config)),
+            settings(OutputLogFormat::Raw, 100, DEFAULT_KEEP)
+        );
+        config.format = Some(OutputLogFormat::Stripped);
+        assert_eq!(Settings::toggled_on(Some(&config)).format, OutputLogFormat::Stripped);
+    }
+
+    #[test]
+    fn raw_rotates_by_size() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut log = OutputLog::open(dir.path(), &settings(OutputLogFormat::Raw, 4, 2), 24, 80)?;
+        for chunk in ["aaaa", "bbbb", "cccc", "dd", "d"] {
+            log.write(chunk.as_bytes())?;
+        }
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("output.log"), "ddd");
+        assert_eq!(read("output.log.1"), "cccc");
+        assert_eq!(read("output.log.2"), "bbbb");
+        assert!(!dir.path().join("output.log.3").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn rotates_by_age() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let settings = Settings {
+            format: OutputLogFormat::Raw,
+            max_size: u64::MAX,
+            max_age: Some(time::Duration::ZERO),
+            keep: 1,
+        };
+        let mut log = OutputLog::open(dir.path(), &settings, 24, 80)?;
+        log.write(b"one")?;
+        log.write(b"two")?;
+        assert_eq!(fs::read_to_string(dir.path().join("output.log"))?, "two");
+        assert_eq!(fs::read_to_string(dir.path().join("output.log.1"))?, "one");
+        Ok(())
+    }
+
+    #[test]
+    fn stripped_drops_escapes() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut log =
+            OutputLog::open(dir.path(), &settings(OutputLogFormat::Stripped, 1024, 1), 24, 80)?;
+        log.write(b"\x1b[1;32mgreen\x1b[0m text\r\n$ ")?;
+        // An escape sequence split across writes.
+        log.write(b"\x1b[3")?;
+        log.write(b"1mred\x1b[0m\r\n")?;
+        assert_eq!(fs::read_to_string(dir.path().join("output.txt"))?, "green text\n$ red\n");
+        Ok(())
+    }
+
+    #[test]
+    fn asciicast_records_output_and_resizes() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut log =
+            OutputLog::open(dir.path(), &settings(OutputLogFormat::Asciicast, 1024, 1), 24, 80)?;
+        log.write(b"hi \xe2\x9c")?;
+        log.write(b"\x93\r\n")?;
+        log.resize(30, 100)?;
+
+        let content = fs::read_to_string(dir.path().join("output.cast"))?;
+        let lines: Vec<serde_json::Value> =
+            content.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[0]["width"], 80);
+        assert_eq!(lines[0]["height"], 24);
+        assert_eq!(lines[1][1], "o");
+        assert_eq!(lines[1][2], "hi ");
+        assert_eq!(lines[2][2], "\u{2713}\r\n");
+        assert_eq!(lines[3][1], "r");
+        assert_eq!(lines[3][2], "100x30");
+        assert!(lines[3][0].as_f64().unwrap() >= lines[1][0].as_f64().unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn asciicast_gets_a_header_per_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        // Leftovers from an earlier session with the same name.
+        fs::write(dir.path().join("output.cast"), "old\n")?;
+        let mut log =
+            OutputLog::open(dir.path(), &settings(OutputLogFormat::Asciicast, 64, 2), 24, 80)?;
+        log.write(&[b'x'; 40])?;
+        log.write(b"y")?;
+
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("output.cast.2"), "old\n");
+        let first = read("output.cast.1");
+        let first: Vec<&str> = first.lines().collect();
+        let live = read("output.cast");
+        let live: Vec<&str> = live.lines().collect();
+        assert_eq!(first.len(), 2);
+        assert!(first[0].contains("\"version\":2"));
+        assert_eq!(live.len(), 2);
+        assert!(live[0].contains("\"version\":2"));
+        assert!(live[1].ends_with(",\"o\",\"y\"]"));
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*! Output watches let the rest of the daemon look at a session's
+  output as it goes by. The shell->client thread owns the pty, so
+  watches get handed to it over a control channel and it feeds every
+  chunk of output it reads to each of them, right alongside the spool
+  and the output log. A watch decides for itself when it has seen
+  enough, at which point the shell->client thread drops it.
+
+  `shpool wait-for-output` is built on a RegexWatch, which reports
+  the first match for a pattern back over a channel.
+*/
+
+use std::time;
+
+use tracing::info;
+
+/// A watch on a session's output.
+pub trait OutputWatch: Send {
+    /// Look at the next chunk of output. Returns false once the watch
+    /// has no interest in seeing any more.
+    fn feed(&mut self, output: &[u8]) -> bool;
+
+    /// Called every so often while no output is coming in. Returns
+    /// false once the watch has no interest in seeing any more.
+    fn idle(&mut self) -> bool {
+        true
+    }
+}
+
+/// Messages to the shell->client thread to manage output watches.
+pub enum Msg {
+    New {
+        id: usize,
+        watch: Box<dyn OutputWatch>,
+        /// Feed the watch what is currently on the screen before any
+        /// fresh output.
+        with_screen: bool,
+    },
+    /// Stop feeding the watch with the given id.
+    Drop(usize),
+}
+
+/// How much of an unfinished line to hang on to. Anything before
+/// that gets dropped, so a pattern can't match a line longer than
+/// this.
+const MAX_LINE_LEN: usize = 4096;
+
+/// How long the output has to stop for before a match running all the
+/// way to the end of an unfinished line counts.
+const QUIET_PERIOD: time::Duration = time::Duration::from_millis(250);
+
+/// Matches a regex against the output a line at a time, with escape
+/// codes stripped out.
+///
+/// Reads from the pty can split a line anywhere, so a match in an
+/// unfinished line only counts right away if it ends before the end
+/// of what has come in so far. Otherwise `port \d+` could match just
+/// the first digit of a port number. A match running up to the end
+/// has to wait for the newline, or for the output to go quiet for a
+/// moment, which is what lets a pattern match a prompt.
+pub struct RegexWatch {
+    re: regex::Regex,
+    /// The last unfinished line.
+    line: Vec<u8>,
+    /// When the unfinished line last grew, if it has been checked for
+    /// matches running up to its end yet.
+    pending_since: Option<time::Instant>,
+    matched: crossbeam_channel::Sender<String>,
+}
+
+impl RegexWatch {
+    pub fn new(re: regex::Regex, matched: crossbeam_channel::Sender<String>) -> Self {
+        RegexWatch { re, line: vec![], pending_since: None, matched }
+    }
+
+    /// Look for a match in the given line. Unless `to_end` is set, a
+    /// match only counts if it ends before the end of the line.
+    fn find(&self, line: &[u8], to_end: bool) -> Option<String> {
+        let line = strip_ansi_escapes::strip(line);
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches(['\r', '\n']);
+        self.re
+            .find(line)
+            .filter(|m| to_end || m.end() < line.len())
+            .map(|m| String::from(m.as_str()))
+    }
+
+    /// Report a match. Always returns false, since the watch is done.
+    fn report(&self, found: String) -> bool {
+        info!("output matched /{}/", self.re);
+        // Nobody listening anymore is just as good a reason to stop.
+        let _ = self.matched.send(found);
+        false
+    }
+}
+
+impl OutputWatch for RegexWatch {
+    fn feed(&mut self, output: &[u8]) -> bool {
+        for chunk in output.split_inclusive(|b| *b == b'\n') {
+            self.line.extend_from_slice(chunk);
+            if self.line.len() > MAX_LINE_LEN {
+                self.line.drain(..self.line.len() - MAX_LINE_LEN);
+            }
+
+            let finished = chunk.ends_with(b"\n");
+            let found = self.find(&self.line, finished);
+            if finished {
+                self.line.clear();
+            }
+            if let Some(found) = found {
+                return self.report(found);
+            }
+        }
+        self.pending_since = if self.line.is_empty() { None } else { Some(time::Instant::now()) };
+        true
+    }
+
+    fn idle(&mut self) -> bool {
+        match self.pending_since {
+            Some(since) if since.elapsed() >= QUIET_PERIOD => {
+                self.pending_since = None;
+                match self.find(&self.line, true) {
+                    Some(found) => self.report(found),
+                    None => true,
+                }
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn watch(pattern: &str) -> (RegexWatch, crossbeam_channel::Receiver<String>) {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        (RegexWatch::new(regex::Regex::new(pattern).unwrap(), tx), rx)
+    }
+
+    #[test]
+    fn regex_watch() {
+        let cases = vec![
+            ("port \\d+", vec!["listening on port 8080\r\n"], Some("port 8080")),
+            ("port \\d+", vec!["listening on po", "rt 80\r\n"], Some("port 80")),
+            // A read boundary in the middle of the match.
+            ("port \\d+", vec!["listening on port 8", "080\r\n"], Some("port 8080")),
+            ("port \\d+", vec!["listening on port 8", "080 now"], Some("port 8080")),
+            ("port \\d+", vec!["listening on port 8"], None),
+            ("^done$", vec!["not done\r\n", "done\r\n"], Some("done")),
+            ("^done$", vec!["done", "ness\r\n"], None),
+            ("^a.*b$", vec!["a\r\nb\r\n"], None),
+            ("ready>", vec!["\x1b[1;32mready\x1b[0m> "], Some("ready>")),
+            ("nope", vec!["some\r\n", "output"], None),
+        ];
+
+        for (pattern, chunks, want) in cases.into_iter() {
+            let (mut w, rx) = watch(pattern);
+            let mut wants_more = true;
+            for chunk in chunks.iter() {
+                wants_more = w.feed(chunk.as_bytes());
+                if !wants_more {
+                    break;
+                }
+            }
+            assert_eq!(wants_more, want.is_none(), "pattern={pattern} chunks={chunks:?}");
+            assert_eq!(rx.try_recv().ok().as_deref(), want, "pattern={pattern} chunks={chunks:?}");
+        }
+    }
+
+    #[test]
+    fn regex_watch_idle() {
+        let (mut w, rx) = watch("prompt> $");
+        assert!(w.feed(b"prompt> "));
+        // Too soon for the match at the end of the line to count.
+        assert!(w.idle());
+        assert!(rx.try_recv().is_err());
+
+        w.pending_since = Some(time::Instant::now() - QUIET_PERIOD);
+        assert!(!w.idle());
+        assert_eq!(rx.try_recv().ok().as_deref(), Some("prompt> "));
+    }
+
+    #[test]
+    fn regex_watch_caps_line() {
+        let (mut w, rx) = watch("^x.*z");
+        assert!(w.feed(b"x"));
+        // Push the start of the line out of the buffer, then make sure
+        // the match is only against what is left.
+        assert!(w.feed(&[b'y'; MAX_LINE_LEN]));
+        assert!(w.feed(b"z"));
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*! The restarter keeps a session's command running according to its
+  restart policy. Rather than tearing the session down when its command
+  exits, which would take the output spool and any attached clients
+  along with it, the daemon starts the command again on the same pty.
+  In between runs the daemon holds on to a handle of its own on the
+  pty's slave side, so the shell->client thread never sees the pty hang
+  up until the restarter finally gives up.
+
+  The restart loop runs on the session's child watcher thread in place
+  of the plain waitpid that sessions without a restart policy get.
+*/
+
+use std::{
+    collections::HashMap,
+    ffi::{CString, OsStr},
+    fs,
+    io::{self, Write},
+    os::unix::{ffi::OsStrExt, fs::OpenOptionsExt, process::CommandExt},
+    process,
+    sync::Arc,
+    time,
+};
+
+use anyhow::{anyhow, Context};
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use parking_lot::Mutex;
+use shpool_protocol::{AttachHeader, RestartPolicy};
+use shpool_pty::fork::Fork;
+use tracing::{info, warn};
+
+use super::{events, shell};
+use crate::{
+    common::{self, PollStrategy},
+    config, consts, duration,
+};
+
+/// How long to wait before the first restart if the restart profile
+/// doesn't say.
+const DEFAULT_BACKOFF: time::Duration = time::Duration::from_secs(1);
+
+/// The longest we will ever wait between restarts.
+const MAX_BACKOFF: time::Duration = time::Duration::from_secs(60);
+
+/// If the command manages to stay up for this long, we consider it
+/// healthy again and go back to the initial backoff.
+const HEALTHY_RUN: time::Duration = time::Duration::from_secs(60);
+
+/// How to restart a session's command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spec {
+    pub policy: RestartPolicy,
+    pub max_restarts: Option<u32>,
+    pub backoff: time::Duration,
+}
+
+impl Spec {
+    /// Figure out how to restart a new session running `cmd`, if at
+    /// all. Flags in the attach header win over the first restart_profile
+    /// whose patterns match the session.
+    pub fn resolve(config: &config::Manager, header: &AttachHeader, cmd: &str) -> Option<Self> {
+        let config = config.get();
+        let profile = config.restart_profile.iter().flatten().find(|p| {
+            p.sessions.iter().any(|pat| common::glob_match(pat, &header.name))
+                || p.cmds.iter().any(|pat| common::glob_match(pat, cmd))
+        });
+
+        let policy = header.restart.or(profile.map(|p| p.restart)).unwrap_or_default();
+        if policy == RestartPolicy::Never {
+            return None;
+        }
+        let backoff = match profile.and_then(|p| p.backoff.as_ref()) {
+            Some(src) => duration::parse(src).unwrap_or_else(|e| {
+                warn!("parsing restart backoff, using the default: {:?}", e);
+                DEFAULT_BACKOFF
+            }),
+            None => DEFAULT_BACKOFF,
+        };
+
+        Some(Spec {
+            policy,
+            max_restarts: header.max_restarts.or(profile.and_then(|p| p.max_restarts)),
+            backoff,
+        })
+    }
+
+    fn should_restart(&self, exit_status: i32, restarts: u32) -> bool {
+        let wants = match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => exit_status != 0,
+            RestartPolicy::Always => true,
+        };
+        wants && self.max_restarts.is_none_or(|max| restarts < max)
+    }
+}
+
+/// Everything the restart loop needs to start a session's command
+/// again.
+pub struct Restarter {
+    pub spec: Spec,
+    /// The command to run. It gets exec'd again in a fresh child for
+    /// every restart.
+    pub cmd: process::Command,
+    /// The path of the pty's slave side, for the new child to open.
+    pub slave_path: CString,
+    /// The daemon's own handle on the slave side, which keeps the pty
+    /// from hanging up while the command is down.
+    pub slave: fs::File,
+}
+
+impl Restarter {
+    /// Get ready to restart `cmd`, which `fork` has just started on a
+    /// fresh pty.
+    pub fn new(spec: Spec, cmd: process::Command, fork: &Fork) -> anyhow::Result<Self> {
+        let master = fork.is_parent().context("getting pty master")?;
+        let mut buf = vec![0; 1024];
+        master.ptsname_r(&mut buf).map_err(|e| anyhow!("getting pty slave name: {:?}", e))?;
+        let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+        buf.truncate(len);
+        let slave_path = CString::new(buf).context("building pty slave path")?;
+        let slave = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(OsStr::from_bytes(slave_path.as_bytes()))
+            .context("opening pty slave")?;
+        Ok(Restarter { spec, cmd, slave_path, slave })
+    }
+
+    /// Wait on the session's command, starting it again whenever it exits
+    /// until the restart policy says to stop or the session gets killed.
+    /// Returns the command's last exit status. Should be invoked in a
+    /// dedicated thread.
+    pub fn run(
+        mut self,
+        child: Arc<shell::Child>,
+        shells: Arc<Mutex<HashMap<String, Box<shell::Session>>>>,
+        events_bus: Arc<events::EventBus>,
+    ) -> i32 {
+        let mut backoff = self.spec.backoff;
+        let mut restarts = 0;
+        let mut started_at = time::Instant::now();
+        loop {
+            let exit_status = child.pid().map(wait).unwrap_or(127);
+            child.set_pid(None);
+            info!("command exited with status {}", exit_status);
+
+            if child.stopping() || !self.spec.should_restart(exit_status, restarts) {
+                return exit_status;
+            }
+
+            if started_at.elapsed() >= HEALTHY_RUN {
+                backoff = self.spec.backoff;
+            }
+            let interrupted = common::sleep_unless(
+                backoff,
+                || child.stopping(),
+                PollStrategy::Uniform { interval: time::Duration::from_millis(50) },
+            );
+            if interrupted {
+                return exit_status;
+            }
+            backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+
+            restarts += 1;
+            let _ = write!(self.slave, "\r\n[shpool] restarted (exit {exit_status})\r\n");
+
+            // Look the session up every time since it might have been renamed.
+            let session = {
+                let shells = shells.lock();
+                shells.iter().find(|(_, s)| Arc::ptr_eq(&s.child, &child)).map(|(n, _)| n.clone())
+            };
+            if let Some(session) = session {
+                info!("session '{}' restarted after exit status {}", session, exit_status);
+                events_bus.publish(events::Event::SessionRestarted {
+                    session,
+                    exit_status,
+                    restarts,
+                });
+            }
+
+            started_at = time::Instant::now();
+            match self.spawn() {
+                Ok(pid) => {
+                    child.set_pid(Some(pid));
+                    // The session might have been killed while there was
+                    // no command around to get the SIGHUP.
+                    if child.stopping() {
+                        let _ = signal::kill(Pid::from_raw(pid), Signal::SIGHUP);
+                    }
+                }
+                Err(e) => warn!("restarting command: {:?}", e),
+            }
+        }
+    }
+
+    /// Start the command in a new child which has the pty as its
+    /// controlling terminal, just like the pty crate sets up the first one.
+    fn spawn(&mut self) -> anyhow::Result<libc::pid_t> {
+        // Safety: basic ffi. Like the first child, this one goes on to exec
+        // right away.
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()).context("forking command"),
+            0 => {
+                // Safety: basic ffi on fds we own, in the freshly forked child.
+                unsafe {
+                    libc::setsid();
+                    let fd = libc::open(self.slave_path.as_ptr(), libc::O_RDWR);
+                    if fd < 0 {
+                        process::exit(1);
+                    }
+                    libc::ioctl(fd, libc::TIOCSCTTY, 0);
+                    for std_fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+                        libc::dup2(fd, std_fd);
+                    }
+                }
+                for fd in (consts::STDERR_FD + 1)..(nix::unistd::SysconfVar::OPEN_MAX as i32) {
+                    let _ = nix::unistd::close(fd);
+                }
+                let err = self.cmd.exec();
+                eprintln!("shpool: running command: {err:?}\r");
+                process::exit(127);
+            }
+            pid => Ok(pid),
+        }
+    }
+}
+
+/// Reap the command with the given pid, returning its exit status, or
+/// 128 plus the signal number if it was killed by a signal.
+fn wait(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+    loop {
+        // Safety: basic ffi, status is valid for the duration of the call.
+        match unsafe { libc::waitpid(pid, &mut status, 0) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                warn!("waiting for command: {:?}", err);
+                return 1;
+            }
+            0 => continue,
+            _ if libc::WIFEXITED(status) => return libc::WEXITSTATUS(status),
+            _ if libc::WIFSIGNALED(status) => return 128 + libc::WTERMSIG(status),
+            _ => continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spec(policy: RestartPolicy, max_restarts: Option<u32>) -> Spec {
+        Spec { policy, max_restarts, backoff: time::Duration::ZERO }
+    }
+
+    #[test]
+    fn should_restart() {
+        assert!(!spec(RestartPolicy::Never, None).should_restart(1, 0));
+        assert!(spec(RestartPolicy::OnFailure, None).should_restart(1, 0));
+        assert!(!spec(RestartPolicy::OnFailure, None).should_restart(0, 0));
+        assert!(spec(RestartPolicy::Always, None).should_restart(0, 100));
+        assert!(spec(RestartPolicy::Always, Some(2)).should_restart(0, 1));
+        assert!(!spec(RestartPolicy::Always, Some(2)).should_restart(0, 2));
+        assert!(!spec(RestartPolicy::OnFailure, Some(0)).should_restart(1, 0));
+    }
+}
@@ -16,9 +16,12 @@ use std::{
     collections::HashMap,
     env,
     ffi::OsString,
-    fs, io, net,
+    fs,
+    io::{self, Read as _, Write as _},
+    net,
     ops::Add,
     os,
+    os::fd::AsFd as _,
     os::unix::{
         fs::PermissionsExt as _,
         net::{UnixListener, UnixStream},
@@ -26,20 +29,28 @@ use std::{
     },
     path::{Path, PathBuf},
     process,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
     thread, time,
     time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, Context};
-use nix::unistd;
+use nix::{
+    poll::{self, PollFd, PollFlags, PollTimeout},
+    unistd,
+};
 use parking_lot::{ArcMutexGuard, Mutex, RawMutex};
 use shpool_protocol::{
-    AttachHeader, AttachReplyHeader, AttachStatus, ConnectHeader, DetachReply, DetachRequest,
-    KillReply, KillRequest, ListReply, LogLevel, MaybeSwitch, ModifyVarReply, ModifyVarRequest,
-    ResizeReply, Session, SessionMessageDetachReply, SessionMessageReply, SessionMessageRequest,
-    SessionMessageRequestPayload, SessionStatus, SetLogLevelReply, SetLogLevelRequest,
-    VersionHeader,
+    AttachHeader, AttachReplyHeader, AttachStatus, CaptureReply, CaptureRequest, ConnectHeader,
+    DetachReply, DetachRequest, KillReply, KillRequest, ListReply, LogLevel, MaybeSwitch,
+    ModifyVarReply, ModifyVarRequest, RenameReply, RenameRequest, ResizeReply, ResizeRequest,
+    RestartPolicy, SendInputReply, SendInputRequest, Session, SessionMessageReply,
+    SessionMessageRequest, SessionMessageRequestPayload, SessionStatus, SetLogLevelReply,
+    SetLogLevelRequest, TtlAction, TtlReply, TtlRequest, VersionHeader, WaitForOutputReply,
+    WaitForOutputRequest, WaitReply, WaitRequest, WaitUntil,
 };
 use tracing::{debug, error, info, instrument, span, warn, Level};
 
@@ -48,10 +59,11 @@ use crate::{
     config::MotdDisplayMode,
     consts,
     daemon::{
-        etc_environment, events, exit_notify::ExitNotifier, hooks, pager, pager::PagerError, shell,
-        shell_inject, show_motd, ttl_reaper,
+        checkpoint, etc_environment, events, exit_notify::ExitNotifier, hooks, keybindings, linger,
+        output_log, output_watch, pager, pager::PagerError, restarter, shell, shell_inject,
+        show_motd, ttl_reaper,
     },
-    protocol, test_hooks, tty, user,
+    protocol, template, test_hooks, tty, user,
 };
 
 const DEFAULT_INITIAL_SHELL_PATH: &str = "/usr/bin:/bin:/usr/sbin:/sbin";
@@ -64,6 +76,15 @@ const DEFAULT_PROMPT_PREFIX: &str = "shpool:$SHPOOL_SESSION_NAME ";
 // global session table lock held.
 const SESSION_MSG_TIMEOUT: time::Duration = time::Duration::from_millis(500);
 
+// How long to wait for a session's shell to take all the input from
+// `shpool send-keys` before giving up on it. A shell which is not reading
+// its input would otherwise leave the handler stuck on a full pty forever.
+const SEND_INPUT_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+// How often `shpool wait` handlers check on the shell and on whether
+// their client is still around when no events are coming in.
+const WAIT_POLL_INTERVAL: time::Duration = time::Duration::from_millis(250);
+
 pub struct Server {
     config: config::Manager,
     /// A map from shell session names to session descriptors.
@@ -73,8 +94,16 @@ pub struct Server {
     /// handle_conn can delegate to worker threads and quickly allow
     /// the main thread to become available to accept new connections.
     shells: Arc<Mutex<HashMap<String, Box<shell::Session>>>>,
+    /// Sessions left behind by a previous daemon which can be brought
+    /// back by attaching to them. Only populated when the
+    /// persist_sessions config option is set.
+    restorable: Arc<Mutex<HashMap<String, checkpoint::Restorable>>>,
+    /// Sessions whose shell has exited, kept around because the linger
+    /// config option or the --linger flag asked for it. Always lock
+    /// `shells` first if you need both.
+    exited: Arc<Mutex<HashMap<String, linger::Exited>>>,
     runtime_dir: PathBuf,
-    register_new_reapable_session: crossbeam_channel::Sender<(String, Instant)>,
+    register_new_reapable_session: crossbeam_channel::Sender<ttl_reaper::Msg>,
     hooks: Box<dyn hooks::Hooks + Send + Sync>,
     events_bus: Arc<events::EventBus>,
     daily_messenger: Arc<show_motd::DailyMessenger>,
@@ -83,6 +112,9 @@ pub struct Server {
         tracing_subscriber::registry::Registry,
     >,
     vars: Mutex<HashMap<String, String>>,
+    /// Handed to every session so that keybindings can ask us to kill
+    /// or switch sessions.
+    server_action: crossbeam_channel::Sender<shell::ServerAction>,
 }
 
 impl Server {
@@ -111,21 +143,63 @@ impl Server {
             }
         });
 
+        let restorable = Arc::new(Mutex::new(if checkpoint::enabled(&config) {
+            checkpoint::load_all(&runtime_dir.join("sessions"))
+                .context("loading session checkpoints")?
+        } else {
+            HashMap::new()
+        }));
+        thread::spawn({
+            let shells = Arc::clone(&shells);
+            let restorable = Arc::clone(&restorable);
+            let sessions_dir = runtime_dir.join("sessions");
+            let config = config.clone();
+            move || {
+                if let Err(e) = checkpoint::run(shells, restorable, sessions_dir, config) {
+                    warn!("checkpointer exited with error: {:?}", e);
+                }
+            }
+        });
+
+        // Shells from a previous daemon are gone, so their state files
+        // are just litter.
+        if let Err(e) = fs::remove_dir_all(runtime_dir.join("shell-state")) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("clearing stale shell state: {:?}", e);
+            }
+        }
+
+        let exited = Arc::new(Mutex::new(HashMap::new()));
+        thread::spawn({
+            let shells = Arc::clone(&shells);
+            let exited = Arc::clone(&exited);
+            let config = config.clone();
+            let events_bus = Arc::clone(&events_bus);
+            move || {
+                if let Err(e) = linger::run(shells, exited, config, events_bus) {
+                    warn!("linger thread exited with error: {:?}", e);
+                }
+            }
+        });
+
         let vars = Mutex::new(
             config
                 .get()
                 .var_default
                 .clone()
-                .unwrap_or(vec![])
+                .unwrap_or_default()
                 .into_iter()
                 .map(|v| (v.var, v.value))
                 .collect(),
         );
 
         let daily_messenger = Arc::new(show_motd::DailyMessenger::new(config.clone())?);
-        Ok(Arc::new(Server {
+        let (server_action_tx, server_action_rx) = crossbeam_channel::bounded(10);
+        let server = Arc::new(Server {
             config,
             shells,
+            restorable,
+            exited,
             runtime_dir,
             register_new_reapable_session: new_sess_tx,
             hooks,
@@ -133,7 +207,22 @@ impl Server {
             daily_messenger,
             log_level_handle,
             vars,
-        }))
+            server_action: server_action_tx,
+        });
+        thread::spawn({
+            let server = Arc::downgrade(&server);
+            move || {
+                for action in server_action_rx.iter() {
+                    let Some(server) = server.upgrade() else {
+                        break;
+                    };
+                    if let Err(e) = server.handle_server_action(action) {
+                        warn!("handling keybinding action: {:?}", e);
+                    }
+                }
+            }
+        });
+        Ok(server)
     }
 
     #[instrument(skip_all)]
@@ -232,6 +321,12 @@ impl Server {
             ConnectHeader::SetLogLevel(r) => self.handle_set_log_level(stream, r),
             ConnectHeader::GetVars => self.handle_get_vars(stream),
             ConnectHeader::ModifyVar(r) => self.handle_modify_var(stream, r),
+            ConnectHeader::Capture(r) => self.handle_capture(stream, r),
+            ConnectHeader::SendInput(r) => self.handle_send_input(stream, r),
+            ConnectHeader::Rename(r) => self.handle_rename(stream, r),
+            ConnectHeader::Ttl(r) => self.handle_ttl(stream, r),
+            ConnectHeader::Wait(r) => self.handle_wait(stream, r),
+            ConnectHeader::WaitForOutput(r) => self.handle_wait_for_output(stream, conn_id, r),
         }
     }
 
@@ -242,11 +337,7 @@ impl Server {
         conn_id: usize,
         header: AttachHeader,
     ) -> anyhow::Result<()> {
-        if header.name.chars().any(|c| '/' == c || c.is_whitespace())
-            || header.name == "."
-            || header.name == ".."
-            || header.name.is_empty()
-        {
+        if !valid_session_name(&header.name) {
             write_reply(
                 &mut stream,
                 AttachReplyHeader {
@@ -259,6 +350,30 @@ impl Server {
             return Ok(());
         }
 
+        if let Some(profile) = &header.keybinding_profile {
+            let known =
+                self.config.get().keybinding_profile.iter().flatten().any(|p| &p.name == profile);
+            if !known {
+                write_reply(
+                    &mut stream,
+                    AttachReplyHeader {
+                        status: AttachStatus::UnexpectedError(format!(
+                            "unknown keybinding profile '{profile}'"
+                        )),
+                    },
+                )?;
+                return Ok(());
+            }
+        }
+
+        if self.replay_exited(&mut stream, &header.name)? {
+            return Ok(());
+        }
+
+        if header.watch || (header.share && self.is_attached(&header.name)) {
+            return self.handle_shared_attach(stream, conn_id, header);
+        }
+
         let user_info = user::info().context("resolving user info")?;
         let shell_env = self.build_shell_env(&user_info, &header).context("building shell env")?;
 
@@ -341,20 +456,44 @@ impl Server {
             }
             info!("bidi stream loop finished child_done={}", child_done);
 
+            // The session might have been renamed while we were attached, so
+            // from here on we go by the name it has now.
+            let name = {
+                let _s = span!(Level::INFO, "1_lock(shells)").entered();
+                session_name_of(&self.shells.lock(), &inner)
+            };
+            let hook_name = name.as_deref().unwrap_or(&header.name);
+
             if child_done {
-                info!("'{}' exited, removing from session table", header.name);
-                if let Err(err) = self.hooks.on_shell_disconnect(&header.name) {
+                info!("'{}' exited, removing from session table", hook_name);
+                if let Err(err) = self.hooks.on_shell_disconnect(hook_name) {
                     warn!("shell_disconnect hook: {:?}", err);
                 }
 
                 {
                     let _s = span!(Level::INFO, "2_lock(shells)").entered();
                     let mut shells = self.shells.lock();
-                    // The publish below is gated on `is_some()` because a
-                    // concurrent kill or reaper may have already removed the
-                    // entry (and published) while we were waiting for the lock.
-                    if shells.remove(&header.name).is_some() {
-                        self.events_bus.publish(&events::Event::SessionRemoved);
+                    // The publish below is gated on the entry still being
+                    // there because a concurrent kill or reaper may have
+                    // already removed it (and published) while we were
+                    // waiting for the lock.
+                    if let Some(name) = session_name_of(&shells, &inner) {
+                        let lingering = linger::retire(
+                            &name,
+                            &mut shells,
+                            &mut self.exited.lock(),
+                            &self.config,
+                            &self.events_bus,
+                        );
+                        if lingering {
+                            // The session lives on in the exited table.
+                        } else if let Some(session) = shells.remove(&name) {
+                            self.events_bus.publish(events::Event::SessionRemoved {
+                                session: name,
+                                reason: events::RemovalReason::Exited,
+                                exit_status: session.exit_status(),
+                            });
+                        }
                     }
                 }
 
@@ -373,13 +512,15 @@ impl Server {
                 {
                     let _s = span!(Level::INFO, "disconnect_lock(shells)").entered();
                     let shells = self.shells.lock();
-                    if let Some(session) = shells.get(&header.name) {
-                        session.lifecycle_timestamps.lock().last_disconnected_at =
-                            Some(time::SystemTime::now());
-                        self.events_bus.publish(&events::Event::SessionDetached);
+                    if let Some(name) = session_name_of(&shells, &inner) {
+                        if let Some(session) = shells.get(&name) {
+                            session.lifecycle_timestamps.lock().last_disconnected_at =
+                                Some(time::SystemTime::now());
+                        }
+                        self.events_bus.publish(events::Event::SessionDetached { session: name });
                     }
                 }
-                if let Err(err) = self.hooks.on_client_disconnect(&header.name) {
+                if let Err(err) = self.hooks.on_client_disconnect(hook_name) {
                     warn!("client_disconnect hook: {:?}", err);
                 }
             }
@@ -392,6 +533,126 @@ impl Server {
         Ok(())
     }
 
+    /// If the named session has exited but is still lingering, show its
+    /// final screen and exit status to the client instead of attaching.
+    /// Returns true if the client has been taken care of.
+    fn replay_exited(&self, stream: &mut UnixStream, session_name: &str) -> anyhow::Result<bool> {
+        let exited = {
+            let _s = span!(Level::INFO, "replay_exited_lock(shells)").entered();
+            let mut shells = self.shells.lock();
+            let mut exited = self.exited.lock();
+            // A detached session might have exited since the linger thread
+            // last looked.
+            linger::sweep(&mut shells, &mut exited, &self.config, &self.events_bus);
+            match exited.get(session_name) {
+                Some(e) => {
+                    let mut replay = vec![];
+                    e.replay(&mut replay).context("rendering final screen")?;
+                    Some((e.exit_status, replay))
+                }
+                None => None,
+            }
+        };
+        let Some((exit_status, replay)) = exited else {
+            return Ok(false);
+        };
+
+        info!("'{}' exited with status {}, replaying its final screen", session_name, exit_status);
+        write_reply(
+            &mut *stream,
+            AttachReplyHeader {
+                status: AttachStatus::Attached {
+                    warnings: vec![format!(
+                        "session '{session_name}' exited with status {exit_status}, this is its final screen"
+                    )],
+                },
+            },
+        )?;
+        stream.write_all(&replay).context("replaying exited session")?;
+        stream.flush().context("flushing exited session replay")?;
+
+        // Wait for the client to hang up before closing our end. Closing
+        // with unread client input sitting in the socket resets the
+        // connection, which can throw away the replay before the client
+        // has read it.
+        if let Err(e) = stream.shutdown(net::Shutdown::Write) {
+            debug!("shutting down replay stream: {:?}", e);
+        }
+        if let Err(e) = stream.set_read_timeout(Some(consts::SOCK_STREAM_TIMEOUT)) {
+            debug!("setting replay stream read timeout: {:?}", e);
+        } else if let Err(e) = io::copy(stream, &mut io::sink()) {
+            debug!("waiting for client to hang up after replay: {:?}", e);
+        }
+        Ok(true)
+    }
+
+    /// True if the given session exists and has a client attached to it.
+    fn is_attached(&self, session_name: &str) -> bool {
+        let _s = span!(Level::INFO, "is_attached_lock(shells)").entered();
+        let shells = self.shells.lock();
+        shells.get(session_name).map(|s| s.inner.is_locked()).unwrap_or(false)
+    }
+
+    /// Hook up an additional client to an existing session, either as a
+    /// read-only watcher or as a writer sharing the session with the
+    /// client that is already attached. Shared clients don't count as
+    /// being attached, so they don't show up in lifecycle timestamps,
+    /// hooks, or events.
+    #[instrument(skip_all)]
+    fn handle_shared_attach(
+        &self,
+        mut stream: UnixStream,
+        conn_id: usize,
+        header: AttachHeader,
+    ) -> anyhow::Result<()> {
+        let session = {
+            let _s = span!(Level::INFO, "lock(shells)").entered();
+            let shells = self.shells.lock();
+            shells.get(&header.name).map(|s| {
+                (
+                    Arc::clone(&s.shell_to_client_ctl),
+                    Arc::clone(&s.child_exit_notifier),
+                    s.pty_master.clone(),
+                    s.cmd.clone(),
+                )
+            })
+        };
+        let Some((shell_to_client_ctl, child_exit_notifier, pty_master, cmd)) = session else {
+            info!("no session '{}' to watch", header.name);
+            write_reply(&mut stream, AttachReplyHeader { status: AttachStatus::NotFound })?;
+            return Ok(());
+        };
+        if child_exit_notifier.wait(Some(time::Duration::ZERO)).is_some() {
+            info!("session '{}' has already exited, refusing to watch", header.name);
+            write_reply(&mut stream, AttachReplyHeader { status: AttachStatus::NotFound })?;
+            return Ok(());
+        }
+
+        let (status, pty_master) = if header.watch {
+            (AttachStatus::Attached { warnings: vec![] }, None)
+        } else {
+            (AttachStatus::Shared { client_id: conn_id as u64 }, Some(pty_master))
+        };
+        write_reply(&mut stream, AttachReplyHeader { status })?;
+        let bindings = shell::ClientBindings::new(
+            &self.config,
+            &header.name,
+            cmd.as_deref(),
+            header.keybinding_profile.as_deref(),
+        )?;
+        shell::attach_shared(
+            &shell_to_client_ctl,
+            pty_master,
+            conn_id,
+            bindings,
+            stream,
+            header.local_tty_size,
+        )
+        .context("attaching shared client")?;
+        info!("shared client for '{}' finished", header.name);
+        Ok(())
+    }
+
     #[allow(clippy::type_complexity)]
     fn select_shell_desc(
         &self,
@@ -439,6 +700,8 @@ impl Server {
                             // the channel is still open so the subshell is still running
                             info!("taking over existing session inner");
                             inner.client_stream = Some(stream.try_clone()?);
+                            inner.name.clone_from(&header.name);
+                            inner.keybinding_profile.clone_from(&header.keybinding_profile);
                             session.lifecycle_timestamps.lock().last_connected_at =
                                 Some(time::SystemTime::now());
 
@@ -454,7 +717,9 @@ impl Server {
                             } else {
                                 // Reattach confirmed; the create path won't run
                                 // and clobber the entry, so it's safe to publish.
-                                self.events_bus.publish(&events::Event::SessionAttached);
+                                self.events_bus.publish(events::Event::SessionAttached {
+                                    session: header.name.clone(),
+                                });
                                 if let Err(err) = self.hooks.on_reattach(&header.name) {
                                     warn!("reattach hook: {:?}", err);
                                 }
@@ -501,6 +766,8 @@ impl Server {
                     // The stream is busy, so we just inform the client and close the stream.
                     write_reply(&mut stream, AttachReplyHeader { status: AttachStatus::Busy })?;
                     stream.shutdown(net::Shutdown::Both).context("closing stream")?;
+                    self.events_bus
+                        .publish(events::Event::SessionBusy { session: header.name.clone() });
                     if let Err(err) = self.hooks.on_busy(&header.name) {
                         warn!("busy hook: {:?}", err);
                     }
@@ -515,6 +782,11 @@ impl Server {
         if let Err(err) = self.hooks.on_new_session(&header.name) {
             warn!("new_session hook: {:?}", err);
         }
+        self.unlink_renamed_session_dir(&header.name)?;
+        let restored = self.restorable.lock().remove(&header.name);
+        if restored.is_some() {
+            info!("restoring '{}' from checkpoint", header.name);
+        }
         let motd = self.config.get().motd.clone().unwrap_or_default();
         let session = self.spawn_subshell(
             conn_id,
@@ -523,6 +795,7 @@ impl Server {
             user_info,
             shell_env,
             matches!(motd, MotdDisplayMode::Dump),
+            restored,
         )?;
 
         session.lifecycle_timestamps.lock().last_connected_at = Some(time::SystemTime::now());
@@ -532,11 +805,16 @@ impl Server {
             // If we're replacing a stale entry whose shell process is
             // gone, surface that to subscribers before announcing the
             // replacement.
-            if shells.insert(header.name.clone(), Box::new(session)).is_some() {
-                self.events_bus.publish(&events::Event::SessionRemoved);
+            if let Some(stale) = shells.insert(header.name.clone(), Box::new(session)) {
+                self.events_bus.publish(events::Event::SessionRemoved {
+                    session: header.name.clone(),
+                    reason: events::RemovalReason::Reaped,
+                    exit_status: stale.exit_status(),
+                });
             }
-            self.events_bus.publish(&events::Event::SessionCreated);
-            self.events_bus.publish(&events::Event::SessionAttached);
+            self.events_bus.publish(events::Event::SessionCreated { session: header.name.clone() });
+            self.events_bus
+                .publish(events::Event::SessionAttached { session: header.name.clone() });
         }
 
         // we unwrap to propagate the poison as an unwind
@@ -669,6 +947,10 @@ impl Server {
         };
         if let Err(e) = self.log_level_handle.modify(|filter| *filter = level_filter) {
             error!("modifying log level: {}", e);
+        } else {
+            self.events_bus.publish(events::Event::LogLevelChanged {
+                level: level_filter.to_string().to_lowercase(),
+            });
         }
 
         write_reply(&mut stream, SetLogLevelReply {}).context("writing set log level reply")?;
@@ -681,7 +963,7 @@ impl Server {
             let var_map = self.vars.lock();
             let vars: Vec<(String, String)> =
                 var_map.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
-            shpool_protocol::MaybeSwitch { switch_to: None, vars }
+            shpool_protocol::MaybeSwitch { switch_to: None, vars, choose: false, renamed_to: None }
         };
 
         write_reply(&mut stream, maybe_switch).context("writing maybe_switch reply")?;
@@ -696,22 +978,33 @@ impl Server {
     ) -> anyhow::Result<()> {
         let maybe_switch = {
             let mut vars = self.vars.lock();
-            if let Some(val) = request.val {
-                vars.insert(request.var, val);
+            if let Some(val) = request.val.clone() {
+                vars.insert(request.var.clone(), val);
             } else {
                 vars.remove(&request.var);
             }
+            // Published under the vars lock so that racing changes to the
+            // same var reach subscribers in the order they were applied.
+            self.events_bus
+                .publish(events::Event::VarChanged { var: request.var, value: request.val });
 
             MaybeSwitch {
                 switch_to: None,
                 vars: vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+                choose: false,
+                renamed_to: None,
             }
         };
 
         let mut ctls = Vec::new();
         {
             let shells = self.shells.lock();
-            for session in shells.values() {
+            for (name, session) in shells.iter() {
+                if let Some(state_file) = session.state_file.as_ref() {
+                    if let Err(e) = self.write_shell_state(name, state_file) {
+                        warn!("writing shell state for '{}': {:?}", name, e);
+                    }
+                }
                 ctls.push(Arc::clone(&session.shell_to_client_ctl));
             }
         }
@@ -728,42 +1021,137 @@ impl Server {
 
     #[instrument(skip_all)]
     fn handle_kill(&self, mut stream: UnixStream, request: KillRequest) -> anyhow::Result<()> {
+        let not_found_sessions = self.kill_sessions(request.sessions)?;
+        write_reply(&mut stream, KillReply { not_found_sessions }).context("writing kill reply")?;
+
+        Ok(())
+    }
+
+    /// Kill the given sessions, returning the ones that could not be found.
+    fn kill_sessions(&self, sessions: Vec<String>) -> anyhow::Result<Vec<String>> {
         let mut not_found_sessions = vec![];
         {
             let _s = span!(Level::INFO, "lock(shells)").entered();
             let mut shells = self.shells.lock();
+            // Make sure detached sessions which already exited get
+            // dismissed rather than killed.
+            linger::sweep(&mut shells, &mut self.exited.lock(), &self.config, &self.events_bus);
 
-            let mut to_remove = Vec::with_capacity(request.sessions.len());
-            for session in request.sessions.into_iter() {
-                if let Some(s) = shells.get(&session) {
-                    s.kill().context("killing shell proc")?;
-
+            let mut removed_any = false;
+            for session in sessions.into_iter() {
+                if shells.contains_key(&session) {
                     // we don't need to wait since the dedicated reaping thread is active
                     // even when a tty is not attached
-                    to_remove.push(session);
+                    removed_any = true;
+                    shell::kill_and_remove(
+                        &mut shells,
+                        &session,
+                        events::RemovalReason::Killed,
+                        &self.events_bus,
+                    )
+                    .context("killing shell proc")?;
+                } else if self.restorable.lock().remove(&session).is_some() {
+                    // There is no process to kill for a dead session, we just
+                    // need to forget about it.
+                    checkpoint::clear(&self.session_dir(&session));
+                } else if let Some(exited) = self.exited.lock().remove(&session) {
+                    self.events_bus.publish(events::Event::SessionRemoved {
+                        session,
+                        reason: events::RemovalReason::Dismissed,
+                        exit_status: Some(exited.exit_status),
+                    });
                 } else {
                     not_found_sessions.push(session);
                 }
             }
 
-            for session in to_remove.iter() {
-                shells.remove(session);
-                self.events_bus.publish(&events::Event::SessionRemoved);
-            }
-            if !to_remove.is_empty() {
+            if removed_any {
                 test_hooks::emit("daemon-handle-kill-removed-shells");
             }
         }
 
-        write_reply(&mut stream, KillReply { not_found_sessions }).context("writing kill reply")?;
+        Ok(not_found_sessions)
+    }
+
+    /// Carry out a keybinding action fired in one of the sessions.
+    #[instrument(skip_all, fields(s = action.session))]
+    fn handle_server_action(&self, action: shell::ServerAction) -> anyhow::Result<()> {
+        info!("handling {:?} keybinding action", action.action);
+        let session = {
+            let shells = self.shells.lock();
+            shells
+                .iter()
+                .find(|(_, s)| Arc::ptr_eq(&s.shell_to_client_ctl, &action.shell_to_client_ctl))
+                .map(|(name, _)| name.clone())
+        };
+        let Some(session) = session else {
+            return Err(anyhow!("session '{}' is gone", action.session));
+        };
+        let switch_to = match action.action {
+            keybindings::Action::Kill => {
+                self.kill_sessions(vec![session]).context("killing session")?;
+                return Ok(());
+            }
+            keybindings::Action::Switch(target) => {
+                if !valid_session_name(&target) || target == session {
+                    warn!("not switching to '{}'", target);
+                    return Ok(());
+                }
+                let shells = self.shells.lock();
+                if shells.get(&target).is_some_and(|s| s.inner.try_lock().is_none()) {
+                    warn!("not switching to '{}': it already has a terminal attached", target);
+                    return Ok(());
+                }
+                Some(target)
+            }
+            keybindings::Action::Previous => {
+                // The session we most recently detached from, skipping any
+                // that someone else has attached to since.
+                let shells = self.shells.lock();
+                let previous = shells
+                    .iter()
+                    .filter(|(name, s)| **name != session && s.inner.try_lock().is_some())
+                    .filter_map(|(name, s)| {
+                        s.lifecycle_timestamps.lock().last_disconnected_at.map(|t| (t, name))
+                    })
+                    .max()
+                    .map(|(_, name)| name.clone());
+                match previous {
+                    Some(previous) => Some(previous),
+                    None => {
+                        info!("no previous session to switch to");
+                        return Ok(());
+                    }
+                }
+            }
+            keybindings::Action::Choose => None,
+            action => return Err(anyhow!("{:?} is not a server action", action)),
+        };
 
+        let maybe_switch = MaybeSwitch {
+            choose: switch_to.is_none(),
+            switch_to,
+            vars: self.vars.lock().iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            renamed_to: None,
+        };
+        action
+            .shell_to_client_ctl
+            .lock()
+            .maybe_switch
+            .send_timeout(maybe_switch, SESSION_MSG_TIMEOUT)
+            .context("sending maybe_switch")?;
         Ok(())
     }
 
     #[instrument(skip_all)]
     fn handle_list(&self, mut stream: UnixStream) -> anyhow::Result<()> {
         let _s = span!(Level::INFO, "lock(shells)").entered();
-        let shells = self.shells.lock();
+        let mut shells = self.shells.lock();
+        let exited_sessions: Vec<Session> = {
+            let mut exited = self.exited.lock();
+            linger::sweep(&mut shells, &mut exited, &self.config, &self.events_bus);
+            exited.iter().map(|(name, e)| e.to_session(name)).collect()
+        };
         let sessions: anyhow::Result<Vec<Session>> = shells
             .iter()
             .map(|(k, v)| {
@@ -782,6 +1170,13 @@ impl Server {
                     .map(|t| t.duration_since(time::UNIX_EPOCH).map(|d| d.as_millis() as i64))
                     .transpose()?;
 
+                let ttl_expires_at_unix_ms = v
+                    .ttl_expires_at
+                    .lock()
+                    .map(|t| t.duration_since(time::UNIX_EPOCH).map(|d| d.as_millis() as i64))
+                    .transpose()?;
+                let foreground_proc = v.foreground_proc();
+
                 Ok(Session {
                     name: k.to_string(),
                     started_at_unix_ms: v.started_at.duration_since(time::UNIX_EPOCH)?.as_millis()
@@ -789,14 +1184,508 @@ impl Server {
                     last_connected_at_unix_ms,
                     last_disconnected_at_unix_ms,
                     status,
+                    pid: v.child.pid(),
+                    foreground_pid: foreground_proc.as_ref().map(|(pid, _)| *pid),
+                    foreground_cmd: foreground_proc.map(|(_, name)| name),
+                    cwd: v.cwd(),
+                    cmd: v.cmd.clone(),
+                    start_cmd: v.start_cmd.clone(),
+                    ttl_expires_at_unix_ms,
+                    tty_size: v.tty_size(),
+                    output_bytes_since_attach: Some(
+                        v.output_bytes_since_attach.load(Ordering::Relaxed),
+                    ),
+                    exited_at_unix_ms: None,
+                    restorable: false,
+                    exit_status: None,
                 })
             })
             .collect();
-        let sessions = sessions.context("collecting running session metadata")?;
+        let mut sessions = sessions.context("collecting running session metadata")?;
+        sessions.extend(exited_sessions);
+        sessions.extend(self.restorable.lock().values().map(|r| Session {
+            name: r.meta.name.clone(),
+            started_at_unix_ms: r.meta.started_at_unix_ms,
+            last_connected_at_unix_ms: r.meta.last_connected_at_unix_ms,
+            last_disconnected_at_unix_ms: r.meta.last_disconnected_at_unix_ms,
+            status: SessionStatus::Disconnected,
+            restorable: true,
+            cwd: r.meta.cwd.clone(),
+            cmd: r.meta.cmd.clone(),
+            ..Session::default()
+        }));
         write_reply(&mut stream, ListReply { sessions })?;
         Ok(())
     }
 
+    #[instrument(skip_all, fields(s = &request.session))]
+    fn handle_capture(
+        &self,
+        mut stream: UnixStream,
+        request: CaptureRequest,
+    ) -> anyhow::Result<()> {
+        let shell_to_client_ctl = {
+            let _s = span!(Level::INFO, "lock(shells)").entered();
+            let shells = self.shells.lock();
+            shells.get(&request.session).map(|s| Arc::clone(&s.shell_to_client_ctl))
+        };
+
+        let reply = if let Some(shell_to_client_ctl) = shell_to_client_ctl {
+            // The capture gets served by the shell->client thread straight
+            // out of the spool, so any attached client never notices.
+            let _s = span!(Level::INFO, "lock(shell_to_client_ctl)").entered();
+            let shell_to_client_ctl = shell_to_client_ctl.lock();
+            shell_to_client_ctl
+                .capture
+                .send_timeout(request.lines, SESSION_MSG_TIMEOUT)
+                .context("sending capture request to shell->client")?;
+            match shell_to_client_ctl
+                .capture_reply
+                .recv_timeout(SESSION_MSG_TIMEOUT)
+                .context("recving capture reply")?
+            {
+                Some(contents) => CaptureReply::Contents(contents),
+                None => CaptureReply::NoSpool,
+            }
+        } else {
+            CaptureReply::NotFound
+        };
+
+        write_reply(&mut stream, reply).context("writing capture reply")?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(s = &request.session))]
+    fn handle_send_input(
+        &self,
+        mut stream: UnixStream,
+        request: SendInputRequest,
+    ) -> anyhow::Result<()> {
+        let pty_master = {
+            let _s = span!(Level::INFO, "lock(shells)").entered();
+            let shells = self.shells.lock();
+            shells.get(&request.session).map(|s| s.pty_master.clone())
+        };
+
+        let reply = if let Some(mut pty_master) = pty_master {
+            info!("sending {} bytes of input", request.input.len());
+            // The write happens on a thread of its own so that we can stop
+            // waiting on it. If it times out, the thread stays parked on the
+            // pty until the shell reads or the session goes away.
+            let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+            thread::spawn(move || {
+                let _ = done_tx.send(pty_master.write_all(&request.input));
+            });
+            match done_rx.recv_timeout(SEND_INPUT_TIMEOUT) {
+                Ok(Ok(())) => SendInputReply::Ok,
+                Ok(Err(e)) => {
+                    warn!("writing input to pty: {:?}", e);
+                    SendInputReply::Error(e.to_string())
+                }
+                Err(_) => {
+                    warn!("timed out writing input to pty");
+                    SendInputReply::TimedOut
+                }
+            }
+        } else {
+            SendInputReply::NotFound
+        };
+
+        write_reply(&mut stream, reply).context("writing send input reply")?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(s = &request.session))]
+    fn handle_ttl(&self, mut stream: UnixStream, request: TtlRequest) -> anyhow::Result<()> {
+        let now = time::SystemTime::now();
+        let expires_at = {
+            let _s = span!(Level::INFO, "lock(shells)").entered();
+            let shells = self.shells.lock();
+            let Some(session) = shells.get(&request.session) else {
+                write_reply(&mut stream, TtlReply::NotFound).context("writing ttl reply")?;
+                return Ok(());
+            };
+            let mut expires_at = session.ttl_expires_at.lock();
+            *expires_at = match request.action {
+                TtlAction::Get => *expires_at,
+                TtlAction::Set { secs } => Some(now + Duration::from_secs(secs)),
+                TtlAction::Extend { secs } => {
+                    Some(expires_at.unwrap_or(now) + Duration::from_secs(secs))
+                }
+                TtlAction::Clear => None,
+            };
+            *expires_at
+        };
+
+        // Registering a new deadline, or cancelling, bumps the session's
+        // generation in the reaper, so the old deadline gets ignored.
+        if request.action != TtlAction::Get {
+            info!("new ttl expiry for '{}': {:?}", request.session, expires_at);
+            let msg = match expires_at {
+                Some(t) => ttl_reaper::Msg::Register(
+                    request.session,
+                    Instant::now() + t.duration_since(now).unwrap_or_default(),
+                ),
+                None => ttl_reaper::Msg::Cancel(request.session),
+            };
+            self.register_new_reapable_session.send(msg).context("sending reaper ttl msg")?;
+        }
+
+        let expires_at_unix_ms = expires_at
+            .map(|t| t.duration_since(time::UNIX_EPOCH).map(|d| d.as_millis() as i64))
+            .transpose()?;
+        write_reply(&mut stream, TtlReply::Ok { expires_at_unix_ms })
+            .context("writing ttl reply")?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(s = &request.session))]
+    fn handle_wait(&self, mut stream: UnixStream, request: WaitRequest) -> anyhow::Result<()> {
+        if let Some(reply) = self.wait_for(&stream, request)? {
+            write_reply(&mut stream, reply).context("writing wait reply")?;
+        }
+        Ok(())
+    }
+
+    /// Block until the session reaches the requested state. Returns None
+    /// if the client gives up waiting first.
+    fn wait_for(
+        &self,
+        stream: &UnixStream,
+        request: WaitRequest,
+    ) -> anyhow::Result<Option<WaitReply>> {
+        let deadline = request.timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
+        let until = request.until;
+        let mut name = request.session;
+
+        // Start watching before looking at the session so that nothing can
+        // happen to it between the look and the wait.
+        let events = self.events_bus.watch();
+        let (child_exit_notifier, attached, lingers, exit_status) = {
+            let _s = span!(Level::INFO, "lock(shells)").entered();
+            let mut shells = self.shells.lock();
+            let mut exited = self.exited.lock();
+            linger::sweep(&mut shells, &mut exited, &self.config, &self.events_bus);
+            if let Some(session) = shells.get(&name) {
+                let notifier = Arc::clone(&session.child_exit_notifier);
+                let exit_status = notifier.wait(Some(Duration::ZERO));
+                let lingers = linger::lingers(&self.config, session);
+                (Some(notifier), session.inner.try_lock().is_none(), lingers, exit_status)
+            } else if let Some(e) = exited.get(&name) {
+                (None, false, true, Some(e.exit_status))
+            } else {
+                return Ok(Some(WaitReply::NotFound));
+            }
+        };
+
+        match (until, exit_status) {
+            (WaitUntil::Exited, Some(_)) => return Ok(Some(WaitReply::Reached { exit_status })),
+            (WaitUntil::Attached | WaitUntil::Detached, Some(_)) => {
+                return Ok(Some(WaitReply::Unreachable { exit_status }))
+            }
+            (WaitUntil::Attached, None) if attached => {
+                return Ok(Some(WaitReply::Reached { exit_status: None }))
+            }
+            (WaitUntil::Detached, None) if !attached => {
+                return Ok(Some(WaitReply::Reached { exit_status: None }))
+            }
+            _ => {}
+        }
+        info!("waiting for '{}' to be {:?}", name, until);
+
+        loop {
+            let mut timeout = WAIT_POLL_INTERVAL;
+            if let Some(deadline) = deadline {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Ok(Some(WaitReply::TimedOut));
+                }
+                timeout = timeout.min(left);
+            }
+
+            match events.recv_timeout(timeout) {
+                Ok(event) => {
+                    if let Some(reply) = wait_event_reply(&mut name, until, event) {
+                        return Ok(Some(reply));
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(anyhow!("event bus stopped publishing"));
+                }
+            }
+
+            // No event fires when a detached session's shell exits, so the
+            // exit notifier has the final say on that. A dead session that
+            // doesn't linger only leaves the table once someone attaches
+            // to it, which may never happen, so it counts as removed
+            // already.
+            let exit_status =
+                child_exit_notifier.as_ref().and_then(|n| n.wait(Some(Duration::ZERO)));
+            if exit_status.is_some() {
+                match until {
+                    WaitUntil::Exited => return Ok(Some(WaitReply::Reached { exit_status })),
+                    WaitUntil::Attached | WaitUntil::Detached => {
+                        return Ok(Some(WaitReply::Unreachable { exit_status }))
+                    }
+                    WaitUntil::Removed if !lingers => {
+                        return Ok(Some(WaitReply::Reached { exit_status }))
+                    }
+                    WaitUntil::Removed => {}
+                }
+            }
+
+            if client_hung_up(stream) {
+                info!("client stopped waiting for '{}'", name);
+                return Ok(None);
+            }
+        }
+    }
+
+    #[instrument(skip_all, fields(s = &request.session))]
+    fn handle_wait_for_output(
+        &self,
+        mut stream: UnixStream,
+        conn_id: usize,
+        request: WaitForOutputRequest,
+    ) -> anyhow::Result<()> {
+        if let Some(reply) = self.wait_for_output(&stream, conn_id, request)? {
+            write_reply(&mut stream, reply).context("writing wait-for-output reply")?;
+        }
+        Ok(())
+    }
+
+    /// Block until the session's output matches the requested pattern.
+    /// Returns None if the client gives up waiting first.
+    fn wait_for_output(
+        &self,
+        stream: &UnixStream,
+        conn_id: usize,
+        request: WaitForOutputRequest,
+    ) -> anyhow::Result<Option<WaitForOutputReply>> {
+        let deadline = request.timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
+        let re = match regex::Regex::new(&request.pattern) {
+            Ok(re) => re,
+            Err(e) => return Ok(Some(WaitForOutputReply::InvalidPattern(e.to_string()))),
+        };
+
+        let (shell_to_client_ctl, child_exit_notifier) = {
+            let _s = span!(Level::INFO, "lock(shells)").entered();
+            let shells = self.shells.lock();
+            if let Some(session) = shells.get(&request.session) {
+                (Arc::clone(&session.shell_to_client_ctl), Arc::clone(&session.child_exit_notifier))
+            } else if let Some(e) = self.exited.lock().get(&request.session) {
+                return Ok(Some(WaitForOutputReply::Exited { exit_status: Some(e.exit_status) }));
+            } else {
+                return Ok(Some(WaitForOutputReply::NotFound));
+            }
+        };
+        let exited = || WaitForOutputReply::Exited {
+            exit_status: child_exit_notifier.wait(Some(WAIT_POLL_INTERVAL)),
+        };
+
+        // The watch holds the only sender, so the channel disconnects if the
+        // shell->client thread goes away without a match.
+        let (matched_tx, matched_rx) = crossbeam_channel::bounded(1);
+        let output_watch = {
+            let _s = span!(Level::INFO, "lock(shell_to_client_ctl)").entered();
+            shell_to_client_ctl.lock().output_watch.clone()
+        };
+        let watch = output_watch::RegexWatch::new(re, matched_tx);
+        let msg = output_watch::Msg::New {
+            id: conn_id,
+            watch: Box::new(watch),
+            with_screen: request.screen,
+        };
+        match output_watch.send_timeout(msg, SESSION_MSG_TIMEOUT) {
+            Ok(()) => {}
+            Err(crossbeam_channel::SendTimeoutError::Disconnected(_)) => return Ok(Some(exited())),
+            Err(crossbeam_channel::SendTimeoutError::Timeout(_)) => {
+                return Err(anyhow!("timed out sending output watch to shell->client"))
+            }
+        }
+        info!("waiting for output matching /{}/", request.pattern);
+
+        let drop_watch = || {
+            if let Err(e) =
+                output_watch.send_timeout(output_watch::Msg::Drop(conn_id), SESSION_MSG_TIMEOUT)
+            {
+                // Most likely the shell->client thread is already gone,
+                // taking the watch with it.
+                info!("dropping output watch: {:?}", e);
+            }
+        };
+        loop {
+            let mut timeout = WAIT_POLL_INTERVAL;
+            if let Some(deadline) = deadline {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    drop_watch();
+                    return Ok(Some(WaitForOutputReply::TimedOut));
+                }
+                timeout = timeout.min(left);
+            }
+
+            match matched_rx.recv_timeout(timeout) {
+                Ok(text) => return Ok(Some(WaitForOutputReply::Matched(text))),
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {}
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                    return Ok(Some(exited()))
+                }
+            }
+
+            if client_hung_up(stream) {
+                info!("client stopped waiting for output");
+                drop_watch();
+                return Ok(None);
+            }
+        }
+    }
+
+    #[instrument(skip_all, fields(s = &request.old_name))]
+    fn handle_rename(&self, mut stream: UnixStream, request: RenameRequest) -> anyhow::Result<()> {
+        let reply = self.rename_session(&request.old_name, &request.new_name)?;
+        write_reply(&mut stream, reply).context("writing rename reply")?;
+        Ok(())
+    }
+
+    fn rename_session(&self, old_name: &str, new_name: &str) -> anyhow::Result<RenameReply> {
+        if !valid_session_name(new_name) {
+            return Ok(RenameReply::InvalidName);
+        }
+
+        let (ctl, has_ttl) = {
+            let _s = span!(Level::INFO, "lock(shells)").entered();
+            let mut shells = self.shells.lock();
+            let mut restorable = self.restorable.lock();
+            if !shells.contains_key(old_name) && !restorable.contains_key(old_name) {
+                return Ok(RenameReply::NotFound);
+            }
+            if old_name == new_name {
+                return Ok(RenameReply::Ok);
+            }
+            if shells.contains_key(new_name)
+                || restorable.contains_key(new_name)
+                || self.exited.lock().contains_key(new_name)
+            {
+                return Ok(RenameReply::NameTaken);
+            }
+
+            self.move_session_dir(old_name, new_name).context("moving session dir")?;
+            let renamed = if let Some(session) = shells.remove(old_name) {
+                // If nobody is attached we can fix up the name right away,
+                // otherwise the next attach takes care of it.
+                if let Some(mut inner) = session.inner.try_lock() {
+                    inner.name = String::from(new_name);
+                }
+                if let Some(state_file) = session.state_file.as_ref() {
+                    if let Err(e) = self.write_shell_state(new_name, state_file) {
+                        warn!("writing shell state: {:?}", e);
+                    }
+                }
+                let renamed = Some((
+                    Arc::clone(&session.shell_to_client_ctl),
+                    session.ttl_expires_at.lock().is_some(),
+                ));
+                shells.insert(String::from(new_name), session);
+                renamed
+            } else if let Some(mut r) = restorable.remove(old_name) {
+                r.meta.name = String::from(new_name);
+                if let Err(e) = checkpoint::write_meta(&self.session_dir(new_name), &r.meta) {
+                    warn!("checkpointing renamed session: {:?}", e);
+                }
+                restorable.insert(String::from(new_name), r);
+                None
+            } else {
+                None
+            };
+            info!("renamed '{}' to '{}'", old_name, new_name);
+            self.events_bus.publish(events::Event::SessionRenamed {
+                session: String::from(new_name),
+                old_name: String::from(old_name),
+            });
+
+            match renamed {
+                Some(r) => r,
+                None => return Ok(RenameReply::Ok),
+            }
+        };
+
+        if has_ttl {
+            self.register_new_reapable_session
+                .send(ttl_reaper::Msg::Rename {
+                    old_name: String::from(old_name),
+                    new_name: String::from(new_name),
+                })
+                .context("sending reaper rename msg")?;
+        }
+
+        let ctl = ctl.lock();
+        ctl.rename
+            .send_timeout(
+                shell::Rename {
+                    name: String::from(new_name),
+                    session_dir: self.session_dir(new_name),
+                },
+                SESSION_MSG_TIMEOUT,
+            )
+            .context("sending rename to shell->client")?;
+        ctl.maybe_switch
+            .send_timeout(
+                MaybeSwitch {
+                    switch_to: None,
+                    vars: self.vars.lock().iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+                    choose: false,
+                    renamed_to: Some(String::from(new_name)),
+                },
+                SESSION_MSG_TIMEOUT,
+            )
+            .context("sending maybe_switch")?;
+
+        Ok(RenameReply::Ok)
+    }
+
+    /// Move everything in the old session dir over to the new one. The
+    /// shell still has paths into the old dir in its environment (most
+    /// importantly SSH_AUTH_SOCK), so the old dir gets replaced with a
+    /// link to the new one.
+    fn move_session_dir(&self, old_name: &str, new_name: &str) -> anyhow::Result<()> {
+        let old_dir = self.session_dir(old_name);
+        let new_dir = self.session_dir(new_name);
+        self.unlink_renamed_session_dir(new_name)?;
+
+        let entries = match fs::read_dir(&old_dir) {
+            Ok(e) => e,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).context("reading old session dir"),
+        };
+        // The new dir may already be there, left behind by an earlier
+        // session with the new name, in which case the files from this
+        // session win.
+        fs::create_dir_all(&new_dir).context("creating new session dir")?;
+        for entry in entries {
+            let entry = entry.context("reading old session dir entry")?;
+            fs::rename(entry.path(), new_dir.join(entry.file_name()))
+                .with_context(|| format!("moving {:?}", entry.path()))?;
+        }
+        fs::remove_dir(&old_dir).context("removing old session dir")?;
+        os::unix::fs::symlink(new_name, &old_dir).context("linking old session dir")?;
+
+        Ok(())
+    }
+
+    /// Remove the link a rename left in place of the given session's dir,
+    /// if there is one, so that a new session with that name gets a dir of
+    /// its own rather than writing into the renamed session's dir.
+    fn unlink_renamed_session_dir(&self, session_name: &str) -> anyhow::Result<()> {
+        let dir = self.session_dir(session_name);
+        if fs::symlink_metadata(&dir).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+            info!("unlinking {:?} left behind by a rename", dir);
+            fs::remove_file(&dir).context("removing renamed session dir link")?;
+        }
+        Ok(())
+    }
+
     #[instrument(skip_all, fields(s = &header.session_name))]
     fn handle_session_message(
         &self,
@@ -814,85 +1703,63 @@ impl Server {
         &self,
         header: SessionMessageRequest,
     ) -> anyhow::Result<SessionMessageReply> {
-        match header.payload {
-            SessionMessageRequestPayload::Resize(resize_request) => {
-                let pager_ctl = {
-                    let _s = span!(Level::INFO, "resize_lock_1(shells)").entered();
-                    let shells = self.shells.lock();
-                    if let Some(session) = shells.get(&header.session_name) {
-                        Arc::clone(&session.pager_ctl)
-                    } else {
-                        return Ok(SessionMessageReply::NotFound);
-                    }
-                };
-                let _s = span!(Level::INFO, "lock(pager_ctl)").entered();
-                let pager_ctl = pager_ctl.lock();
-
-                if let Some(pager_ctl) = pager_ctl.as_ref() {
-                    info!("resizing pager");
-                    pager_ctl
-                        .tty_size_change
-                        .send_timeout(resize_request.tty_size.clone(), SESSION_MSG_TIMEOUT)
-                        .context("sending tty size change to pager")?;
-                    pager_ctl
-                        .tty_size_change_ack
-                        .recv_timeout(SESSION_MSG_TIMEOUT)
-                        .context("recving tty size change ack from pager")?;
-                } else {
-                    let shell_to_client_ctl = {
-                        let _s = span!(Level::INFO, "resize_lock_2(shells)").entered();
-                        let shells = self.shells.lock();
-                        if let Some(session) = shells.get(&header.session_name) {
-                            Arc::clone(&session.shell_to_client_ctl)
-                        } else {
-                            return Ok(SessionMessageReply::NotFound);
-                        }
-                    };
-                    let _s = span!(Level::INFO, "lock(shell_to_client_ctl)").entered();
-                    let shell_to_client_ctl = shell_to_client_ctl.lock();
-
-                    shell_to_client_ctl
-                        .tty_size_change
-                        .send_timeout(resize_request.tty_size, SESSION_MSG_TIMEOUT)
-                        .context("sending tty size change to shell->client")?;
-                    shell_to_client_ctl
-                        .tty_size_change_ack
-                        .recv_timeout(SESSION_MSG_TIMEOUT)
-                        .context("recving tty size ack")?;
-                }
-
-                Ok(SessionMessageReply::Resize(ResizeReply::Ok))
+        let (pager_ctl, shell_to_client_ctl) = {
+            let _s = span!(Level::INFO, "session_message_lock(shells)").entered();
+            let shells = self.shells.lock();
+            if let Some(session) = shells.get(&header.session_name) {
+                (Arc::clone(&session.pager_ctl), Arc::clone(&session.shell_to_client_ctl))
+            } else {
+                return Ok(SessionMessageReply::NotFound);
             }
-            SessionMessageRequestPayload::Detach => {
-                let shell_to_client_ctl = {
-                    let _s = span!(Level::INFO, "detach_lock(shells)").entered();
-                    let shells = self.shells.lock();
-                    if let Some(session) = shells.get(&header.session_name) {
-                        Arc::clone(&session.shell_to_client_ctl)
-                    } else {
-                        return Ok(SessionMessageReply::NotFound);
-                    }
-                };
-                let _s = span!(Level::INFO, "lock(shell_to_client_ctl)").entered();
-                let shell_to_client_ctl = shell_to_client_ctl.lock();
-
-                shell_to_client_ctl
-                    .client_connection
-                    .send_timeout(shell::ClientConnectionMsg::Disconnect, SESSION_MSG_TIMEOUT)
-                    .context("sending client detach to shell->client")?;
-                let status = shell_to_client_ctl
-                    .client_connection_ack
+        };
+
+        // While the motd pager is up, it owns the screen, so it needs to
+        // hear about resizes from the main client instead of the shell.
+        if let SessionMessageRequestPayload::Resize(ResizeRequest { tty_size, client_id: None }) =
+            &header.payload
+        {
+            let _s = span!(Level::INFO, "lock(pager_ctl)").entered();
+            let pager_ctl = pager_ctl.lock();
+            if let Some(pager_ctl) = pager_ctl.as_ref() {
+                info!("resizing pager");
+                pager_ctl
+                    .tty_size_change
+                    .send_timeout(tty_size.clone(), SESSION_MSG_TIMEOUT)
+                    .context("sending tty size change to pager")?;
+                pager_ctl
+                    .tty_size_change_ack
                     .recv_timeout(SESSION_MSG_TIMEOUT)
-                    .context("getting client conn ack")?;
-                info!("detached session({}), status = {:?}", header.session_name, status);
-                Ok(SessionMessageReply::Detach(SessionMessageDetachReply::Ok))
+                    .context("recving tty size change ack from pager")?;
+                return Ok(SessionMessageReply::Resize(ResizeReply::Ok));
             }
         }
+
+        let mailbox = {
+            let _s = span!(Level::INFO, "lock(shell_to_client_ctl)").entered();
+            shell_to_client_ctl.lock().session_message.clone()
+        };
+        let (reply_tx, reply_rx) = crossbeam_channel::bounded(1);
+        mailbox
+            .send_timeout(
+                shell::SessionMail { payload: header.payload, reply: reply_tx },
+                SESSION_MSG_TIMEOUT,
+            )
+            .context("sending session message to shell->client")?;
+        let reply =
+            reply_rx.recv_timeout(SESSION_MSG_TIMEOUT).context("recving session message reply")?;
+        info!("session({}) message reply = {:?}", header.session_name, reply);
+
+        Ok(reply)
     }
 
     /// Spawn a subshell and return the sessession descriptor for it. The
     /// session is wrapped in an Arc so the inner session can hold a Weak
     /// back-reference to the session.
+    ///
+    /// If `restored` is provided, the subshell replaces a session from a
+    /// previous daemon, so it starts in the saved directory with the saved
+    /// command and replays the saved output spool.
+    #[allow(clippy::too_many_arguments)]
     #[instrument(skip_all)]
     fn spawn_subshell(
         &self,
@@ -902,7 +1769,15 @@ impl Server {
         user_info: &user::Info,
         shell_env: &[(OsString, OsString)],
         dump_motd_on_new_session: bool,
+        restored: Option<checkpoint::Restorable>,
     ) -> anyhow::Result<shell::Session> {
+        let (restored_meta, restored_spool) = match restored {
+            Some(r) => (Some(r.meta), Some(r.spool)),
+            None => (None, None),
+        };
+        let cmd_str =
+            header.cmd.clone().or_else(|| restored_meta.as_ref().and_then(|m| m.cmd.clone()));
+
         let shell = if let Some(s) = &self.config.get().shell {
             s.clone()
         } else {
@@ -914,16 +1789,24 @@ impl Server {
         // We will exec this command after a fork, so we want to just inherit
         // stdout/stderr/stdin. The pty crate automatically `dup2`s the file
         // descriptors for us.
-        let mut cmd = if let Some(cmd_str) = &header.cmd {
+        let mut restart_spec = None;
+        let mut cmd = if let Some(cmd_str) = &cmd_str {
             let cmd_parts = shell_words::split(cmd_str).context("parsing cmd")?;
             info!("running cmd: {:?}", cmd_parts);
             if cmd_parts.is_empty() {
                 return Err(anyhow!("no command to run"));
             }
+            restart_spec = restarter::Spec::resolve(&self.config, header, cmd_str);
+            if let Some(spec) = &restart_spec {
+                info!("restarting cmd according to {:?}", spec);
+            }
             let mut cmd = process::Command::new(&cmd_parts[0]);
             cmd.args(&cmd_parts[1..]);
             cmd
         } else {
+            if header.restart.is_some_and(|r| r != RestartPolicy::Never) {
+                warn!("only sessions with a custom cmd can be restarted, ignoring --restart");
+            }
             let mut cmd = process::Command::new(&shell);
             if self.config.get().norc.unwrap_or(false) {
                 if shell.ends_with("bash") {
@@ -937,7 +1820,9 @@ impl Server {
             cmd
         };
 
-        let start_dir = match header.dir.as_deref() {
+        let restored_dir =
+            restored_meta.as_ref().and_then(|m| m.cwd.as_deref()).filter(|d| Path::new(d).is_dir());
+        let start_dir = match restored_dir.or(header.dir.as_deref()) {
             None => user_info.home_dir.clone(),
             Some(path) => String::from(path),
         };
@@ -983,7 +1868,7 @@ impl Server {
             }
         });
 
-        if header.cmd.is_none() {
+        if cmd_str.is_none() {
             // spawn the shell as a login shell by setting
             // arg0 to be the basename of the shell path
             // proceeded with a "-". You can see sshd doing the
@@ -998,13 +1883,28 @@ impl Server {
         };
 
         let noecho = self.config.get().noecho.unwrap_or(false);
+        // A restartable command has to wait to be exec'd until we have our
+        // own handle on the pty's slave side, otherwise a command that exits
+        // right away could make the pty hang up before we get there. We let
+        // it go by closing the write end of this pipe.
+        let exec_gate = match &restart_spec {
+            Some(_) => {
+                Some(unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).context("creating exec gate")?)
+            }
+            None => None,
+        };
+
         info!("about to fork subshell noecho={}", noecho);
         let mut fork = shpool_pty::fork::Fork::from_ptmx().context("forking pty")?;
         if let Ok(slave) = fork.is_child() {
             if noecho {
                 tty::disable_echo(slave.borrow_fd()).context("disabling echo on pty")?;
             }
-            for fd in consts::STDERR_FD + 1..(nix::unistd::SysconfVar::OPEN_MAX as i32) {
+            if let Some((read_end, write_end)) = exec_gate {
+                drop(write_end);
+                let _ = fs::File::from(read_end).read(&mut [0; 1]);
+            }
+            for fd in (consts::STDERR_FD + 1)..(nix::unistd::SysconfVar::OPEN_MAX as i32) {
                 let _ = nix::unistd::close(fd);
             }
             let err = cmd.exec();
@@ -1027,12 +1927,38 @@ impl Server {
         // to read the wrong file (for example, the config file contents if the
         // config watcher reloads).
         let waitable_child_pid = fork.child_pid().ok_or(anyhow!("missing child pid"))?;
+        let child = Arc::new(shell::Child::new(waitable_child_pid));
+        let restarter = match (restart_spec, exec_gate) {
+            (Some(spec), Some((_read_end, write_end))) => {
+                let restarter = restarter::Restarter::new(spec, cmd, &fork);
+                // Let the command run whether or not that worked out.
+                drop(write_end);
+                match restarter {
+                    Ok(r) => Some(r),
+                    Err(e) => {
+                        warn!("setting up restarts, the cmd won't be restarted: {:?}", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
         let session_name = header.name.clone();
         let notifiable_child_exit_notifier = Arc::clone(&child_exit_notifier);
         let shell_to_client_child_exit_notifier = Arc::clone(&child_exit_notifier);
+        let watched_child = Arc::clone(&child);
+        let shells = Arc::clone(&self.shells);
+        let events_bus = Arc::clone(&self.events_bus);
         thread::spawn(move || {
             let _s = span!(Level::INFO, "child_watcher", s = session_name, cid = conn_id).entered();
 
+            if let Some(restarter) = restarter {
+                let status = restarter.run(watched_child, shells, events_bus);
+                info!("done restarting, child exited with status {}", status);
+                notifiable_child_exit_notifier.notify_exit(status);
+                return;
+            }
+
             let mut err = None;
             let mut status = 0;
             let mut unpacked_status = None;
@@ -1069,7 +1995,7 @@ impl Server {
 
         let prompt_prefix_is_blank =
             self.config.get().prompt_prefix.as_ref().map(|p| p.is_empty()).unwrap_or(false);
-        let supports_sentinels = header.cmd.is_none()
+        let supports_sentinels = cmd_str.is_none()
             && (header.start_cmd.as_ref().map(|c| !c.is_empty()).unwrap_or(false)
                 || !prompt_prefix_is_blank)
             && !does_not_support_sentinels(&shell);
@@ -1078,28 +2004,31 @@ impl Server {
         // Inject the prompt prefix, if any. For custom commands, avoid doing this
         // since we have no idea what the command is so the shell code probably won't
         // work.
-        if supports_sentinels {
+        let state_file = if supports_sentinels {
             info!("injecting prompt prefix");
-            let prompt_prefix = self
-                .config
-                .get()
-                .prompt_prefix
-                .clone()
-                .unwrap_or(String::from(DEFAULT_PROMPT_PREFIX));
+            let state_file =
+                self.runtime_dir.join("shell-state").join(format!("{}-{}", process::id(), conn_id));
+            if let Err(err) = self.write_shell_state(&header.name, &state_file) {
+                warn!("writing shell state: {:?}", err);
+            }
             if let Err(err) = shell_inject::maybe_setup(
                 &mut fork,
-                &prompt_prefix,
+                &self.prompt_template(),
                 header.start_cmd.as_ref().map(|c| c.as_ref()).unwrap_or(""),
-                &header.name,
+                &state_file,
             ) {
                 warn!("issue injecting prefix: {:?}", err);
             }
-        }
+            Some(state_file)
+        } else {
+            None
+        };
 
         let (client_connection_tx, client_connection_rx) = crossbeam_channel::bounded(0);
         let (client_connection_ack_tx, client_connection_ack_rx) = crossbeam_channel::bounded(0);
-        let (tty_size_change_tx, tty_size_change_rx) = crossbeam_channel::bounded(0);
-        let (tty_size_change_ack_tx, tty_size_change_ack_rx) = crossbeam_channel::bounded(0);
+        // Every message carries its own reply channel, so the mailbox can
+        // afford a bit of buffering.
+        let (session_message_tx, session_message_rx) = crossbeam_channel::bounded(10);
 
         let (heartbeat_tx, heartbeat_rx) = crossbeam_channel::bounded(0);
         let (heartbeat_ack_tx, heartbeat_ack_rx) = crossbeam_channel::bounded(0);
@@ -1108,16 +2037,37 @@ impl Server {
         // no ack chan so we can afford to buffer a bit.
         let (maybe_switch_tx, maybe_switch_rx) = crossbeam_channel::bounded(10);
 
+        // Buffered for the same reason, shared clients just fire and forget.
+        let (shared_client_tx, shared_client_rx) = crossbeam_channel::bounded(10);
+
+        let (capture_tx, capture_rx) = crossbeam_channel::bounded(0);
+        let (capture_reply_tx, capture_reply_rx) = crossbeam_channel::bounded(0);
+
+        // Buffered like shared_client, adding or dropping a watch is fire
+        // and forget.
+        let (output_watch_tx, output_watch_rx) = crossbeam_channel::bounded(10);
+
+        let (keybinding_action_tx, keybinding_action_rx) = crossbeam_channel::bounded(10);
+        let (rename_tx, rename_rx) = crossbeam_channel::bounded(10);
+
         let shell_to_client_ctl = Arc::new(Mutex::new(shell::ShellToClientCtl {
             client_connection: client_connection_tx,
             client_connection_ack: client_connection_ack_rx,
-            tty_size_change: tty_size_change_tx,
-            tty_size_change_ack: tty_size_change_ack_rx,
+            session_message: session_message_tx,
             heartbeat: heartbeat_tx,
             heartbeat_ack: heartbeat_ack_rx,
             maybe_switch: maybe_switch_tx,
+            shared_client: shared_client_tx,
+            capture: capture_tx,
+            capture_reply: capture_reply_rx,
+            output_watch: output_watch_tx,
+            keybinding_action: keybinding_action_tx,
+            rename: rename_tx,
         }));
 
+        let output_bytes_since_attach = Arc::new(AtomicU64::new(0));
+        let final_screen = Arc::new(Mutex::new(None));
+
         let mut session_inner = shell::SessionInner {
             name: header.name.clone(),
             shell_to_client_ctl: Arc::clone(&shell_to_client_ctl),
@@ -1129,8 +2079,29 @@ impl Server {
             daily_messenger: Arc::clone(&self.daily_messenger),
             needs_initial_motd_dump: dump_motd_on_new_session,
             supports_sentinels,
+            server_action: self.server_action.clone(),
+            cmd: header.cmd.clone(),
+            keybinding_profile: header.keybinding_profile.clone(),
         };
-        let child_pid = session_inner.pty_master.child_pid().ok_or(anyhow!("no child pid"))?;
+        let pty_master = session_inner.pty_master.is_parent().context("getting pty master")?;
+        let output_log_config = self.config.get().output_log.clone();
+        let output_log_settings =
+            output_log::Settings::resolve(output_log_config.as_ref(), header.output_log);
+        let output_log = output_log_settings.as_ref().and_then(|settings| {
+            let size = &header.local_tty_size;
+            match output_log::OutputLog::open(
+                &self.session_dir(&header.name),
+                settings,
+                size.rows,
+                size.cols,
+            ) {
+                Ok(log) => Some(log),
+                Err(e) => {
+                    warn!("opening output log, not logging this session: {:?}", e);
+                    None
+                }
+            }
+        });
         session_inner.shell_to_client_join_h =
             Some(session_inner.spawn_shell_to_client(shell::ShellToClientArgs {
                 conn_id,
@@ -1145,25 +2116,58 @@ impl Server {
                 },
                 client_connection: client_connection_rx,
                 client_connection_ack: client_connection_ack_tx,
-                tty_size_change: tty_size_change_rx,
-                tty_size_change_ack: tty_size_change_ack_tx,
+                session_message: session_message_rx,
+                output_bytes_since_attach: Arc::clone(&output_bytes_since_attach),
                 heartbeat: heartbeat_rx,
                 heartbeat_ack: heartbeat_ack_tx,
                 maybe_switch: maybe_switch_rx,
                 child_exit_notifier: shell_to_client_child_exit_notifier,
+                events_bus: Arc::clone(&self.events_bus),
+                shared_client: shared_client_rx,
+                capture: capture_rx,
+                capture_reply: capture_reply_tx,
+                output_watch: output_watch_rx,
+                checkpoint_dir: if checkpoint::enabled(&self.config) {
+                    Some(self.session_dir(&header.name))
+                } else {
+                    None
+                },
+                restored_spool,
+                output_log,
+                session_dir: self.session_dir(&header.name),
+                output_log_settings: output_log_settings.unwrap_or_else(|| {
+                    output_log::Settings::toggled_on(output_log_config.as_ref())
+                }),
+                keybinding_action: keybinding_action_rx,
+                rename: rename_rx,
+                state_file: state_file.clone(),
+                final_screen: Arc::clone(&final_screen),
             })?);
 
+        let ttl_expires_at =
+            header.ttl_secs.map(|ttl_secs| time::SystemTime::now() + Duration::from_secs(ttl_secs));
         if let Some(ttl_secs) = header.ttl_secs {
             info!("registering session with ttl with the reaper");
             self.register_new_reapable_session
-                .send((header.name.clone(), Instant::now().add(Duration::from_secs(ttl_secs))))
+                .send(ttl_reaper::Msg::Register(
+                    header.name.clone(),
+                    Instant::now().add(Duration::from_secs(ttl_secs)),
+                ))
                 .context("sending reapable session registration msg")?;
         }
 
         Ok(shell::Session {
             shell_to_client_ctl,
             pager_ctl: Arc::new(Mutex::new(None)),
-            child_pid,
+            child,
+            pty_master,
+            cmd: cmd_str,
+            start_cmd: header.start_cmd.clone(),
+            ttl_expires_at: Mutex::new(ttl_expires_at),
+            state_file,
+            linger: header.linger,
+            final_screen,
+            output_bytes_since_attach,
             child_exit_notifier,
             started_at: time::SystemTime::now(),
             lifecycle_timestamps: Mutex::new(shell::SessionLifecycleTimestamps::default()),
@@ -1289,6 +2293,100 @@ impl Server {
     fn session_dir<P: AsRef<Path>>(&self, session_name: P) -> PathBuf {
         self.runtime_dir.join("sessions").join(session_name)
     }
+
+    fn prompt_template(&self) -> template::Template {
+        let config = self.config.get();
+        shell_inject::prompt_template(
+            config.prompt_prefix.as_deref().unwrap_or(DEFAULT_PROMPT_PREFIX),
+            config.prompt_prefix_vars.unwrap_or(false),
+        )
+    }
+
+    /// Write out the state that the prompt hook in a session's shell picks
+    /// up, so it can follow renames and var changes.
+    fn write_shell_state(&self, session_name: &str, state_file: &Path) -> anyhow::Result<()> {
+        let prompt_vars = {
+            let vars = self.vars.lock();
+            self.prompt_template()
+                .vars()
+                .map(|var| (String::from(var), vars.get(var).cloned().unwrap_or_default()))
+                .collect()
+        };
+        shell_inject::write_state(
+            state_file,
+            &shell_inject::ShellState {
+                session_name,
+                session_dir: &self.session_dir(session_name),
+                ssh_auth_sock: &self.ssh_auth_sock_symlink(session_name),
+                prompt_vars,
+            },
+        )
+    }
+}
+
+/// Find the name of the session with the given inner, which can differ from
+/// the name a client attached with if the session has been renamed since.
+fn session_name_of(
+    shells: &HashMap<String, Box<shell::Session>>,
+    inner: &ArcMutexGuard<RawMutex, shell::SessionInner>,
+) -> Option<String> {
+    let inner = ArcMutexGuard::mutex(inner);
+    shells.iter().find(|(_, s)| Arc::ptr_eq(&s.inner, inner)).map(|(name, _)| name.clone())
+}
+
+/// Work out whether an event settles a `shpool wait` for the session
+/// called `name`, following the session through renames. Shell exits
+/// are left to the session's exit notifier, which sees them whether or
+/// not an event gets published.
+fn wait_event_reply(
+    name: &mut String,
+    until: WaitUntil,
+    event: events::Event,
+) -> Option<WaitReply> {
+    use events::Event;
+    match event {
+        Event::SessionRenamed { session, old_name } if old_name == *name => {
+            *name = session;
+            None
+        }
+        Event::SessionAttached { session } if session == *name && until == WaitUntil::Attached => {
+            Some(WaitReply::Reached { exit_status: None })
+        }
+        Event::SessionDetached { session } if session == *name && until == WaitUntil::Detached => {
+            Some(WaitReply::Reached { exit_status: None })
+        }
+        Event::SessionRemoved { session, exit_status, .. } if session == *name => match until {
+            WaitUntil::Removed => Some(WaitReply::Reached { exit_status }),
+            WaitUntil::Attached | WaitUntil::Detached => {
+                Some(WaitReply::Unreachable { exit_status })
+            }
+            WaitUntil::Exited => None,
+        },
+        _ => None,
+    }
+}
+
+/// True if the client on the other end of the stream has hung up. Only
+/// makes sense for a client that is not going to send anything more.
+fn client_hung_up(stream: &UnixStream) -> bool {
+    let mut fds = [PollFd::new(stream.as_fd(), PollFlags::POLLIN)];
+    match poll::poll(&mut fds, PollTimeout::ZERO) {
+        Ok(n) => n > 0,
+        Err(e) => {
+            warn!("polling client stream: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Session names become directory names, so they can't be blank, special
+/// directory names or contain slashes. Whitespace would make them a pain
+/// to type.
+fn valid_session_name(name: &str) -> bool {
+    !(name.chars().any(|c| '/' == c || c.is_whitespace())
+        || name == "."
+        || name == ".."
+        || name.is_empty())
 }
 
 // HACK: this is not a good way to detect shells that don't support our
@@ -13,13 +13,15 @@
 // limitations under the License.
 
 use std::{
-    io,
+    collections::HashMap,
+    fs, io,
     io::{Read, Write},
     net,
     ops::Add,
     os::unix::net::UnixStream,
+    path::PathBuf,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering},
         Arc,
     },
     thread, time,
@@ -27,15 +29,19 @@ use std::{
 };
 
 use anyhow::{anyhow, Context};
-use nix::{poll, poll::PollFlags, sys::signal, unistd::Pid};
+use nix::{poll, poll::PollFlags, sys::signal, unistd, unistd::Pid};
 use parking_lot::Mutex;
-use shpool_protocol::{Chunk, ChunkKind, MaybeSwitch, TtySize};
+use shpool_protocol::{
+    Chunk, ChunkKind, MaybeSwitch, RecordReply, RecordRequest, ResizeReply, ResizeRequest,
+    SessionMessageDetachReply, SessionMessageReply, SessionMessageRequestPayload, TtySize,
+};
 use tracing::{debug, error, info, instrument, span, trace, warn, Level};
 
 use crate::{
     common, consts,
     daemon::{
-        config, exit_notify::ExitNotifier, keybindings, pager::PagerCtl, shell_inject, show_motd,
+        checkpoint, config, events, exit_notify::ExitNotifier, keybindings, output_log,
+        output_watch, pager::PagerCtl, shell_inject, show_motd,
     },
     protocol,
     protocol::ChunkExt as _,
@@ -63,6 +69,13 @@ const SHELL_TO_CLIENT_POLL_MS: u16 = 50;
 // shell->client thread.
 const SHELL_TO_CLIENT_CTL_TIMEOUT: time::Duration = time::Duration::from_millis(300);
 
+// How many chunks of output a shared client can fall behind by before
+// we hang up on it.
+const SHARED_CLIENT_QUEUE_LEN: usize = 128;
+
+// How long a write to a shared client can block before we give up on it.
+const SHARED_CLIENT_WRITE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
 /// Timestamps tracking when sessions were last connected/disconnected.
 /// Combined behind a single lock to avoid taking multiple locks.
 #[derive(Debug, Default)]
@@ -71,12 +84,70 @@ pub struct SessionLifecycleTimestamps {
     pub last_disconnected_at: Option<time::SystemTime>,
 }
 
+/// The process at the root of a session, i.e. the shell or custom
+/// command. Sessions with a restart policy get a new one every time the
+/// restarter starts the command again.
+#[derive(Debug)]
+pub struct Child {
+    /// The child's pid, or 0 while the command is down between restarts.
+    pid: AtomicI32,
+    /// Set once the session is being killed so that the restarter lets
+    /// the command stay dead.
+    stopping: AtomicBool,
+}
+
+impl Child {
+    pub fn new(pid: libc::pid_t) -> Self {
+        Child { pid: AtomicI32::new(pid), stopping: AtomicBool::new(false) }
+    }
+
+    /// The child's pid, or None if there is no child right now.
+    pub fn pid(&self) -> Option<libc::pid_t> {
+        Some(self.pid.load(Ordering::SeqCst)).filter(|pid| *pid > 0)
+    }
+
+    pub fn set_pid(&self, pid: Option<libc::pid_t>) {
+        self.pid.store(pid.unwrap_or(0), Ordering::SeqCst);
+    }
+
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    pub fn stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+}
+
 /// Session represent a shell session
 #[derive(Debug)]
 pub struct Session {
     pub started_at: time::SystemTime,
     pub lifecycle_timestamps: Mutex<SessionLifecycleTimestamps>,
-    pub child_pid: libc::pid_t,
+    /// The process at the root of the session.
+    pub child: Arc<Child>,
+    /// A handle on the pty so that shared clients can write input to the
+    /// shell without needing to take the inner lock.
+    pub pty_master: shpool_pty::fork::Master,
+    /// The custom command the session was launched with, if any.
+    pub cmd: Option<String>,
+    /// The command injected into the shell on startup, if any.
+    pub start_cmd: Option<String>,
+    /// When the ttl reaper will kill the session, if it has a ttl.
+    /// Changed by `shpool ttl`.
+    pub ttl_expires_at: Mutex<Option<time::SystemTime>>,
+    /// The file the prompt hook in the shell reloads before each prompt,
+    /// if we managed to inject one.
+    pub state_file: Option<PathBuf>,
+    /// If true, the session lingers after its shell exits no matter
+    /// what the linger config option says.
+    pub linger: bool,
+    /// What was on the screen when the shell exited, filled in by the
+    /// shell->client thread on its way out.
+    pub final_screen: Arc<Mutex<Option<Vec<u8>>>>,
+    /// Bytes of output produced since the main client last attached.
+    /// Maintained by the shell->client thread.
+    pub output_bytes_since_attach: Arc<AtomicU64>,
     pub child_exit_notifier: Arc<ExitNotifier>,
     pub shell_to_client_ctl: Arc<Mutex<ShellToClientCtl>>,
     pub pager_ctl: Arc<Mutex<Option<PagerCtl>>>,
@@ -95,17 +166,94 @@ impl Session {
         // from a process. We can't use the normal SIGTERM graceful-shutdown
         // signal since shells just forward those to their child process,
         // but for shells SIGHUP serves as the graceful shutdown signal.
-        signal::kill(Pid::from_raw(self.child_pid), Some(signal::Signal::SIGHUP))
-            .context("sending SIGHUP to child proc")?;
+        self.child.stop();
+        if let Some(pid) = self.child.pid() {
+            signal::kill(Pid::from_raw(pid), Some(signal::Signal::SIGHUP))
+                .context("sending SIGHUP to child proc")?;
+        }
 
         if self.child_exit_notifier.wait(Some(SHELL_KILL_TIMEOUT)).is_none() {
             info!("child failed to exit within kill timeout, no longer being polite");
-            signal::kill(Pid::from_raw(self.child_pid), Some(signal::Signal::SIGKILL))
-                .context("sending SIGKILL to child proc")?;
+            if let Some(pid) = self.child.pid() {
+                signal::kill(Pid::from_raw(pid), Some(signal::Signal::SIGKILL))
+                    .context("sending SIGKILL to child proc")?;
+            }
         }
 
         Ok(())
     }
+
+    /// The shell's exit status, or None if it has not exited (or has not
+    /// been reaped yet).
+    pub fn exit_status(&self) -> Option<i32> {
+        self.child_exit_notifier.wait(Some(time::Duration::ZERO))
+    }
+
+    /// The working directory of the shell.
+    pub fn cwd(&self) -> Option<String> {
+        let pid = self.child.pid()?;
+        libproc::proc_pid::pidcwd(pid).ok().map(|p| p.to_string_lossy().into_owned())
+    }
+
+    /// The pid and name of whatever process is in the foreground of the
+    /// session's pty.
+    pub fn foreground_proc(&self) -> Option<(libc::pid_t, String)> {
+        let pgrp = unistd::tcgetpgrp(self.pty_master.borrow_fd())
+            .map_err(|e| debug!("getting foreground pgrp: {:?}", e))
+            .ok()?;
+        let name = libproc::proc_pid::name(pgrp.as_raw())
+            .map_err(|e| debug!("getting foreground proc name: {:?}", e))
+            .ok()?;
+        Some((pgrp.as_raw(), name))
+    }
+
+    /// The current size of the session's pty.
+    pub fn tty_size(&self) -> Option<TtySize> {
+        TtySize::from_fd(self.pty_master.raw_fd())
+            .map_err(|e| debug!("getting pty size: {:?}", e))
+            .ok()
+    }
+
+    /// Snapshot the metadata needed to bring this session back after
+    /// a daemon restart.
+    pub fn checkpoint_meta(&self, name: &str) -> checkpoint::SessionMeta {
+        let unix_ms = |t: time::SystemTime| {
+            t.duration_since(time::UNIX_EPOCH).map(|d| d.as_millis() as i64).ok()
+        };
+        let timestamps = self.lifecycle_timestamps.lock();
+        checkpoint::SessionMeta {
+            name: String::from(name),
+            cwd: self.cwd(),
+            cmd: self.cmd.clone(),
+            started_at_unix_ms: unix_ms(self.started_at).unwrap_or(0),
+            last_connected_at_unix_ms: timestamps.last_connected_at.and_then(unix_ms),
+            last_disconnected_at_unix_ms: timestamps.last_disconnected_at.and_then(unix_ms),
+        }
+    }
+}
+
+/// Kill the named session, take it out of the session table and let
+/// everyone listening for events know why it is gone. The session gets
+/// removed even if killing it fails, since a shell we can't signal is
+/// most likely already dead, but the error still gets passed back.
+/// Does nothing if there is no such session.
+pub fn kill_and_remove(
+    shells: &mut HashMap<String, Box<Session>>,
+    name: &str,
+    reason: events::RemovalReason,
+    events_bus: &events::EventBus,
+) -> anyhow::Result<()> {
+    let Some(session) = shells.get(name) else {
+        return Ok(());
+    };
+    let killed = session.kill();
+    let exit_status = shells.remove(name).and_then(|s| s.exit_status());
+    events_bus.publish(events::Event::SessionRemoved {
+        session: String::from(name),
+        reason,
+        exit_status,
+    });
+    killed
 }
 
 /// ShellSessionInner contains values that the pipe thread needs to be
@@ -121,6 +269,14 @@ pub struct SessionInner {
     pub daily_messenger: Arc<show_motd::DailyMessenger>,
     pub needs_initial_motd_dump: bool,
     pub supports_sentinels: bool,
+    /// Where to send keybinding actions that need the whole session
+    /// table to carry out, like killing or switching sessions.
+    pub server_action: crossbeam_channel::Sender<ServerAction>,
+    /// The custom command the session was launched with, if any. Used
+    /// to pick a keybinding profile.
+    pub cmd: Option<String>,
+    /// The keybinding profile the attached client asked for, if any.
+    pub keybinding_profile: Option<String>,
 
     /// The join handle for the always-on background shell->client thread.
     /// Only wrapped in an option so we can spawn the thread after
@@ -174,6 +330,268 @@ where
     })
 }
 
+/// Build the keybinding engine for a client of the given session. The
+/// bindings come from the profile the client asked for, or else the first
+/// profile whose patterns match the session, or else the top level ones,
+/// falling back to the default detach binding if none are set.
+fn compile_bindings(
+    config: &config::Manager,
+    session: &str,
+    cmd: Option<&str>,
+    profile: Option<&str>,
+) -> anyhow::Result<keybindings::Bindings> {
+    let config = config.get();
+    let profiles = config.keybinding_profile.as_deref().unwrap_or_default();
+    let requested = profile.and_then(|name| {
+        let found = profiles.iter().find(|p| p.name == name);
+        if found.is_none() {
+            warn!("keybinding profile '{}' is gone, picking one by pattern", name);
+        }
+        found
+    });
+    let chosen = requested.or_else(|| {
+        profiles.iter().find(|p| {
+            p.sessions.iter().any(|pat| common::glob_match(pat, session))
+                || cmd.is_some_and(|cmd| p.cmds.iter().any(|pat| common::glob_match(pat, cmd)))
+        })
+    });
+    if let Some(profile) = chosen {
+        info!("using keybinding profile '{}'", profile.name);
+    }
+
+    let empty_bindings = vec![config::Keybinding {
+        binding: String::from("Ctrl-Space Ctrl-q"),
+        action: keybindings::Action::Detach,
+    }];
+    // A profile without any bindings would leave no way to detach, so it
+    // gets the default binding rather than nothing at all.
+    let profile_bindings = chosen.map(|p| {
+        if p.keybinding.is_empty() {
+            warn!("keybinding profile '{}' is empty, using the default binding", p.name);
+            &empty_bindings[..]
+        } else {
+            &p.keybinding[..]
+        }
+    });
+    keybindings::Bindings::new(
+        profile_bindings
+            .or(config.keybinding.as_deref())
+            .unwrap_or(&empty_bindings)
+            .iter()
+            .map(|binding| (binding.binding.as_str(), binding.action.clone())),
+    )
+}
+
+/// The keybindings for a single client, which get rebuilt whenever the
+/// config changes.
+pub struct ClientBindings<'config> {
+    config: &'config config::Manager,
+    session: String,
+    cmd: Option<String>,
+    profile: Option<String>,
+    /// The config generation the filter was built from.
+    generation: usize,
+    filter: keybindings::InputFilter,
+    /// How long to wait for the rest of a partially typed keybinding.
+    timeout: Option<time::Duration>,
+}
+
+impl<'config> ClientBindings<'config> {
+    pub fn new(
+        config: &'config config::Manager,
+        session: &str,
+        cmd: Option<&str>,
+        profile: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let generation = config.generation();
+        let bindings = compile_bindings(config, session, cmd, profile)
+            .context("compiling keybindings engine")?;
+        Ok(ClientBindings {
+            config,
+            session: String::from(session),
+            cmd: cmd.map(String::from),
+            profile: profile.map(String::from),
+            generation,
+            filter: keybindings::InputFilter::new(bindings),
+            timeout: keybinding_timeout(config),
+        })
+    }
+
+    /// Rebuild the keybindings if the config has changed since they were
+    /// built. Any input held back for a partial match gets flushed into
+    /// `out`, since the new bindings might not care about it.
+    fn refresh(&mut self, out: &mut Vec<u8>) {
+        let generation = self.config.generation();
+        if generation == self.generation {
+            return;
+        }
+        self.generation = generation;
+        self.timeout = keybinding_timeout(self.config);
+        match compile_bindings(
+            self.config,
+            &self.session,
+            self.cmd.as_deref(),
+            self.profile.as_deref(),
+        ) {
+            Ok(bindings) => {
+                info!("rebuilt keybindings after config reload");
+                self.filter.flush(out);
+                self.filter = keybindings::InputFilter::new(bindings);
+            }
+            Err(e) => warn!("compiling new keybindings, keeping the old ones: {:?}", e),
+        }
+    }
+}
+
+/// How long to wait for the rest of a partially typed keybinding.
+fn keybinding_timeout(config: &config::Manager) -> Option<time::Duration> {
+    config.get().keybinding_timeout_ms.map(time::Duration::from_millis)
+}
+
+/// Wait for input from `stream`, giving up after the keybinding timeout
+/// if some input is being held back as the start of a keybinding, in
+/// which case the held input gets flushed into `out`. Returns the number
+/// of bytes read, with zero meaning EOF, or None if we gave up.
+fn read_client_input(
+    stream: &mut UnixStream,
+    buf: &mut [u8],
+    bindings: &mut ClientBindings,
+    out: &mut Vec<u8>,
+) -> io::Result<Option<usize>> {
+    if bindings.timeout.is_some() {
+        let read_timeout = if bindings.filter.is_holding() { bindings.timeout } else { None };
+        if let Err(e) = stream.set_read_timeout(read_timeout) {
+            // This can fail if the client has already hung up, in which
+            // case the read will tell us all about it.
+            debug!("setting keybinding read timeout: {:?}", e);
+        }
+    }
+    match stream.read(buf) {
+        Ok(len) => {
+            bindings.refresh(out);
+            Ok(Some(len))
+        }
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+            debug!("keybinding timed out, flushing held input");
+            bindings.filter.flush(out);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Hook up an additional client to a session which may already have
+/// a client attached, and block until it goes away. If `pty_master` is
+/// provided, the client is a full writer whose input gets merged with
+/// everyone else's. Otherwise, it is a read-only watcher and everything
+/// it types is discarded. Either way, the detach keybinding only
+/// disconnects this client.
+#[instrument(skip_all)]
+pub fn attach_shared(
+    shell_to_client_ctl: &Mutex<ShellToClientCtl>,
+    pty_master: Option<shpool_pty::fork::Master>,
+    conn_id: usize,
+    mut bindings: ClientBindings,
+    mut stream: UnixStream,
+    size: TtySize,
+) -> anyhow::Result<()> {
+    {
+        let shared_client = SharedClient::new(conn_id, pty_master.is_none(), &stream, size)?;
+        let shell_to_client_ctl = shell_to_client_ctl.lock();
+        shell_to_client_ctl
+            .shared_client
+            .send_timeout(SharedClientMsg::New(shared_client), SHELL_TO_CLIENT_CTL_TIMEOUT)
+            .context("sending shared client to shell->client thread")?;
+    }
+
+    let mut master_writer = pty_master;
+    let mut buf: Vec<u8> = vec![0; consts::BUF_SIZE];
+    let mut to_write: Vec<u8> = Vec::with_capacity(consts::BUF_SIZE);
+    loop {
+        to_write.clear();
+        let len = match read_client_input(&mut stream, &mut buf, &mut bindings, &mut to_write) {
+            Ok(Some(0)) => break,
+            Ok(Some(len)) => len,
+            Ok(None) => 0,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                // The shell->client thread shuts down the stream when the
+                // session goes away, so errors here are just hangups.
+                debug!("reading from shared client, assuming hangup: {:?}", e);
+                break;
+            }
+        };
+
+        let mut input = &buf[..len];
+        let mut detach = false;
+        while !input.is_empty() {
+            let (scanned, action) = bindings.filter.scan(input, &mut to_write);
+            input = &input[scanned..];
+            match action {
+                Some(keybindings::Action::Detach) => {
+                    info!("shared client fired detach keybinding");
+                    detach = true;
+                    break;
+                }
+                Some(keybindings::Action::NoOp | keybindings::Action::SendPrefix) | None => {}
+                Some(action) => {
+                    // Everything else acts on the session as a whole, which
+                    // is the main client's call to make.
+                    info!("ignoring {:?} keybinding from shared client", action);
+                }
+            }
+        }
+        if let Some(master_writer) = master_writer.as_mut() {
+            if !to_write.is_empty() {
+                master_writer.write_all(&to_write).context("writing shared client input")?;
+                master_writer.flush().context("flushing shared client input")?;
+            }
+        }
+        if detach {
+            break;
+        }
+    }
+
+    // Let the shell->client thread do the hanging up so that the exit
+    // chunk does not get interleaved with output. If the thread is already
+    // gone, it will have hung up on us already.
+    let shell_to_client_ctl = shell_to_client_ctl.lock();
+    if let Err(e) = shell_to_client_ctl
+        .shared_client
+        .send_timeout(SharedClientMsg::Detach(conn_id), SHELL_TO_CLIENT_CTL_TIMEOUT)
+    {
+        info!("could not detach shared client, shutting down stream: {:?}", e);
+        shutdown_socket(&stream, net::Shutdown::Both)
+            .context("shutting down shared client stream")?;
+    }
+    Ok(())
+}
+
+/// Pick the size the pty should be given the sizes of all the clients
+/// that get a say in it. Each size is tagged with a sequence number
+/// which goes up every time a client attaches or resizes.
+fn arbitrate_size<'a>(
+    policy: config::SharedSizePolicy,
+    sizes: impl Iterator<Item = (u64, &'a TtySize)>,
+) -> Option<TtySize> {
+    use config::SharedSizePolicy::*;
+    let combine = |a: TtySize, b: &TtySize, f: fn(u16, u16) -> u16| TtySize {
+        rows: f(a.rows, b.rows),
+        cols: f(a.cols, b.cols),
+        xpixel: f(a.xpixel, b.xpixel),
+        ypixel: f(a.ypixel, b.ypixel),
+    };
+    match policy {
+        Smallest => sizes.map(|(_, s)| s).fold(None, |acc: Option<TtySize>, s| {
+            Some(acc.map(|a| combine(a, s, std::cmp::min)).unwrap_or_else(|| s.clone()))
+        }),
+        Largest => sizes.map(|(_, s)| s).fold(None, |acc: Option<TtySize>, s| {
+            Some(acc.map(|a| combine(a, s, std::cmp::max)).unwrap_or_else(|| s.clone()))
+        }),
+        MostRecent => sizes.max_by_key(|(seq, _)| *seq).map(|(_, s)| s.clone()),
+    }
+}
+
 /// Shutdown a socket, ignoring ENOTCONN errors which can occur on macOS
 /// when the peer has already disconnected.
 fn shutdown_socket(
@@ -202,19 +620,171 @@ pub enum ClientConnectionMsg {
     Disconnect,
 }
 
+/// A client which joined a session in addition to (or in the absence
+/// of) the main client, see `attach_shared`.
+///
+/// Each shared client gets a writer thread of its own, which the
+/// shell->client thread hands output to over a bounded queue, so that
+/// a client which stops reading can't hold up the session. A client
+/// which lets the queue fill up gets hung up on.
+pub struct SharedClient {
+    /// The conn_id of the connection the client came in on.
+    id: usize,
+    /// Watchers get a mirror of the output but have no say in the
+    /// size of the pty.
+    read_only: bool,
+    /// The size of the client tty.
+    size: TtySize,
+    /// When this client last reported its size, relative to the
+    /// other clients. Used by the most-recent size policy.
+    size_seq: u64,
+    /// Encoded chunks for the writer thread to pass on. Dropping this
+    /// makes the writer thread hang up once it has written out what
+    /// is left.
+    output: crossbeam_channel::Sender<Vec<u8>>,
+    /// The raw unix socket stream, only used to hang up right away.
+    stream: UnixStream,
+}
+
+impl SharedClient {
+    fn new(id: usize, read_only: bool, stream: &UnixStream, size: TtySize) -> anyhow::Result<Self> {
+        let writer_stream = stream.try_clone().context("cloning shared client stream")?;
+        writer_stream
+            .set_write_timeout(Some(SHARED_CLIENT_WRITE_TIMEOUT))
+            .context("setting write timeout on shared client stream")?;
+        let (output, output_rx) = crossbeam_channel::bounded::<Vec<u8>>(SHARED_CLIENT_QUEUE_LEN);
+        thread::Builder::new()
+            .name(format!("shared client({id})"))
+            .spawn(move || {
+                let mut sink = io::BufWriter::new(&writer_stream);
+                for buf in output_rx.iter() {
+                    if let Err(e) = sink.write_all(&buf).and_then(|_| sink.flush()) {
+                        info!("shared client {} write err, hanging up: {:?}", id, e);
+                        break;
+                    }
+                }
+                if let Err(e) = shutdown_socket(&writer_stream, net::Shutdown::Both) {
+                    debug!("shutting down shared client stream: {:?}", e);
+                }
+            })
+            .context("spawning shared client writer thread")?;
+
+        Ok(SharedClient {
+            id,
+            read_only,
+            size,
+            size_seq: 0,
+            output,
+            stream: stream.try_clone().context("cloning shared client stream")?,
+        })
+    }
+
+    /// Queue up encoded chunks for the client. Returns false if the
+    /// client has gone away or has fallen too far behind, in which case
+    /// it should be dropped.
+    fn send(&self, buf: Vec<u8>) -> bool {
+        match self.output.try_send(buf) {
+            Ok(()) => true,
+            Err(crossbeam_channel::TrySendError::Full(_)) => {
+                info!("shared client {} fell behind, hanging up", self.id);
+                // This also knocks the writer thread out of any write it
+                // is stuck in.
+                if let Err(e) = shutdown_socket(&self.stream, net::Shutdown::Both) {
+                    debug!("shutting down shared client stream: {:?}", e);
+                }
+                false
+            }
+            Err(crossbeam_channel::TrySendError::Disconnected(_)) => {
+                info!("shared client {} writer is gone, dropping it", self.id);
+                false
+            }
+        }
+    }
+}
+
+/// Encode a chunk to hand off to a shared client's writer thread.
+fn encode_chunk(chunk: &Chunk) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(chunk.buf.len() + 5);
+    // writing to a vec can't fail
+    let _ = chunk.write_to(&mut buf);
+    buf
+}
+
+/// Messages to the shell->client thread to manage shared clients.
+pub enum SharedClientMsg {
+    /// Start mirroring output to a new shared client.
+    New(SharedClient),
+    /// Hang up on the shared client with the given id.
+    Detach(usize),
+}
+
+/// A SessionMessage delivered to the shell->client thread.
+pub struct SessionMail {
+    pub payload: SessionMessageRequestPayload,
+    /// Where to send the reply once the message has been handled.
+    pub reply: crossbeam_channel::Sender<SessionMessageReply>,
+}
+
+/// A keybinding action fired in the named session which the server
+/// has to carry out.
+#[derive(Debug)]
+pub struct ServerAction {
+    /// The name the session had when the client attached. Only good for
+    /// logging, since the session may have been renamed since then.
+    pub session: String,
+    /// Identifies the session the action was fired in.
+    pub shell_to_client_ctl: Arc<Mutex<ShellToClientCtl>>,
+    pub action: keybindings::Action,
+}
+
+/// Tells the shell->client thread that its session has been renamed.
+#[derive(Debug)]
+pub struct Rename {
+    pub name: String,
+    pub session_dir: PathBuf,
+}
+
 pub struct ShellToClientArgs {
     pub conn_id: usize,
     pub tty_size: TtySize,
     pub scrollback_lines: usize,
     pub client_connection: crossbeam_channel::Receiver<ClientConnectionMsg>,
     pub client_connection_ack: crossbeam_channel::Sender<ClientConnectionStatus>,
-    pub tty_size_change: crossbeam_channel::Receiver<TtySize>,
-    pub tty_size_change_ack: crossbeam_channel::Sender<()>,
+    pub session_message: crossbeam_channel::Receiver<SessionMail>,
+    /// Tallies output for `shpool list`, reset whenever a new main
+    /// client connects.
+    pub output_bytes_since_attach: Arc<AtomicU64>,
     pub heartbeat: crossbeam_channel::Receiver<()>,
     pub maybe_switch: crossbeam_channel::Receiver<MaybeSwitch>,
     // true if the client is still live, false if it has hung up on us
     pub heartbeat_ack: crossbeam_channel::Sender<bool>,
     pub child_exit_notifier: Arc<ExitNotifier>,
+    /// Where to announce pty resizes.
+    pub events_bus: Arc<events::EventBus>,
+    /// Additional clients which should get a mirror of the shell output.
+    pub shared_client: crossbeam_channel::Receiver<SharedClientMsg>,
+    pub capture: crossbeam_channel::Receiver<Option<u16>>,
+    pub capture_reply: crossbeam_channel::Sender<Option<Vec<u8>>>,
+    pub output_watch: crossbeam_channel::Receiver<output_watch::Msg>,
+    /// If set, the session dir to periodically save the output spool into
+    /// so that it can be restored after a daemon restart.
+    pub checkpoint_dir: Option<PathBuf>,
+    /// The saved output spool from a previous incarnation of this session,
+    /// which gets replayed to the first client to connect.
+    pub restored_spool: Option<Vec<u8>>,
+    /// Where to tee the shell's output, if anywhere.
+    pub output_log: Option<output_log::OutputLog>,
+    /// The session dir and settings to open a fresh output log with
+    /// when the togglelog keybinding switches logging on.
+    pub session_dir: PathBuf,
+    pub output_log_settings: output_log::Settings,
+    /// Keybinding actions which act on the output log or the spool.
+    pub keybinding_action: crossbeam_channel::Receiver<keybindings::Action>,
+    pub rename: crossbeam_channel::Receiver<Rename>,
+    /// The shell's state file, to clean up once the session is gone.
+    pub state_file: Option<PathBuf>,
+    /// Where to leave the final screen once the shell exits.
+    pub final_screen: Arc<Mutex<Option<Vec<u8>>>>,
 }
 
 impl SessionInner {
@@ -224,7 +794,7 @@ impl SessionInner {
     #[instrument(skip_all, fields(s = self.name))]
     pub fn spawn_shell_to_client(
         &self,
-        args: ShellToClientArgs,
+        mut args: ShellToClientArgs,
     ) -> anyhow::Result<thread::JoinHandle<anyhow::Result<()>>> {
         let term_db = Arc::clone(&self.term_db);
         let mut prompt_sentinel_scanner =
@@ -239,13 +809,34 @@ impl SessionInner {
 
         let mut pty_master = self.pty_master.is_parent()?;
         let watchable_master = pty_master.clone();
-        let name = self.name.clone();
+        let mut name = self.name.clone();
         let config = self.config.clone();
-        let closure = move || {
+        let checkpoint_interval = checkpoint::interval(&config);
+        // Owned by the thread wrapper so that it can clear the checkpoint
+        // no matter how the loop exits, but it has to follow renames.
+        let mut checkpoint_dir = args.checkpoint_dir.take();
+        let state_file = args.state_file.take();
+        let closure = move |checkpoint_dir: &mut Option<PathBuf>| {
             let _s = span!(Level::INFO, "shell->client", s = name, cid = args.conn_id).entered();
 
             let mut output_spool =
-                session_restore::new(config, &args.tty_size, args.scrollback_lines);
+                session_restore::new(config.clone(), &args.tty_size, args.scrollback_lines);
+            let mut last_checkpoint = time::Instant::now();
+            let mut needs_checkpoint = false;
+
+            // If we are bringing a session back from the dead, feed the saved
+            // restore buffer through the spool so that it shows up for the first
+            // client and any that attach later on.
+            let mut needs_restore = false;
+            if let Some(restored_spool) = args.restored_spool.as_ref() {
+                info!("replaying restored spool len={}", restored_spool.len());
+                output_spool.process(restored_spool);
+                needs_restore = true;
+            }
+            let mut output_log = args.output_log;
+            // The file and cast for the `shpool record` recording in
+            // progress, if any.
+            let mut recording: Option<(String, output_log::Cast)> = None;
             let mut buf: Vec<u8> = vec![0; consts::BUF_SIZE];
             let mut poll_fds = [poll::PollFd::new(
                 watchable_master.borrow_fd(),
@@ -262,20 +853,40 @@ impl SessionInner {
                 .context("sending initial client connection ack")?;
             info!("got initial client connection");
 
+            // Clients sharing the session with the main client. These get
+            // dropped as soon as a write to them fails.
+            let mut shared_clients: Vec<SharedClient> = vec![];
+
+            // Watches on the output, along with their ids.
+            let mut output_watches: Vec<(usize, Box<dyn output_watch::OutputWatch>)> = vec![];
+
+            // The sizes of the main client and the writable shared clients all
+            // get fed through arbitrate_size to pick the actual pty size.
+            let mut size_seq: u64 = 0;
+            let mut owner_size: Option<(u64, TtySize)> = None;
+            let mut needs_size_arbitration = false;
+
+            // What the pty was last resized to, so that resizes that end up
+            // back at the same size (like the reattach jiggle) aren't
+            // announced.
+            let mut pty_size = (args.tty_size.rows, args.tty_size.cols);
+
             let mut resize_cmd = if let ClientConnectionMsg::New(conn) = &client_conn {
+                owner_size = Some((size_seq, conn.size.clone()));
                 Some(ResizeCmd { size: conn.size.clone(), when: time::Instant::now() })
             } else {
                 None
             };
 
             loop {
-                let mut do_reattach = false;
+                let mut do_reattach = std::mem::take(&mut needs_restore);
                 crossbeam_channel::select! {
                     recv(args.client_connection) -> new_connection => {
                         match new_connection {
                             Ok(ClientConnectionMsg::New(conn)) => {
                                 info!("got new connection (rows={}, cols={})", conn.size.rows, conn.size.cols);
                                 do_reattach = true;
+                                args.output_bytes_since_attach.store(0, Ordering::Relaxed);
                                 let ack = if let ClientConnectionMsg::New(mut old_conn) = client_conn {
                                     Self::write_exit_chunk(&mut old_conn.sink, 0);
                                     shutdown_socket(&old_conn.stream, net::Shutdown::Both)?;
@@ -284,25 +895,30 @@ impl SessionInner {
                                     ClientConnectionStatus::New
                                 };
 
+                                size_seq += 1;
+                                owner_size = Some((size_seq, conn.size.clone()));
+                                let size = Self::pty_size(&config, &owner_size, &shared_clients)
+                                    .unwrap_or_else(|| conn.size.clone());
+
                                 // Always instantly resize the spool, since we don't
                                 // need to inject a delay into that.
-                                output_spool.resize(conn.size.clone());
+                                output_spool.resize(size.clone());
 
                                 // First resize the pty to be bigger than it needs to be,
                                 // we do this immediately so that the extra size
                                 // can "bake" for a little bit, which emacs seems
                                 // to require in order to pick up the jiggle.
                                 let oversize = TtySize {
-                                    rows: conn.size.rows + 1,
-                                    cols: conn.size.cols + 1,
-                                    xpixel: conn.size.xpixel,
-                                    ypixel: conn.size.ypixel,
+                                    rows: size.rows + 1,
+                                    cols: size.cols + 1,
+                                    xpixel: size.xpixel,
+                                    ypixel: size.ypixel,
                                 };
                                 oversize.set_fd(pty_master.raw_fd())?;
 
                                 // Prepare a resize command for pty to execute later.
                                 resize_cmd = Some(ResizeCmd {
-                                    size: conn.size.clone(),
+                                    size,
                                     when: time::Instant::now().add(REATTACH_RESIZE_DELAY),
                                 });
                                 client_conn = ClientConnectionMsg::New(conn);
@@ -311,16 +927,9 @@ impl SessionInner {
                                     .context("sending client connection ack")?;
                             }
                             Ok(ClientConnectionMsg::Disconnect) => {
-                                let ack = if let ClientConnectionMsg::New(mut old_conn) = client_conn {
-                                    info!("disconnect, shutting down client stream");
-                                    Self::write_exit_chunk(&mut old_conn.sink, 0);
-                                    shutdown_socket(&old_conn.stream, net::Shutdown::Both)?;
-                                    ClientConnectionStatus::Detached
-                                } else {
-                                    info!("disconnect, no client stream to shut down");
-                                    ClientConnectionStatus::DetachNone
-                                };
-                                client_conn = ClientConnectionMsg::Disconnect;
+                                let ack = Self::disconnect_client(&mut client_conn)?;
+                                owner_size = None;
+                                needs_size_arbitration = true;
 
                                 args.client_connection_ack.send(ack)
                                     .context("sending client disconnect ack")?;
@@ -344,6 +953,7 @@ impl SessionInner {
                                 };
                                 args.client_connection_ack.send(ack)
                                     .context("sending client disconnect exit ack")?;
+                                Self::disconnect_shared_clients(shared_clients, Some(exit_status));
 
                                 return Ok(());
                             }
@@ -355,10 +965,36 @@ impl SessionInner {
                             },
                         }
                     }
-                    recv(args.tty_size_change) -> new_size => {
-                        match new_size {
-                            Ok(size) => {
-                                info!("resize size={:?}", size);
+                    recv(args.session_message) -> mail => {
+                        let mail = match mail {
+                            Ok(mail) => mail,
+                            Err(err) => {
+                                warn!("session message: bailing due to: {:?}", err);
+                                return Ok(());
+                            }
+                        };
+
+                        let reply = match mail.payload {
+                            SessionMessageRequestPayload::Resize(ResizeRequest {
+                                tty_size,
+                                client_id: Some(id),
+                            }) => {
+                                let id = id as usize;
+                                if let Some(c) = shared_clients.iter_mut().find(|c| c.id == id && !c.read_only) {
+                                    info!("shared client {} resized to {:?}", id, tty_size);
+                                    size_seq += 1;
+                                    c.size_seq = size_seq;
+                                    c.size = tty_size;
+                                    needs_size_arbitration = true;
+                                }
+                                SessionMessageReply::Resize(ResizeReply::Ok)
+                            }
+                            SessionMessageRequestPayload::Resize(ResizeRequest { tty_size, client_id: None }) => {
+                                info!("resize size={:?}", tty_size);
+                                size_seq += 1;
+                                owner_size = Some((size_seq, tty_size.clone()));
+                                let size = Self::pty_size(&config, &owner_size, &shared_clients)
+                                    .unwrap_or(tty_size);
                                 output_spool.resize(size.clone());
                                 resize_cmd = Some(ResizeCmd {
                                     size,
@@ -366,13 +1002,52 @@ impl SessionInner {
                                     // for reconnects.
                                     when: time::Instant::now(),
                                 });
-                                args.tty_size_change_ack.send(())
-                                    .context("sending size change ack")?;
+                                SessionMessageReply::Resize(ResizeReply::Ok)
                             }
-                            Err(err) => {
-                                warn!("size change: bailing due to: {:?}", err);
-                                return Ok(());
+                            SessionMessageRequestPayload::Detach => {
+                                let status = Self::disconnect_client(&mut client_conn)?;
+                                info!("detach message, status = {:?}", status);
+                                owner_size = None;
+                                needs_size_arbitration = true;
+                                SessionMessageReply::Detach(SessionMessageDetachReply::Ok)
+                            }
+                            SessionMessageRequestPayload::Record(RecordRequest::Start { path }) => {
+                                SessionMessageReply::Record(match &recording {
+                                    Some((current, _)) => RecordReply::AlreadyRecording { path: current.clone() },
+                                    None => match output_log::Cast::record(PathBuf::from(&path), pty_size.0, pty_size.1) {
+                                        Ok(mut cast) => {
+                                            info!("recording to {:?}", path);
+                                            // Start off with whatever is on the screen right now so
+                                            // that the recording makes sense on its own.
+                                            let screen = output_spool.restore_buffer();
+                                            if let Err(e) = cast.output(&screen) {
+                                                warn!("recording initial screen: {:?}", e);
+                                            }
+                                            recording = Some((path, cast));
+                                            RecordReply::Started
+                                        }
+                                        Err(e) => {
+                                            warn!("starting recording: {:?}", e);
+                                            RecordReply::Error(format!("{e:#}"))
+                                        }
+                                    },
+                                })
+                            }
+                            SessionMessageRequestPayload::Record(RecordRequest::Stop) => {
+                                SessionMessageReply::Record(match recording.take() {
+                                    Some((path, _)) => {
+                                        info!("stopped recording to {:?}", path);
+                                        RecordReply::Stopped { path }
+                                    }
+                                    None => RecordReply::NotRecording,
+                                })
                             }
+                        };
+
+                        // The sender might have given up on us, in which case
+                        // there is nobody left to tell.
+                        if mail.reply.send(reply).is_err() {
+                            warn!("session message sender hung up before reply");
                         }
                     }
                     recv(args.heartbeat) -> _ => {
@@ -398,6 +1073,45 @@ impl SessionInner {
 
                         args.heartbeat_ack.send(client_present)
                             .context("sending heartbeat ack")?;
+
+                        // Piggyback on the main client's heartbeat to notice
+                        // shared clients that have gone away while the shell is quiet.
+                        let n_shared_clients = shared_clients.len();
+                        let heartbeat = encode_chunk(&Chunk { kind: ChunkKind::Heartbeat, buf: &[] });
+                        shared_clients.retain(|c| c.send(heartbeat.clone()));
+                        needs_size_arbitration |= n_shared_clients != shared_clients.len();
+                    }
+                    recv(args.shared_client) -> shared_client => {
+                        match shared_client {
+                            Ok(SharedClientMsg::New(mut shared_client)) => {
+                                info!("got new shared client {} (read_only={}, n_shared_clients={})",
+                                    shared_client.id, shared_client.read_only, shared_clients.len() + 1);
+                                let mut restore_buf = vec![];
+                                // writing to a vec can't fail
+                                let _ = Self::write_restore_buf(&mut restore_buf, &output_spool.restore_buffer());
+                                if !shared_client.send(restore_buf) {
+                                    info!("could not send restore buf to shared client, dropping it");
+                                } else {
+                                    size_seq += 1;
+                                    shared_client.size_seq = size_seq;
+                                    needs_size_arbitration |= !shared_client.read_only;
+                                    shared_clients.push(shared_client);
+                                }
+                            }
+                            Ok(SharedClientMsg::Detach(id)) => {
+                                info!("detaching shared client {}", id);
+                                let (detached, rest): (Vec<_>, Vec<_>) = shared_clients
+                                    .into_iter()
+                                    .partition(|c| c.id == id);
+                                shared_clients = rest;
+                                needs_size_arbitration |= detached.iter().any(|c| !c.read_only);
+                                Self::disconnect_shared_clients(detached, Some(0));
+                            }
+                            Err(e) => {
+                                warn!("shared client: bailing due to: {:?}", e);
+                                return Ok(());
+                            }
+                        }
                     }
                     recv(args.maybe_switch) -> maybe_switch => {
                         let maybe_switch = match maybe_switch {
@@ -408,6 +1122,24 @@ impl SessionInner {
                             },
                         };
 
+                        let mut encoded = Vec::new();
+                        if let Err(e) = protocol::encode_to(&maybe_switch, &mut encoded) {
+                            error!("error encoding MaybeSwitch: {:?}", e);
+                            continue;
+                        }
+                        let chunk = Chunk { kind: ChunkKind::MaybeSwitch, buf: &encoded[..] };
+
+                        // Shared clients have no business switching along with
+                        // the main client, but they do need to know about a new
+                        // name so that they keep sending resizes to the right
+                        // place.
+                        if maybe_switch.renamed_to.is_some() {
+                            let encoded = encode_chunk(&chunk);
+                            let n_shared_clients = shared_clients.len();
+                            shared_clients.retain(|c| c.send(encoded.clone()));
+                            needs_size_arbitration |= n_shared_clients != shared_clients.len();
+                        }
+
                         let conn = if let ClientConnectionMsg::New(c) = &mut client_conn {
                             c
                         } else {
@@ -415,13 +1147,6 @@ impl SessionInner {
                             continue;
                         };
 
-                        let mut encoded = Vec::new();
-                        if let Err(e) = protocol::encode_to(&maybe_switch, &mut encoded) {
-                            error!("error encoding MaybeSwitch: {:?}", e);
-                            continue;
-                        }
-
-                        let chunk = Chunk { kind: ChunkKind::MaybeSwitch, buf: &encoded[..] };
                         match chunk.write_to(&mut conn.sink).and_then(|_| conn.sink.flush()) {
                             Ok(_) => {
                                 trace!("wrote MaybeSwitch");
@@ -435,12 +1160,99 @@ impl SessionInner {
                             }
                         }
                     }
+                    recv(args.capture) -> lines => {
+                        let lines = match lines {
+                            Ok(lines) => lines,
+                            Err(e) => {
+                                warn!("capture: bailing due to: {:?}", e);
+                                return Ok(());
+                            }
+                        };
+                        info!("capturing spool (lines={:?})", lines);
+                        args.capture_reply.send(output_spool.capture(lines))
+                            .context("sending capture reply")?;
+                    }
+                    recv(args.output_watch) -> msg => {
+                        match msg {
+                            Ok(output_watch::Msg::New { id, mut watch, with_screen }) => {
+                                info!("got new output watch {} (with_screen={})", id, with_screen);
+                                let screen = if with_screen { output_spool.capture(None) } else { None };
+                                if screen.map(|screen| watch.feed(&screen)).unwrap_or(true) {
+                                    output_watches.push((id, watch));
+                                }
+                            }
+                            Ok(output_watch::Msg::Drop(id)) => {
+                                info!("dropping output watch {}", id);
+                                output_watches.retain(|(watch_id, _)| *watch_id != id);
+                            }
+                            Err(e) => {
+                                warn!("output watch: bailing due to: {:?}", e);
+                                return Ok(());
+                            }
+                        }
+                    }
+                    recv(args.keybinding_action) -> action => {
+                        let action = match action {
+                            Ok(action) => action,
+                            Err(e) => {
+                                warn!("keybinding action: bailing due to: {:?}", e);
+                                return Ok(());
+                            }
+                        };
+                        match action {
+                            keybindings::Action::ToggleLog => match output_log.take() {
+                                Some(_) => info!("output logging off"),
+                                None => match output_log::OutputLog::open(
+                                    &args.session_dir,
+                                    &args.output_log_settings,
+                                    pty_size.0,
+                                    pty_size.1,
+                                ) {
+                                    Ok(log) => {
+                                        info!("output logging on");
+                                        output_log = Some(log);
+                                    }
+                                    Err(e) => warn!("opening output log: {:?}", e),
+                                },
+                            },
+                            keybindings::Action::ClearSpool => {
+                                info!("clearing output spool");
+                                let size = TtySize { rows: pty_size.0, cols: pty_size.1, xpixel: 0, ypixel: 0 };
+                                output_spool = session_restore::new(config.clone(), &size, args.scrollback_lines);
+                                needs_checkpoint = true;
+                            }
+                            action => warn!("shell->client thread can't handle {:?}", action),
+                        }
+                    }
+                    recv(args.rename) -> rename => {
+                        let rename = match rename {
+                            Ok(rename) => rename,
+                            Err(e) => {
+                                warn!("rename: bailing due to: {:?}", e);
+                                return Ok(());
+                            }
+                        };
+                        info!("session renamed to '{}'", rename.name);
+                        if checkpoint_dir.is_some() {
+                            *checkpoint_dir = Some(rename.session_dir.clone());
+                        }
+                        args.session_dir = rename.session_dir;
+                        name = rename.name;
+                    }
 
                     // make this select non-blocking so we spend most of our time parked
                     // in poll
                     default => {}
                 }
 
+                if std::mem::take(&mut needs_size_arbitration) {
+                    if let Some(size) = Self::pty_size(&config, &owner_size, &shared_clients) {
+                        info!("arbitrated pty size (rows={}, cols={})", size.rows, size.cols);
+                        output_spool.resize(size.clone());
+                        resize_cmd = Some(ResizeCmd { size, when: time::Instant::now() });
+                    }
+                }
+
                 let mut executed_resize = false;
                 if let Some(resize_cmd) = resize_cmd.as_ref() {
                     if resize_cmd.when.saturating_duration_since(time::Instant::now())
@@ -454,6 +1266,27 @@ impl SessionInner {
                             "resized fd (rows={}, cols={})",
                             resize_cmd.size.rows, resize_cmd.size.cols
                         );
+                        let new_size = (resize_cmd.size.rows, resize_cmd.size.cols);
+                        if new_size != pty_size {
+                            pty_size = new_size;
+                            if let Some(log) = output_log.as_mut() {
+                                if let Err(e) = log.resize(new_size.0, new_size.1) {
+                                    warn!("logging resize, giving up on output log: {:?}", e);
+                                    output_log = None;
+                                }
+                            }
+                            if let Some((_, cast)) = recording.as_mut() {
+                                if let Err(e) = cast.resize(new_size.0, new_size.1) {
+                                    warn!("recording resize, stopping recording: {:?}", e);
+                                    recording = None;
+                                }
+                            }
+                            args.events_bus.publish(events::Event::SessionResized {
+                                session: name.clone(),
+                                rows: new_size.0,
+                                cols: new_size.1,
+                            });
+                        }
                     }
                 }
                 if executed_resize {
@@ -467,18 +1300,21 @@ impl SessionInner {
                         (!restore_buf.is_empty(), &mut client_conn)
                     {
                         trace!("restore chunk='{}'", String::from_utf8_lossy(&restore_buf[..]));
-                        // send the restore buffer, broken up into chunks so that we don't make
-                        // the client allocate too much
-                        for block in restore_buf.as_slice().chunks(consts::BUF_SIZE) {
-                            let chunk = Chunk { kind: ChunkKind::Data, buf: block };
-
-                            if let Err(err) = chunk.write_to(&mut conn.sink) {
-                                warn!("err writing session-restore buf: {:?}", err);
-                            }
+                        if let Err(err) = Self::write_restore_buf(&mut conn.sink, &restore_buf) {
+                            warn!("err writing session-restore buf: {:?}", err);
                         }
-                        if let Err(err) = conn.sink.flush() {
-                            warn!("err flushing session-restore: {:?}", err);
+                    }
+                }
+
+                if let (true, Some(dir)) = (needs_checkpoint, checkpoint_dir.as_ref()) {
+                    if last_checkpoint.elapsed() >= checkpoint_interval {
+                        debug!("checkpointing output spool");
+                        if let Err(e) = checkpoint::write_spool(dir, &output_spool.restore_buffer())
+                        {
+                            warn!("checkpointing output spool: {:?}", e);
                         }
+                        last_checkpoint = time::Instant::now();
+                        needs_checkpoint = false;
                     }
                 }
 
@@ -498,6 +1334,7 @@ impl SessionInner {
                 };
                 if nready == 0 {
                     // if timeout
+                    output_watches.retain_mut(|(_, watch)| watch.idle());
                     continue;
                 }
                 if nready != 1 {
@@ -509,16 +1346,17 @@ impl SessionInner {
                     .unwrap_or(false);
                 if hangup {
                     info!("pty master hung up, exiting shell->client thread");
+                    *args.final_screen.lock() = Some(output_spool.restore_buffer());
 
                     // If we have an attached client conn, make a best effort attempt
                     // to forward the exit status.
-                    if let ClientConnectionMsg::New(mut conn) = client_conn {
-                        if let Some(exit_status) =
-                            args.child_exit_notifier.wait(Some(SHELL_EXIT_WAIT_DUR))
-                        {
-                            Self::write_exit_chunk(&mut conn.sink, exit_status);
-                        }
+                    let exit_status = args.child_exit_notifier.wait(Some(SHELL_EXIT_WAIT_DUR));
+                    if let (ClientConnectionMsg::New(mut conn), Some(exit_status)) =
+                        (client_conn, exit_status)
+                    {
+                        Self::write_exit_chunk(&mut conn.sink, exit_status);
                     }
+                    Self::disconnect_shared_clients(shared_clients, exit_status);
                     return Ok(());
                 }
                 let len = match pty_master.read(&mut buf) {
@@ -551,6 +1389,21 @@ impl SessionInner {
 
                 if has_seen_prompt_sentinel {
                     output_spool.process(buf);
+                    args.output_bytes_since_attach.fetch_add(buf.len() as u64, Ordering::Relaxed);
+                    needs_checkpoint = true;
+                    output_watches.retain_mut(|(_, watch)| watch.feed(buf));
+                    if let Some(log) = output_log.as_mut() {
+                        if let Err(e) = log.write(buf) {
+                            warn!("logging output, giving up on output log: {:?}", e);
+                            output_log = None;
+                        }
+                    }
+                    if let Some((_, cast)) = recording.as_mut() {
+                        if let Err(e) = cast.output(buf) {
+                            warn!("recording output, stopping recording: {:?}", e);
+                            recording = None;
+                        }
+                    }
                 }
 
                 let mut reset_client_conn = false;
@@ -581,15 +1434,92 @@ impl SessionInner {
                 if reset_client_conn {
                     client_conn = ClientConnectionMsg::Disconnect;
                 }
+
+                if has_seen_prompt_sentinel && !shared_clients.is_empty() {
+                    let encoded = encode_chunk(&Chunk { kind: ChunkKind::Data, buf });
+                    let n_shared_clients = shared_clients.len();
+                    shared_clients.retain(|c| c.send(encoded.clone()));
+                    needs_size_arbitration |= n_shared_clients != shared_clients.len();
+                }
             }
         };
 
-        Ok(thread::Builder::new()
-            .name(format!("shell->client({})", self.name))
-            .spawn(move || log_if_error("error in shell->client", closure()))?)
+        Ok(thread::Builder::new().name(format!("shell->client({})", self.name)).spawn(
+            move || {
+                let res = log_if_error("error in shell->client", closure(&mut checkpoint_dir));
+                // Once this thread is done the session is going away, so make sure
+                // it does not get resurrected by a later daemon.
+                if let Some(dir) = checkpoint_dir {
+                    checkpoint::clear(&dir);
+                }
+                if let Some(state_file) = state_file {
+                    if let Err(e) = fs::remove_file(&state_file) {
+                        warn!("removing shell state file: {:?}", e);
+                    }
+                }
+                res
+            },
+        )?)
     }
 
-    fn write_exit_chunk<W: io::Write>(mut sink: W, status: i32) {
+    /// Send the restore buffer, broken up into chunks so that we don't make
+    /// the client allocate too much.
+    pub fn write_restore_buf<W: io::Write>(mut sink: W, restore_buf: &[u8]) -> io::Result<()> {
+        for block in restore_buf.chunks(consts::BUF_SIZE) {
+            let chunk = Chunk { kind: ChunkKind::Data, buf: block };
+            chunk.write_to(&mut sink)?;
+        }
+        sink.flush()
+    }
+
+    /// Hang up on the main client, if there is one.
+    fn disconnect_client(
+        client_conn: &mut ClientConnectionMsg,
+    ) -> anyhow::Result<ClientConnectionStatus> {
+        match std::mem::replace(client_conn, ClientConnectionMsg::Disconnect) {
+            ClientConnectionMsg::New(mut old_conn) => {
+                info!("disconnect, shutting down client stream");
+                Self::write_exit_chunk(&mut old_conn.sink, 0);
+                shutdown_socket(&old_conn.stream, net::Shutdown::Both)?;
+                Ok(ClientConnectionStatus::Detached)
+            }
+            _ => {
+                info!("disconnect, no client stream to shut down");
+                Ok(ClientConnectionStatus::DetachNone)
+            }
+        }
+    }
+
+    /// Hang up on the given shared clients, forwarding the exit status if
+    /// the shell is gone. The writer threads do the actual hanging up
+    /// once they have written out everything queued up before it.
+    fn disconnect_shared_clients(shared_clients: Vec<SharedClient>, exit_status: Option<i32>) {
+        for shared_client in shared_clients.into_iter() {
+            if let Some(exit_status) = exit_status {
+                let mut buf = vec![];
+                Self::write_exit_chunk(&mut buf, exit_status);
+                shared_client.send(buf);
+            }
+        }
+    }
+
+    /// The size the pty should be given the main client and all the
+    /// writable shared clients. Read-only watchers don't get a say.
+    fn pty_size(
+        config: &config::Manager,
+        owner_size: &Option<(u64, TtySize)>,
+        shared_clients: &[SharedClient],
+    ) -> Option<TtySize> {
+        let policy = config.get().shared_size_policy.unwrap_or_default();
+        arbitrate_size(
+            policy,
+            owner_size.iter().map(|(seq, size)| (*seq, size)).chain(
+                shared_clients.iter().filter(|c| !c.read_only).map(|c| (c.size_seq, &c.size)),
+            ),
+        )
+    }
+
+    pub fn write_exit_chunk<W: io::Write>(mut sink: W, status: i32) {
         let status_buf: [u8; 4] = status.to_le_bytes();
         let chunk = Chunk { kind: ChunkKind::ExitStatus, buf: status_buf.as_slice() };
         match chunk.write_to(&mut sink).and_then(|_| sink.flush()) {
@@ -768,18 +1698,11 @@ impl SessionInner {
         pty_master: &'scope shpool_pty::fork::Master,
         shell_to_client_client_stream: &'scope mut UnixStream,
     ) -> anyhow::Result<thread::ScopedJoinHandle<'scope, anyhow::Result<()>>> {
-        let empty_bindings = vec![config::Keybinding {
-            binding: String::from("Ctrl-Space Ctrl-q"),
-            action: keybindings::Action::Detach,
-        }];
-        let bindings = keybindings::Bindings::new(
-            self.config
-                .get()
-                .keybinding
-                .as_ref()
-                .unwrap_or(&empty_bindings)
-                .iter()
-                .map(|binding| (binding.binding.as_str(), binding.action)),
+        let bindings = ClientBindings::new(
+            &self.config,
+            &self.name,
+            self.cmd.as_deref(),
+            self.keybinding_profile.as_deref(),
         );
 
         thread::Builder::new()
@@ -787,14 +1710,12 @@ impl SessionInner {
             .spawn_scoped(scope, move || -> anyhow::Result<()> {
                 let _s =
                     span!(Level::INFO, "client->shell", s = self.name, cid = conn_id).entered();
-                let mut bindings = bindings.context("compiling keybindings engine")?;
+                let mut bindings = bindings?;
 
                 let mut master_writer = pty_master.clone();
 
-                let mut snip_sections = vec![]; // (<len>, <end offset>)
-                let mut keep_sections = vec![]; // (<start offset>, <end offset>)
                 let mut buf: Vec<u8> = vec![0; consts::BUF_SIZE];
-                let mut partial_keybinding = vec![];
+                let mut to_write: Vec<u8> = Vec::with_capacity(consts::BUF_SIZE);
 
                 loop {
                     if stop.load(Ordering::Relaxed) {
@@ -809,101 +1730,67 @@ impl SessionInner {
                     //
                     // Also, note that we don't access through the mutex because reads
                     // don't need to be excluded from trampling on writes.
-                    let mut len = shell_to_client_client_stream
-                        .read(&mut buf)
-                        .context("reading client chunk")?;
-                    if len == 0 {
+                    to_write.clear();
+                    let len = read_client_input(
+                        shell_to_client_client_stream,
+                        &mut buf,
+                        &mut bindings,
+                        &mut to_write,
+                    )
+                    .context("reading client chunk")?;
+                    if len == Some(0) {
                         info!("EOF");
                         return Ok(());
                     }
-                    test_hooks::emit("daemon-read-c2s-chunk");
-                    trace!("read client len={}: '{}'", len, String::from_utf8_lossy(&buf[..len]),);
+                    let len = len.unwrap_or(0);
+                    if len > 0 {
+                        test_hooks::emit("daemon-read-c2s-chunk");
+                        trace!(
+                            "read client len={}: '{}'",
+                            len,
+                            String::from_utf8_lossy(&buf[..len]),
+                        );
+                    }
 
                     // We might be able to gain some perf by doing this scanning in
                     // a background thread (though maybe not given the need to copy
                     // the data), but just doing it inline doesn't seem have have
                     // a major perf impact, and this way is simpler.
-                    snip_sections.clear();
-                    for (i, byte) in buf[0..len].iter().enumerate() {
-                        use keybindings::BindingResult::*;
-                        match bindings.transition(*byte) {
-                            NoMatch
-                                if !partial_keybinding.is_empty()
-                                    && i < partial_keybinding.len() =>
-                            {
-                                // it turned out the partial keybinding match was not
-                                // a real match, so flush it to the output stream
-                                debug!(
-                                    "flushing partial keybinding_len={} i={}",
-                                    partial_keybinding.len(),
-                                    i
-                                );
+                    let mut input = &buf[..len];
+                    while !input.is_empty() {
+                        let (scanned, action) = bindings.filter.scan(input, &mut to_write);
+                        input = &input[scanned..];
+                        let Some(action) = action else {
+                            continue;
+                        };
+                        info!("{:?} keybinding action fired", action);
+
+                        // Get the input from before the keybinding to the
+                        // shell before acting on it.
+                        master_writer.write_all(&to_write).context("writing client chunk")?;
+                        to_write.clear();
+
+                        use keybindings::Action::*;
+                        match action {
+                            Detach => {
                                 master_writer
-                                    .write_all(&partial_keybinding)
-                                    .context("writing partial keybinding")?;
-                                if i > 0 {
-                                    // snip the leading part of the input chunk that
-                                    // was part of this keybinding
-                                    snip_sections.push((i, i - 1));
-                                }
-                                partial_keybinding.clear()
-                            }
-                            NoMatch => {
-                                partial_keybinding.clear();
-                            }
-                            Partial => {
-                                partial_keybinding.push(*byte);
-                            }
-                            Match(action) => {
-                                info!("{:?} keybinding action fired", action);
-                                let keybinding_len = partial_keybinding.len() + 1;
-                                if keybinding_len < i {
-                                    // this keybinding is wholly contained in buf
-                                    debug!("snipping keybinding_len={} i={}", keybinding_len, i);
-                                    snip_sections.push((keybinding_len, i));
-                                } else {
-                                    // this keybinding was split across multiple
-                                    // input buffers, just snip the last bit
-                                    debug!("snipping split keybinding i={}", i);
-                                    snip_sections.push((i + 1, i));
-                                }
-                                partial_keybinding.clear();
-
-                                use keybindings::Action::*;
-                                match action {
-                                    Detach => {
-                                        self.action_detach()?;
-                                        debug!("exiting client->shell thread after detach");
-                                        return Ok(());
-                                    }
-                                    NoOp => {}
-                                }
+                                    .flush()
+                                    .context("flushing input from client to shell")?;
+                                self.action_detach()?;
+                                debug!("exiting client->shell thread after detach");
+                                return Ok(());
                             }
+                            NoOp | SendPrefix => {}
+                            ToggleLog | ClearSpool => self.action_shell_to_client(action)?,
+                            Kill | Switch(_) | Previous | Choose => self.action_server(action)?,
                         }
                     }
-                    if !partial_keybinding.is_empty() {
-                        // we have a partial keybinding pending, so don't write
-                        // it to the output stream immediately
-                        let snip_chunk_len = if partial_keybinding.len() > len {
-                            len
-                        } else {
-                            partial_keybinding.len()
-                        };
-                        debug!(
-                            "end of buf w/ partial keybinding_len={} snip_chunk_len={} buf_len={}",
-                            partial_keybinding.len(),
-                            snip_chunk_len,
-                            len
-                        );
-                        snip_sections.push((snip_chunk_len, len - 1));
-                    }
-                    len = snip_buf(&mut buf[..], len, &snip_sections[..], &mut keep_sections);
 
-                    master_writer.write_all(&buf[0..len]).context("writing client chunk")?;
+                    master_writer.write_all(&to_write).context("writing client chunk")?;
 
                     master_writer.flush().context("flushing input from client to shell")?;
 
-                    debug!("flushed chunk of len {}", len);
+                    debug!("flushed chunk of len {}", to_write.len());
                 }
             })
             .map_err(|e| anyhow!("{:?}", e))
@@ -1039,6 +1926,29 @@ impl SessionInner {
         info!("action detach, status={:?}", status);
         Ok(())
     }
+
+    fn action_shell_to_client(&self, action: keybindings::Action) -> anyhow::Result<()> {
+        let shell_to_client_ctl = self.shell_to_client_ctl.lock();
+        shell_to_client_ctl
+            .keybinding_action
+            .send_timeout(action, SHELL_TO_CLIENT_CTL_TIMEOUT)
+            .context("sending keybinding action to shell->client thread")?;
+        Ok(())
+    }
+
+    fn action_server(&self, action: keybindings::Action) -> anyhow::Result<()> {
+        self.server_action
+            .send_timeout(
+                ServerAction {
+                    session: self.name.clone(),
+                    shell_to_client_ctl: Arc::clone(&self.shell_to_client_ctl),
+                    action,
+                },
+                SHELL_TO_CLIENT_CTL_TIMEOUT,
+            )
+            .context("sending keybinding action to server")?;
+        Ok(())
+    }
 }
 
 /// A handle for poking at the always-running shell->client thread.
@@ -1056,13 +1966,10 @@ pub struct ShellToClientCtl {
     /// fresh client connection.
     pub client_connection_ack: crossbeam_channel::Receiver<ClientConnectionStatus>,
 
-    /// A control channel for the shell->client thread. Used to signal size
-    /// changes so that the output spool will correctly reflect the size of
-    /// the user's tty.
-    pub tty_size_change: crossbeam_channel::Sender<TtySize>,
-    /// A control channel for the shell->client thread. Acks the completion of a
-    /// spool resize.
-    pub tty_size_change_ack: crossbeam_channel::Receiver<()>,
+    /// The session's mailbox. SessionMessages get routed here so that
+    /// they can be handled whether or not a client is attached, since
+    /// the shell->client thread is always running.
+    pub session_message: crossbeam_channel::Sender<SessionMail>,
 
     // A control channel telling the shell->client thread to issue
     // a heartbeat to check if the client is still listening.
@@ -1071,66 +1978,35 @@ pub struct ShellToClientCtl {
     // on us.
     pub heartbeat_ack: crossbeam_channel::Receiver<bool>,
 
+    /// A control channel for managing shared clients, which get a mirror
+    /// of the output alongside the main client. There is no ack channel
+    /// since a shared client can never displace anyone else.
+    pub shared_client: crossbeam_channel::Sender<SharedClientMsg>,
+
     /// A control channel telling the shell->client thread to
     /// broadcast the given MaybeSwitch. There is no ack channel
     /// because we just blast this out and the caller doesn't need
     /// to know about completion.
     pub maybe_switch: crossbeam_channel::Sender<MaybeSwitch>,
-}
 
-/// Given a buffer, a length after which the data is not valid, a list of
-/// sections to remove, and some scratch space, compact the given buffer and
-/// return a new len.
-///
-/// The snip sections must all be within buf[..len], and must be
-/// non-overlapping.
-fn snip_buf(
-    buf: &mut [u8],
-    len: usize,
-    snip_sections: &[(usize, usize)],        // (<len>, <end offset>)
-    keep_sections: &mut Vec<(usize, usize)>, // re-usable scratch
-) -> usize {
-    if snip_sections.is_empty() {
-        return len;
-    }
+    /// A control channel asking the shell->client thread to dump the
+    /// current screen (None) or the last n lines of the output spool.
+    pub capture: crossbeam_channel::Sender<Option<u16>>,
+    /// A control channel for the shell->client thread. Returns the
+    /// captured output, or None if there is no spool to capture from.
+    pub capture_reply: crossbeam_channel::Receiver<Option<Vec<u8>>>,
 
-    // build up the sections to keep in a more normal format
-    keep_sections.clear();
-    let mut cur_start = 0;
-    for (len, end_offset) in snip_sections.iter() {
-        let end_open = *end_offset + 1;
-        let snip_start = end_open - len;
-        if snip_start > cur_start {
-            keep_sections.push((cur_start, snip_start));
-        }
-        cur_start = end_open;
-    }
-    keep_sections.push((cur_start, len));
+    /// A control channel for adding and dropping watches on the
+    /// session's output. Fire and forget like shared_client.
+    pub output_watch: crossbeam_channel::Sender<output_watch::Msg>,
 
-    let mut last_end = 0;
-    for (start, end) in keep_sections.iter() {
-        if *start == *end {
-            continue;
-        }
-        if *start == last_end {
-            last_end = *end;
-            continue;
-        }
-        let section_len = *end - *start;
-        // Safety: we are copying sections of buf into itself, just overwriting
-        //         little sections of the buffer. This should be fine because it
-        //         is all happening within the same section of memory and
-        //         std::ptr::copy (memmove in c) allows overlapping buffers.
-        //         Also, these assertions should make it safer.
-        assert!(last_end + section_len < buf.len());
-        assert!(*start + section_len - 1 < buf.len());
-        unsafe {
-            std::ptr::copy(&buf[*start] as *const u8, &mut buf[last_end] as *mut u8, section_len);
-        }
-        last_end += section_len;
-    }
+    /// Keybinding actions for the shell->client thread to carry out,
+    /// since it owns the output log and the spool.
+    pub keybinding_action: crossbeam_channel::Sender<keybindings::Action>,
 
-    last_end
+    /// A control channel telling the shell->client thread about a new
+    /// name for its session. Fire and forget like maybe_switch.
+    pub rename: crossbeam_channel::Sender<Rename>,
 }
 
 #[cfg(test)]
@@ -1138,20 +2014,21 @@ mod test {
     use super::*;
 
     #[test]
-    fn test_snip_buf() {
+    fn test_arbitrate_size() {
+        use config::SharedSizePolicy::*;
+        let size = |rows, cols| TtySize { rows, cols, xpixel: 0, ypixel: 0 };
         let cases = vec![
-            (vec![1, 1], 2, vec![(2, 1)], vec![]),
-            (vec![1, 1, 3], 3, vec![(2, 1)], vec![3]),
-            (vec![1, 1, 3, 4, 5], 5, vec![(2, 1), (1, 3)], vec![3, 5]),
-            (vec![1, 1, 3, 4, 5, 8, 9, 1, 3], 5, vec![(2, 1), (1, 3)], vec![3, 5]),
-            (vec![1, 1, 3, 4, 5, 8, 9, 1, 3], 9, vec![(5, 7)], vec![1, 1, 3, 3]),
+            (Smallest, vec![], None),
+            (Smallest, vec![(0, size(24, 80))], Some((24, 80))),
+            (Smallest, vec![(0, size(24, 100)), (1, size(40, 80))], Some((24, 80))),
+            (Largest, vec![(0, size(24, 100)), (1, size(40, 80))], Some((40, 100))),
+            (MostRecent, vec![(3, size(24, 100)), (1, size(40, 80))], Some((24, 100))),
+            (MostRecent, vec![(3, size(24, 100)), (7, size(40, 80))], Some((40, 80))),
         ];
 
-        let mut keep_sections = vec![];
-        for (mut buf, len, snips, want_buf) in cases.into_iter() {
-            let got_len = snip_buf(&mut buf, len, &snips[..], &mut keep_sections);
-            dbg!(got_len);
-            assert_eq!(&buf[..got_len], &want_buf[..]);
+        for (policy, sizes, want) in cases.into_iter() {
+            let got = arbitrate_size(policy, sizes.iter().map(|(seq, s)| (*seq, s)));
+            assert_eq!(got.map(|s| (s.rows, s.cols)), want, "policy={policy:?}");
         }
     }
 }
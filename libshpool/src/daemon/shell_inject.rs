@@ -14,9 +14,16 @@
 
 // This file contains the logic for injecting the `prompt_annotation`
 // config option into a user's prompt for known shells.
+//
+// Along with the prompt, we inject a hook which reloads a little state
+// file before every prompt. The daemon rewrites the state file whenever
+// the session gets renamed or a var changes, which is how those changes
+// make it into the prompt and the environment of an already running shell.
 
 use std::{
+    fs,
     io::{Read, Write},
+    path::Path,
     time,
 };
 
@@ -27,7 +34,7 @@ use tracing::{debug, error, info, instrument, warn};
 use crate::{
     consts::{SENTINEL_FLAG_VAR, STARTUP_SENTINEL},
     daemon::trie::{Trie, TrieCursor},
-    exe, test_hooks,
+    exe, template, test_hooks,
 };
 
 // We don't need an agressive poll cadence because the normal case is
@@ -40,6 +47,10 @@ const SENTINEL_POLL_MS: u16 = 500;
 // 90 seconds.
 const SENTINEL_POLL_TIMEOUT: time::Duration = time::Duration::from_secs(90);
 
+// The shell variable a shpool var used in the prompt prefix gets stored in
+// is the var name with this prefix.
+const PROMPT_VAR_PREFIX: &str = "SHPOOL__VAR_";
+
 #[derive(Debug, Clone)]
 enum KnownShell {
     Bash,
@@ -52,13 +63,14 @@ enum KnownShell {
 /// injecting the prefix.
 ///
 /// If either the prefix or startup cmd are blank, we do nothing for that
-/// option.
+/// option. The injected prompt hook reads `state_file`, which ought to
+/// already have been written with `write_state`.
 #[instrument(skip_all)]
 pub fn maybe_setup(
     pty_master: &mut shpool_pty::fork::Fork,
-    prompt_prefix: &str,
+    prompt_tmpl: &template::Template,
     start_cmd: &str,
-    session_name: &str,
+    state_file: &Path,
 ) -> anyhow::Result<()> {
     let shell_pid = pty_master.child_pid().ok_or(anyhow!("no child pid"))?;
     // scan for the startup sentinel so we know it is safe to sniff the shell
@@ -69,30 +81,74 @@ pub fn maybe_setup(
     debug!("sniffed shell type: {:?}", shell_type);
 
     // now actually inject the prompt
-    let prompt_prefix = prompt_prefix.replace("$SHPOOL_SESSION_NAME", session_name);
+    let state_file = state_file.to_string_lossy();
 
-    let mut script = match (prompt_prefix.as_str(), shell_type) {
-        (_, Ok(KnownShell::Bash)) => format!(
-            r#"
+    let mut script = match shell_type {
+        Ok(KnownShell::Bash) => {
+            // With promptvars on, bash expands PS1 again before every
+            // prompt, so the vars get escaped to keep their values from
+            // being expanded twice.
+            let (deferred, now) = prompt_prefixes(prompt_tmpl);
+            format!(
+                r#"
+            SHPOOL__STATE_FILE="{state_file}"
+            function __shpool__load_state() {{
+               local key val
+               [[ -r "${{SHPOOL__STATE_FILE}}" ]] || return 0
+               while IFS='=' read -r key val; do
+                  case "${{key}}" in
+                     {PROMPT_VAR_PREFIX}*) printf -v "${{key}}" '%s' "${{val}}" ;;
+                     SHPOOL_SESSION_NAME|SHPOOL_SESSION_DIR|SSH_AUTH_SOCK) export "${{key}}=${{val}}" ;;
+                  esac
+               done < "${{SHPOOL__STATE_FILE}}"
+            }}
+            function __shpool__status() {{
+               return "$1"
+            }}
+            __shpool__load_state
             if [[ -z "${{PROMPT_COMMAND+x}}" ]]; then
-               PS1="{prompt_prefix}${{PS1}}"
+               SHPOOL__OLD_PROMPT_COMMAND=()
             else
                SHPOOL__OLD_PROMPT_COMMAND=("${{PROMPT_COMMAND[@]}}")
-               SHPOOL__OLD_PS1="${{PS1}}"
-               function __shpool__prompt_command() {{
-                  PS1="${{SHPOOL__OLD_PS1}}"
-                  for prompt_hook in "${{SHPOOL__OLD_PROMPT_COMMAND[@]}}"
-                  do
-                    eval "${{prompt_hook}}"
-                  done
-                  PS1="{prompt_prefix}${{PS1}}"
-               }}
-               PROMPT_COMMAND=__shpool__prompt_command
             fi
+            SHPOOL__OLD_PS1="${{PS1}}"
+            function __shpool__prompt_command() {{
+               local status=$?
+               __shpool__load_state
+               PS1="${{SHPOOL__OLD_PS1}}"
+               for prompt_hook in "${{SHPOOL__OLD_PROMPT_COMMAND[@]}}"
+               do
+                 __shpool__status "${{status}}"
+                 eval "${{prompt_hook}}"
+               done
+               if shopt -q promptvars; then
+                  PS1="{deferred}${{PS1}}"
+               else
+                  PS1="{now}${{PS1}}"
+               fi
+               return "${{status}}"
+            }}
+            PROMPT_COMMAND=__shpool__prompt_command
         "#
-        ),
-        (_, Ok(KnownShell::Zsh)) => format!(
-            r#"
+            )
+        }
+        Ok(KnownShell::Zsh) => {
+            // Same deal as bash, with PROMPT_SUBST standing in for promptvars.
+            let (deferred, now) = prompt_prefixes(prompt_tmpl);
+            format!(
+                r#"
+            SHPOOL__STATE_FILE="{state_file}"
+            function __shpool__load_state() {{
+                local key val
+                [[ -r "${{SHPOOL__STATE_FILE}}" ]] || return 0
+                while IFS='=' read -r key val; do
+                    case "${{key}}" in
+                        {PROMPT_VAR_PREFIX}*) typeset -g "${{key}}=${{val}}" ;;
+                        SHPOOL_SESSION_NAME|SHPOOL_SESSION_DIR|SSH_AUTH_SOCK) export "${{key}}=${{val}}" ;;
+                    esac
+                done < "${{SHPOOL__STATE_FILE}}"
+            }}
+            __shpool__load_state
             typeset -a precmd_functions
             SHPOOL__OLD_PROMPT="${{PROMPT}}"
             function __shpool__reset_rprompt() {{
@@ -100,18 +156,51 @@ pub fn maybe_setup(
             }}
             precmd_functions[1,0]=(__shpool__reset_rprompt)
             function __shpool__prompt_command() {{
-               PROMPT="{prompt_prefix}${{PROMPT}}"
+               __shpool__load_state
+               if [[ -o promptsubst ]]; then
+                   PROMPT="{deferred}${{PROMPT}}"
+               else
+                   PROMPT="{now}${{PROMPT}}"
+               fi
             }}
             precmd_functions+=(__shpool__prompt_command)
         "#
-        ),
-        (_, Ok(KnownShell::Fish)) => format!(
-            r#"
+            )
+        }
+        Ok(KnownShell::Fish) => {
+            // fish has no way to delimit a var inside a string, so each var
+            // gets passed to printf as an arg of its own.
+            let prompt_prefix = prompt_tmpl
+                .fill(|var| shell_var(var).map(|v| format!("\" \"${v}\" \"")).unwrap_or_default());
+            format!(
+                r#"
+            set -g SHPOOL__STATE_FILE "{state_file}"
+            function __shpool__load_state
+                test -r $SHPOOL__STATE_FILE; or return 0
+                while read -l line
+                    set -l kv (string split -m 1 = -- $line)
+                    switch $kv[1]
+                        case '{PROMPT_VAR_PREFIX}*'
+                            set -g $kv[1] $kv[2..-1]
+                        case SHPOOL_SESSION_NAME SHPOOL_SESSION_DIR SSH_AUTH_SOCK
+                            set -gx $kv[1] $kv[2..-1]
+                    end
+                end < $SHPOOL__STATE_FILE
+            end
+            function __shpool__status; return $argv[1]; end
+            __shpool__load_state
             functions --copy fish_prompt shpool__old_prompt
-            function fish_prompt; echo -n "{prompt_prefix}"; shpool__old_prompt; end
+            function fish_prompt
+                set -l last_status $status
+                __shpool__load_state
+                printf '%s' "{prompt_prefix}"
+                __shpool__status $last_status
+                shpool__old_prompt
+            end
         "#
-        ),
-        (_, Err(e)) => {
+            )
+        }
+        Err(e) => {
             warn!("could not sniff shell: {}", e);
 
             // not the end of the world, we will just not inject a prompt prefix
@@ -141,6 +230,71 @@ pub fn maybe_setup(
     Ok(())
 }
 
+/// The metadata the hook injected into the shell loads before every prompt.
+pub struct ShellState<'a> {
+    pub session_name: &'a str,
+    pub session_dir: &'a Path,
+    pub ssh_auth_sock: &'a Path,
+    /// Values for the vars used in the prompt prefix.
+    pub prompt_vars: Vec<(String, String)>,
+}
+
+/// Write out the state file for a shell to pick up at its next prompt.
+pub fn write_state(state_file: &Path, state: &ShellState) -> anyhow::Result<()> {
+    // The hook reads the file line by line, so values can't span lines.
+    let line = |key: &str, val: &str| format!("{key}={}\n", val.replace('\n', " "));
+    let mut buf = line("SHPOOL_SESSION_NAME", state.session_name);
+    buf.push_str(&line("SHPOOL_SESSION_DIR", &state.session_dir.to_string_lossy()));
+    buf.push_str(&line("SSH_AUTH_SOCK", &state.ssh_auth_sock.to_string_lossy()));
+    for (var, val) in state.prompt_vars.iter() {
+        if let Some(shell_var) = shell_var(var) {
+            buf.push_str(&line(&shell_var, val));
+        }
+    }
+
+    // Swap in a whole new file so the shell never sees half of one.
+    let dir = state_file.parent().ok_or(anyhow!("no state file parent dir"))?;
+    fs::create_dir_all(dir).context("creating state file dir")?;
+    let mut tmp = tempfile::NamedTempFile::new_in(dir).context("creating state tmp file")?;
+    tmp.write_all(buf.as_bytes()).context("writing state tmp file")?;
+    tmp.persist(state_file).context("persisting state file")?;
+    Ok(())
+}
+
+/// The template to fill in the prompt prefix with. Unless `expand_vars`
+/// is set, the prefix is used as is.
+pub fn prompt_template(prompt_prefix: &str, expand_vars: bool) -> template::Template {
+    if !expand_vars {
+        return template::Template::literal(prompt_prefix);
+    }
+    // Plenty of shell prompt syntax uses braces too, so a prefix that
+    // doesn't parse as a template is just taken as is.
+    template::Template::new_for_shell(prompt_prefix)
+        .unwrap_or_else(|_| template::Template::literal(prompt_prefix))
+}
+
+/// Fill in the prompt prefix for bash or zsh, once with the vars
+/// escaped so they are left for the shell to expand when it draws the
+/// prompt, and once with them expanded right away when the prefix gets
+/// put into the prompt.
+fn prompt_prefixes(prompt_tmpl: &template::Template) -> (String, String) {
+    let fill = |escape: &str| {
+        prompt_tmpl
+            .fill(|var| shell_var(var).map(|v| format!("{escape}${{{v}}}")).unwrap_or_default())
+    };
+    (fill("\\"), fill(""))
+}
+
+// The shell variable holding the given shpool var, if the var's name
+// makes for a legal shell variable name.
+fn shell_var(var: &str) -> Option<String> {
+    if var.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Some(format!("{PROMPT_VAR_PREFIX}{var}"))
+    } else {
+        None
+    }
+}
+
 #[instrument(skip_all)]
 fn wait_for_startup(pty_master: &mut shpool_pty::fork::Master) -> anyhow::Result<()> {
     test_hooks::emit("wait-for-startup-enter");
@@ -33,10 +33,23 @@ use tracing::{info, span, warn, Level};
 use super::events;
 use super::shell;
 
+/// A message to the reaper thread.
+#[derive(Debug)]
+pub enum Msg {
+    /// Reap the named session at the given time, replacing any time
+    /// it was going to be reaped at before.
+    Register(String, Instant),
+    /// Don't reap the named session after all.
+    Cancel(String),
+    /// The session has a new name, so any pending reaping should
+    /// follow it.
+    Rename { old_name: String, new_name: String },
+}
+
 /// Run the reaper thread loop. Should be invoked in a dedicated
 /// thread.
 pub fn run(
-    new_sess: crossbeam_channel::Receiver<(String, Instant)>,
+    new_sess: crossbeam_channel::Receiver<Msg>,
     shells: Arc<Mutex<HashMap<String, Box<shell::Session>>>>,
     events_bus: Arc<events::EventBus>,
 ) -> anyhow::Result<()> {
@@ -49,7 +62,7 @@ pub fn run(
         // empty heap loop, just waiting for new sessions to watch
         while heap.is_empty() {
             match new_sess.recv() {
-                Ok((session_name, reap_at)) => {
+                Ok(Msg::Register(session_name, reap_at)) => {
                     let gen_id = gen_ids.entry(session_name.clone()).or_insert(0);
                     *gen_id += 1;
                     info!(
@@ -58,6 +71,12 @@ pub fn run(
                     );
                     heap.push(Reapable { session_name, gen_id: *gen_id, reap_at });
                 }
+                Ok(Msg::Rename { old_name, new_name }) => {
+                    rename(&mut heap, &mut gen_ids, &old_name, &new_name);
+                }
+                Ok(Msg::Cancel(session_name)) => {
+                    cancel(&mut gen_ids, session_name);
+                }
                 Err(crossbeam_channel::RecvError) => {
                     info!("bailing due to RecvError in empty heap loop");
                     return Ok(());
@@ -76,7 +95,7 @@ pub fn run(
             crossbeam_channel::select! {
                 recv(new_sess) -> new_sess_msg => {
                     match new_sess_msg {
-                        Ok((session_name, reap_at)) => {
+                        Ok(Msg::Register(session_name, reap_at)) => {
                             let gen_id = gen_ids.entry(session_name.clone()).or_insert(0);
                             *gen_id += 1;
                             info!("scheduling {}:{} to be reaped at {:?}",
@@ -87,6 +106,12 @@ pub fn run(
                                 reap_at,
                             });
                         }
+                        Ok(Msg::Rename { old_name, new_name }) => {
+                            rename(&mut heap, &mut gen_ids, &old_name, &new_name);
+                        }
+                        Ok(Msg::Cancel(session_name)) => {
+                            cancel(&mut gen_ids, session_name);
+                        }
                         Err(crossbeam_channel::RecvError) => {
                             info!("bailing due to RecvError");
                             return Ok(())
@@ -107,24 +132,65 @@ pub fn run(
 
                     let _s = span!(Level::INFO, "lock(shells)").entered();
                     let mut shells = shells.lock();
-                    if let Some(sess) = shells.get(&reapable.session_name) {
-                        if let Err(e) = sess.kill() {
-                            warn!("error trying to kill '{}': {:?}",
-                                  reapable.session_name, e);
-                        }
-                    } else {
+                    if !shells.contains_key(&reapable.session_name) {
                         warn!("tried to kill '{}' but it wasn't in the shells tab",
                               reapable.session_name);
                         continue;
                     }
-                    shells.remove(&reapable.session_name);
-                    events_bus.publish(&events::Event::SessionRemoved);
+                    if let Err(e) = shell::kill_and_remove(
+                        &mut shells,
+                        &reapable.session_name,
+                        events::RemovalReason::Ttl,
+                        &events_bus,
+                    ) {
+                        warn!("error trying to kill '{}': {:?}",
+                              reapable.session_name, e);
+                    }
                 }
             }
         }
     }
 }
 
+/// Forget about any pending reaping of a session. The entry stays in
+/// the heap, but with a stale gen so it gets skipped when it comes up.
+fn cancel(gen_ids: &mut HashMap<String, usize>, session_name: String) {
+    let gen_id = gen_ids.entry(session_name.clone()).or_insert(0);
+    *gen_id += 1;
+    info!("cancelling reaping of {}, now at gen {}", session_name, *gen_id);
+}
+
+/// Move the pending reaping of a session over to its new name. The
+/// session gets a fresh generation under the new name so that reapings
+/// left over from an earlier session with that name stay dead, and the
+/// old name's generation is bumped so that nothing still scheduled under
+/// it can hit a later session which takes the old name.
+fn rename(
+    heap: &mut BinaryHeap<Reapable>,
+    gen_ids: &mut HashMap<String, usize>,
+    old_name: &str,
+    new_name: &str,
+) {
+    let old_gen = gen_ids.entry(String::from(old_name)).or_insert(0);
+    let live_gen = *old_gen;
+    *old_gen += 1;
+    let new_gen = gen_ids.entry(String::from(new_name)).or_insert(0);
+    *new_gen += 1;
+    let new_gen = *new_gen;
+
+    *heap = std::mem::take(heap)
+        .into_iter()
+        .map(|mut r| {
+            if r.session_name == old_name && r.gen_id == live_gen {
+                info!("rescheduling {}:{} as {}:{}", old_name, live_gen, new_name, new_gen);
+                r.session_name = String::from(new_name);
+                r.gen_id = new_gen;
+            }
+            r
+        })
+        .collect();
+}
+
 /// A record in the min heap that we use to track the
 /// sessions that need to be cleaned up.
 #[derive(Debug)]
@@ -153,3 +219,32 @@ impl cmp::Ord for Reapable {
         other.reap_at.cmp(&self.reap_at)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rename_follows_live_gen() {
+        let now = Instant::now();
+        let mut heap = BinaryHeap::new();
+        let mut gen_ids = HashMap::new();
+        // a stale reaping from an earlier 'a', then the live one
+        gen_ids.insert(String::from("a"), 2);
+        heap.push(Reapable { session_name: String::from("a"), gen_id: 1, reap_at: now });
+        heap.push(Reapable { session_name: String::from("a"), gen_id: 2, reap_at: now });
+        // an earlier 'b' that is long gone
+        gen_ids.insert(String::from("b"), 1);
+
+        rename(&mut heap, &mut gen_ids, "a", "b");
+
+        let live: Vec<_> = heap
+            .iter()
+            .filter(|r| gen_ids.get(&r.session_name) == Some(&r.gen_id))
+            .map(|r| r.session_name.as_str())
+            .collect();
+        assert_eq!(live, vec!["b"]);
+        assert_eq!(gen_ids["b"], 2);
+        assert_eq!(gen_ids["a"], 3);
+    }
+}
@@ -78,6 +78,26 @@ fn make_suffix_duration(n: u64, c: char) -> Option<time::Duration> {
     }
 }
 
+/// Formats a duration in the suffix format accepted by `parse`, using
+/// at most the two largest units (i.e. '1d3h' or '5m12s').
+pub fn format(d: time::Duration) -> String {
+    let units = [('d', 60 * 60 * 24), ('h', 60 * 60), ('m', 60), ('s', 1)];
+    let secs = d.as_secs();
+    let Some(i) = units.iter().position(|(_, unit_secs)| secs >= *unit_secs) else {
+        return String::from("0s");
+    };
+
+    let (suffix, unit_secs) = units[i];
+    let mut out = format!("{}{}", secs / unit_secs, suffix);
+    if let Some((next_suffix, next_unit_secs)) = units.get(i + 1) {
+        let n = (secs % unit_secs) / next_unit_secs;
+        if n > 0 {
+            out.push_str(&format!("{n}{next_suffix}"));
+        }
+    }
+    out
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -106,6 +126,22 @@ mod test {
         }
     }
 
+    #[test]
+    fn formatting() {
+        let cases = vec![
+            (0, "0s"),
+            (5, "5s"),
+            (65, "1m5s"),
+            (60 * 60, "1h"),
+            (60 * 60 * 24 + 60 * 60 + 30, "1d1h"),
+            (3 * 60 * 60 + 59, "3h"),
+        ];
+
+        for (secs, want) in cases.into_iter() {
+            assert_eq!(format(time::Duration::from_secs(secs)), want);
+        }
+    }
+
     #[test]
     fn errors() {
         let cases = vec![
@@ -5,19 +5,40 @@
 //! `crate::daemon::events`.
 
 use std::{
+    collections::HashMap,
     io::{self, BufRead, BufReader, Write},
     os::unix::net::UnixStream,
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use shpool_protocol::{ConnectHeader, MaybeSwitch};
+
+use crate::{protocol, protocol::ClientResult, template::Template};
+
+/// Which part of the stream to ask the daemon for.
+pub struct Subscription {
+    /// Replay the retained events after this seq before going live.
+    pub resume_from: Option<u64>,
+    /// Event type globs; an event must match one of them.
+    pub types: Vec<String>,
+    /// Session name globs, which may contain `{var}` substitutions; an
+    /// event must be about a session matching one of them.
+    pub sessions: Vec<String>,
+}
 
 /// Connect to the events socket, copy each line to stdout, and flush per
 /// line so the stream is usable in pipes (`shpool events | jq`). Returns
 /// when the daemon closes the connection.
-pub fn run(socket_path: &Path) -> anyhow::Result<()> {
-    let stream = UnixStream::connect(socket_path)
-        .with_context(|| format!("connecting to events socket {:?}", socket_path))?;
+pub fn run(socket: PathBuf, subscription: Subscription) -> anyhow::Result<()> {
+    let request = request_line(&socket, subscription)?;
+    let events_socket = crate::daemon::events::socket_path(&socket);
+    let mut stream = UnixStream::connect(&events_socket)
+        .with_context(|| format!("connecting to events socket {:?}", events_socket))?;
+    // Always send a request line, even a blank one, so the daemon starts
+    // the stream right away instead of waiting to see if we will ask for
+    // anything.
+    stream.write_all(request.as_bytes()).context("writing events request")?;
     let reader = BufReader::new(stream);
     let mut stdout = io::stdout().lock();
     for line in reader.lines() {
@@ -28,3 +49,56 @@ pub fn run(socket_path: &Path) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+/// Build the newline terminated request line for `subscription`, filling
+/// in any session templates from the daemon's current vars.
+fn request_line(socket: &Path, subscription: Subscription) -> anyhow::Result<String> {
+    let mut fields = vec![];
+    if let Some(seq) = subscription.resume_from {
+        fields.push(format!("resume_from={seq}"));
+    }
+    for pattern in subscription.types {
+        fields.push(format!("type={}", check_pattern(pattern)?));
+    }
+    if !subscription.sessions.is_empty() {
+        let vars = if subscription.sessions.iter().any(|s| s.contains('{')) {
+            get_vars(socket)?
+        } else {
+            HashMap::new()
+        };
+        for pattern in subscription.sessions {
+            let tmpl = Template::new(&pattern).context("parsing session tmpl")?;
+            fields.push(format!("session={}", check_pattern(tmpl.apply(&vars))?));
+        }
+    }
+    Ok(format!("{}\n", fields.join(" ")))
+}
+
+/// The request line is whitespace separated, so patterns can't contain any.
+fn check_pattern(pattern: String) -> anyhow::Result<String> {
+    if pattern.is_empty() || pattern.contains(char::is_whitespace) {
+        eprintln!("event filter patterns must be non-empty and contain no whitespace");
+        return Err(anyhow!("bad event filter pattern {:?}", pattern));
+    }
+    Ok(pattern)
+}
+
+fn get_vars(socket: &Path) -> anyhow::Result<HashMap<String, String>> {
+    let mut client = match protocol::Client::new(socket) {
+        Ok(ClientResult::JustClient(c)) => c,
+        Ok(ClientResult::VersionMismatch { warning, client }) => {
+            eprintln!("warning: {warning}, try restarting your daemon");
+            client
+        }
+        Err(err) => {
+            let io_err = err.downcast::<io::Error>()?;
+            if io_err.kind() == io::ErrorKind::NotFound {
+                eprintln!("could not connect to daemon");
+            }
+            return Err(io_err).context("connecting to daemon");
+        }
+    };
+    client.write_connect_header(ConnectHeader::GetVars).context("getting vars")?;
+    let maybe_switch: MaybeSwitch = client.read_reply().context("reading reply")?;
+    Ok(maybe_switch.vars.into_iter().collect())
+}
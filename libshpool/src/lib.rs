@@ -28,6 +28,8 @@ use tracing::error;
 use tracing_subscriber::{fmt::format::FmtSpan, prelude::*};
 
 mod attach;
+mod capture;
+mod choose;
 mod common;
 mod config;
 mod config_watcher;
@@ -42,13 +44,20 @@ mod hooks;
 mod kill;
 mod list;
 mod protocol;
+mod record;
+mod rename;
+mod replay;
+mod send_keys;
 mod session_restore;
 mod set_log_level;
 mod template;
 mod test_hooks;
+mod ttl;
 mod tty;
 mod user;
 mod var;
+mod wait;
+mod wait_for_output;
 
 /// The command line arguments that shpool expects.
 /// These can be directly parsed with clap or manually
@@ -138,6 +147,30 @@ pub enum Commands {
             help = "Create/attach the session and immediately detach (use with --force to detach any existing client first)"
         )]
         background: bool,
+        #[clap(
+            short,
+            long,
+            conflicts_with_all = ["force", "background"],
+            long_help = "Watch an existing session without taking it over
+
+Rather than detaching any tty already attached to the session, get a
+read-only mirror of its output. Input is ignored, except that the detach
+keybinding stops watching. Resizing the local terminal has no effect on
+the session."
+        )]
+        watch: bool,
+        #[clap(
+            long,
+            conflicts_with_all = ["force", "background", "watch"],
+            long_help = "Share the session with any tty that is already attached
+
+Rather than being rejected as busy, join the session as an additional
+client. Output goes to every client and input from all of them gets
+merged. The size of the session is picked based on the
+shared_size_policy config option. The detach keybinding only
+disconnects this client."
+        )]
+        share: bool,
         #[clap(
             long,
             long_help = "Automatically kill the session after the given time
@@ -152,6 +185,70 @@ using a number with a trailing letter to indicate time unit
 (i.e. '3d', '19h', or '5s')."
         )]
         ttl: Option<String>,
+        #[clap(
+            long,
+            value_name = "FORMAT",
+            long_help = "Log the session's output to disk in the given format
+
+Overrides the output_log config option for this session. The log is
+written to the session's $SHPOOL_SESSION_DIR and rotated according to
+the output_log config. Use 'off' to turn logging off for a session even
+though the config has it on.
+
+This option only applies when first creating a session, it is ignored on
+reattach."
+        )]
+        output_log: Option<shpool_protocol::OutputLogFormat>,
+        #[clap(
+            long,
+            value_name = "PROFILE",
+            long_help = "Use the named keybinding profile from the config
+
+Rather than the keybindings the session would normally get from the
+keybinding_profile sessions and cmds patterns or the top level
+keybindings, use the ones from the named profile for as long as this
+client is attached."
+        )]
+        keybindings: Option<String>,
+        #[clap(
+            long,
+            value_name = "POLICY",
+            long_help = "When to start the session's command again after it exits
+
+Overrides the restart_profile config option for this session. With
+'on-failure' the command is restarted when it exits with a non-zero
+status or is killed by a signal, with 'always' it is restarted no
+matter how it exits. The session keeps its output and any attached
+clients across restarts. Only works together with --cmd.
+
+This option only applies when first creating a session, it is ignored on
+reattach."
+        )]
+        restart: Option<shpool_protocol::RestartPolicy>,
+        #[clap(
+            long,
+            value_name = "N",
+            long_help = "Give up restarting the session's command after N restarts
+
+Overrides the max_restarts from the restart_profile config option for
+this session. Unlimited by default.
+
+This option only applies when first creating a session, it is ignored on
+reattach."
+        )]
+        max_restarts: Option<u32>,
+        #[clap(
+            long,
+            long_help = "Keep the session around after its shell exits
+
+Rather than disappearing, the session stays listed with its exit status
+and attaching to it shows its final screen, as if the linger config
+option were set. Dismiss it with 'shpool kill'.
+
+This option only applies when first creating a session, it is ignored on
+reattach."
+        )]
+        linger: bool,
         #[clap(
             short,
             long,
@@ -196,6 +293,36 @@ new session name. See 'shpool help var' for more details.")]
         name: String,
     },
 
+    #[clap(about = "Print the contents of a session's screen
+
+This dumps what the session would show if you attached to it, without
+disturbing any tty that is currently attached. Terminal control codes
+are kept so that colors and other formatting come through. Requires
+session_restore_mode to be something other than \"simple\".")]
+    #[non_exhaustive]
+    Capture {
+        #[clap(
+            short = 'n',
+            long,
+            help = "Capture the last n lines, including scrollback, rather than the screen"
+        )]
+        lines: Option<u16>,
+        #[clap(short, long, help = "Output as JSON")]
+        json: bool,
+        #[clap(help = "the session to capture")]
+        session: String,
+    },
+
+    #[clap(about = "Pick a session to attach to from a full-screen list
+
+Shows every session along with its status, its age and how long ago a
+terminal was last attached to it, and keeps the list up to date as
+sessions come and go. Type to fuzzy filter the list, use the arrow keys
+to move around, and hit enter to attach to the selected session, or to
+create a new one named after the filter. Ctrl-k kills the selected
+session, Ctrl-d detaches it, Ctrl-r renames it and Esc quits.")]
+    Choose,
+
     #[clap(about = "Make the given session detach from shpool
 
 This does not close the shell. If no session name is provided
@@ -213,7 +340,7 @@ Connects to the events socket and writes each event (one JSON object
 per line) to stdout, flushing after every line so the stream is
 pipeline-friendly (e.g. `shpool events | jq`). See EVENTS.md for
 details.")]
-    Events,
+    Events(EventsArgs),
 
     #[clap(about = "Kill the given sessions
 
@@ -232,6 +359,131 @@ will be used if it is present in the environment.")]
     List {
         #[clap(short, long, help = "Output as JSON, includes extra fields")]
         json: bool,
+        #[clap(
+            short,
+            long,
+            conflicts_with = "json",
+            long_help = "A comma separated list of columns to display
+
+The available columns are name, status, pid (the shell's pid), fg (the
+process running in the foreground), fg_pid, cwd, cmd, start_cmd, ttl (the
+time left before the session gets killed), size (the pty size as COLSxROWS),
+bytes (the output produced since a client last attached) and exited (how long
+ago a lingering session's shell exited). Defaults to 'name,status'."
+        )]
+        format: Option<String>,
+    },
+
+    #[clap(about = "Give a session a new name
+
+The shell keeps running and any attached terminal stays attached.
+If shpool injected its prompt prefix into the shell, the prompt and
+$SHPOOL_SESSION_NAME pick up the new name at the next prompt.")]
+    #[non_exhaustive]
+    Rename {
+        #[clap(help = "the session to rename")]
+        old_name: String,
+        #[clap(help = "the new name for the session")]
+        new_name: String,
+    },
+
+    #[clap(about = "Inspect or change a session's ttl
+
+The ttl is normally set with 'shpool attach --ttl' when the session is
+created. These commands show how long a session has left, or give it a
+new deadline, no matter how it was created.")]
+    #[non_exhaustive]
+    Ttl {
+        #[clap(subcommand)]
+        command: TtlCommands,
+    },
+
+    #[clap(about = "Wait for a session's shell to exit
+
+Blocks until the session's shell exits, then exits with the shell's exit
+status, which makes it easy for scripts to kick off a job with
+'shpool attach -b -c ...' and pick up its result later. With --until,
+waits for the session to be attached, detached or removed instead. If
+the session gets renamed while waiting, shpool keeps waiting on it under
+its new name.")]
+    #[non_exhaustive]
+    Wait {
+        #[clap(
+            long,
+            value_enum,
+            default_value_t = shpool_protocol::WaitUntil::Exited,
+            long_help = "The state to wait for
+
+When waiting for the session to be attached or detached, shpool exits with
+status 1 if the shell exits first. When waiting for the session to be
+removed, shpool exits with the shell's exit status if it is known and 0
+otherwise. A session whose shell has exited counts as removed unless it is
+lingering."
+        )]
+        until: shpool_protocol::WaitUntil,
+        #[clap(
+            long,
+            value_name = "DURATION",
+            long_help = "Give up after DURATION (e.g. '10m')
+
+Takes a duration in the same format as 'shpool attach --ttl'. shpool exits
+with status 124 if the timeout runs out first."
+        )]
+        timeout: Option<String>,
+        #[clap(help = "the session to wait on")]
+        session: String,
+    },
+
+    #[clap(about = "Wait for a session's output to match a regex
+
+Blocks until a line of the session's output matches PATTERN, then prints
+the matching text. Output gets matched one line at a time with escape
+codes stripped, so a pattern can't span lines. A match that runs to the
+end of an unfinished line (like a prompt) only counts once the output goes
+quiet for a moment. Only new output is matched unless --screen is given.
+shpool exits with status 1 if the shell exits first.")]
+    #[non_exhaustive]
+    WaitForOutput {
+        #[clap(
+            long,
+            help = "Also match what is already on the screen, so output that showed up just before waiting started doesn't get missed"
+        )]
+        screen: bool,
+        #[clap(
+            long,
+            value_name = "DURATION",
+            long_help = "Give up after DURATION (e.g. '10m')
+
+Takes a duration in the same format as 'shpool attach --ttl'. shpool exits
+with status 124 if the timeout runs out first."
+        )]
+        timeout: Option<String>,
+        #[clap(help = "the session to watch")]
+        session: String,
+        #[clap(help = "the regex to wait for")]
+        pattern: String,
+    },
+
+    #[clap(about = "Type some input into a session
+
+This writes input to the session's shell as if it had been typed into an
+attached terminal, whether or not a client is actually attached. Each
+argument that names a single key in the keybinding language (i.e. Ctrl-c
+or Space) gets sent as that key, and all other arguments are sent as
+literal text. Like tmux, arguments are sent back to back with nothing in
+between, so `shpool send-keys dev echo hi` types `echohi`. Quote text
+with spaces in it, or put Space between the words. Use Ctrl-m to hit
+enter, for example `shpool send-keys dev 'make run' Ctrl-m`.")]
+    #[non_exhaustive]
+    SendKeys {
+        #[clap(short, long, help = "Send all the arguments as literal text, ignoring key names")]
+        literal: bool,
+        #[clap(long, conflicts_with_all = ["keys", "literal"], help = "Send stdin instead of the arguments")]
+        stdin: bool,
+        #[clap(help = "the session to send input to")]
+        session: String,
+        #[clap(help = "text and key names to send")]
+        keys: Vec<String>,
     },
 
     #[clap(about = "Dynamically change daemon log level
@@ -263,6 +515,72 @@ you could just do `shpool var set workspace key-bugfix`.
         #[clap(subcommand)]
         command: VarCommands,
     },
+
+    #[clap(about = "Record a session's output
+
+Recordings are asciicast v2 files, the format used by asciinema, which
+capture the session's output along with timestamps and resizes. They
+can be played back with 'shpool replay' or any asciicast player.")]
+    #[non_exhaustive]
+    Record {
+        #[clap(subcommand)]
+        command: RecordCommands,
+    },
+
+    #[clap(about = "Play back an asciicast recording
+
+Plays a recording made with 'shpool record' (or any other asciicast v2
+file) to the terminal. While playing, space pauses and resumes, '+' and
+'-' double and halve the speed, and 'q' quits.")]
+    #[non_exhaustive]
+    Replay {
+        #[clap(
+            short,
+            long,
+            default_value_t = 1.0,
+            help = "Playback speed, i.e. 2 plays twice as fast as recorded"
+        )]
+        speed: f64,
+        #[clap(
+            long,
+            value_name = "DURATION",
+            help = "Never pause longer than DURATION between events (e.g. '2s')"
+        )]
+        idle_limit: Option<String>,
+        #[clap(help = "the recording to play")]
+        file: PathBuf,
+    },
+}
+
+/// The arguments to the events command. These live in a struct of their
+/// own so that the `Events` variant stays the same shape as options get
+/// added.
+#[derive(clap::Args, Debug, Default)]
+#[non_exhaustive]
+pub struct EventsArgs {
+    #[clap(
+        long,
+        value_name = "SEQ",
+        help = "First replay the retained events after sequence number SEQ"
+    )]
+    pub resume_from: Option<u64>,
+    #[clap(
+        long = "type",
+        value_name = "GLOB",
+        help = "Only show events whose type matches GLOB (e.g. 'session.*')
+
+May be given more than once to show events matching any of the globs."
+    )]
+    pub types: Vec<String>,
+    #[clap(
+        long = "session",
+        value_name = "GLOB",
+        help = "Only show events about sessions whose name matches GLOB
+
+GLOB may use {var} substitutions, which are filled in from the
+daemon's vars when subscribing. May be given more than once."
+    )]
+    pub sessions: Vec<String>,
 }
 
 /// The subcommds of the var command.
@@ -294,6 +612,78 @@ This removes the given variable from the environment.")]
     Unset { var: String },
 }
 
+/// The subcommands of the ttl command. Durations are given in the same
+/// format as 'shpool attach --ttl'.
+#[derive(Subcommand, Debug)]
+#[non_exhaustive]
+pub enum TtlCommands {
+    #[clap(about = "Print how long the session has left
+
+Prints 'none' if the session does not have a ttl.")]
+    #[non_exhaustive]
+    Get {
+        #[clap(help = "the session to inspect")]
+        session: String,
+    },
+    #[clap(about = "Kill the session once the given time has passed from now")]
+    #[non_exhaustive]
+    Set {
+        #[clap(help = "the session to change")]
+        session: String,
+        #[clap(help = "how long from now to kill the session, i.e. '2h'")]
+        duration: String,
+    },
+    #[clap(about = "Give the session more time
+
+A session without a ttl gets one the given time from now.")]
+    #[non_exhaustive]
+    Extend {
+        #[clap(help = "the session to change")]
+        session: String,
+        #[clap(help = "how much time to add, i.e. '30m'")]
+        duration: String,
+    },
+    #[clap(about = "Remove the session's ttl so it is never killed automatically")]
+    #[non_exhaustive]
+    Clear {
+        #[clap(help = "the session to change")]
+        session: String,
+    },
+}
+
+/// The subcommands of the record command.
+#[derive(Subcommand, Debug)]
+#[non_exhaustive]
+pub enum RecordCommands {
+    #[clap(about = "Start recording a session
+
+The recording starts off with the session's current screen and then
+captures all of its output until it is stopped, the session exits,
+or the daemon shuts down. If no session name is provided
+$SHPOOL_SESSION_NAME will be used if it is present in the environment.")]
+    #[non_exhaustive]
+    Start {
+        #[clap(
+            short,
+            long,
+            value_name = "FILE",
+            help = "Where to write the recording, <session>-<unix time>.cast by default"
+        )]
+        output: Option<PathBuf>,
+        #[clap(help = "the session to record")]
+        session: Option<String>,
+    },
+    #[clap(about = "Stop recording a session
+
+Prints the path of the finished recording. If no session name is provided
+$SHPOOL_SESSION_NAME will be used if it is present in the environment.")]
+    #[non_exhaustive]
+    Stop {
+        #[clap(help = "the session to stop recording")]
+        session: Option<String>,
+    },
+}
+
 impl Args {
     /// Version indicates if the wrapping binary must display the
     /// version then exit.
@@ -382,7 +772,6 @@ pub unsafe fn run(
         }
         _ => {}
     }
-
     let log_level_filter = if args.verbose == 0 {
         tracing_subscriber::filter::LevelFilter::INFO
     } else if args.verbose == 1 {
@@ -443,7 +832,10 @@ pub unsafe fn run(
 
     if !config_manager.get().nodaemonize.unwrap_or(false) || args.daemonize {
         let arg0 = env::args().next().ok_or(anyhow!("arg0 missing"))?;
-        if !args.no_daemonize && !matches!(args.command, Commands::Daemon) {
+        // Replaying a recording is purely local, so there is no need for a
+        // daemon.
+        if !args.no_daemonize && !matches!(args.command, Commands::Daemon | Commands::Replay { .. })
+        {
             daemonize::maybe_launch_daemon(&config_manager, &args, arg0, &socket)?;
         }
     }
@@ -468,15 +860,60 @@ pub unsafe fn run(
             log_level_handle,
             socket,
         ),
-        Commands::Attach { force, background, ttl, cmd, dir, start_cmd, name } => {
-            attach::run(socket, config_manager, name, force, background, ttl, cmd, dir, start_cmd)
-        }
+        Commands::Attach {
+            force,
+            background,
+            watch,
+            share,
+            ttl,
+            output_log,
+            keybindings,
+            restart,
+            max_restarts,
+            linger,
+            cmd,
+            dir,
+            start_cmd,
+            name,
+        } => attach::run(
+            socket,
+            config_manager,
+            name,
+            force,
+            background,
+            watch,
+            share,
+            ttl,
+            cmd,
+            dir,
+            start_cmd,
+            output_log,
+            keybindings,
+            restart,
+            max_restarts,
+            linger,
+        ),
+        Commands::Capture { lines, json, session } => capture::run(session, lines, json, socket),
+        Commands::Choose => choose::run(socket, config_manager),
         Commands::Detach { sessions } => detach::run(sessions, socket),
         Commands::Kill { sessions } => kill::run(sessions, socket),
-        Commands::List { json } => list::run(socket, json),
+        Commands::List { json, format } => list::run(socket, json, format),
+        Commands::Rename { old_name, new_name } => rename::run(old_name, new_name, socket),
+        Commands::Ttl { command } => ttl::run(command, socket),
+        Commands::Wait { until, timeout, session } => wait::run(session, until, timeout, socket),
+        Commands::WaitForOutput { screen, timeout, session, pattern } => {
+            wait_for_output::run(session, pattern, screen, timeout, socket)
+        }
+        Commands::SendKeys { literal, stdin, session, keys } => {
+            send_keys::run(session, keys, literal, stdin, socket)
+        }
         Commands::SetLogLevel { level } => set_log_level::run(level, socket),
         Commands::Var { command } => var::run(socket, command),
-        Commands::Events => events::run(&crate::daemon::events::socket_path(&socket)),
+        Commands::Record { command } => record::run(command, socket),
+        Commands::Replay { speed, idle_limit, file } => replay::run(file, speed, idle_limit),
+        Commands::Events(EventsArgs { resume_from, types, sessions }) => {
+            events::run(socket, events::Subscription { resume_from, types, sessions })
+        }
     };
 
     if let Err(err) = res {
@@ -12,14 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{io, path::PathBuf};
+use std::{io, path::PathBuf, time};
 
-use anyhow::Context;
-use shpool_protocol::{ConnectHeader, ListReply};
+use anyhow::{anyhow, Context};
+use shpool_protocol::{ConnectHeader, ListReply, Session};
 
-use crate::{protocol, protocol::ClientResult};
+use crate::{duration, protocol, protocol::ClientResult};
+
+/// The columns that get displayed if no --format is given.
+const DEFAULT_FORMAT: &str = "name,status";
+
+pub fn run(socket: PathBuf, json_output: bool, format: Option<String>) -> anyhow::Result<()> {
+    let columns = match parse_format(format.as_deref().unwrap_or(DEFAULT_FORMAT)) {
+        Ok(columns) => columns,
+        Err(err) => {
+            eprintln!("{err}");
+            return Err(err);
+        }
+    };
 
-pub fn run(socket: PathBuf, json_output: bool) -> anyhow::Result<()> {
     let mut client = match protocol::Client::new(socket) {
         Ok(ClientResult::JustClient(c)) => c,
         Ok(ClientResult::VersionMismatch { warning, client }) => {
@@ -41,18 +52,135 @@ pub fn run(socket: PathBuf, json_output: bool) -> anyhow::Result<()> {
     if json_output {
         println!("{}", serde_json::to_string_pretty(&reply)?);
     } else {
-        let longest_session_name_len =
-            reply.sessions.iter().map(|s| s.name.len()).max().unwrap_or(0);
-
-        println!("NAME\tSTATUS");
-        for session in reply.sessions.iter() {
-            let mut name = session.name.clone();
-            while name.len() < longest_session_name_len {
-                name.push(' ');
+        let now = time::SystemTime::now();
+        let rows: Vec<Vec<String>> = reply
+            .sessions
+            .iter()
+            .map(|session| columns.iter().map(|c| c.value(session, now)).collect())
+            .collect();
+
+        // Pad every column but the last out to its widest value so that
+        // the rows line up.
+        let widths: Vec<usize> = (0..columns.len())
+            .map(|i| rows.iter().map(|row| row[i].len()).max().unwrap_or(0))
+            .collect();
+
+        println!("{}", columns.iter().map(|c| c.header()).collect::<Vec<_>>().join("\t"));
+        for row in rows.into_iter() {
+            let mut cells = vec![];
+            for (i, mut cell) in row.into_iter().enumerate() {
+                if i + 1 < columns.len() {
+                    while cell.len() < widths[i] {
+                        cell.push(' ');
+                    }
+                }
+                cells.push(cell);
             }
-            println!("{}\t{}", name, session.status);
+            println!("{}", cells.join("\t"));
         }
     }
 
     Ok(())
 }
+
+/// A column that can be selected with `shpool list --format`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Column {
+    Name,
+    Status,
+    Pid,
+    Fg,
+    FgPid,
+    Cwd,
+    Cmd,
+    StartCmd,
+    Ttl,
+    Size,
+    Bytes,
+    Exited,
+}
+
+impl Column {
+    const ALL: [(&'static str, Column); 12] = [
+        ("name", Column::Name),
+        ("status", Column::Status),
+        ("pid", Column::Pid),
+        ("fg", Column::Fg),
+        ("fg_pid", Column::FgPid),
+        ("cwd", Column::Cwd),
+        ("cmd", Column::Cmd),
+        ("start_cmd", Column::StartCmd),
+        ("ttl", Column::Ttl),
+        ("size", Column::Size),
+        ("bytes", Column::Bytes),
+        ("exited", Column::Exited),
+    ];
+
+    fn header(&self) -> String {
+        Self::ALL
+            .iter()
+            .find(|(_, c)| c == self)
+            .map(|(name, _)| name.to_uppercase())
+            .unwrap_or_default()
+    }
+
+    fn value(&self, session: &Session, now: time::SystemTime) -> String {
+        let or_dash = |v: Option<String>| v.unwrap_or_else(|| String::from("-"));
+        match self {
+            Column::Name => session.name.clone(),
+            Column::Status => session.status_label(),
+            Column::Pid => or_dash(session.pid.map(|p| p.to_string())),
+            Column::Fg => or_dash(session.foreground_cmd.clone()),
+            Column::FgPid => or_dash(session.foreground_pid.map(|p| p.to_string())),
+            Column::Cwd => or_dash(session.cwd.clone()),
+            Column::Cmd => or_dash(session.cmd.clone()),
+            Column::StartCmd => or_dash(session.start_cmd.clone()),
+            Column::Ttl => or_dash(session.ttl_expires_at_unix_ms.map(|ms| {
+                let expires_at = time::UNIX_EPOCH + time::Duration::from_millis(ms.max(0) as u64);
+                duration::format(expires_at.duration_since(now).unwrap_or_default())
+            })),
+            Column::Size => {
+                or_dash(session.tty_size.as_ref().map(|s| format!("{}x{}", s.cols, s.rows)))
+            }
+            Column::Bytes => or_dash(session.output_bytes_since_attach.map(|b| b.to_string())),
+            Column::Exited => or_dash(session.exited_at_unix_ms.map(|ms| {
+                let exited_at = time::UNIX_EPOCH + time::Duration::from_millis(ms.max(0) as u64);
+                format!(
+                    "{} ago",
+                    duration::format(now.duration_since(exited_at).unwrap_or_default())
+                )
+            })),
+        }
+    }
+}
+
+/// Parse a comma separated list of column names.
+fn parse_format(format: &str) -> anyhow::Result<Vec<Column>> {
+    format
+        .split(',')
+        .map(|name| {
+            let name = name.trim();
+            Column::ALL.iter().find(|(n, _)| *n == name).map(|(_, c)| *c).ok_or_else(|| {
+                let known: Vec<&str> = Column::ALL.iter().map(|(n, _)| *n).collect();
+                anyhow!("unknown column '{}', expected one of: {}", name, known.join(", "))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() -> anyhow::Result<()> {
+        assert_eq!(parse_format(DEFAULT_FORMAT)?, vec![Column::Name, Column::Status]);
+        assert_eq!(parse_format("name, fg,ttl")?, vec![Column::Name, Column::Fg, Column::Ttl]);
+        assert_eq!(Column::FgPid.header(), "FG_PID");
+
+        let err = parse_format("name,bogus").unwrap_err();
+        assert!(err.to_string().contains("unknown column 'bogus'"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{env, io, path::PathBuf, time};
+
+use anyhow::{anyhow, Context};
+use shpool_protocol::{
+    ConnectHeader, RecordReply, RecordRequest, SessionMessageReply, SessionMessageRequest,
+    SessionMessageRequestPayload,
+};
+
+use crate::{common, protocol, protocol::ClientResult, RecordCommands};
+
+pub fn run(command: RecordCommands, socket: PathBuf) -> anyhow::Result<()> {
+    let (session, request) = match command {
+        RecordCommands::Start { output, session } => {
+            let session = resolve_session(session, "record")?;
+            let path = match output {
+                Some(path) => path,
+                None => {
+                    let now = time::SystemTime::now()
+                        .duration_since(time::UNIX_EPOCH)
+                        .context("getting timestamp")?;
+                    PathBuf::from(format!("{}-{}.cast", session, now.as_secs()))
+                }
+            };
+            // The daemon writes the file, so relative paths need to be
+            // resolved here rather than against the daemon's cwd.
+            let path = env::current_dir().context("getting cwd")?.join(path);
+            let path = path.to_str().ok_or(anyhow!("recording path is not utf8"))?.to_string();
+            (session, RecordRequest::Start { path })
+        }
+        RecordCommands::Stop { session } => {
+            (resolve_session(session, "stop recording")?, RecordRequest::Stop)
+        }
+    };
+
+    let mut client = match protocol::Client::new(socket) {
+        Ok(ClientResult::JustClient(c)) => c,
+        Ok(ClientResult::VersionMismatch { warning, client }) => {
+            eprintln!("warning: {warning}, try restarting your daemon");
+            client
+        }
+        Err(err) => {
+            let io_err = err.downcast::<io::Error>()?;
+            if io_err.kind() == io::ErrorKind::NotFound {
+                eprintln!("could not connect to daemon");
+            }
+            return Err(io_err).context("connecting to daemon");
+        }
+    };
+
+    client
+        .write_connect_header(ConnectHeader::SessionMessage(SessionMessageRequest {
+            session_name: session.clone(),
+            payload: SessionMessageRequestPayload::Record(request),
+        }))
+        .context("writing record request")?;
+    let reply: SessionMessageReply = client.read_reply().context("reading reply")?;
+
+    match reply {
+        SessionMessageReply::Record(RecordReply::Started) => Ok(()),
+        SessionMessageReply::Record(RecordReply::Stopped { path }) => {
+            println!("{path}");
+            Ok(())
+        }
+        SessionMessageReply::Record(RecordReply::AlreadyRecording { path }) => {
+            eprintln!("session '{session}' is already being recorded to {path}");
+            Err(anyhow!("already recording {session}"))
+        }
+        SessionMessageReply::Record(RecordReply::NotRecording) => {
+            eprintln!("session '{session}' is not being recorded");
+            Err(anyhow!("not recording {session}"))
+        }
+        SessionMessageReply::Record(RecordReply::Error(e)) => {
+            eprintln!("could not start recording: {e}");
+            Err(anyhow!("starting recording: {e}"))
+        }
+        SessionMessageReply::NotFound => {
+            eprintln!("not found: {session}");
+            Err(anyhow!("not found: {session}"))
+        }
+        reply => Err(anyhow!("unexpected reply: {:?}", reply)),
+    }
+}
+
+fn resolve_session(session: Option<String>, action: &str) -> anyhow::Result<String> {
+    let mut sessions: Vec<String> = session.into_iter().collect();
+    common::resolve_sessions(&mut sessions, action)?;
+    Ok(sessions.swap_remove(0))
+}
@@ -0,0 +1,221 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*! The `replay` subcommand: play an asciicast v2 recording, such as one
+  made with `shpool record`, back to the local terminal.
+
+  When stdin is a terminal, playback can be controlled with the keyboard:
+  space pauses and resumes, `+` and `-` double and halve the speed, and
+  `q` or Ctrl-C quits.
+*/
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::fd::AsFd,
+    path::PathBuf,
+    thread, time,
+};
+
+use anyhow::{anyhow, Context};
+use nix::{
+    poll::{self, PollFd, PollFlags, PollTimeout},
+    unistd::isatty,
+};
+use serde_derive::Deserialize;
+
+use crate::{duration, tty};
+
+const MAX_SPEED: f64 = 64.0;
+const MIN_SPEED: f64 = 1.0 / 64.0;
+
+pub fn run(file: PathBuf, speed: f64, idle_limit: Option<String>) -> anyhow::Result<()> {
+    if !(speed.is_finite() && speed > 0.0) {
+        eprintln!("--speed must be a positive number");
+        return Err(anyhow!("bad speed {speed}"));
+    }
+    let idle_limit = match idle_limit {
+        Some(src) => Some(duration::parse(&src).context("parsing idle limit")?),
+        None => None,
+    };
+
+    let mut lines = BufReader::new(
+        fs::File::open(&file).with_context(|| format!("opening recording {:?}", file))?,
+    )
+    .lines();
+    let header = lines.next().ok_or(anyhow!("empty recording"))?.context("reading header")?;
+    parse_header(&header)?;
+
+    let interactive = isatty(io::stdin())? && isatty(io::stdout())?;
+    let _tty_guard = if interactive { Some(tty::set_attach_flags()?) } else { None };
+    let mut controls = Controls { interactive, speed, paused: false };
+    let mut stdout = io::stdout().lock();
+
+    let mut last_time = 0.0;
+    for line in lines {
+        let line = line.context("reading event")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = parse_event(&line)?;
+        if !controls.wait(delay(last_time, event.time, idle_limit))? {
+            break;
+        }
+        last_time = event.time;
+        // Resizes and any other event types can't be reproduced on the
+        // local terminal, so only output gets played.
+        if event.code == "o" {
+            stdout.write_all(event.data.as_bytes()).context("writing output")?;
+            stdout.flush().context("flushing output")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Header {
+    version: u64,
+}
+
+fn parse_header(line: &str) -> anyhow::Result<()> {
+    let header: Header = serde_json::from_str(line).context("parsing recording header")?;
+    if header.version != 2 {
+        return Err(anyhow!("unsupported asciicast version {}", header.version));
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+struct Event {
+    /// Seconds since the start of the recording.
+    time: f64,
+    code: String,
+    data: String,
+}
+
+fn parse_event(line: &str) -> anyhow::Result<Event> {
+    let (time, code, data): (f64, String, String) =
+        serde_json::from_str(line).with_context(|| format!("parsing event {:?}", line))?;
+    Ok(Event { time, code, data })
+}
+
+/// How long to wait (at normal speed) between events at the given times,
+/// never waiting longer than `idle_limit`.
+fn delay(prev: f64, next: f64, idle_limit: Option<time::Duration>) -> time::Duration {
+    let d = time::Duration::try_from_secs_f64(next - prev).unwrap_or(time::Duration::ZERO);
+    match idle_limit {
+        Some(limit) => d.min(limit),
+        None => d,
+    }
+}
+
+struct Controls {
+    interactive: bool,
+    speed: f64,
+    paused: bool,
+}
+
+impl Controls {
+    /// Wait for `recorded` worth of recording time while handling key
+    /// presses. Returns false if the user asked to quit.
+    fn wait(&mut self, recorded: time::Duration) -> anyhow::Result<bool> {
+        if !self.interactive {
+            thread::sleep(recorded.div_f64(self.speed));
+            return Ok(true);
+        }
+
+        let stdin = io::stdin();
+        let mut remaining = recorded;
+        loop {
+            if !self.paused && remaining.is_zero() {
+                return Ok(true);
+            }
+            let timeout = if self.paused {
+                PollTimeout::NONE
+            } else {
+                // Round up so we don't spin on sub-millisecond waits.
+                let ms = remaining.div_f64(self.speed).as_millis() + 1;
+                PollTimeout::from(u16::try_from(ms).unwrap_or(u16::MAX))
+            };
+            let started = time::Instant::now();
+            let mut poll_fds = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
+            let nready = match poll::poll(&mut poll_fds, timeout) {
+                Ok(n) => n,
+                Err(nix::errno::Errno::EINTR) => 0,
+                Err(e) => return Err(e).context("polling stdin"),
+            };
+            if !self.paused {
+                remaining = remaining.saturating_sub(started.elapsed().mul_f64(self.speed));
+            }
+            if nready == 0 {
+                continue;
+            }
+
+            let mut buf = [0u8; 64];
+            let n = io::stdin().lock().read(&mut buf).context("reading stdin")?;
+            if n == 0 {
+                // stdin went away, so there is nothing left to control
+                // playback with.
+                self.interactive = false;
+                thread::sleep(remaining.div_f64(self.speed));
+                return Ok(true);
+            }
+            for key in &buf[..n] {
+                match key {
+                    b' ' => self.paused = !self.paused,
+                    b'+' | b'=' => self.speed = (self.speed * 2.0).min(MAX_SPEED),
+                    b'-' => self.speed = (self.speed / 2.0).max(MIN_SPEED),
+                    // q, Ctrl-C, Ctrl-D
+                    b'q' | 0x03 | 0x04 => return Ok(false),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header() {
+        assert!(parse_header(r#"{"version":2,"width":80,"height":24}"#).is_ok());
+        assert!(parse_header(r#"{"version":1,"width":80,"height":24}"#).is_err());
+        assert!(parse_header("[0.1, \"o\", \"hi\"]").is_err());
+    }
+
+    #[test]
+    fn event() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_event(r#"[1.5, "o", "hi\r\n"]"#)?,
+            Event { time: 1.5, code: String::from("o"), data: String::from("hi\r\n") }
+        );
+        assert_eq!(parse_event(r#"[2, "r", "100x30"]"#)?.code, "r");
+        assert!(parse_event(r#"[1.5, "o"]"#).is_err());
+        assert!(parse_event("garbage").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn delays() {
+        let ms = time::Duration::from_millis;
+        assert_eq!(delay(1.0, 1.25, None), ms(250));
+        assert_eq!(delay(1.0, 11.0, Some(ms(2000))), ms(2000));
+        assert_eq!(delay(1.0, 1.5, Some(ms(2000))), ms(500));
+        // out of order timestamps don't make us wait
+        assert_eq!(delay(2.0, 1.0, None), time::Duration::ZERO);
+    }
+}
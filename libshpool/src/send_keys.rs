@@ -0,0 +1,117 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Context};
+use shpool_protocol::{ConnectHeader, SendInputReply, SendInputRequest};
+
+use crate::{daemon::keybindings, protocol, protocol::ClientResult};
+
+pub fn run(
+    session: String,
+    keys: Vec<String>,
+    literal: bool,
+    stdin: bool,
+    socket: PathBuf,
+) -> anyhow::Result<()> {
+    let input = if stdin {
+        let mut input = vec![];
+        io::stdin().read_to_end(&mut input).context("reading input from stdin")?;
+        input
+    } else {
+        resolve_keys(&keys, literal)
+    };
+
+    let mut client = match protocol::Client::new(socket) {
+        Ok(ClientResult::JustClient(c)) => c,
+        Ok(ClientResult::VersionMismatch { warning, client }) => {
+            eprintln!("warning: {warning}, try restarting your daemon");
+            client
+        }
+        Err(err) => {
+            let io_err = err.downcast::<io::Error>()?;
+            if io_err.kind() == io::ErrorKind::NotFound {
+                eprintln!("could not connect to daemon");
+            }
+            return Err(io_err).context("connecting to daemon");
+        }
+    };
+
+    client
+        .write_connect_header(ConnectHeader::SendInput(SendInputRequest {
+            session: session.clone(),
+            input,
+        }))
+        .context("sending send-input header")?;
+    let reply: SendInputReply = client.read_reply().context("reading reply")?;
+
+    match reply {
+        SendInputReply::Ok => Ok(()),
+        SendInputReply::NotFound => {
+            eprintln!("not found: {session}");
+            Err(anyhow!("not found: {session}"))
+        }
+        SendInputReply::TimedOut => {
+            eprintln!("timed out: {session} is not reading its input");
+            Err(anyhow!("timed out: {session} is not reading its input"))
+        }
+        SendInputReply::Error(e) => {
+            eprintln!("error: {e}");
+            Err(anyhow!("sending input: {e}"))
+        }
+    }
+}
+
+/// Turn the command line args into the bytes to send. Each arg which names
+/// a single key in the keybinding language gets sent as the code for that key,
+/// everything else gets sent as is. Args are not separated by anything.
+fn resolve_keys(keys: &[String], literal: bool) -> Vec<u8> {
+    let mut input = vec![];
+    for key in keys.iter() {
+        match keybindings::key_code(key) {
+            Ok(code) if !literal => input.extend(code),
+            _ => input.extend(key.as_bytes()),
+        }
+    }
+    input
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_keys() {
+        let cases = vec![
+            (vec!["echo hi", "Ctrl-m"], false, b"echo hi\r".to_vec()),
+            (vec!["Ctrl-c"], false, vec![3]),
+            (vec!["Ctrl-c"], true, b"Ctrl-c".to_vec()),
+            (vec!["a", "Space", "b"], false, b"a b".to_vec()),
+            // no spaces get added between args, with or without --literal
+            (vec!["ls", "-l"], false, b"ls-l".to_vec()),
+            (vec!["echo", "hi"], true, b"echohi".to_vec()),
+            (vec!["Up", "Enter"], false, b"\x1b[A\r".to_vec()),
+            (vec!["Alt-b"], false, b"\x1bb".to_vec()),
+        ];
+
+        for (keys, literal, want) in cases.into_iter() {
+            let keys: Vec<String> = keys.into_iter().map(String::from).collect();
+            assert_eq!(resolve_keys(&keys, literal), want);
+        }
+    }
+}
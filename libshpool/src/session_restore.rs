@@ -54,6 +54,13 @@ pub trait SessionSpool {
     /// etc.
     fn restore_buffer(&self) -> Vec<u8>;
 
+    /// Gets the current screen, or the last `lines` lines of output if
+    /// given, for `shpool capture`.
+    ///
+    /// Unlike `restore_buffer`, this does not depend on the restore mode.
+    /// Returns None if the spool does not keep track of the output at all.
+    fn capture(&self, lines: Option<u16>) -> Option<Vec<u8>>;
+
     /// Process bytes from pty master.
     fn process(&mut self, bytes: &[u8]);
 }
@@ -68,6 +75,10 @@ impl SessionSpool for NullSpool {
         vec![]
     }
 
+    fn capture(&self, _: Option<u16>) -> Option<Vec<u8>> {
+        None
+    }
+
     fn process(&mut self, _: &[u8]) {}
 }
 
@@ -89,6 +100,10 @@ impl SessionSpool for Vt100Screen {
         self.parser.screen().contents_formatted()
     }
 
+    fn capture(&self, lines: Option<u16>) -> Option<Vec<u8>> {
+        Some(vt100_capture(&self.parser, lines))
+    }
+
     fn process(&mut self, bytes: &[u8]) {
         self.parser.process(bytes)
     }
@@ -114,6 +129,10 @@ impl SessionSpool for Vt100Lines {
         self.parser.screen().last_n_rows_contents_formatted(self.nlines)
     }
 
+    fn capture(&self, lines: Option<u16>) -> Option<Vec<u8>> {
+        Some(vt100_capture(&self.parser, lines))
+    }
+
     fn process(&mut self, bytes: &[u8]) {
         self.parser.process(bytes)
     }
@@ -141,11 +160,26 @@ impl SessionSpool for Vterm {
         }
     }
 
+    fn capture(&self, lines: Option<u16>) -> Option<Vec<u8>> {
+        let region = match lines {
+            Some(nlines) => shpool_vterm::ContentRegion::BottomLines(nlines as usize),
+            None => shpool_vterm::ContentRegion::Screen,
+        };
+        Some(self.term.contents(region))
+    }
+
     fn process(&mut self, bytes: &[u8]) {
         self.term.process(bytes);
     }
 }
 
+fn vt100_capture(parser: &shpool_vt100::Parser, lines: Option<u16>) -> Vec<u8> {
+    match lines {
+        Some(nlines) => parser.screen().last_n_rows_contents_formatted(nlines),
+        None => parser.screen().contents_formatted(),
+    }
+}
+
 /// Creates a spool given a `mode`.
 pub fn new(
     config: config::Manager,
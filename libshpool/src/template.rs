@@ -42,15 +42,34 @@ enum Chunk {
 
 impl Template {
     pub fn new(src: &str) -> anyhow::Result<Template> {
+        Self::parse(src, false)
+    }
+
+    /// Like `new`, except that braces right after a `$` get left alone,
+    /// so that shell syntax like `${NAME}` can be mixed in with vars.
+    pub fn new_for_shell(src: &str) -> anyhow::Result<Template> {
+        Self::parse(src, true)
+    }
+
+    fn parse(src: &str, skip_shell_params: bool) -> anyhow::Result<Template> {
         let mut chunks = vec![];
+        let mut raw = String::new();
         let mut rest = src;
 
         // We could speed this up even further with the memchr
         // crate that uses SIMD, but it's not worth the dep.
         while let Some(start) = rest.find('{') {
+            if skip_shell_params && rest[..start].ends_with('$') {
+                let end = rest[start..].find('}').map(|i| start + i + 1).unwrap_or(rest.len());
+                raw.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+
             // Push any raw text before the '{'
-            if start > 0 {
-                chunks.push(Chunk::Raw(rest[..start].to_string()));
+            raw.push_str(&rest[..start]);
+            if !raw.is_empty() {
+                chunks.push(Chunk::Raw(std::mem::take(&mut raw)));
             }
             rest = &rest[start + 1..];
 
@@ -70,8 +89,9 @@ impl Template {
         }
 
         // Push any remaining text
-        if !rest.is_empty() {
-            chunks.push(Chunk::Raw(rest.to_string()));
+        raw.push_str(rest);
+        if !raw.is_empty() {
+            chunks.push(Chunk::Raw(raw));
         }
 
         let mut instantiated_size_guess = 0;
@@ -85,6 +105,39 @@ impl Template {
         Ok(Template { chunks, instantiated_size_guess })
     }
 
+    /// A template which always instantiates to exactly `text`, even if
+    /// it contains braces.
+    pub fn literal(text: &str) -> Template {
+        Template {
+            chunks: vec![Chunk::Raw(String::from(text))],
+            instantiated_size_guess: text.len(),
+        }
+    }
+
+    /// The names of the vars the template refers to.
+    pub fn vars(&self) -> impl Iterator<Item = &str> {
+        self.chunks.iter().filter_map(|c| match c {
+            Chunk::Raw(_) => None,
+            Chunk::Var(var) => Some(var.as_str()),
+        })
+    }
+
+    /// Instantiate the template, calling `fill_var` to get the text to
+    /// put in place of each var.
+    pub fn fill<F>(&self, mut fill_var: F) -> String
+    where
+        F: FnMut(&str) -> String,
+    {
+        let mut res = String::with_capacity(self.instantiated_size_guess);
+        for c in self.chunks.iter() {
+            match c {
+                Chunk::Raw(text) => res.push_str(text),
+                Chunk::Var(var) => res.push_str(&fill_var(var)),
+            }
+        }
+        res
+    }
+
     /// Given a variable mapping, instantiate the given template.
     /// Any missing vars resolve to the empty string.
     pub fn apply(&self, vars: &HashMap<String, String>) -> String {
@@ -149,6 +202,32 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn parse_for_shell() -> anyhow::Result<()> {
+        let cases = vec![
+            ("${USER}", vec![Chunk::Raw(String::from("${USER}"))]),
+            (
+                "${USER}@{host} $",
+                vec![
+                    Chunk::Raw(String::from("${USER}@")),
+                    Chunk::Var(String::from("host")),
+                    Chunk::Raw(String::from(" $")),
+                ],
+            ),
+            ("${a:-${b}}", vec![Chunk::Raw(String::from("${a:-${b}}"))]),
+            ("${unclosed", vec![Chunk::Raw(String::from("${unclosed"))]),
+        ];
+
+        for (src, want) in cases.into_iter() {
+            let tmpl = Template::new_for_shell(src)?;
+            assert_eq!(tmpl.chunks, want, "src={src}");
+        }
+        // Plain templates treat the same thing as a var.
+        assert_eq!(Template::new("${USER}")?.vars().collect::<Vec<_>>(), vec!["USER"]);
+
+        Ok(())
+    }
+
     #[test]
     fn parse_err() -> anyhow::Result<()> {
         let cases = vec![
@@ -199,4 +278,13 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn fill() -> anyhow::Result<()> {
+        let tmpl = Template::new("[{a}-{b}]")?;
+        assert_eq!(tmpl.vars().collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(tmpl.fill(|var| format!("${var}")), "[$a-$b]");
+
+        Ok(())
+    }
 }
@@ -0,0 +1,83 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, path::PathBuf, time};
+
+use anyhow::{anyhow, Context};
+use shpool_protocol::{ConnectHeader, TtlAction, TtlReply, TtlRequest};
+
+use crate::{duration, protocol, protocol::ClientResult, TtlCommands};
+
+pub fn run(command: TtlCommands, socket: PathBuf) -> anyhow::Result<()> {
+    let parse = |src: &str| match duration::parse(src) {
+        Ok(d) => Ok(d.as_secs()),
+        Err(e) => {
+            eprintln!("could not parse duration '{src}': {e}");
+            Err(e).context("parsing duration")
+        }
+    };
+    let (session, action) = match command {
+        TtlCommands::Get { session } => (session, TtlAction::Get),
+        TtlCommands::Set { session, duration } => {
+            (session, TtlAction::Set { secs: parse(&duration)? })
+        }
+        TtlCommands::Extend { session, duration } => {
+            (session, TtlAction::Extend { secs: parse(&duration)? })
+        }
+        TtlCommands::Clear { session } => (session, TtlAction::Clear),
+    };
+    let is_get = action == TtlAction::Get;
+
+    let mut client = match protocol::Client::new(socket) {
+        Ok(ClientResult::JustClient(c)) => c,
+        Ok(ClientResult::VersionMismatch { warning, client }) => {
+            eprintln!("warning: {warning}, try restarting your daemon");
+            client
+        }
+        Err(err) => {
+            let io_err = err.downcast::<io::Error>()?;
+            if io_err.kind() == io::ErrorKind::NotFound {
+                eprintln!("could not connect to daemon");
+            }
+            return Err(io_err).context("connecting to daemon");
+        }
+    };
+
+    client
+        .write_connect_header(ConnectHeader::Ttl(TtlRequest { session: session.clone(), action }))
+        .context("writing ttl request header")?;
+
+    let reply: TtlReply = client.read_reply().context("reading reply")?;
+    match reply {
+        TtlReply::Ok { expires_at_unix_ms } => {
+            if is_get {
+                match expires_at_unix_ms {
+                    Some(ms) => {
+                        let expires_at =
+                            time::UNIX_EPOCH + time::Duration::from_millis(ms.max(0) as u64);
+                        let left =
+                            expires_at.duration_since(time::SystemTime::now()).unwrap_or_default();
+                        println!("{}", duration::format(left));
+                    }
+                    None => println!("none"),
+                }
+            }
+            Ok(())
+        }
+        TtlReply::NotFound => {
+            eprintln!("not found: {session}");
+            Err(anyhow!("not found: {session}"))
+        }
+    }
+}
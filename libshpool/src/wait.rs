@@ -0,0 +1,83 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, path::PathBuf};
+
+use anyhow::{anyhow, Context};
+use shpool_protocol::{ConnectHeader, WaitReply, WaitRequest, WaitUntil};
+
+use crate::{duration, protocol, protocol::ClientResult};
+
+/// What `shpool wait` exits with if its timeout runs out, matching
+/// timeout(1).
+const TIMED_OUT_STATUS: i32 = 124;
+
+pub fn run(
+    session: String,
+    until: WaitUntil,
+    timeout: Option<String>,
+    socket: PathBuf,
+) -> anyhow::Result<()> {
+    let timeout_ms = match timeout.as_deref().map(duration::parse).transpose() {
+        Ok(d) => d.map(|d| d.as_millis() as u64),
+        Err(e) => {
+            eprintln!("could not parse timeout: {e}");
+            return Err(e).context("parsing timeout");
+        }
+    };
+
+    let mut client = match protocol::Client::new(socket) {
+        Ok(ClientResult::JustClient(c)) => c,
+        Ok(ClientResult::VersionMismatch { warning, client }) => {
+            eprintln!("warning: {warning}, try restarting your daemon");
+            client
+        }
+        Err(err) => {
+            let io_err = err.downcast::<io::Error>()?;
+            if io_err.kind() == io::ErrorKind::NotFound {
+                eprintln!("could not connect to daemon");
+            }
+            return Err(io_err).context("connecting to daemon");
+        }
+    };
+
+    client
+        .write_connect_header(ConnectHeader::Wait(WaitRequest {
+            session: session.clone(),
+            until,
+            timeout_ms,
+        }))
+        .context("writing wait request header")?;
+
+    let reply: WaitReply = client.read_reply().context("reading reply")?;
+    match reply {
+        WaitReply::Reached { exit_status } => std::process::exit(exit_status.unwrap_or(0)),
+        WaitReply::Unreachable { exit_status: Some(status) } => {
+            eprintln!("'{session}' exited with status {status} first");
+            std::process::exit(1);
+        }
+        WaitReply::Unreachable { exit_status: None } => {
+            eprintln!("'{session}' went away first");
+            std::process::exit(1);
+        }
+        WaitReply::TimedOut => {
+            eprintln!("timed out waiting on '{session}'");
+            std::process::exit(TIMED_OUT_STATUS);
+        }
+        WaitReply::NotFound => {
+            eprintln!("not found: {session}");
+            Err(anyhow!("not found: {session}"))
+        }
+    }
+}
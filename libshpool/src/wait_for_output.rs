@@ -0,0 +1,92 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{io, path::PathBuf};
+
+use anyhow::{anyhow, Context};
+use shpool_protocol::{ConnectHeader, WaitForOutputReply, WaitForOutputRequest};
+
+use crate::{duration, protocol, protocol::ClientResult};
+
+/// What `shpool wait-for-output` exits with if its timeout runs out,
+/// same as `shpool wait`.
+const TIMED_OUT_STATUS: i32 = 124;
+
+pub fn run(
+    session: String,
+    pattern: String,
+    screen: bool,
+    timeout: Option<String>,
+    socket: PathBuf,
+) -> anyhow::Result<()> {
+    let timeout_ms = match timeout.as_deref().map(duration::parse).transpose() {
+        Ok(d) => d.map(|d| d.as_millis() as u64),
+        Err(e) => {
+            eprintln!("could not parse timeout: {e}");
+            return Err(e).context("parsing timeout");
+        }
+    };
+
+    let mut client = match protocol::Client::new(socket) {
+        Ok(ClientResult::JustClient(c)) => c,
+        Ok(ClientResult::VersionMismatch { warning, client }) => {
+            eprintln!("warning: {warning}, try restarting your daemon");
+            client
+        }
+        Err(err) => {
+            let io_err = err.downcast::<io::Error>()?;
+            if io_err.kind() == io::ErrorKind::NotFound {
+                eprintln!("could not connect to daemon");
+            }
+            return Err(io_err).context("connecting to daemon");
+        }
+    };
+
+    client
+        .write_connect_header(ConnectHeader::WaitForOutput(WaitForOutputRequest {
+            session: session.clone(),
+            pattern,
+            screen,
+            timeout_ms,
+        }))
+        .context("writing wait-for-output request header")?;
+
+    let reply: WaitForOutputReply = client.read_reply().context("reading reply")?;
+    match reply {
+        WaitForOutputReply::Matched(text) => {
+            println!("{text}");
+            Ok(())
+        }
+        WaitForOutputReply::Exited { exit_status: Some(status) } => {
+            eprintln!("'{session}' exited with status {status} first");
+            std::process::exit(1);
+        }
+        WaitForOutputReply::Exited { exit_status: None } => {
+            eprintln!("'{session}' exited first");
+            std::process::exit(1);
+        }
+        WaitForOutputReply::TimedOut => {
+            eprintln!("timed out waiting on '{session}'");
+            std::process::exit(TIMED_OUT_STATUS);
+        }
+        WaitForOutputReply::NotFound => {
+            eprintln!("not found: {session}");
+            Err(anyhow!("not found: {session}"))
+        }
+        WaitForOutputReply::InvalidPattern(err) => {
+            eprintln!("invalid pattern: {err}");
+            Err(anyhow!("invalid pattern: {err}"))
+        }
+    }
+}
@@ -48,12 +48,9 @@ pub enum ConnectHeader {
     List,
     /// A message for a named, running sessions. This
     /// provides a mechanism for RPC-like calls to be
-    /// made to running sessions. Messages are only
-    /// delivered if there is currently a client attached
-    /// to the session because we need a servicing thread
-    /// with access to the SessionInner to respond to requests
-    /// (we could implement a mailbox system or something
-    /// for detached threads, but so far we have not needed to).
+    /// made to running sessions. Messages get delivered
+    /// to a per-session mailbox which is serviced whether
+    /// or not there is a client attached to the session.
     SessionMessage(SessionMessageRequest),
     /// A message to request that a list of running
     /// sessions get detached from.
@@ -67,6 +64,33 @@ pub enum ConnectHeader {
     GetVars,
     /// A request to modify the variable environment.
     ModifyVar(ModifyVarRequest),
+    /// Dump the contents of a session's output spool without
+    /// attaching to it.
+    ///
+    /// Responds with a CaptureReply.
+    Capture(CaptureRequest),
+    /// Write some input to a session as if it had been typed into
+    /// an attached terminal. Works whether or not a client is attached.
+    ///
+    /// Responds with a SendInputReply.
+    SendInput(SendInputRequest),
+    /// Give a session a new name.
+    ///
+    /// Responds with a RenameReply.
+    Rename(RenameRequest),
+    /// Inspect or change when a session's ttl runs out.
+    ///
+    /// Responds with a TtlReply.
+    Ttl(TtlRequest),
+    /// Block until a session reaches some state.
+    ///
+    /// Responds with a WaitReply once it does, once it becomes clear
+    /// that it never will, or once the request's timeout runs out.
+    Wait(WaitRequest),
+    /// Block until a session's output matches a pattern.
+    ///
+    /// Responds with a WaitForOutputReply.
+    WaitForOutput(WaitForOutputRequest),
 }
 
 /// KillRequest represents a request to kill
@@ -115,6 +139,34 @@ pub enum LogLevel {
     Trace,
 }
 
+/// How a session's output gets written to its on-disk log.
+#[derive(Serialize, Deserialize, Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputLogFormat {
+    /// Don't log the session's output.
+    Off,
+    /// The bytes exactly as the shell wrote them.
+    Raw,
+    /// Plain text with terminal escape sequences removed.
+    Stripped,
+    /// An asciinema v2 cast with timestamps, suitable for replay.
+    Asciicast,
+}
+
+/// When a session's command gets started again after it exits.
+#[derive(Serialize, Deserialize, Debug, ValueEnum, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Let the session end with its command.
+    #[default]
+    Never,
+    /// Restart the command if it exits with a non-zero status or
+    /// gets killed by a signal.
+    OnFailure,
+    /// Restart the command no matter how it exits.
+    Always,
+}
+
 // SetLogLevelRequest contains a request to set a new
 // log level
 #[derive(Serialize, Deserialize, Debug)]
@@ -137,6 +189,185 @@ pub struct ModifyVarRequest {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ModifyVarReply {}
 
+/// CaptureRequest asks for what is currently on a session's screen,
+/// as tracked by its output spool.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CaptureRequest {
+    /// The session to capture.
+    #[serde(default)]
+    pub session: String,
+    /// If set, capture the last n lines of output, reaching back into
+    /// the scrollback if n is larger than the screen. If unset, just
+    /// the visible screen is captured.
+    #[serde(default)]
+    pub lines: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum CaptureReply {
+    /// The captured output. Formatting is preserved, so this
+    /// includes terminal control codes.
+    Contents(Vec<u8>),
+    /// The session was not found in the session table.
+    NotFound,
+    /// The session does not keep an output spool (because
+    /// `session_restore_mode` is "simple"), so there is nothing
+    /// to capture.
+    NoSpool,
+}
+
+/// SendInputRequest contains raw bytes to write to the named
+/// session's pty.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SendInputRequest {
+    /// The session to send input to.
+    #[serde(default)]
+    pub session: String,
+    /// The bytes to write. Key names have already been resolved to
+    /// the codes they generate by the client.
+    #[serde(default)]
+    pub input: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum SendInputReply {
+    Ok,
+    /// The session was not found in the session table.
+    NotFound,
+    /// The shell stopped reading its input, so not all of it could be
+    /// written before the daemon gave up waiting.
+    TimedOut,
+    /// Writing the input to the session's pty failed.
+    Error(String),
+}
+
+/// RenameRequest asks for a session to be renamed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RenameRequest {
+    #[serde(default)]
+    pub old_name: String,
+    #[serde(default)]
+    pub new_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum RenameReply {
+    Ok,
+    /// There is no session called `old_name`.
+    NotFound,
+    /// There is already a session called `new_name`.
+    NameTaken,
+    /// `new_name` is not a valid session name.
+    InvalidName,
+}
+
+/// TtlRequest asks about, or changes, the ttl of a running session.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TtlRequest {
+    #[serde(default)]
+    pub session: String,
+    #[serde(default)]
+    pub action: TtlAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub enum TtlAction {
+    /// Just report the current expiry.
+    #[default]
+    Get,
+    /// Kill the session this many seconds from now.
+    Set { secs: u64 },
+    /// Push the expiry back by this many seconds. A session without a
+    /// ttl gets one this many seconds from now.
+    Extend { secs: u64 },
+    /// Never kill the session.
+    Clear,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum TtlReply {
+    /// When the session will get killed, after applying the action, if
+    /// it has a ttl at all.
+    Ok { expires_at_unix_ms: Option<i64> },
+    /// The session was not found in the session table.
+    NotFound,
+}
+
+/// WaitRequest asks the daemon to reply once a session reaches the
+/// given state.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WaitRequest {
+    #[serde(default)]
+    pub session: String,
+    #[serde(default)]
+    pub until: WaitUntil,
+    /// How long to wait before giving up. Forever if not given.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// A session state that `shpool wait` can block on.
+#[derive(Serialize, Deserialize, Debug, ValueEnum, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum WaitUntil {
+    /// The session's shell has exited.
+    #[default]
+    Exited,
+    /// A client is attached to the session.
+    Attached,
+    /// No client is attached to the session.
+    Detached,
+    /// The session is gone from the session table.
+    Removed,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum WaitReply {
+    /// The session reached the requested state. Carries the shell's
+    /// exit status if it has exited and the status is known.
+    Reached { exit_status: Option<i32> },
+    /// The session exited or went away before reaching the requested
+    /// state.
+    Unreachable { exit_status: Option<i32> },
+    /// The timeout ran out first.
+    TimedOut,
+    /// The session was not found.
+    NotFound,
+}
+
+/// WaitForOutputRequest asks the daemon to reply once a session's
+/// output matches a regex.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WaitForOutputRequest {
+    #[serde(default)]
+    pub session: String,
+    /// The regex to match the output against, a line at a time.
+    #[serde(default)]
+    pub pattern: String,
+    /// If true, what is already on the screen gets matched too, not
+    /// just new output.
+    #[serde(default)]
+    pub screen: bool,
+    /// How long to wait before giving up. Forever if not given.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum WaitForOutputReply {
+    /// The output matched. Holds the text of the match.
+    Matched(String),
+    /// The session's shell exited before the output matched. Carries
+    /// the exit status if it is known.
+    Exited { exit_status: Option<i32> },
+    /// The timeout ran out first.
+    TimedOut,
+    /// The session was not found.
+    NotFound,
+    /// The pattern is not a valid regex. Holds the parse error.
+    InvalidPattern(String),
+}
+
 /// SessionMessageRequest represents a request that
 /// ought to be routed to the session indicated by
 /// `session_name`.
@@ -161,6 +392,19 @@ pub enum SessionMessageRequestPayload {
     /// by the server from a batch detach request.
     #[default]
     Detach,
+    /// Start or stop recording a session's output.
+    Record(RecordRequest),
+}
+
+/// RecordRequest starts or stops an asciicast recording of a
+/// session's output.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum RecordRequest {
+    /// Start recording into the file at the given absolute path,
+    /// replacing anything already there.
+    Start { path: String },
+    /// Stop the current recording.
+    Stop,
 }
 
 /// ResizeRequest resizes the pty for a named session.
@@ -173,6 +417,10 @@ pub struct ResizeRequest {
     /// The size of the client's tty
     #[serde(default)]
     pub tty_size: TtySize,
+    /// The id handed out in `AttachStatus::Shared` if the client
+    /// is sharing the session rather than owning it.
+    #[serde(default)]
+    pub client_id: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -181,11 +429,33 @@ pub enum SessionMessageReply {
     NotFound,
     /// There is not terminal attached to the session so
     /// it can't handle messages right now.
+    ///
+    /// NOTE: no longer generated now that detached sessions can
+    /// service messages, but kept so old clients still understand
+    /// replies from new daemons and vice versa.
     NotAttached,
     /// The response to a resize message
     Resize(ResizeReply),
     /// The response to a detach message
     Detach(SessionMessageDetachReply),
+    /// The response to a record message
+    Record(RecordReply),
+}
+
+/// A reply to a record message
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum RecordReply {
+    /// The recording was started.
+    Started,
+    /// The recording into the given file was stopped.
+    Stopped { path: String },
+    /// A start was refused since the session is already being recorded
+    /// into the given file.
+    AlreadyRecording { path: String },
+    /// A stop was refused since the session is not being recorded.
+    NotRecording,
+    /// The recording could not be started.
+    Error(String),
 }
 
 /// A reply to a detach message
@@ -242,6 +512,40 @@ pub struct AttachHeader {
     /// already set up for example.
     #[serde(default)]
     pub start_cmd: Option<String>,
+    /// If true, attach as a read-only watcher rather than taking over
+    /// the session. Watchers get a mirror of the session output, but
+    /// their input and tty size are ignored and they never kick off
+    /// the client that owns the session. The session must already exist.
+    #[serde(default)]
+    pub watch: bool,
+    /// If true and the session already has a client attached, join
+    /// it as an additional writer rather than being rejected as busy.
+    /// Output is fanned out to every client and input from all of
+    /// them is merged.
+    #[serde(default)]
+    pub share: bool,
+    /// If specified, overrides the output_log config option for a newly
+    /// created session. Ignored on reattach.
+    #[serde(default)]
+    pub output_log: Option<OutputLogFormat>,
+    /// If specified, the name of the keybinding profile from the config
+    /// to use for this client, rather than whichever one the session would
+    /// get by default.
+    #[serde(default)]
+    pub keybinding_profile: Option<String>,
+    /// If specified, overrides the restart policy the restart_profile
+    /// config option would give a newly created session. Only applies
+    /// to sessions with a custom `cmd`. Ignored on reattach.
+    #[serde(default)]
+    pub restart: Option<RestartPolicy>,
+    /// If specified, how many times to restart the command before giving
+    /// up, overriding the restart profile. Ignored on reattach.
+    #[serde(default)]
+    pub max_restarts: Option<u32>,
+    /// If true, keep the session around after its shell exits, as if
+    /// the linger config option were set. Ignored on reattach.
+    #[serde(default)]
+    pub linger: bool,
 }
 
 impl AttachHeader {
@@ -267,7 +571,7 @@ pub struct ListReply {
 }
 
 /// Session describes an active session.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Session {
     #[serde(default)]
     pub name: String,
@@ -279,6 +583,64 @@ pub struct Session {
     pub last_disconnected_at_unix_ms: Option<i64>,
     #[serde(default)]
     pub status: SessionStatus,
+    /// The pid of the shell (or custom command) at the root of the session.
+    #[serde(default)]
+    pub pid: Option<i32>,
+    /// The pid of the foreground process group of the session's pty,
+    /// that is whatever the user is currently running in the shell.
+    #[serde(default)]
+    pub foreground_pid: Option<i32>,
+    /// The name of the foreground process.
+    #[serde(default)]
+    pub foreground_cmd: Option<String>,
+    /// The working directory of the shell.
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// The custom command the session was launched with, if any.
+    #[serde(default)]
+    pub cmd: Option<String>,
+    /// The command injected into the shell on startup, if any.
+    #[serde(default)]
+    pub start_cmd: Option<String>,
+    /// When the session will get killed if it has a ttl.
+    #[serde(default)]
+    pub ttl_expires_at_unix_ms: Option<i64>,
+    /// The current size of the session's pty.
+    #[serde(default)]
+    pub tty_size: Option<TtySize>,
+    /// How much output the session has produced since a client
+    /// last attached to it.
+    #[serde(default)]
+    pub output_bytes_since_attach: Option<u64>,
+    /// When the shell exited, for sessions that are lingering after
+    /// their shell exited.
+    #[serde(default)]
+    pub exited_at_unix_ms: Option<i64>,
+    /// The session was saved by a previous daemon and has no running
+    /// shell. Attaching to it will spawn a new shell and replay the
+    /// saved output. These sessions have a `Disconnected` status so
+    /// that older clients still understand the reply.
+    #[serde(default)]
+    pub restorable: bool,
+    /// The exit status of the session's shell, for sessions that are
+    /// lingering after their shell exited. Like restorable sessions,
+    /// these have a `Disconnected` status for the sake of older clients.
+    #[serde(default)]
+    pub exit_status: Option<i32>,
+}
+
+impl Session {
+    /// The status to show for the session, which unlike `status` alone
+    /// also covers sessions without a running shell.
+    pub fn status_label(&self) -> String {
+        if self.restorable {
+            String::from("dead (restorable)")
+        } else if let Some(status) = self.exit_status {
+            format!("exited({status})")
+        } else {
+            self.status.to_string()
+        }
+    }
 }
 
 /// Indicates if a shpool session currently has a client attached.
@@ -320,6 +682,14 @@ pub enum AttachStatus {
     /// Forbidden indicates that the daemon has rejected the connection
     /// attempt for security reasons.
     Forbidden(String),
+    /// Shared indicates that the session already had a client attached,
+    /// and we joined it as an additional writer. The client_id should
+    /// be passed along with resize requests so the daemon can take
+    /// this client's size into account.
+    Shared { client_id: u64 },
+    /// NotFound indicates that a watch request named a session that
+    /// does not exist. Regular attaches just create the session instead.
+    NotFound,
     /// Some unexpected error
     UnexpectedError(String),
 }
@@ -346,14 +716,23 @@ pub struct MaybeSwitch {
     /// should hang up, then reattach to the given session name.
     ///
     /// Session switching is incompatible with session name templates,
-    /// so the attach process should exit and display an error to the
-    /// user if it gets a MaybeSwitch telling to to switch to a specific
-    /// session name.
+    /// so an attach process which switches to a specific session name
+    /// stops following its template from then on.
     pub switch_to: Option<String>,
     /// The shpool wide variable environment. The attach process should
     /// hang up and reattach if it has a session name template which
     /// produces a new result with this new environment.
     pub vars: Vec<(String, String)>,
+    /// If true, the attach process should hang up and let the user
+    /// pick a session to switch to.
+    #[serde(default)]
+    pub choose: bool,
+    /// If set, the session the receiving attach process is attached to
+    /// has been renamed to this. The attach process stays attached, but
+    /// uses the new name from then on, so just like after a switch it
+    /// stops following its session name template.
+    #[serde(default)]
+    pub renamed_to: Option<String>,
 }
 
 /// ChunkKind is a tag that indicates what type of frame is being transmitted
@@ -592,6 +592,194 @@ fn busy_background_force() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[timeout(30000)]
+fn watch() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "restore_screen.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut tty1 = daemon_proc.attach("sh1", Default::default()).context("attaching from tty1")?;
+    let mut line_matcher1 = tty1.line_matcher()?;
+    tty1.run_cmd("echo before-watch")?;
+    line_matcher1.scan_until_re("before-watch$")?;
+
+    let mut watcher = daemon_proc
+        .attach("sh1", AttachArgs { watch: true, ..Default::default() })
+        .context("attaching watcher")?;
+    let mut watcher_matcher = watcher.line_matcher()?;
+    // the watcher starts off with a snapshot of the screen
+    watcher_matcher.scan_until_re("before-watch$")?;
+
+    tty1.run_cmd("echo after-watch")?;
+    line_matcher1.scan_until_re("after-watch$")?;
+    watcher_matcher.scan_until_re("after-watch$")?;
+
+    // input from the watcher never makes it to the shell
+    watcher.run_cmd("export LEAKED=yes")?;
+    tty1.run_cmd(r#"echo "leaked=$LEAKED""#)?;
+    line_matcher1.scan_until_re("leaked=$")?;
+    watcher_matcher.scan_until_re("leaked=$")?;
+
+    // and watching does not displace the owner
+    let list_out = daemon_proc.list()?;
+    assert!(String::from_utf8_lossy(&list_out.stdout).contains("sh1\tattached"));
+
+    // the detach keybinding stops watching
+    watcher.run_raw_cmd(vec![0, 17])?; // Ctrl-Space Ctrl-q
+    let _watcher_stdin = watcher.proc.stdin.take();
+    assert!(watcher.proc.wait()?.success());
+
+    tty1.run_cmd("echo still-here")?;
+    line_matcher1.scan_until_re("still-here$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn watch_gets_exit_status() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "restore_screen.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut tty1 = daemon_proc.attach("sh1", Default::default()).context("attaching from tty1")?;
+    let mut line_matcher1 = tty1.line_matcher()?;
+    tty1.run_cmd("echo up")?;
+    line_matcher1.scan_until_re("up$")?;
+
+    let mut watcher = daemon_proc
+        .attach("sh1", AttachArgs { watch: true, ..Default::default() })
+        .context("attaching watcher")?;
+    let mut watcher_matcher = watcher.line_matcher()?;
+    watcher_matcher.scan_until_re("up$")?;
+
+    tty1.run_cmd("exit 19")?;
+    let _watcher_stdin = watcher.proc.stdin.take();
+    assert_eq!(watcher.proc.wait()?.code(), Some(19));
+
+    Ok(())
+}
+
+// A watcher which stops reading must not hold up the session.
+#[test]
+#[timeout(30000)]
+fn watch_stalled() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut tty1 = daemon_proc.attach("sh1", Default::default()).context("attaching from tty1")?;
+    let mut line_matcher1 = tty1.line_matcher()?;
+    tty1.run_cmd("echo up")?;
+    line_matcher1.scan_until_re("up$")?;
+
+    // Nothing ever reads the watcher's stdout, so it backs up once the
+    // pipe fills.
+    let _watcher = daemon_proc
+        .attach("sh1", AttachArgs { watch: true, ..Default::default() })
+        .context("attaching watcher")?;
+
+    tty1.run_cmd("yes 0123456789abcdefghijklmnopqrstuvwxyz0123456789 | head -n 60000")?;
+    tty1.run_cmd("echo flood-$((1+1))")?;
+    line_matcher1.scan_until_re("flood-2$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn share() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "restore_screen.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut tty1 = daemon_proc.attach("sh1", Default::default()).context("attaching from tty1")?;
+    let mut line_matcher1 = tty1.line_matcher()?;
+    tty1.run_cmd("echo before-share")?;
+    line_matcher1.scan_until_re("before-share$")?;
+
+    let mut tty2 = daemon_proc
+        .attach("sh1", AttachArgs { share: true, ..Default::default() })
+        .context("attaching from tty2")?;
+    let mut line_matcher2 = tty2.line_matcher()?;
+    line_matcher2.scan_until_re("before-share$")?;
+
+    // input from either client drives the same shell, and both see the output
+    tty2.run_cmd("export SHARED=from-tty2 ; echo exported")?;
+    // input from the two clients can race, so make sure the export has
+    // landed before using it
+    line_matcher1.scan_until_re("exported$")?;
+    line_matcher2.scan_until_re("exported$")?;
+    tty1.run_cmd(r#"echo "shared=$SHARED""#)?;
+    line_matcher1.scan_until_re("shared=from-tty2$")?;
+    line_matcher2.scan_until_re("shared=from-tty2$")?;
+
+    // the detach keybinding only disconnects the client that used it
+    tty2.run_raw_cmd(vec![0, 17])?; // Ctrl-Space Ctrl-q
+    let _tty2_stdin = tty2.proc.stdin.take();
+    assert!(tty2.proc.wait()?.success());
+
+    tty1.run_cmd("echo still-here")?;
+    line_matcher1.scan_until_re("still-here$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn share_without_owner() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    // with nobody else attached, a shared attach is just a regular attach
+    let mut tty1 = daemon_proc
+        .attach("sh1", AttachArgs { share: true, ..Default::default() })
+        .context("attaching from tty1")?;
+    let mut line_matcher1 = tty1.line_matcher()?;
+    tty1.run_cmd("echo hi")?;
+    line_matcher1.scan_until_re("hi$")?;
+
+    let list_out = daemon_proc.list()?;
+    assert!(String::from_utf8_lossy(&list_out.stdout).contains("sh1\tattached"));
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn watch_missing_session() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut watcher = daemon_proc
+        .attach("nosuch", AttachArgs { watch: true, ..Default::default() })
+        .context("attaching watcher")?;
+    let mut stderr_matcher = watcher.stderr_line_matcher()?;
+    stderr_matcher.scan_until_re("no session named 'nosuch' to watch$")?;
+    assert!(!watcher.proc.wait()?.success());
+
+    // watching should never create the session
+    let list_out = daemon_proc.list()?;
+    assert!(!String::from_utf8_lossy(&list_out.stdout).contains("nosuch"));
+
+    Ok(())
+}
+
 #[test]
 #[timeout(30000)]
 fn blank_session_not_allowed() -> anyhow::Result<()> {
@@ -863,6 +1051,332 @@ fn custom_keybinding_detach() -> anyhow::Result<()> {
     Ok(())
 }
 
+fn keybinding_actions_daemon() -> anyhow::Result<support::daemon::Proc> {
+    support::daemon::Proc::new(
+        "keybinding_actions.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")
+}
+
+fn session_status_re(session: &str, status: &str) -> anyhow::Result<Regex> {
+    Ok(Regex::new(&format!(r"(?m)^{session}\s+{status}$"))?)
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_kill() -> anyhow::Result<()> {
+    let mut daemon_proc = keybinding_actions_daemon()?;
+    let mut a1 = daemon_proc.attach("sess", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+
+    a1.run_cmd("echo ready")?;
+    lm1.scan_until_re("ready$")?;
+
+    a1.run_raw(vec![24, b'k'])?; // Ctrl-x k
+    let _a1_stdin = a1.proc.stdin.take();
+    a1.proc.wait()?;
+
+    daemon_proc.wait_until_list_matches(|out| !out.contains("sess"))?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_switch_and_previous() -> anyhow::Result<()> {
+    let mut daemon_proc = keybinding_actions_daemon()?;
+    let mut a1 = daemon_proc.attach("main", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+
+    a1.run_cmd("echo first=$SHPOOL_SESSION_NAME")?;
+    lm1.scan_until_re("first=main$")?;
+
+    a1.run_raw(vec![24, b's'])?; // Ctrl-x s
+    let other_attached = session_status_re("other", "attached")?;
+    let main_disconnected = session_status_re("main", "disconnected")?;
+    daemon_proc.wait_until_list_matches(|out| {
+        other_attached.is_match(out) && main_disconnected.is_match(out)
+    })?;
+    a1.run_cmd("echo second=$SHPOOL_SESSION_NAME")?;
+    lm1.scan_until_re("second=other$")?;
+
+    a1.run_raw(vec![24, b'p'])?; // Ctrl-x p
+    let main_attached = session_status_re("main", "attached")?;
+    let other_disconnected = session_status_re("other", "disconnected")?;
+    daemon_proc.wait_until_list_matches(|out| {
+        main_attached.is_match(out) && other_disconnected.is_match(out)
+    })?;
+    a1.run_cmd("echo third=$SHPOOL_SESSION_NAME")?;
+    lm1.scan_until_re("third=main$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_send_prefix() -> anyhow::Result<()> {
+    let mut daemon_proc = keybinding_actions_daemon()?;
+    let mut a1 = daemon_proc.attach("sess", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+
+    a1.run_cmd(r#"echo reading; read -r l; echo "got=$l" | cat -v"#)?;
+    lm1.scan_until_re("reading$")?;
+
+    // Ctrl-x Ctrl-x sends a single Ctrl-x through
+    a1.run_raw(vec![24, 24])?;
+    a1.run_cmd("end")?;
+    lm1.scan_until_re(r"got=\^Xend$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_toggle_log() -> anyhow::Result<()> {
+    let mut daemon_proc = keybinding_actions_daemon()?;
+    let mut a1 = daemon_proc.attach("sess", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+
+    a1.run_cmd("echo ready")?;
+    lm1.scan_until_re("ready$")?;
+
+    a1.run_raw(vec![24, b'l'])?; // Ctrl-x l
+    a1.run_cmd(
+        "while [ ! -e $SHPOOL_SESSION_DIR/output.log ]; do sleep 0.1; done; echo on-$((1 + 1))",
+    )?;
+    lm1.scan_until_re("on-2$")?;
+
+    a1.run_raw(vec![24, b'l'])?;
+    a1.run_cmd("sleep 0.5; echo off-$((2 + 2))")?;
+    lm1.scan_until_re("off-4$")?;
+
+    a1.run_cmd(
+        "echo logged=$(grep -c on-2 $SHPOOL_SESSION_DIR/output.log),$(grep -c off-4 $SHPOOL_SESSION_DIR/output.log)",
+    )?;
+    lm1.scan_until_re("logged=1,0$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_clear_spool() -> anyhow::Result<()> {
+    let mut daemon_proc = keybinding_actions_daemon()?;
+    let mut a1 = daemon_proc.attach("sess", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+
+    a1.run_cmd("echo spooled-$((3 + 3))")?;
+    lm1.scan_until_re("spooled-6$")?;
+    support::wait_until(|| {
+        let out = daemon_proc.capture("sess", &[])?;
+        Ok(String::from_utf8_lossy(&out.stdout).contains("spooled-6"))
+    })?;
+
+    a1.run_raw(vec![24, b'x'])?; // Ctrl-x x
+    support::wait_until(|| {
+        let out = daemon_proc.capture("sess", &[])?;
+        Ok(!String::from_utf8_lossy(&out.stdout).contains("spooled-6"))
+    })?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_timeout() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "keybinding_timeout.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut a1 = daemon_proc.attach("sess", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+
+    a1.run_cmd(r#"echo reading; read -r l; echo "got=$l" | cat -v"#)?;
+    lm1.scan_until_re("reading$")?;
+
+    // Ctrl-x k would kill the session, but not with a long pause in between
+    a1.run_raw(vec![24])?;
+    thread::sleep(time::Duration::from_millis(1000));
+    a1.run_cmd("kend")?;
+    lm1.scan_until_re(r"got=\^Xkend$")?;
+
+    a1.run_cmd("echo still-$((1 + 1))")?;
+    lm1.scan_until_re("still-2$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(60000)]
+fn keybinding_paste_passthrough() -> anyhow::Result<()> {
+    let mut daemon_proc = keybinding_actions_daemon()?;
+    let mut a1 = daemon_proc.attach("sess", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+
+    // A big paste full of the first halves of keybindings, none of which
+    // get finished off.
+    let alphabet = [0, 24, 0x1b, b'[', b'a', b'z', b'\r', b'\n', 0xff];
+    let mut state: u32 = 42;
+    let mut paste: Vec<u8> = vec![];
+    for _ in 0..20000 {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        let byte = alphabet[(state >> 16) as usize % alphabet.len()];
+        // Ctrl-x Ctrl-x is the sendprefix binding
+        paste.push(if byte == 24 && paste.last() == Some(&24) { b'a' } else { byte });
+    }
+    // otherwise a trailing prefix would rightly get held back
+    paste.push(b'z');
+    let want_path = daemon_proc.tmp_dir.path().join("paste");
+    fs::write(&want_path, &paste)?;
+
+    a1.run_cmd(&format!(
+        "stty raw -echo -iexten; echo ready; head -c {} | cmp - {} && echo same; stty sane",
+        paste.len(),
+        want_path.display()
+    ))?;
+    lm1.scan_until_re("ready")?;
+    a1.run_raw(paste)?;
+    lm1.scan_until_re("same")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_profile_by_session() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "keybinding_profiles.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut a1 = daemon_proc.attach("vim-1", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+
+    // the top level detach binding gets passed through in the editor profile
+    a1.run_cmd(r#"echo reading; read -r l; echo "got=$l" | cat -v"#)?;
+    lm1.scan_until_re("reading$")?;
+    a1.run_raw(vec![24, b'q'])?; // Ctrl-x q
+    a1.run_cmd("end")?;
+    lm1.scan_until_re(r"got=\^Xqend$")?;
+
+    a1.run_raw(vec![24, b'd'])?; // Ctrl-x d
+    let _a1_stdin = a1.proc.stdin.take();
+    assert!(a1.proc.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_profile_empty() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "keybinding_profiles.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut a1 =
+        daemon_proc.attach("bare-1", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+    a1.run_cmd("echo ready")?;
+    lm1.scan_until_re("ready$")?;
+
+    // the empty profile falls back to the default detach binding
+    a1.run_raw(vec![0, 17])?; // Ctrl-Space Ctrl-q
+    let _a1_stdin = a1.proc.stdin.take();
+    assert!(a1.proc.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_profile_flag() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "keybinding_profiles.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut bad = daemon_proc
+        .attach(
+            "sess",
+            AttachArgs { keybindings: Some(String::from("nope")), ..Default::default() },
+        )
+        .context("starting attach proc")?;
+    assert!(!bad.proc.wait()?.success());
+
+    let mut a1 = daemon_proc
+        .attach(
+            "sess",
+            AttachArgs { keybindings: Some(String::from("editor")), ..Default::default() },
+        )
+        .context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+    a1.run_cmd("echo ready")?;
+    lm1.scan_until_re("ready$")?;
+
+    a1.run_raw(vec![24, b'd'])?; // Ctrl-x d
+    let _a1_stdin = a1.proc.stdin.take();
+    assert!(a1.proc.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_profile_reload() -> anyhow::Result<()> {
+    let tmp_dir = tmpdir::Dir::new("/tmp/shpool-test")?;
+    let config_file = tmp_dir.path().join("keybinding_profiles.toml");
+    let config = fs::read_to_string(support::testdata_file("keybinding_profiles.toml"))?;
+    fs::write(&config_file, &config)?;
+
+    let mut daemon_proc = support::daemon::Proc::new(&config_file, DaemonArgs::default())
+        .context("starting daemon proc")?;
+    let mut a1 = daemon_proc.attach("sess", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+    a1.run_cmd("echo ready")?;
+    lm1.scan_until_re("ready$")?;
+
+    // The attached terminal picks up the new binding without reattaching.
+    // Swap the file in whole so the daemon never sees it half written.
+    let new_config_file = tmp_dir.path().join("new.toml");
+    fs::write(&new_config_file, config.replace(r#""Ctrl-x q""#, r#""Ctrl-x z""#))?;
+    fs::rename(&new_config_file, &config_file)?;
+    daemon_proc.await_event("daemon-config-changed")?;
+
+    a1.run_raw(vec![24, b'z'])?; // Ctrl-x z
+    let _a1_stdin = a1.proc.stdin.take();
+    assert!(a1.proc.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn keybinding_choose() -> anyhow::Result<()> {
+    let mut daemon_proc = keybinding_actions_daemon()?;
+    let mut a1 = daemon_proc.attach("main", Default::default()).context("starting attach proc")?;
+    let mut lm1 = a1.line_matcher()?;
+
+    a1.run_cmd("echo ready")?;
+    lm1.scan_until_re("ready$")?;
+
+    a1.run_raw(vec![24, b'c'])?; // Ctrl-x c
+                                 // All this cares about is that the action brings up the picker with
+                                 // the current session marked, not how the picker lays out its rows.
+    lm1.scan_until_re(r"main\b.*\(current\)")?;
+    a1.run_cmd("picked")?;
+
+    let picked_attached = session_status_re("picked", "attached")?;
+    daemon_proc.wait_until_list_matches(|out| picked_attached.is_match(out))?;
+    a1.run_cmd("echo in=$SHPOOL_SESSION_NAME")?;
+    lm1.scan_until_re("in=picked$")?;
+
+    Ok(())
+}
+
 #[test]
 #[timeout(30000)]
 fn injects_term_even_with_env_config() -> anyhow::Result<()> {
@@ -1180,6 +1694,79 @@ fn prompt_prefix_bash() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[timeout(30000)]
+fn prompt_prefix_bash_follows_rename() -> anyhow::Result<()> {
+    let mut daemon_proc =
+        support::daemon::Proc::new("prompt_prefix_bash.toml", DaemonArgs::default())
+            .context("starting daemon proc")?;
+
+    let mut attach_proc = daemon_proc.attach("sh1", AttachArgs::default())?;
+    let mut lm = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo")?;
+    lm.scan_until_re(".*session_name=sh1 prompt>.*")?;
+
+    let out = daemon_proc.rename("sh1", "dev")?;
+    assert!(out.status.success(), "rename failed: {}", String::from_utf8_lossy(&out.stderr));
+
+    // The shell only notices the rename when it draws its next prompt.
+    attach_proc.run_cmd("echo")?;
+    attach_proc.run_cmd("echo name=$SHPOOL_SESSION_NAME")?;
+    lm.scan_until_re(".*session_name=dev prompt> name=dev$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn prompt_prefix_vars() -> anyhow::Result<()> {
+    let mut daemon_proc =
+        support::daemon::Proc::new("prompt_prefix_vars.toml", DaemonArgs::default())
+            .context("starting daemon proc")?;
+
+    let mut attach_proc = daemon_proc.attach("sh1", AttachArgs::default())?;
+    let mut lm = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo")?;
+    lm.scan_until_re(".*ws= prompt>.*")?;
+
+    let out = daemon_proc.var_set("ws", "proj")?;
+    assert!(out.status.success(), "var set failed: {}", String::from_utf8_lossy(&out.stderr));
+
+    attach_proc.run_cmd("echo")?;
+    attach_proc.run_cmd("echo")?;
+    lm.scan_until_re(".*ws=proj prompt>.*")?;
+
+    Ok(())
+}
+
+// A var's value must show up in the prompt as is, not get run by the
+// shell.
+#[test]
+#[timeout(30000)]
+fn prompt_prefix_vars_not_evaluated() -> anyhow::Result<()> {
+    let mut daemon_proc =
+        support::daemon::Proc::new("prompt_prefix_vars.toml", DaemonArgs::default())
+            .context("starting daemon proc")?;
+    let pwned = daemon_proc.tmp_dir.path().join("pwned");
+
+    let mut attach_proc = daemon_proc.attach("sh1", AttachArgs::default())?;
+    let mut lm = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo")?;
+    lm.scan_until_re(".*ws= prompt>.*")?;
+
+    let out = daemon_proc.var_set("ws", &format!("$(touch {})", pwned.display()))?;
+    assert!(out.status.success(), "var set failed: {}", String::from_utf8_lossy(&out.stderr));
+
+    attach_proc.run_cmd("echo")?;
+    attach_proc.run_cmd("echo")?;
+    lm.scan_until_re(r".*ws=\$\(touch .*pwned\) prompt>.*")?;
+    attach_proc.run_cmd("echo")?;
+    lm.scan_until_re(r".*ws=\$\(touch .*pwned\) prompt>.*")?;
+    assert!(!pwned.exists(), "var value got run by the shell");
+
+    Ok(())
+}
+
 #[test]
 #[timeout(30000)]
 #[cfg_attr(target_os = "macos", ignore)] // hard-coded /usr/bin/zsh path
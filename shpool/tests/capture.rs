@@ -0,0 +1,111 @@
+use anyhow::Context;
+use ntest::timeout;
+
+mod support;
+
+use crate::support::daemon::DaemonArgs;
+
+#[test]
+#[timeout(30000)]
+fn screen() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "restore_screen.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo capture-me")?;
+    line_matcher.scan_until_re("capture-me$")?;
+
+    let out = daemon_proc.capture("sh1", &[])?;
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout[..]);
+    assert!(stdout.contains("capture-me"));
+
+    // capturing does not disturb the attached client
+    let list_out = daemon_proc.list()?;
+    assert!(String::from_utf8_lossy(&list_out.stdout).contains("sh1\tattached"));
+    attach_proc.run_cmd("echo still-here")?;
+    line_matcher.scan_until_re("still-here$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn detached_json() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "restore_screen.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    {
+        let mut attach_proc =
+            daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+        let mut line_matcher = attach_proc.line_matcher()?;
+        attach_proc.run_cmd("echo first")?;
+        line_matcher.scan_until_re("first$")?;
+        for filler in ["a", "b", "c"] {
+            attach_proc.run_cmd(&format!("echo filler-{filler}"))?;
+            line_matcher.scan_until_re(&format!("filler-{filler}$"))?;
+        }
+        attach_proc.run_cmd("echo second")?;
+        line_matcher.scan_until_re("second$")?;
+    }
+    daemon_proc.wait_until_list_matches(|out| out.contains("sh1\tdisconnected"))?;
+
+    let out = daemon_proc.capture("sh1", &["--json", "--lines", "2"])?;
+    assert!(out.status.success());
+    let capture: serde_json::Value = serde_json::from_slice(&out.stdout[..])?;
+    assert_eq!(capture["session"], "sh1");
+    assert_eq!(capture["lines"], 2);
+    let contents = capture["contents"].as_str().context("contents not a string")?;
+    assert!(contents.contains("second"));
+    assert!(!contents.contains("first"));
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn not_found() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "restore_screen.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let out = daemon_proc.capture("nosuchsession", &[])?;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr[..]);
+    assert!(stderr.contains("not found: nosuchsession"));
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn no_spool() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo hi")?;
+    line_matcher.scan_until_re("hi$")?;
+
+    let out = daemon_proc.capture("sh1", &[])?;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr[..]);
+    assert!(stderr.contains("has no output to capture"));
+
+    Ok(())
+}
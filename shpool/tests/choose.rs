@@ -0,0 +1,110 @@
+use anyhow::Context;
+use ntest::timeout;
+use regex::Regex;
+
+mod support;
+
+use crate::support::daemon::{AttachArgs, DaemonArgs};
+
+fn start_background(daemon_proc: &mut support::daemon::Proc, name: &str) -> anyhow::Result<()> {
+    let mut proc = daemon_proc
+        .attach(name, AttachArgs { background: true, null_stdin: true, ..Default::default() })
+        .context("starting background attach proc")?;
+    assert!(proc.proc.wait()?.success(), "background attach failed");
+    // The daemon finishes detaching on its own time, and until it does the
+    // session still shows up as attached.
+    let disconnected = Regex::new(&format!(r"(?m)^{name}\s+disconnected$"))?;
+    daemon_proc.wait_until_list_matches(|out| disconnected.is_match(out))
+}
+
+#[test]
+#[timeout(30000)]
+fn attaches_to_pick() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    start_background(&mut daemon_proc, "alpha")?;
+    start_background(&mut daemon_proc, "beta")?;
+
+    let mut chooser = daemon_proc.choose()?;
+    let mut lm = chooser.line_matcher()?;
+    lm.scan_until_re(r"alpha +disconnected +\d+s +\d+s ago")?;
+
+    // the list keeps up with new sessions while it is up
+    start_background(&mut daemon_proc, "gamma")?;
+    lm.scan_until_re("gamma +disconnected")?;
+
+    chooser.run_raw(b"bet\r".to_vec())?;
+    let beta_attached = Regex::new(r"(?m)^beta\s+attached$")?;
+    daemon_proc.wait_until_list_matches(|out| beta_attached.is_match(out))?;
+    chooser.run_cmd("echo in=$SHPOOL_SESSION_NAME")?;
+    lm.scan_until_re("in=beta$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn kill_and_quit() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    start_background(&mut daemon_proc, "alpha")?;
+    start_background(&mut daemon_proc, "beta")?;
+
+    let mut chooser = daemon_proc.choose()?;
+    let mut lm = chooser.line_matcher()?;
+    lm.scan_until_re("alpha +disconnected")?;
+
+    chooser.run_raw(b"alph\x0b".to_vec())?; // Ctrl-k
+    daemon_proc.wait_until_list_matches(|out| !out.contains("alpha") && out.contains("beta"))?;
+    lm.scan_until_re("killed 'alpha'")?;
+
+    chooser.run_raw(vec![0x1b])?;
+    let _stdin = chooser.proc.stdin.take();
+    assert!(chooser.proc.wait()?.success());
+
+    // quitting does not touch the other sessions
+    let out = daemon_proc.list()?;
+    assert!(String::from_utf8_lossy(&out.stdout).contains("beta"));
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn rename() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    start_background(&mut daemon_proc, "alpha")?;
+    start_background(&mut daemon_proc, "beta")?;
+
+    let mut chooser = daemon_proc.choose()?;
+    let mut lm = chooser.line_matcher()?;
+    lm.scan_until_re("alpha +disconnected")?;
+
+    chooser.run_raw(b"alph\x12".to_vec())?; // Ctrl-r
+    lm.scan_until_re("rename 'alpha' to: ")?;
+
+    // a name that is already taken leaves the prompt up for another try
+    chooser.run_raw(b"beta\r".to_vec())?;
+    lm.scan_until_re("there is already a session called 'beta'")?;
+
+    chooser.run_raw(b"\x15omega\r".to_vec())?; // Ctrl-u, then the new name
+    daemon_proc.wait_until_list_matches(|out| out.contains("omega") && !out.contains("alpha"))?;
+    lm.scan_until_re("renamed 'alpha' to 'omega'")?;
+
+    // back to picking, so enter attaches to the renamed session
+    chooser.run_raw(b"\x15omeg\r".to_vec())?;
+    let omega_attached = Regex::new(r"(?m)^omega\s+attached$")?;
+    daemon_proc.wait_until_list_matches(|out| omega_attached.is_match(out))?;
+
+    Ok(())
+}
@@ -1,4 +1,8 @@
-use std::{io::BufReader, os::unix::net::UnixStream};
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    os::unix::net::UnixStream,
+};
 
 use anyhow::{anyhow, Context};
 use ntest::timeout;
@@ -6,7 +10,10 @@ use serde_json::Value;
 
 mod support;
 
-use crate::support::daemon::{self, AttachArgs, DaemonArgs};
+use crate::support::{
+    daemon::{self, AttachArgs, DaemonArgs},
+    tmpdir,
+};
 
 fn next_event(reader: &mut BufReader<UnixStream>) -> anyhow::Result<Value> {
     let mut line = String::new();
@@ -17,6 +24,25 @@ fn next_event(reader: &mut BufReader<UnixStream>) -> anyhow::Result<Value> {
     serde_json::from_str(&line).with_context(|| format!("parsing event JSON: {line:?}"))
 }
 
+/// Read events until one of the given type shows up, skipping any others.
+fn next_event_of_type(reader: &mut BufReader<UnixStream>, ty: &str) -> anyhow::Result<Value> {
+    loop {
+        let event = next_event(reader)?;
+        if event["type"] == ty {
+            return Ok(event);
+        }
+    }
+}
+
+fn next_event_skipping_busy(reader: &mut BufReader<UnixStream>) -> anyhow::Result<Value> {
+    loop {
+        let event = next_event(reader)?;
+        if event["type"] != "session.busy" {
+            return Ok(event);
+        }
+    }
+}
+
 #[test]
 #[timeout(30000)]
 fn lifecycle() -> anyhow::Result<()> {
@@ -33,14 +59,60 @@ fn lifecycle() -> anyhow::Result<()> {
         .attach("s1", AttachArgs { background: true, null_stdin: true, ..AttachArgs::default() })
         .context("starting attach proc")?;
 
-    assert_eq!(next_event(&mut sub)?["type"], "session.created");
-    assert_eq!(next_event(&mut sub)?["type"], "session.attached");
-    assert_eq!(next_event(&mut sub)?["type"], "session.detached");
+    let mut events = vec![];
+    for _ in 0..3 {
+        events.push(next_event(&mut sub)?);
+    }
 
     let kill_out = d.kill(vec!["s1".into()]).context("running kill")?;
     assert!(kill_out.status.success(), "kill failed: {:?}", kill_out);
 
-    assert_eq!(next_event(&mut sub)?["type"], "session.removed");
+    events.push(next_event(&mut sub)?);
+
+    let types: Vec<&Value> = events.iter().map(|e| &e["type"]).collect();
+    assert_eq!(
+        types,
+        ["session.created", "session.attached", "session.detached", "session.removed"]
+    );
+    for (i, event) in events.iter().enumerate() {
+        assert_eq!(event["session"], "s1", "event {event}");
+        assert_eq!(event["seq"], i as u64 + 1, "event {event}");
+        assert!(event["ts_unix_ms"].as_i64().unwrap_or(0) > 0, "event {event}");
+    }
+    assert_eq!(events[3]["reason"], "killed");
+
+    Ok(())
+}
+
+// A shell that exits on its own is removed with reason `exited` and the
+// status it exited with.
+#[test]
+#[timeout(30000)]
+fn exit_reports_reason_and_status() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let _attach = d
+        .attach(
+            "s",
+            AttachArgs {
+                cmd: Some(String::from("/bin/sh -c 'sleep 1; exit 3'")),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+    assert_eq!(next_event(&mut sub)?["type"], "session.created");
+    assert_eq!(next_event(&mut sub)?["type"], "session.attached");
+
+    let removed = next_event(&mut sub)?;
+    assert_eq!(removed["type"], "session.removed");
+    assert_eq!(removed["session"], "s");
+    assert_eq!(removed["reason"], "exited");
+    assert_eq!(removed["exit_status"], 3);
 
     Ok(())
 }
@@ -136,7 +208,9 @@ fn reattach_emits_attached_only() -> anyhow::Result<()> {
 // `session.created`/`session.removed` -- the subshell process survives the
 // whole handover. A kill fence pins this: with the subshell still alive, the
 // known next event is `session.removed`, so a duplicate detached on the kick or
-// a stray create/remove would surface there instead.
+// a stray create/remove would surface there instead. The forced client is
+// turned away as busy until the kick takes effect, so `session.busy` events
+// are skipped.
 #[test]
 #[timeout(30000)]
 fn force_reattach_kicks_old_client_and_keeps_subshell() -> anyhow::Result<()> {
@@ -164,11 +238,11 @@ fn force_reattach_kicks_old_client_and_keeps_subshell() -> anyhow::Result<()> {
         .context("forced reattach")?;
 
     assert_eq!(
-        next_event(&mut sub)?["type"],
+        next_event_skipping_busy(&mut sub)?["type"],
         "session.detached",
         "expected the old client to be kicked"
     );
-    let attached = next_event(&mut sub)?;
+    let attached = next_event_skipping_busy(&mut sub)?;
     assert_eq!(
         attached["type"], "session.attached",
         "expected attached on forced reattach, got {attached}"
@@ -192,6 +266,133 @@ fn force_reattach_kicks_old_client_and_keeps_subshell() -> anyhow::Result<()> {
     Ok(())
 }
 
+// A subscriber that reconnects with `resume_from` gets what happened while
+// it was away, and nothing it had already seen.
+#[test]
+#[timeout(30000)]
+fn resume_after_reconnect() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let _attach = d
+        .attach("s", AttachArgs { background: true, null_stdin: true, ..AttachArgs::default() })
+        .context("starting attach proc")?;
+    assert_eq!(next_event(&mut sub)?["type"], "session.created");
+    assert_eq!(next_event(&mut sub)?["type"], "session.attached");
+    let detached = next_event(&mut sub)?;
+    assert_eq!(detached["type"], "session.detached");
+    let last_seen = detached["seq"].as_u64().ok_or(anyhow!("no seq in {detached}"))?;
+    drop(sub);
+
+    let kill_out = d.kill(vec!["s".into()]).context("running kill")?;
+    assert!(kill_out.status.success(), "kill failed: {:?}", kill_out);
+
+    let mut sub = d.connect_events_resuming(last_seen)?;
+    let removed = next_event(&mut sub)?;
+    assert_eq!(removed["type"], "session.removed");
+    assert_eq!(removed["seq"], last_seen + 1);
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn var_and_log_level_changes() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let out = d.var_set("myvar", "myval")?;
+    assert!(out.status.success(), "var set failed: {:?}", out);
+    let set = next_event(&mut sub)?;
+    assert_eq!(set["type"], "var.changed");
+    assert_eq!(set["var"], "myvar");
+    assert_eq!(set["value"], "myval");
+
+    let out = d.var_unset("myvar")?;
+    assert!(out.status.success(), "var unset failed: {:?}", out);
+    let unset = next_event(&mut sub)?;
+    assert_eq!(unset["type"], "var.changed");
+    assert_eq!(unset["var"], "myvar");
+    assert_eq!(unset["value"], Value::Null);
+
+    let out = d.set_log_level("debug")?;
+    assert!(out.status.success(), "set-log-level failed: {:?}", out);
+    let level = next_event(&mut sub)?;
+    assert_eq!(level["type"], "log_level.changed");
+    assert_eq!(level["level"], "debug");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn busy_attach() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let _attach1 = d
+        .attach("s", AttachArgs { null_stdin: true, ..AttachArgs::default() })
+        .context("first attach")?;
+    assert_eq!(next_event(&mut sub)?["type"], "session.created");
+    assert_eq!(next_event(&mut sub)?["type"], "session.attached");
+    d.wait_until_list_matches(|out| out.contains("attached"))?;
+
+    let _attach2 = d
+        .attach("s", AttachArgs { null_stdin: true, ..AttachArgs::default() })
+        .context("second attach")?;
+    let busy = next_event(&mut sub)?;
+    assert_eq!(busy["type"], "session.busy");
+    assert_eq!(busy["session"], "s");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn config_reload() -> anyhow::Result<()> {
+    let tmp_dir = tmpdir::Dir::new("/tmp/shpool-test")?;
+    let config_file = tmp_dir.path().join("config.toml");
+    fs::write(&config_file, "norc = true\n")?;
+
+    let d = daemon::Proc::new(
+        &config_file,
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    // A reload can also fire right after startup, so skip ahead to the
+    // failure, which only this write can cause.
+    fs::write(&config_file, "norc = \"not a bool\n")?;
+    let failed = next_event_of_type(&mut sub, "config.reloaded")?;
+    let failed = if failed["ok"] == true {
+        next_event_of_type(&mut sub, "config.reloaded")?
+    } else {
+        failed
+    };
+    assert_eq!(failed["ok"], false);
+    assert!(failed["error"].is_string(), "no error in {failed}");
+
+    fs::write(&config_file, "norc = true\n")?;
+    let fixed = next_event_of_type(&mut sub, "config.reloaded")?;
+    assert_eq!(fixed["ok"], true);
+    assert_eq!(fixed["error"], Value::Null);
+
+    Ok(())
+}
+
 // SIGTERM should clean up both sockets via the signal handler, since
 // process::exit bypasses any RAII guard.
 #[test]
@@ -244,3 +445,58 @@ fn multiple_subscribers_each_get_independent_streams() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+#[timeout(30000)]
+fn filtered_subscription() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut all = d.connect_events()?;
+
+    let out = d.var_set("proj", "dev")?;
+    assert!(out.status.success(), "var set failed: {:?}", out);
+    for name in ["prod-1", "dev-1"] {
+        let _attach = d
+            .attach(
+                name,
+                AttachArgs { background: true, null_stdin: true, ..AttachArgs::default() },
+            )
+            .context("starting attach proc")?;
+        next_event_of_type(&mut all, "session.detached")?;
+    }
+    let kill_out = d.kill(vec!["prod-1".into(), "dev-1".into()]).context("running kill")?;
+    assert!(kill_out.status.success(), "kill failed: {:?}", kill_out);
+    next_event_of_type(&mut all, "session.removed")?;
+    next_event_of_type(&mut all, "session.removed")?;
+
+    // Resuming from the start replays the history through the filter, so
+    // there is no race with the subscription going live.
+    let mut proc =
+        d.events(&["--resume-from", "0", "--type", "session.*", "--session", "{proj}-*"])?;
+    let mut events = BufReader::new(proc.stdout.take().unwrap());
+    let mut seen = vec![];
+    for _ in 0..4 {
+        let mut line = String::new();
+        events.read_line(&mut line).context("reading event line")?;
+        let event: Value = serde_json::from_str(&line)?;
+        seen.push((event["type"].clone(), event["session"].clone()));
+    }
+    proc.kill()?;
+    proc.wait()?;
+
+    assert_eq!(
+        seen,
+        ["session.created", "session.attached", "session.detached", "session.removed"]
+            .map(|ty| (Value::from(ty), Value::from("dev-1")))
+    );
+
+    let mut raw = d.connect_events_with_request("type=session.* bogus\n")?;
+    let rejected = next_event(&mut raw)?;
+    assert_eq!(rejected["type"], "events.rejected");
+    assert!(next_event(&mut raw).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::{
+    io::{BufRead, BufReader},
+    os::unix::net::UnixStream,
+};
+
+use anyhow::{anyhow, Context};
+use ntest::timeout;
+use serde_json::Value;
+
+mod support;
+
+use crate::support::daemon::{self, AttachArgs, DaemonArgs};
+
+/// Skip ahead to the next event of the given type.
+fn next_event_of(reader: &mut BufReader<UnixStream>, event_type: &str) -> anyhow::Result<Value> {
+    loop {
+        let mut line = String::new();
+        let n = reader.read_line(&mut line).context("reading event line")?;
+        if n == 0 {
+            return Err(anyhow!("events socket closed unexpectedly"));
+        }
+        let event: Value =
+            serde_json::from_str(&line).with_context(|| format!("parsing event JSON: {line:?}"))?;
+        if event["type"] == event_type {
+            return Ok(event);
+        }
+        assert_ne!(event["type"], "session.removed", "unexpected removal: {event}");
+    }
+}
+
+#[test]
+#[timeout(30000)]
+fn detached_exit_lingers() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "linger.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let mut bg = d
+        .attach(
+            "s",
+            AttachArgs {
+                background: true,
+                null_stdin: true,
+                cmd: Some(String::from("/bin/sh -c 'echo all done; sleep 1; exit 7'")),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+    assert!(bg.proc.wait()?.success());
+
+    let exited = next_event_of(&mut sub, "session.exited")?;
+    assert_eq!(exited["session"], "s");
+    assert_eq!(exited["exit_status"], 7);
+
+    let list_out = d.list_format("name,status,exited")?;
+    assert!(list_out.status.success(), "list failed: {list_out:?}");
+    let stdout = String::from_utf8_lossy(&list_out.stdout);
+    assert!(stdout.contains("exited(7)"), "list output: {stdout}");
+    assert!(stdout.contains(" ago"), "list output: {stdout}");
+
+    let json_out = d.list_json()?;
+    let parsed: serde_json::Value = serde_json::from_slice(&json_out.stdout[..])?;
+    assert_eq!(parsed["sessions"][0]["status"], "Disconnected");
+    assert_eq!(parsed["sessions"][0]["exit_status"], 7);
+
+    // Reattaching shows the final screen, then exits like the shell did,
+    // and can be done more than once.
+    for _ in 0..2 {
+        let mut attach = d.attach("s", AttachArgs::default()).context("reattaching")?;
+        let mut line_matcher = attach.line_matcher()?;
+        line_matcher.scan_until_re("all done")?;
+        assert_eq!(attach.proc.wait()?.code(), Some(7));
+    }
+
+    let kill_out = d.kill(vec![String::from("s")])?;
+    assert!(kill_out.status.success(), "kill failed: {kill_out:?}");
+    let removed = next_event_of(&mut sub, "session.removed")?;
+    assert_eq!(removed["reason"], "dismissed");
+    assert_eq!(removed["exit_status"], 7);
+
+    let list_out = d.list()?;
+    let stdout = String::from_utf8_lossy(&list_out.stdout);
+    assert!(!stdout.contains("exited"), "list output: {stdout}");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn attached_exit_lingers_with_flag() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let mut attach = d
+        .attach(
+            "s",
+            AttachArgs {
+                cmd: Some(String::from("/bin/sh -c 'sleep 1; exit 3'")),
+                linger: true,
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+    // Waiting on the proc closes its stdin, which would detach it.
+    let exited = next_event_of(&mut sub, "session.exited")?;
+    assert_eq!(exited["exit_status"], 3);
+    assert_eq!(attach.proc.wait()?.code(), Some(3));
+
+    let list_out = d.list()?;
+    let stdout = String::from_utf8_lossy(&list_out.stdout);
+    assert!(stdout.contains("exited(3)"), "list output: {stdout}");
+
+    let mut attach = d.attach("s", AttachArgs::default()).context("reattaching")?;
+    assert_eq!(attach.proc.wait()?.code(), Some(3));
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn expires_after_timeout() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "linger_timeout.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let mut bg = d
+        .attach(
+            "s",
+            AttachArgs {
+                background: true,
+                null_stdin: true,
+                cmd: Some(String::from("/bin/sh -c 'sleep 1; exit 2'")),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+    assert!(bg.proc.wait()?.success());
+
+    next_event_of(&mut sub, "session.exited")?;
+    let removed = next_event_of(&mut sub, "session.removed")?;
+    assert_eq!(removed["session"], "s");
+    assert_eq!(removed["reason"], "expired");
+    assert_eq!(removed["exit_status"], 2);
+
+    Ok(())
+}
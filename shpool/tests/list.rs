@@ -1,4 +1,4 @@
-use std::process::Command;
+use std::{process::Command, time};
 
 use anyhow::{anyhow, Context};
 use ntest::timeout;
@@ -6,7 +6,7 @@ use regex::Regex;
 
 mod support;
 
-use crate::support::daemon::DaemonArgs;
+use crate::support::daemon::{AttachArgs, DaemonArgs};
 
 #[test]
 #[timeout(30000)]
@@ -201,3 +201,51 @@ fn json_output() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+#[timeout(30000)]
+fn rich_metadata() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut attach_proc = daemon_proc.attach(
+        "sh1",
+        AttachArgs {
+            ttl: Some(time::Duration::from_secs(60 * 60)),
+            dir: Some(String::from("/")),
+            ..Default::default()
+        },
+    )?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo up")?;
+    line_matcher.scan_until_re("up$")?;
+    attach_proc.run_cmd("sleep 1000")?;
+
+    let out = daemon_proc.list_json()?;
+    assert!(out.status.success(), "list --json proc did not exit successfully");
+    let parsed: serde_json::Value = serde_json::from_slice(&out.stdout[..])?;
+    let session = &parsed["sessions"][0];
+    assert!(session["pid"].as_i64().is_some());
+    assert_eq!(session["cwd"], "/");
+    assert!(session["ttl_expires_at_unix_ms"].as_i64().is_some());
+    assert!(session["tty_size"]["rows"].as_u64().is_some());
+    assert!(session["output_bytes_since_attach"].as_u64().unwrap_or(0) > 0);
+
+    // the sleep should take over the foreground of the pty shortly
+    support::wait_until(|| {
+        let out = daemon_proc.list_format("name,fg,cwd,ttl")?;
+        let stdout = String::from_utf8_lossy(&out.stdout[..]);
+        eprintln!("list --format out: {stdout}");
+        Ok(stdout.starts_with("NAME\tFG\tCWD\tTTL\n") && stdout.contains("sh1\tsleep\t/\t"))
+    })?;
+
+    let out = daemon_proc.list_format("name,bogus")?;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr[..]);
+    assert!(stderr.contains("unknown column 'bogus'"));
+
+    Ok(())
+}
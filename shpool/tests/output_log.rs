@@ -0,0 +1,71 @@
+use anyhow::Context;
+use ntest::timeout;
+
+mod support;
+
+use crate::support::daemon::{AttachArgs, DaemonArgs, Proc};
+
+fn daemon() -> anyhow::Result<Proc> {
+    Proc::new("output_log.toml", DaemonArgs { listen_events: false, ..DaemonArgs::default() })
+        .context("starting daemon proc")
+}
+
+#[test]
+#[timeout(30000)]
+fn config_format() -> anyhow::Result<()> {
+    let mut daemon_proc = daemon()?;
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+
+    attach_proc.run_cmd("echo cast-marker")?;
+    line_matcher.scan_until_re("cast-marker$")?;
+    attach_proc.run_cmd("head -n 1 $SHPOOL_SESSION_DIR/output.cast")?;
+    line_matcher.scan_until_re(r#"\{"version":2,"width":\d+,"height":\d+,"timestamp":\d+\}$"#)?;
+    attach_proc.run_cmd(
+        r#"echo markers=$(grep -c '"o",".*cast-marker' $SHPOOL_SESSION_DIR/output.cast)"#,
+    )?;
+    line_matcher.scan_until_re("markers=1$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn attach_flag_overrides_config() -> anyhow::Result<()> {
+    let mut daemon_proc = daemon()?;
+    let mut attach_proc = daemon_proc
+        .attach(
+            "sh1",
+            AttachArgs { output_log: Some(String::from("stripped")), ..AttachArgs::default() },
+        )
+        .context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+
+    attach_proc.run_cmd(r"printf '\033[31mred\033[0m-marker\n'")?;
+    line_matcher.scan_until_re("red.*-marker$")?;
+    attach_proc.run_cmd(
+        r"echo lines=$(grep -c 'red-marker' $SHPOOL_SESSION_DIR/output.txt) escapes=$(grep -c $'\e' $SHPOOL_SESSION_DIR/output.txt) casts=$(ls $SHPOOL_SESSION_DIR | grep -c cast)",
+    )?;
+    line_matcher.scan_until_re("lines=1 escapes=0 casts=0$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn attach_flag_turns_logging_off() -> anyhow::Result<()> {
+    let mut daemon_proc = daemon()?;
+    let mut attach_proc = daemon_proc
+        .attach(
+            "sh1",
+            AttachArgs { output_log: Some(String::from("off")), ..AttachArgs::default() },
+        )
+        .context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+
+    attach_proc.run_cmd("echo logs=$(ls $SHPOOL_SESSION_DIR | grep -c output)")?;
+    line_matcher.scan_until_re("logs=0$")?;
+
+    Ok(())
+}
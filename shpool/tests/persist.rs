@@ -0,0 +1,92 @@
+use std::{thread, time};
+
+use anyhow::Context;
+use ntest::timeout;
+
+mod support;
+
+use crate::support::{daemon::DaemonArgs, tmpdir};
+
+#[test]
+#[timeout(30000)]
+fn restores_after_restart() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "persist_sessions.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let work_dir = tmpdir::Dir::new("/tmp/shpool-test")?;
+
+    {
+        let mut attach_proc =
+            daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+        let mut line_matcher = attach_proc.line_matcher()?;
+
+        attach_proc.run_cmd(&format!("cd {}", work_dir.path().display()))?;
+        attach_proc.run_cmd("echo persist-marker")?;
+        line_matcher.scan_until_re("persist-marker$")?;
+
+        // give the checkpoints a chance to get written
+        thread::sleep(time::Duration::from_millis(2500));
+    }
+
+    daemon_proc.restart().context("restarting daemon")?;
+    daemon_proc.wait_until_list_matches(|out| out.contains("sh1\tdead (restorable)"))?;
+
+    // older clients that don't know about the flag just see a disconnected session
+    let out = daemon_proc.list_json()?;
+    let parsed: serde_json::Value = serde_json::from_slice(&out.stdout[..])?;
+    let session = &parsed["sessions"][0];
+    assert_eq!(session["status"], "Disconnected");
+    assert_eq!(session["restorable"], true);
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+
+    // the saved scrollback gets replayed
+    line_matcher.scan_until_re("persist-marker$")?;
+
+    // and the new shell starts where the old one left off
+    attach_proc.run_cmd("pwd")?;
+    line_matcher.scan_until_re(&format!("{}$", work_dir.path().display()))?;
+
+    let list_out = daemon_proc.list()?;
+    let list_stdout = String::from_utf8_lossy(&list_out.stdout[..]);
+    assert!(list_stdout.contains("sh1\tattached"), "list output: {list_stdout}");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn kill_forgets_restorable() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "persist_sessions.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    {
+        let mut attach_proc =
+            daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+        let mut line_matcher = attach_proc.line_matcher()?;
+        attach_proc.run_cmd("echo hi")?;
+        line_matcher.scan_until_re("hi$")?;
+        thread::sleep(time::Duration::from_millis(2500));
+    }
+
+    daemon_proc.restart().context("restarting daemon")?;
+    daemon_proc.wait_until_list_matches(|out| out.contains("dead (restorable)"))?;
+
+    let kill_out = daemon_proc.kill(vec![String::from("sh1")])?;
+    assert!(kill_out.status.success(), "kill failed: {:?}", kill_out);
+    daemon_proc.wait_until_list_matches(|out| !out.contains("sh1"))?;
+
+    // the checkpoint is gone too, so another restart does not bring it back
+    daemon_proc.restart().context("restarting daemon")?;
+    let list_out = daemon_proc.list()?;
+    assert!(!String::from_utf8_lossy(&list_out.stdout[..]).contains("sh1"));
+
+    Ok(())
+}
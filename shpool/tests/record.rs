@@ -0,0 +1,70 @@
+use std::fs;
+
+use anyhow::Context;
+use ntest::timeout;
+
+mod support;
+
+use crate::support::daemon::{DaemonArgs, Proc};
+
+#[test]
+#[timeout(30000)]
+fn record_and_replay() -> anyhow::Result<()> {
+    let mut daemon_proc =
+        Proc::new("norc.toml", DaemonArgs { listen_events: false, ..DaemonArgs::default() })
+            .context("starting daemon proc")?;
+    let cast = daemon_proc.tmp_dir.path().join("sh1.cast");
+    let cast_str = cast.to_str().unwrap();
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo before-marker")?;
+    line_matcher.scan_until_re("before-marker$")?;
+
+    let out = daemon_proc.record(&["start", "-o", cast_str, "sh1"])?;
+    assert!(out.status.success(), "record start failed: {:?}", out);
+    let out = daemon_proc.record(&["start", "sh1"])?;
+    assert!(!out.status.success(), "second record start should fail: {:?}", out);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("already being recorded"));
+
+    attach_proc.run_cmd("echo rec-marker")?;
+    line_matcher.scan_until_re("rec-marker$")?;
+
+    let out = daemon_proc.record(&["stop", "sh1"])?;
+    assert!(out.status.success(), "record stop failed: {:?}", out);
+    assert_eq!(String::from_utf8_lossy(&out.stdout).trim(), cast_str);
+
+    attach_proc.run_cmd("echo after-marker")?;
+    line_matcher.scan_until_re("after-marker$")?;
+
+    let out = daemon_proc.record(&["stop", "sh1"])?;
+    assert!(!out.status.success(), "second record stop should fail: {:?}", out);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("not being recorded"));
+
+    let recording = fs::read_to_string(&cast)?;
+    let header: serde_json::Value = serde_json::from_str(recording.lines().next().unwrap())?;
+    assert_eq!(header["version"], 2);
+    assert!(recording.contains("rec-marker"), "recording: {recording}");
+    assert!(!recording.contains("after-marker"), "recording: {recording}");
+
+    let out = daemon_proc.replay(&["--speed", "64", cast_str])?;
+    assert!(out.status.success(), "replay failed: {:?}", out);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("rec-marker"));
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn record_missing_session() -> anyhow::Result<()> {
+    let mut daemon_proc =
+        Proc::new("norc.toml", DaemonArgs { listen_events: false, ..DaemonArgs::default() })
+            .context("starting daemon proc")?;
+
+    let out = daemon_proc.record(&["start", "nosuchsession"])?;
+    assert!(!out.status.success(), "record start should fail: {:?}", out);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("not found: nosuchsession"));
+
+    Ok(())
+}
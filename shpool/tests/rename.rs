@@ -0,0 +1,118 @@
+use anyhow::Context;
+use ntest::timeout;
+
+mod support;
+
+use crate::support::daemon::DaemonArgs;
+
+#[test]
+#[timeout(30000)]
+fn attached() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo up")?;
+    line_matcher.scan_until_re("up$")?;
+
+    let out = daemon_proc.rename("sh1", "dev")?;
+    assert!(out.status.success(), "rename failed: {}", String::from_utf8_lossy(&out.stderr));
+
+    let list_out = String::from_utf8_lossy(&daemon_proc.list()?.stdout).into_owned();
+    assert!(list_out.contains("dev\tattached"), "list: {list_out}");
+    assert!(!list_out.contains("sh1"), "list: {list_out}");
+
+    // the terminal stays hooked up, and the shell can still get at its
+    // session dir through the old path
+    attach_proc.run_cmd("test -f $SHPOOL_SESSION_DIR/forward.env && echo dir-ok")?;
+    line_matcher.scan_until_re("dir-ok$")?;
+
+    drop(attach_proc);
+    daemon_proc.wait_until_list_matches(|out| out.contains("dev\tdisconnected"))?;
+
+    let mut attach_proc =
+        daemon_proc.attach("dev", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo $SHPOOL_SESSION_NAME-again")?;
+    line_matcher.scan_until_re("sh1-again$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn new_session_takes_old_name() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    {
+        let mut attach_proc =
+            daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+        let mut line_matcher = attach_proc.line_matcher()?;
+        attach_proc.run_cmd("echo up")?;
+        line_matcher.scan_until_re("up$")?;
+    }
+    daemon_proc.wait_until_list_matches(|out| out.contains("sh1\tdisconnected"))?;
+
+    let out = daemon_proc.rename("sh1", "dev")?;
+    assert!(out.status.success(), "rename failed: {}", String::from_utf8_lossy(&out.stderr));
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo fresh")?;
+    line_matcher.scan_until_re("fresh$")?;
+
+    daemon_proc.wait_until_list_matches(|out| {
+        out.contains("sh1\tattached") && out.contains("dev\tdisconnected")
+    })?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn errors() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo up")?;
+    line_matcher.scan_until_re("up$")?;
+    let mut attach_proc2 =
+        daemon_proc.attach("sh2", Default::default()).context("starting attach proc")?;
+    let mut line_matcher2 = attach_proc2.line_matcher()?;
+    attach_proc2.run_cmd("echo up")?;
+    line_matcher2.scan_until_re("up$")?;
+
+    let cases = [
+        ("nosuchsession", "dev", "not found: nosuchsession"),
+        ("sh1", "sh2", "there is already a session called 'sh2'"),
+        ("sh1", "a b", "invalid session name 'a b'"),
+    ];
+    for (old_name, new_name, want) in cases {
+        let out = daemon_proc.rename(old_name, new_name)?;
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr[..]);
+        assert!(stderr.contains(want), "stderr: {stderr}");
+    }
+
+    let list_out = String::from_utf8_lossy(&daemon_proc.list()?.stdout).into_owned();
+    assert!(list_out.contains("sh1\tattached"), "list: {list_out}");
+    assert!(list_out.contains("sh2\tattached"), "list: {list_out}");
+
+    Ok(())
+}
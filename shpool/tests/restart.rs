@@ -0,0 +1,238 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    thread, time,
+};
+
+use anyhow::{anyhow, Context};
+use ntest::timeout;
+use serde_json::Value;
+
+mod support;
+
+use crate::support::daemon::{self, AttachArgs, DaemonArgs};
+
+fn next_event(reader: &mut BufReader<UnixStream>) -> anyhow::Result<Value> {
+    let mut line = String::new();
+    let n = reader.read_line(&mut line).context("reading event line")?;
+    if n == 0 {
+        return Err(anyhow!("events socket closed unexpectedly"));
+    }
+    serde_json::from_str(&line).with_context(|| format!("parsing event JSON: {line:?}"))
+}
+
+/// Collect the restart and removal events up to and including the
+/// session.removed event.
+fn restarts_until_removed(reader: &mut BufReader<UnixStream>) -> anyhow::Result<Vec<Value>> {
+    let mut events = vec![];
+    loop {
+        let event = next_event(reader)?;
+        if event["type"] == "session.restarted" || event["type"] == "session.removed" {
+            events.push(event.clone());
+        }
+        if event["type"] == "session.removed" {
+            return Ok(events);
+        }
+    }
+}
+
+#[test]
+#[timeout(30000)]
+fn on_failure_until_max_restarts() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let _attach = d
+        .attach(
+            "s",
+            AttachArgs {
+                cmd: Some(String::from("/bin/sh -c 'echo run; exit 3'")),
+                restart: Some(String::from("on-failure")),
+                max_restarts: Some(2),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+
+    let events = restarts_until_removed(&mut sub)?;
+    assert_eq!(events.len(), 3, "events: {events:?}");
+    for (i, event) in events[..2].iter().enumerate() {
+        assert_eq!(event["type"], "session.restarted");
+        assert_eq!(event["session"], "s");
+        assert_eq!(event["exit_status"], 3);
+        assert_eq!(event["restarts"], i as u64 + 1);
+    }
+    assert_eq!(events[2]["reason"], "exited");
+    assert_eq!(events[2]["exit_status"], 3);
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn on_failure_lets_success_through() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let _attach = d
+        .attach(
+            "s",
+            AttachArgs {
+                cmd: Some(String::from("/bin/sh -c 'sleep 1; exit 0'")),
+                restart: Some(String::from("on-failure")),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+
+    let events = restarts_until_removed(&mut sub)?;
+    assert_eq!(events.len(), 1, "events: {events:?}");
+    assert_eq!(events[0]["exit_status"], 0);
+
+    Ok(())
+}
+
+// The output from before a restart sticks around, along with a marker
+// showing where the command got restarted.
+#[test]
+#[timeout(30000)]
+fn keeps_output_across_restarts() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "restart_profile.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let mut attach = d
+        .attach(
+            "s",
+            AttachArgs {
+                cmd: Some(String::from("/bin/sh -c 'echo run; sleep 1; exit 4'")),
+                restart: Some(String::from("always")),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+    // The client stays attached across the restart.
+    let mut line_matcher = attach.line_matcher()?;
+    line_matcher.scan_until_re("run")?;
+    line_matcher.scan_until_re(r"\[shpool\] restarted \(exit 4\)")?;
+    line_matcher.scan_until_re("run")?;
+    loop {
+        if next_event(&mut sub)?["type"] == "session.restarted" {
+            break;
+        }
+    }
+
+    // The marker and the report race through different pipes.
+    let mut screen = String::new();
+    for _ in 0..20 {
+        let out = d.capture("s", &[])?;
+        screen = String::from_utf8_lossy(&out.stdout).into_owned();
+        if screen.contains("[shpool] restarted (exit 4)") {
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(100));
+    }
+    assert!(screen.contains("run"), "screen: {screen}");
+    assert!(screen.contains("[shpool] restarted (exit 4)"), "screen: {screen}");
+
+    let kill_out = d.kill(vec![String::from("s")])?;
+    assert!(kill_out.status.success(), "kill failed: {kill_out:?}");
+    let removed = restarts_until_removed(&mut sub)?;
+    assert_eq!(removed.last().unwrap()["reason"], "killed");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn profile_from_config() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "restart_profile.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let mut sub = d.connect_events()?;
+
+    let _attach = d
+        .attach(
+            "s",
+            AttachArgs { cmd: Some(String::from("/bin/sh -c 'exit 5'")), ..AttachArgs::default() },
+        )
+        .context("starting attach proc")?;
+
+    let events = restarts_until_removed(&mut sub)?;
+    assert_eq!(events.len(), 2, "events: {events:?}");
+    assert_eq!(events[0]["type"], "session.restarted");
+    assert_eq!(events[0]["exit_status"], 5);
+    assert_eq!(events[1]["exit_status"], 5);
+
+    Ok(())
+}
+
+// Killing a session takes out the command for good, even if the command
+// ignores SIGHUP.
+#[test]
+#[timeout(30000)]
+#[cfg_attr(target_os = "macos", ignore)] // checks for the command in /proc
+fn kill_reaches_command() -> anyhow::Result<()> {
+    let mut d = daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let pid_file = d.tmp_dir.path().join("cmd.pid");
+
+    let _attach = d
+        .attach(
+            "s",
+            AttachArgs {
+                cmd: Some(format!(
+                    "/bin/sh -c 'trap \"\" HUP; echo $$ > {}; exec sleep 1000'",
+                    pid_file.display()
+                )),
+                restart: Some(String::from("always")),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+
+    let mut pid = String::new();
+    for _ in 0..50 {
+        pid = fs::read_to_string(&pid_file).unwrap_or_default();
+        if pid.ends_with('\n') {
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(100));
+    }
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid.trim()));
+    assert!(proc_dir.exists(), "command is not running (pid {pid:?})");
+
+    // The command is the session's root process, so list reports on it
+    // rather than on some go-between.
+    let list_out = d.list_json()?;
+    let parsed: Value = serde_json::from_slice(&list_out.stdout[..])?;
+    assert_eq!(parsed["sessions"][0]["pid"].to_string(), pid.trim());
+
+    let kill_out = d.kill(vec![String::from("s")])?;
+    assert!(kill_out.status.success(), "kill failed: {kill_out:?}");
+    for _ in 0..50 {
+        if !proc_dir.exists() {
+            return Ok(());
+        }
+        thread::sleep(time::Duration::from_millis(100));
+    }
+    Err(anyhow!("command is still running after the session was killed"))
+}
@@ -0,0 +1,185 @@
+use std::{thread, time};
+
+use anyhow::Context;
+use ntest::timeout;
+
+mod support;
+
+use crate::support::daemon::{AttachArgs, DaemonArgs};
+
+#[test]
+#[timeout(30000)]
+fn attached() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo up")?;
+    line_matcher.scan_until_re("up$")?;
+
+    let out = daemon_proc.send_keys("sh1", &["echo from-", "send-keys", "Ctrl-m"], None)?;
+    assert!(out.status.success());
+    line_matcher.scan_until_re("from-send-keys$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn no_separator() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo up")?;
+    line_matcher.scan_until_re("up$")?;
+
+    // The args run together, just like in tmux. The quotes keep the echoed
+    // command line from matching.
+    let out =
+        daemon_proc.send_keys("sh1", &["echo", "Space", "'left'", "'right'", "Ctrl-m"], None)?;
+    assert!(out.status.success());
+    line_matcher.scan_until_re("leftright$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn detached() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "restore_screen.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    {
+        let mut attach_proc =
+            daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+        let mut line_matcher = attach_proc.line_matcher()?;
+        attach_proc.run_cmd("echo up")?;
+        line_matcher.scan_until_re("up$")?;
+    }
+    daemon_proc.wait_until_list_matches(|out| out.contains("sh1\tdisconnected"))?;
+
+    let out = daemon_proc.send_keys("sh1", &["echo while-detached", "Ctrl-m"], None)?;
+    assert!(out.status.success());
+
+    support::wait_until(|| {
+        let out = daemon_proc.capture("sh1", &[])?;
+        Ok(String::from_utf8_lossy(&out.stdout[..]).contains("while-detached"))
+    })?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn ctrl_c() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo up")?;
+    line_matcher.scan_until_re("up$")?;
+
+    // if the Ctrl-c doesn't interrupt the sleep, we will time out
+    // waiting for the echo
+    attach_proc.run_cmd("sleep 1000")?;
+    let out = daemon_proc.send_keys("sh1", &["Ctrl-c"], None)?;
+    assert!(out.status.success());
+    attach_proc.run_cmd("echo interrupted")?;
+    line_matcher.scan_until_re("interrupted$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn stdin() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let mut attach_proc =
+        daemon_proc.attach("sh1", Default::default()).context("starting attach proc")?;
+    let mut line_matcher = attach_proc.line_matcher()?;
+    attach_proc.run_cmd("echo up")?;
+    line_matcher.scan_until_re("up$")?;
+
+    let out = daemon_proc.send_keys("sh1", &[], Some(b"echo Ctrl-c-from-stdin\n"))?;
+    assert!(out.status.success());
+    line_matcher.scan_until_re("Ctrl-c-from-stdin$")?;
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn not_found() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let out = daemon_proc.send_keys("nosuchsession", &["hi"], None)?;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr[..]);
+    assert!(stderr.contains("not found: nosuchsession"));
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn not_reading() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let marker_path = daemon_proc.tmp_dir.path().join("raw-marker");
+    let cmd = format!("sh -c 'stty raw -echo; touch {}; sleep 100'", marker_path.display());
+    let _attach_proc = daemon_proc
+        .attach(
+            "sh1",
+            AttachArgs { background: true, null_stdin: true, cmd: Some(cmd), ..Default::default() },
+        )
+        .context("starting attach proc")?;
+    while !marker_path.exists() {
+        thread::sleep(time::Duration::from_millis(100));
+    }
+
+    // A terminal in raw mode blocks writers rather than dropping input once
+    // it is full, and sleep never reads, so this is more than the pty can hold.
+    let input = vec![b'a'; 1024 * 1024];
+    let out = daemon_proc.send_keys("sh1", &[], Some(&input))?;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr[..]);
+    assert!(stderr.contains("not reading its input"), "stderr: {stderr}");
+
+    // the daemon is still happy to serve other requests
+    let out = daemon_proc.list()?;
+    assert!(out.status.success());
+
+    Ok(())
+}
@@ -4,7 +4,7 @@
 use std::{
     default::Default,
     env,
-    io::BufReader,
+    io::{BufReader, Write as _},
     os::unix::{net::UnixStream, prelude::ExitStatusExt as _},
     path::{Path, PathBuf},
     process,
@@ -22,6 +22,9 @@ use super::{attach, events::Events, shpool_bin, testdata_file, tmpdir, wait_unti
 /// It kills the subprocess when it goes out of scope.
 pub struct Proc {
     pub proc: Option<process::Child>,
+    // Kept around so that `restart` can respawn the daemon with the
+    // same args. Only present when created by new().
+    cmd: Option<Command>,
     subproc_counter: usize,
     pub log_file: PathBuf,
     pub tmp_dir: tmpdir::Dir,
@@ -51,11 +54,18 @@ pub struct AttachArgs {
     pub config: Option<String>,
     pub force: bool,
     pub background: bool,
+    pub watch: bool,
+    pub share: bool,
     pub extra_env: Vec<(String, String)>,
     pub ttl: Option<time::Duration>,
     pub cmd: Option<String>,
     pub dir: Option<String>,
     pub start_cmd: Option<String>,
+    pub output_log: Option<String>,
+    pub keybindings: Option<String>,
+    pub restart: Option<String>,
+    pub max_restarts: Option<u32>,
+    pub linger: bool,
     pub null_stdin: bool,
 }
 
@@ -155,20 +165,11 @@ impl Proc {
         let events =
             if args.listen_events { Some(Events::new(&test_hook_socket_path)?) } else { None };
 
-        // spin until we can dial the socket successfully
-        let events_socket_path = socket_path.with_file_name("events.socket");
-        let mut sleep_dur = time::Duration::from_millis(5);
-        for _ in 0..12 {
-            if UnixStream::connect(&socket_path).is_ok() && events_socket_path.exists() {
-                break;
-            } else {
-                std::thread::sleep(sleep_dur);
-                sleep_dur *= 2;
-            }
-        }
+        wait_for_daemon_sockets(&socket_path);
 
         Ok(Proc {
             proc: Some(proc),
+            cmd: Some(cmd),
             tmp_dir,
             log_file,
             subproc_counter: 0,
@@ -249,20 +250,11 @@ impl Proc {
             }
         });
 
-        // spin until we can dial the socket successfully
-        let events_socket_path = socket_path.with_file_name("events.socket");
-        let mut sleep_dur = time::Duration::from_millis(5);
-        for _ in 0..12 {
-            if UnixStream::connect(&socket_path).is_ok() && events_socket_path.exists() {
-                break;
-            } else {
-                std::thread::sleep(sleep_dur);
-                sleep_dur *= 2;
-            }
-        }
+        wait_for_daemon_sockets(&socket_path);
 
         Ok(Proc {
             proc: None,
+            cmd: None,
             tmp_dir,
             log_file,
             subproc_counter: 0,
@@ -283,10 +275,25 @@ impl Proc {
     /// daemon has bound it. 9 iterations starting at 20ms double up to a
     /// total max sleep of ~10.2s.
     pub fn connect_events(&self) -> anyhow::Result<BufReader<UnixStream>> {
+        self.connect_events_with_request("\n")
+    }
+
+    /// Like connect_events, but first asks for the retained events after
+    /// `seq`.
+    pub fn connect_events_resuming(&self, seq: u64) -> anyhow::Result<BufReader<UnixStream>> {
+        self.connect_events_with_request(&format!("resume_from={seq}\n"))
+    }
+
+    /// Connect to the events socket and send `request` as the request line.
+    pub fn connect_events_with_request(
+        &self,
+        request: &str,
+    ) -> anyhow::Result<BufReader<UnixStream>> {
         let path = self.events_socket_path();
         let mut sleep_dur = time::Duration::from_millis(20);
         for _ in 0..9 {
-            if let Ok(stream) = UnixStream::connect(&path) {
+            if let Ok(mut stream) = UnixStream::connect(&path) {
+                stream.write_all(request.as_bytes()).context("writing events request")?;
                 return Ok(BufReader::new(stream));
             }
             std::thread::sleep(sleep_dur);
@@ -295,6 +302,26 @@ impl Proc {
         Err(anyhow!("events socket never became available at {:?}", path))
     }
 
+    /// Kill the daemon without giving it a chance to clean up, then start
+    /// a new one with the same args, socket and runtime dir. Only works
+    /// for daemons created with new().
+    pub fn restart(&mut self) -> anyhow::Result<()> {
+        let cmd = self.cmd.as_mut().ok_or(anyhow!("no daemon command to restart"))?;
+        if let Some(mut proc) = self.proc.take() {
+            proc.kill().context("killing daemon proc")?;
+            proc.wait().context("waiting for daemon proc")?;
+        }
+
+        eprintln!("respawning daemon proc with log {:?}", self.log_file);
+        self.proc = Some(cmd.spawn().context("respawning daemon process")?);
+        if self.events.is_some() {
+            self.events = Some(Events::new(self.tmp_dir.path().join("hook.sock"))?);
+        }
+        wait_for_daemon_sockets(&self.socket_path);
+
+        Ok(())
+    }
+
     pub fn proc_kill(&mut self) -> std::io::Result<()> {
         if let Some(proc) = &mut self.proc {
             proc.kill()
@@ -363,6 +390,12 @@ impl Proc {
         if args.background {
             cmd.arg("-b");
         }
+        if args.watch {
+            cmd.arg("--watch");
+        }
+        if args.share {
+            cmd.arg("--share");
+        }
         if let Some(ttl) = args.ttl {
             cmd.arg("--ttl");
             cmd.arg(format!("{}s", ttl.as_secs()));
@@ -379,6 +412,25 @@ impl Proc {
             cmd.arg("--start-cmd");
             cmd.arg(start_cmd);
         }
+        if let Some(output_log) = &args.output_log {
+            cmd.arg("--output-log");
+            cmd.arg(output_log);
+        }
+        if let Some(keybindings) = &args.keybindings {
+            cmd.arg("--keybindings");
+            cmd.arg(keybindings);
+        }
+        if let Some(restart) = &args.restart {
+            cmd.arg("--restart");
+            cmd.arg(restart);
+        }
+        if let Some(max_restarts) = args.max_restarts {
+            cmd.arg("--max-restarts");
+            cmd.arg(max_restarts.to_string());
+        }
+        if args.linger {
+            cmd.arg("--linger");
+        }
         let proc = cmd.arg(name).spawn().context(format!("spawning attach proc for {name}"))?;
 
         let events = Events::new(&test_hook_socket_path)?;
@@ -386,6 +438,37 @@ impl Proc {
         Ok(attach::Proc { proc, log_file, events: Some(events) })
     }
 
+    /// choose spawns a `shpool choose` process with stdin and stdout
+    /// piped, so it can be driven just like an attach proc.
+    pub fn choose(&mut self) -> anyhow::Result<attach::Proc> {
+        let log_file = self.tmp_dir.path().join(format!("choose_{}.log", self.subproc_counter));
+        eprintln!("spawning choose proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        let mut cmd = Command::new(&self.bin_path);
+        cmd.stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdin(Stdio::piped())
+            .arg("--config-file")
+            .arg(&self.config_path)
+            .arg("-v")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("--no-daemonize")
+            .env_clear()
+            .arg("choose");
+        for var in ["XDG_RUNTIME_DIR", "HOME", "PATH"] {
+            if let Ok(val) = env::var(var) {
+                cmd.env(var, val);
+            }
+        }
+        let proc = cmd.spawn().context("spawning choose proc")?;
+
+        Ok(attach::Proc { proc, log_file, events: None })
+    }
+
     pub fn detach(&mut self, sessions: Vec<String>) -> anyhow::Result<process::Output> {
         let log_file = self.tmp_dir.path().join(format!("detach_{}.log", self.subproc_counter));
         eprintln!("spawning detach proc with log {:?}", log_file);
@@ -424,6 +507,196 @@ impl Proc {
         cmd.output().context("spawning kill proc")
     }
 
+    pub fn ttl(&mut self, args: &[&str]) -> anyhow::Result<process::Output> {
+        let log_file = self.tmp_dir.path().join(format!("ttl_{}.log", self.subproc_counter));
+        eprintln!("spawning ttl proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        Command::new(&self.bin_path)
+            .arg("-vv")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("ttl")
+            .args(args)
+            .output()
+            .context("spawning ttl proc")
+    }
+
+    pub fn rename(&mut self, old_name: &str, new_name: &str) -> anyhow::Result<process::Output> {
+        let log_file = self.tmp_dir.path().join(format!("rename_{}.log", self.subproc_counter));
+        eprintln!("spawning rename proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        Command::new(&self.bin_path)
+            .arg("-vv")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("rename")
+            .arg(old_name)
+            .arg(new_name)
+            .output()
+            .context("spawning rename proc")
+    }
+
+    /// wait spawns a `shpool wait` process with the given args and its
+    /// stderr piped.
+    pub fn wait(&mut self, args: &[&str]) -> anyhow::Result<process::Child> {
+        let log_file = self.tmp_dir.path().join(format!("wait_{}.log", self.subproc_counter));
+        eprintln!("spawning wait proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        Command::new(&self.bin_path)
+            .arg("-vv")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("wait")
+            .args(args)
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("spawning wait proc")
+    }
+
+    /// wait_for_output spawns a `shpool wait-for-output` process with the
+    /// given args and its stdout and stderr piped.
+    pub fn wait_for_output(&mut self, args: &[&str]) -> anyhow::Result<process::Child> {
+        let log_file =
+            self.tmp_dir.path().join(format!("wait_for_output_{}.log", self.subproc_counter));
+        eprintln!("spawning wait-for-output proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        Command::new(&self.bin_path)
+            .arg("-vv")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("wait-for-output")
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("spawning wait-for-output proc")
+    }
+
+    /// events spawns a `shpool events` process with the given args and its
+    /// stdout piped. The caller is responsible for killing it.
+    pub fn events(&mut self, args: &[&str]) -> anyhow::Result<process::Child> {
+        let log_file = self.tmp_dir.path().join(format!("events_{}.log", self.subproc_counter));
+        eprintln!("spawning events proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        Command::new(&self.bin_path)
+            .arg("-vv")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("events")
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("spawning events proc")
+    }
+
+    /// record runs `shpool record` with the given args.
+    pub fn record(&mut self, args: &[&str]) -> anyhow::Result<process::Output> {
+        let log_file = self.tmp_dir.path().join(format!("record_{}.log", self.subproc_counter));
+        eprintln!("spawning record proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        Command::new(&self.bin_path)
+            .arg("-vv")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("record")
+            .args(args)
+            .output()
+            .context("spawning record proc")
+    }
+
+    /// replay runs `shpool replay` with the given args.
+    pub fn replay(&mut self, args: &[&str]) -> anyhow::Result<process::Output> {
+        let log_file = self.tmp_dir.path().join(format!("replay_{}.log", self.subproc_counter));
+        eprintln!("spawning replay proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        Command::new(&self.bin_path)
+            .arg("-vv")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("replay")
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .context("spawning replay proc")
+    }
+
+    /// capture launches a `shpool capture` process for the given session,
+    /// passing along any extra args.
+    pub fn capture(&mut self, session: &str, args: &[&str]) -> anyhow::Result<process::Output> {
+        let log_file = self.tmp_dir.path().join(format!("capture_{}.log", self.subproc_counter));
+        eprintln!("spawning capture proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        Command::new(&self.bin_path)
+            .arg("-vv")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("capture")
+            .args(args)
+            .arg(session)
+            .output()
+            .context("spawning capture proc")
+    }
+
+    /// send_keys launches a `shpool send-keys` process for the given
+    /// session. If stdin is provided, it is piped in and `--stdin` is passed.
+    pub fn send_keys(
+        &mut self,
+        session: &str,
+        keys: &[&str],
+        stdin: Option<&[u8]>,
+    ) -> anyhow::Result<process::Output> {
+        let log_file = self.tmp_dir.path().join(format!("send_keys_{}.log", self.subproc_counter));
+        eprintln!("spawning send-keys proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        let mut cmd = Command::new(&self.bin_path);
+        cmd.arg("-vv")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("send-keys")
+            .arg(session)
+            .args(keys);
+        let Some(stdin) = stdin else {
+            return cmd.output().context("spawning send-keys proc");
+        };
+
+        let mut proc = cmd
+            .arg("--stdin")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("spawning send-keys proc")?;
+        proc.stdin.take().unwrap().write_all(stdin).context("writing send-keys stdin")?;
+        proc.wait_with_output().context("waiting for send-keys proc")
+    }
+
     pub fn wait_until_list_matches<F>(&mut self, pred: F) -> anyhow::Result<()>
     where
         F: Fn(&str) -> bool,
@@ -475,6 +748,24 @@ impl Proc {
             .context("spawning list --json proc")
     }
 
+    pub fn list_format(&mut self, format: &str) -> anyhow::Result<process::Output> {
+        let log_file = self.tmp_dir.path().join(format!("list_{}.log", self.subproc_counter));
+        eprintln!("spawning list --format proc with log {:?}", log_file);
+        self.subproc_counter += 1;
+
+        Command::new(&self.bin_path)
+            .arg("-vv")
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("--socket")
+            .arg(&self.socket_path)
+            .arg("list")
+            .arg("--format")
+            .arg(format)
+            .output()
+            .context("spawning list --format proc")
+    }
+
     // launches a `shpool set-log-level` process
     pub fn set_log_level(&mut self, level: &str) -> anyhow::Result<process::Output> {
         let log_file =
@@ -585,6 +876,20 @@ impl Proc {
     }
 }
 
+// spin until we can dial the socket successfully
+fn wait_for_daemon_sockets(socket_path: &Path) {
+    let events_socket_path = socket_path.with_file_name("events.socket");
+    let mut sleep_dur = time::Duration::from_millis(5);
+    for _ in 0..12 {
+        if UnixStream::connect(socket_path).is_ok() && events_socket_path.exists() {
+            break;
+        } else {
+            std::thread::sleep(sleep_dur);
+            sleep_dur *= 2;
+        }
+    }
+}
+
 impl std::ops::Drop for Proc {
     fn drop(&mut self) {
         if let Err(e) = self.proc_kill() {
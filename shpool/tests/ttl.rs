@@ -0,0 +1,122 @@
+use std::{thread, time};
+
+use anyhow::Context;
+use ntest::timeout;
+
+mod support;
+
+use crate::support::daemon::{AttachArgs, DaemonArgs};
+
+fn ttl_expires_at_unix_ms(daemon_proc: &mut support::daemon::Proc) -> anyhow::Result<Option<i64>> {
+    let out = daemon_proc.list_json()?;
+    assert!(out.status.success(), "list --json failed");
+    let parsed: serde_json::Value = serde_json::from_slice(&out.stdout[..])?;
+    Ok(parsed["sessions"][0]["ttl_expires_at_unix_ms"].as_i64())
+}
+
+fn unix_ms_from_now(d: time::Duration) -> i64 {
+    (time::SystemTime::now() + d).duration_since(time::UNIX_EPOCH).unwrap().as_millis() as i64
+}
+
+#[test]
+#[timeout(30000)]
+fn get_set_extend_clear() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let _attach_proc = daemon_proc
+        .attach("sh1", AttachArgs { background: true, null_stdin: true, ..AttachArgs::default() })
+        .context("starting attach proc")?;
+    daemon_proc.wait_until_list_matches(|out| out.contains("sh1"))?;
+
+    let out = daemon_proc.ttl(&["get", "sh1"])?;
+    assert!(out.status.success(), "ttl get failed: {}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "none\n");
+    assert_eq!(ttl_expires_at_unix_ms(&mut daemon_proc)?, None);
+
+    let hour = time::Duration::from_secs(60 * 60);
+    let out = daemon_proc.ttl(&["set", "sh1", "1h"])?;
+    assert!(out.status.success(), "ttl set failed: {}", String::from_utf8_lossy(&out.stderr));
+    let expires_at = ttl_expires_at_unix_ms(&mut daemon_proc)?.context("no ttl after set")?;
+    assert!((expires_at - unix_ms_from_now(hour)).abs() < 10_000, "expires at {expires_at}");
+    let out = daemon_proc.ttl(&["get", "sh1"])?;
+    assert!(String::from_utf8_lossy(&out.stdout).starts_with("59m"), "get: {:?}", out.stdout);
+
+    let out = daemon_proc.ttl(&["extend", "sh1", "1h"])?;
+    assert!(out.status.success(), "ttl extend failed: {}", String::from_utf8_lossy(&out.stderr));
+    let extended = ttl_expires_at_unix_ms(&mut daemon_proc)?.context("no ttl after extend")?;
+    assert_eq!(extended - expires_at, 60 * 60 * 1000);
+
+    let out = daemon_proc.ttl(&["clear", "sh1"])?;
+    assert!(out.status.success(), "ttl clear failed: {}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(ttl_expires_at_unix_ms(&mut daemon_proc)?, None);
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn set_replaces_old_deadline() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+    let _long = daemon_proc
+        .attach(
+            "long",
+            AttachArgs {
+                background: true,
+                null_stdin: true,
+                ttl: Some(time::Duration::from_secs(1000)),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+    let _short = daemon_proc
+        .attach(
+            "short",
+            AttachArgs {
+                background: true,
+                null_stdin: true,
+                ttl: Some(time::Duration::from_secs(2)),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+    daemon_proc.wait_until_list_matches(|out| out.contains("long") && out.contains("short"))?;
+
+    let out = daemon_proc.ttl(&["set", "long", "1s"])?;
+    assert!(out.status.success(), "ttl set failed: {}", String::from_utf8_lossy(&out.stderr));
+    let out = daemon_proc.ttl(&["clear", "short"])?;
+    assert!(out.status.success(), "ttl clear failed: {}", String::from_utf8_lossy(&out.stderr));
+
+    daemon_proc.wait_until_list_matches(|out| !out.contains("long"))?;
+    thread::sleep(time::Duration::from_secs(3));
+    let list_out = String::from_utf8_lossy(&daemon_proc.list()?.stdout).into_owned();
+    assert!(list_out.contains("short"), "list: {list_out}");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn not_found() -> anyhow::Result<()> {
+    let mut daemon_proc = support::daemon::Proc::new(
+        "norc.toml",
+        DaemonArgs { listen_events: false, ..DaemonArgs::default() },
+    )
+    .context("starting daemon proc")?;
+
+    let out = daemon_proc.ttl(&["get", "nope"])?;
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("not found: nope"));
+
+    let out = daemon_proc.ttl(&["set", "nope", "bogus"])?;
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("could not parse duration"));
+
+    Ok(())
+}
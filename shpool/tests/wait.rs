@@ -0,0 +1,155 @@
+use std::{thread, time};
+
+use anyhow::Context;
+use ntest::timeout;
+
+mod support;
+
+use crate::support::daemon::{self, AttachArgs, DaemonArgs};
+
+fn start_daemon() -> anyhow::Result<daemon::Proc> {
+    daemon::Proc::new("norc.toml", DaemonArgs { listen_events: false, ..DaemonArgs::default() })
+        .context("starting daemon proc")
+}
+
+/// Start a detached session running the given command, or the default
+/// shell.
+fn start_detached(d: &mut daemon::Proc, name: &str, cmd: Option<&str>) -> anyhow::Result<()> {
+    let mut attach = d
+        .attach(
+            name,
+            AttachArgs {
+                background: true,
+                null_stdin: true,
+                cmd: cmd.map(String::from),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+    assert!(attach.proc.wait()?.success());
+    // The daemon takes a moment to notice that the client has gone.
+    d.wait_until_list_matches(|out| !out.contains("attached"))?;
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn exits_with_session_status() -> anyhow::Result<()> {
+    let mut d = start_daemon()?;
+    start_detached(&mut d, "s", Some("/bin/sh -c 'sleep 1; exit 9'"))?;
+
+    let out = d.wait(&["s"])?.wait_with_output()?;
+    assert_eq!(out.status.code(), Some(9), "wait output: {out:?}");
+
+    // Waiting on a session whose shell already exited returns right away.
+    let out = d.wait(&["s"])?.wait_with_output()?;
+    assert_eq!(out.status.code(), Some(9), "wait output: {out:?}");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn until_attached_and_detached() -> anyhow::Result<()> {
+    let mut d = start_daemon()?;
+    start_detached(&mut d, "s", None)?;
+
+    let mut waiter = d.wait(&["--until", "attached", "s"])?;
+    thread::sleep(time::Duration::from_millis(500));
+    assert!(waiter.try_wait()?.is_none(), "wait returned before anyone attached");
+
+    let mut attach = d.attach("s", AttachArgs::default()).context("attaching")?;
+    attach.run_cmd("echo hi")?;
+    assert_eq!(waiter.wait()?.code(), Some(0));
+
+    let mut waiter = d.wait(&["--until", "detached", "s"])?;
+    thread::sleep(time::Duration::from_millis(500));
+    assert!(waiter.try_wait()?.is_none(), "wait returned while still attached");
+
+    let detach_out = d.detach(vec![String::from("s")])?;
+    assert!(detach_out.status.success(), "detach failed: {detach_out:?}");
+    assert_eq!(waiter.wait()?.code(), Some(0));
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn until_attached_fails_if_shell_exits() -> anyhow::Result<()> {
+    let mut d = start_daemon()?;
+    start_detached(&mut d, "s", Some("/bin/sh -c 'sleep 1; exit 4'"))?;
+
+    let out = d.wait(&["--until", "attached", "s"])?.wait_with_output()?;
+    assert_eq!(out.status.code(), Some(1), "wait output: {out:?}");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("exited with status 4"), "stderr: {stderr}");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn until_removed_follows_renames() -> anyhow::Result<()> {
+    let mut d = start_daemon()?;
+    start_detached(&mut d, "s", None)?;
+
+    let mut waiter = d.wait(&["--until", "removed", "--timeout", "20s", "s"])?;
+    thread::sleep(time::Duration::from_millis(500));
+
+    let rename_out = d.rename("s", "t")?;
+    assert!(rename_out.status.success(), "rename failed: {rename_out:?}");
+    thread::sleep(time::Duration::from_millis(500));
+    assert!(waiter.try_wait()?.is_none(), "wait returned after a rename");
+
+    let kill_out = d.kill(vec![String::from("t")])?;
+    assert!(kill_out.status.success(), "kill failed: {kill_out:?}");
+    let status = waiter.wait()?;
+    assert!(status.code().is_some() && status.code() != Some(124), "wait status: {status:?}");
+
+    Ok(())
+}
+
+// A detached session whose shell exits without lingering counts as
+// removed, even though it only leaves the table once someone attaches.
+#[test]
+#[timeout(30000)]
+fn until_removed_after_exit() -> anyhow::Result<()> {
+    let mut d = start_daemon()?;
+    start_detached(&mut d, "s", Some("/bin/sh -c 'sleep 1; exit 6'"))?;
+
+    let out = d.wait(&["--until", "removed", "--timeout", "20s", "s"])?.wait_with_output()?;
+    assert_eq!(out.status.code(), Some(6), "wait output: {out:?}");
+
+    // The same goes for a session that is already dead.
+    let out = d.wait(&["--until", "removed", "--timeout", "20s", "s"])?.wait_with_output()?;
+    assert_eq!(out.status.code(), Some(6), "wait output: {out:?}");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn times_out() -> anyhow::Result<()> {
+    let mut d = start_daemon()?;
+    start_detached(&mut d, "s", None)?;
+
+    let out = d.wait(&["--timeout", "1s", "s"])?.wait_with_output()?;
+    assert_eq!(out.status.code(), Some(124), "wait output: {out:?}");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("timed out"), "stderr: {stderr}");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn not_found() -> anyhow::Result<()> {
+    let mut d = start_daemon()?;
+
+    let out = d.wait(&["nosuchsession"])?.wait_with_output()?;
+    assert_eq!(out.status.code(), Some(1), "wait output: {out:?}");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("not found: nosuchsession"), "stderr: {stderr}");
+
+    Ok(())
+}
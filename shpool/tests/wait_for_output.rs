@@ -0,0 +1,130 @@
+use std::{thread, time};
+
+use anyhow::Context;
+use ntest::timeout;
+
+mod support;
+
+use crate::support::daemon::{self, AttachArgs, DaemonArgs};
+
+fn start_daemon(config: &str) -> anyhow::Result<daemon::Proc> {
+    daemon::Proc::new(config, DaemonArgs { listen_events: false, ..DaemonArgs::default() })
+        .context("starting daemon proc")
+}
+
+/// Start a detached session running the given command, or the default
+/// shell.
+fn start_detached(d: &mut daemon::Proc, name: &str, cmd: Option<&str>) -> anyhow::Result<()> {
+    let mut attach = d
+        .attach(
+            name,
+            AttachArgs {
+                background: true,
+                null_stdin: true,
+                cmd: cmd.map(String::from),
+                ..AttachArgs::default()
+            },
+        )
+        .context("starting attach proc")?;
+    assert!(attach.proc.wait()?.success());
+    d.wait_until_list_matches(|out| !out.contains("attached"))?;
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn prints_match() -> anyhow::Result<()> {
+    let mut d = start_daemon("norc.toml")?;
+    start_detached(&mut d, "s", None)?;
+
+    let waiter = d.wait_for_output(&["s", r"port \d+"])?;
+    // Give the watch a moment to get set up.
+    thread::sleep(time::Duration::from_millis(500));
+
+    // The arithmetic keeps the echoed command line from matching.
+    let out = d.send_keys("s", &["echo listening on port $((8000+80))", "Ctrl-m"], None)?;
+    assert!(out.status.success(), "send-keys failed: {out:?}");
+
+    let out = waiter.wait_with_output()?;
+    assert!(out.status.success(), "wait-for-output failed: {out:?}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "port 8080\n");
+
+    Ok(())
+}
+
+// A prompt never gets a newline, so it matches once the output goes quiet.
+#[test]
+#[timeout(30000)]
+fn matches_prompt() -> anyhow::Result<()> {
+    let mut d = start_daemon("norc.toml")?;
+    start_detached(&mut d, "s", None)?;
+
+    let waiter = d.wait_for_output(&["s", "^prompt> $"])?;
+    thread::sleep(time::Duration::from_millis(500));
+
+    let out = d.send_keys("s", &["true", "Ctrl-m"], None)?;
+    assert!(out.status.success(), "send-keys failed: {out:?}");
+
+    let out = waiter.wait_with_output()?;
+    assert!(out.status.success(), "wait-for-output failed: {out:?}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "prompt> \n");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn screen() -> anyhow::Result<()> {
+    let mut d = start_daemon("restore_screen.toml")?;
+    start_detached(&mut d, "s", Some("/bin/sh -c 'echo ready 42; sleep 100'"))?;
+
+    let out = d.wait_for_output(&["--screen", "s", r"ready \d+"])?.wait_with_output()?;
+    assert!(out.status.success(), "wait-for-output --screen failed: {out:?}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "ready 42\n");
+
+    // Without --screen, output that is already there doesn't count.
+    let out = d.wait_for_output(&["--timeout", "1s", "s", r"ready \d+"])?.wait_with_output()?;
+    assert_eq!(out.status.code(), Some(124), "wait-for-output output: {out:?}");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("timed out"));
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn fails_if_shell_exits() -> anyhow::Result<()> {
+    let mut d = start_daemon("norc.toml")?;
+    start_detached(&mut d, "s", Some("/bin/sh -c 'sleep 1; exit 3'"))?;
+
+    let out = d.wait_for_output(&["s", "never"])?.wait_with_output()?;
+    assert_eq!(out.status.code(), Some(1), "wait-for-output output: {out:?}");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("exited with status 3"), "stderr: {stderr}");
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn invalid_pattern() -> anyhow::Result<()> {
+    let mut d = start_daemon("norc.toml")?;
+    start_detached(&mut d, "s", None)?;
+
+    let out = d.wait_for_output(&["s", "("])?.wait_with_output()?;
+    assert!(!out.status.success(), "wait-for-output succeeded: {out:?}");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("invalid pattern"));
+
+    Ok(())
+}
+
+#[test]
+#[timeout(30000)]
+fn not_found() -> anyhow::Result<()> {
+    let mut d = start_daemon("norc.toml")?;
+
+    let out = d.wait_for_output(&["nosuchsession", "x"])?.wait_with_output()?;
+    assert!(!out.status.success(), "wait-for-output succeeded: {out:?}");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("not found"));
+
+    Ok(())
+}